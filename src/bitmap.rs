@@ -0,0 +1,254 @@
+use crate::packindex::{PackIndex, PackIndexItem};
+use anyhow::{Context, Result, ensure};
+use hex::ToHex;
+use std::collections::HashMap;
+use std::io::{BufReader, Read};
+
+/// An uncompressed bitmap, where bit `n` stands for the `n`th object in pack order.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Bitmap {
+    words: Vec<u64>,
+}
+
+impl Bitmap {
+    pub fn get(&self, n: usize) -> bool {
+        self.words
+            .get(n / 64)
+            .is_some_and(|word| word & (1 << (n % 64)) != 0)
+    }
+
+    pub fn set(&mut self, n: usize) {
+        if self.words.len() <= n / 64 {
+            self.words.resize(n / 64 + 1, 0);
+        }
+        self.words[n / 64] |= 1 << (n % 64);
+    }
+
+    pub fn or(&mut self, other: &Bitmap) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(other.words.iter()) {
+            *word |= other;
+        }
+    }
+
+    fn xor(&mut self, other: &Bitmap) {
+        if self.words.len() < other.words.len() {
+            self.words.resize(other.words.len(), 0);
+        }
+        for (word, other) in self.words.iter_mut().zip(other.words.iter()) {
+            *word ^= other;
+        }
+    }
+
+    /// The number of bits set in both bitmaps.
+    pub fn count_and(&self, other: &Bitmap) -> usize {
+        self.words
+            .iter()
+            .zip(other.words.iter())
+            .map(|(a, b)| (a & b).count_ones() as usize)
+            .sum()
+    }
+}
+
+/// Read an EWAH compressed bitmap as git writes it: the size in bits, a
+/// count of 64 bit words, the words and the position of the last run
+/// length word. Each run length word says how many all-zero or all-one
+/// words it stands for, followed by how many literal words come after it.
+fn read_ewah<R: Read>(reader: &mut R) -> Result<Bitmap> {
+    let bits = read_u32be(reader)? as usize;
+    let count = read_u32be(reader)? as usize;
+    // read what is there rather than trusting the count with an allocation
+    let mut compressed = Vec::new();
+    reader
+        .take(count as u64 * 8)
+        .read_to_end(&mut compressed)
+        .context("reading bitmap words")?;
+    ensure!(
+        compressed.len() == count * 8,
+        "bitmap words run past the end"
+    );
+    let compressed = compressed
+        .chunks_exact(8)
+        .map(|b| u64::from_be_bytes(b.try_into().unwrap()))
+        .collect::<Vec<_>>();
+    read_u32be(reader)?;
+
+    let max_words = bits.div_ceil(64);
+    let mut words = Vec::with_capacity(max_words);
+    let mut i = 0;
+    while i < compressed.len() {
+        let rlw = compressed[i];
+        i += 1;
+        let fill = if rlw & 1 != 0 { u64::MAX } else { 0 };
+        let run = ((rlw >> 1) & 0xffff_ffff) as usize;
+        let literals = (rlw >> 33) as usize;
+        ensure!(
+            i + literals <= compressed.len(),
+            "bitmap literal words run past the end"
+        );
+        // checked before the run is expanded, which a corrupt word makes huge
+        ensure!(
+            words.len() + run + literals <= max_words,
+            "bitmap has more words than its size allows"
+        );
+        words.extend(std::iter::repeat_n(fill, run));
+        words.extend_from_slice(&compressed[i..i + literals]);
+        i += literals;
+    }
+    Ok(Bitmap { words })
+}
+
+fn read_u32be<R: Read>(reader: &mut R) -> Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf).context("reading u32")?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+/// A `.bitmap` file, giving the objects reachable from selected commits in its pack.
+pub struct PackBitmap {
+    commits: Bitmap,
+    reachable: HashMap<[u8; 20], Bitmap>,
+    positions: HashMap<[u8; 20], usize>,
+}
+
+impl PackBitmap {
+    pub fn new<T: Read>(mut reader: BufReader<T>, index: &PackIndex) -> Result<Self> {
+        let mut header = [0; 4];
+        reader
+            .read_exact(&mut header)
+            .context("reading bitmap header")?;
+        ensure!(&header == b"BITM", "invalid bitmap header");
+        let mut version = [0; 2];
+        reader.read_exact(&mut version)?;
+        ensure!(
+            u16::from_be_bytes(version) == 1,
+            "only bitmap version 1 is supported"
+        );
+        let mut flags = [0; 2];
+        reader.read_exact(&mut flags)?;
+        let count = read_u32be(&mut reader)? as usize;
+        let mut pack_sha1 = [0; 20];
+        reader.read_exact(&mut pack_sha1)?;
+        ensure!(
+            pack_sha1 == index.id(),
+            "bitmap is for pack {}",
+            pack_sha1.encode_hex::<String>()
+        );
+
+        let commits = read_ewah(&mut reader).context("reading commit type bitmap")?;
+        // the tree, blob and tag type bitmaps
+        for _ in 0..3 {
+            read_ewah(&mut reader).context("reading type bitmap")?;
+        }
+
+        let by_sha1 = index
+            .iter()
+            .map(|PackIndexItem(sha1, _)| sha1)
+            .collect::<Vec<_>>();
+        let mut entries: Vec<([u8; 20], Bitmap)> = Vec::with_capacity(count);
+        for n in 0..count {
+            let position = read_u32be(&mut reader)? as usize;
+            let mut xor_flags = [0; 2];
+            reader.read_exact(&mut xor_flags)?;
+            let mut bitmap =
+                read_ewah(&mut reader).with_context(|| format!("reading bitmap {}", n))?;
+            // a bitmap may be stored xor'd against one of the entries just before it
+            let xor_offset = xor_flags[0] as usize;
+            if xor_offset > 0 {
+                let (_, base) = n
+                    .checked_sub(xor_offset)
+                    .and_then(|i| entries.get(i))
+                    .with_context(|| format!("bitmap {} xor offset out of range", n))?;
+                bitmap.xor(base);
+            }
+            let sha1 = by_sha1
+                .get(position)
+                .with_context(|| format!("bitmap {} position out of range", n))?;
+            entries.push((*sha1, bitmap));
+        }
+
+        let mut by_offset = index
+            .iter()
+            .map(|PackIndexItem(sha1, offset)| (offset, sha1))
+            .collect::<Vec<_>>();
+        by_offset.sort();
+        let positions = by_offset
+            .into_iter()
+            .enumerate()
+            .map(|(position, (_, sha1))| (sha1, position))
+            .collect();
+
+        Ok(PackBitmap {
+            commits,
+            reachable: entries.into_iter().collect(),
+            positions,
+        })
+    }
+
+    /// The object's bit, if it is in this pack.
+    pub fn position(&self, sha1: [u8; 20]) -> Option<usize> {
+        self.positions.get(&sha1).copied()
+    }
+
+    /// Everything reachable from `commit`, if the bitmap covers it.
+    pub fn reachable(&self, commit: [u8; 20]) -> Option<&Bitmap> {
+        self.reachable.get(&commit)
+    }
+
+    /// The bits that are commits.
+    pub fn commits(&self) -> &Bitmap {
+        &self.commits
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::bitmap::{Bitmap, read_ewah};
+
+    fn ewah(bits: u32, words: &[u64]) -> Vec<u8> {
+        let mut res = Vec::new();
+        res.extend_from_slice(&bits.to_be_bytes());
+        res.extend_from_slice(&(words.len() as u32).to_be_bytes());
+        for word in words {
+            res.extend_from_slice(&word.to_be_bytes());
+        }
+        res.extend_from_slice(&0u32.to_be_bytes());
+        res
+    }
+
+    #[test]
+    fn read_runs_and_literals() {
+        // two words of ones, then one literal word, then a run of one zero word
+        let data = ewah(256, &[(1 << 33) | (2 << 1) | 1, 0b101, 1 << 1]);
+        let bitmap = read_ewah(&mut &data[..]).unwrap();
+        assert!((0..128).all(|n| bitmap.get(n)));
+        assert!(bitmap.get(128) && !bitmap.get(129) && bitmap.get(130));
+        assert!((131..256).all(|n| !bitmap.get(n)));
+
+        let mut ones = Bitmap::default();
+        (0..256).for_each(|n| ones.set(n));
+        assert_eq!(bitmap.count_and(&ones), 130);
+    }
+
+    #[test]
+    fn literals_past_the_end() {
+        let data = ewah(128, &[2 << 33, 0]);
+        assert!(read_ewah(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn corrupt_sizes_are_not_allocated() {
+        // a run of 2^32 - 1 words in a bitmap of two
+        let data = ewah(128, &[0xffff_ffff << 1]);
+        let err = read_ewah(&mut &data[..]).unwrap_err().to_string();
+        assert_eq!(err, "bitmap has more words than its size allows");
+
+        // a word count far beyond the data
+        let mut data = ewah(128, &[1 << 1]);
+        data[4..8].copy_from_slice(&u32::MAX.to_be_bytes());
+        let err = read_ewah(&mut &data[..]).unwrap_err().to_string();
+        assert_eq!(err, "bitmap words run past the end");
+    }
+}
@@ -0,0 +1,211 @@
+use crate::diff::matching_lines;
+use crate::error::WyagError;
+use crate::gitobject::ObjectType;
+use crate::gitobject::identity::Identity;
+use crate::logiterator::{CommitCache, HeapItem};
+use crate::repository::Repository;
+use anyhow::{Context, Result};
+use std::collections::hash_map::Entry;
+use std::collections::{BinaryHeap, HashMap};
+use std::path::Path;
+
+/// A line of a file and the commit that last changed it.
+#[derive(Debug)]
+pub struct BlameLine {
+    pub commit: [u8; 20],
+    pub author: Option<Identity>,
+    /// Where the line was in that commit's version of the file, counting from 0.
+    pub original_line: usize,
+    pub content: Vec<u8>,
+}
+
+/// Attribute each line of `path` at `commit` to the commit that last changed
+/// it. Lines a commit shares with a parent are passed on to that parent,
+/// trying parents in order, and the lines left over were changed by the
+/// commit itself.
+pub fn blame(repository: &Repository, commit: [u8; 20], path: &Path) -> Result<Vec<BlameLine>> {
+    let shallow = repository.shallow()?;
+    let mut cache = CommitCache::new(repository);
+    let blob = repository.object_at_path(commit, path)?;
+    let data = read_blob(repository, blob)?;
+    let lines = data
+        .split_inclusive(|&b| b == b'\n')
+        .map(<[u8]>::to_vec)
+        .collect::<Vec<_>>();
+    let mut blamed = vec![None; lines.len()];
+
+    // commit -> (its blob, [(line in its blob, line in the final blob)])
+    let mut pending = HashMap::new();
+    pending.insert(
+        commit,
+        (blob, (0..lines.len()).map(|i| (i, i)).collect::<Vec<_>>()),
+    );
+    let mut queue = BinaryHeap::new();
+    queue.push(HeapItem(cache.read(commit)?.committer_timestamp(), commit));
+
+    while let Some(HeapItem(_, sha1)) = queue.pop() {
+        let Some((blob, mut remaining)) = pending.remove(&sha1) else {
+            continue;
+        };
+        // the parents of a shallow commit were never fetched
+        let parents = if shallow.contains(&sha1) {
+            Vec::new()
+        } else {
+            let commit = cache.read(sha1)?;
+            repository.parents(sha1, &commit)
+        };
+
+        let mut data = None;
+        for parent in parents {
+            if remaining.is_empty() {
+                break;
+            }
+            let Some(parent_blob) = blob_at(repository, parent, path)? else {
+                continue;
+            };
+            let passed: Vec<(usize, usize)> = if parent_blob == blob {
+                std::mem::take(&mut remaining)
+            } else {
+                let data = match &data {
+                    Some(data) => data,
+                    None => data.insert(read_blob(repository, blob)?),
+                };
+                let parent_data = read_blob(repository, parent_blob)?;
+                let matches = matching_lines(&parent_data, data)
+                    .into_iter()
+                    .map(|(old, new)| (new, old))
+                    .collect::<HashMap<_, _>>();
+                let (passed, kept) = remaining
+                    .into_iter()
+                    .partition::<Vec<_>, _>(|(line, _)| matches.contains_key(line));
+                remaining = kept;
+                passed
+                    .into_iter()
+                    .map(|(line, last)| (matches[&line], last))
+                    .collect()
+            };
+            if passed.is_empty() {
+                continue;
+            }
+
+            // merges can reach the same ancestor through several parents
+            match pending.entry(parent) {
+                Entry::Occupied(mut entry) => entry.get_mut().1.extend(passed),
+                Entry::Vacant(entry) => {
+                    let timestamp = cache.read(parent)?.committer_timestamp();
+                    queue.push(HeapItem(timestamp, parent));
+                    entry.insert((parent_blob, passed));
+                }
+            }
+        }
+        for (line, last) in remaining {
+            blamed[last] = Some((sha1, line));
+        }
+    }
+
+    lines
+        .into_iter()
+        .zip(blamed)
+        .map(|(content, blamed)| {
+            let (commit, original_line) = blamed.context("line was not attributed")?;
+            Ok(BlameLine {
+                commit,
+                author: cache.read(commit)?.author(),
+                original_line,
+                content,
+            })
+        })
+        .collect()
+}
+
+fn read_blob(repository: &Repository, sha1: [u8; 20]) -> Result<Vec<u8>> {
+    let mut data = Vec::new();
+    repository.read_object_data_as(sha1, ObjectType::Blob, &mut data)?;
+    Ok(data)
+}
+
+/// The blob at `path` in `commit`, or `None` if the commit does not have it.
+fn blob_at(repository: &Repository, commit: [u8; 20], path: &Path) -> Result<Option<[u8; 20]>> {
+    match repository.object_at_path(commit, path) {
+        Ok(sha1) => Ok(Some(sha1)),
+        Err(e) if matches!(e.downcast_ref(), Some(WyagError::NotFound(_))) => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::blame::blame;
+    use crate::gitobject::GitObject;
+    use crate::gitobject::blob::BlobObject;
+    use crate::gitobject::commit::CommitObject;
+    use crate::gitobject::tree::TreeBuilder;
+    use crate::testutil::temp_repo;
+    use flate2::Compression;
+    use hex::ToHex;
+    use std::path::Path;
+
+    #[test]
+    fn blame_through_merge() {
+        let (_dir, repo) = temp_repo();
+
+        let mut time = 0;
+        let mut commit = |parents: &[[u8; 20]], author: &str, contents: &str| {
+            let blob = GitObject::Blob(BlobObject::from(contents.as_bytes().to_vec()));
+            let mut tree = TreeBuilder::new();
+            tree.insert(
+                "f",
+                "100644",
+                repo.write_object(&blob, true, Compression::default())
+                    .unwrap(),
+            )
+            .unwrap();
+            let tree = repo
+                .write_object(&GitObject::Tree(tree.build()), true, Compression::default())
+                .unwrap();
+            let mut commit = format!("tree {}\n", tree.encode_hex::<String>());
+            for parent in parents {
+                commit += &format!("parent {}\n", parent.encode_hex::<String>());
+            }
+            time += 1;
+            commit += &format!(
+                "author {author} <a@b> {time} +0000\ncommitter {author} <a@b> {time} +0000\n\nc\n"
+            );
+            let commit = CommitObject::from(commit.into_bytes()).unwrap();
+            repo.write_object(&GitObject::Commit(commit), true, Compression::default())
+                .unwrap()
+        };
+
+        let first = commit(&[], "First", "a\nb\nc\n");
+        let second = commit(&[first], "Second", "a\nB\nc\n");
+        let third = commit(&[second], "Third", "a\nB\nc\nd\n");
+        let side = commit(&[first], "Side", "0\na\nb\nc\n");
+        let merge = commit(&[third, side], "Merge", "0\na\nB\nc\nd\nmerged\n");
+
+        let lines = blame(&repo, merge, Path::new("f")).unwrap();
+        let blamed = lines
+            .iter()
+            .map(|l| {
+                (
+                    l.commit,
+                    l.author.as_ref().unwrap().name.as_str(),
+                    l.original_line,
+                    l.content.as_slice(),
+                )
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            blamed,
+            vec![
+                (side, "Side", 0, &b"0\n"[..]),
+                (first, "First", 0, b"a\n"),
+                (second, "Second", 1, b"B\n"),
+                (first, "First", 2, b"c\n"),
+                (third, "Third", 3, b"d\n"),
+                (merge, "Merge", 5, b"merged\n"),
+            ]
+        );
+
+        assert!(blame(&repo, merge, Path::new("missing")).is_err());
+    }
+}
@@ -2,19 +2,64 @@ use clap::{Parser, Subcommand, ValueEnum};
 use log::LevelFilter;
 use std::fmt::Display;
 use std::path::PathBuf;
+use wyag::ObjectType;
+use wyag::gitobject::identity::DateFormat;
 
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 #[command(propagate_version = true)]
+#[command(
+    after_help = "Exit status is 0 on success, 1 when an object or ref is missing or a check finds \
+                  problems, and 128 on any other error, including bad arguments."
+)]
 pub struct Cli {
     /// Set the log level.
     #[arg(short, long, default_value_t = CommandLogLevel::Off)]
     pub log_level: CommandLogLevel,
 
+    /// Give status, show-ref, ls-tree and branch a stable format for scripts.
+    #[arg(long, global = true)]
+    pub porcelain: bool,
+
+    /// End each entry of status, show-ref, ls-tree and branch --porcelain with NUL
+    /// instead of a newline, and print paths as they are rather than quoted. Implies
+    /// --porcelain for status.
+    #[arg(short = 'z', global = true)]
+    pub null_terminated: bool,
+
+    /// Leave out informational messages, such as progress.
+    #[arg(short, long, global = true)]
+    pub quiet: bool,
+
     #[command(subcommand)]
     pub command: Commands,
 }
 
+impl Cli {
+    pub fn output(&self) -> Output {
+        Output {
+            porcelain: self.porcelain,
+            null_terminated: self.null_terminated,
+            quiet: self.quiet,
+        }
+    }
+}
+
+/// How commands should print what they find.
+#[derive(Copy, Clone, Default)]
+pub struct Output {
+    pub porcelain: bool,
+    pub null_terminated: bool,
+    pub quiet: bool,
+}
+
+impl Output {
+    /// What ends each entry of a listing.
+    pub fn terminator(&self) -> char {
+        if self.null_terminated { '\0' } else { '\n' }
+    }
+}
+
 #[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
 pub enum CommandLogLevel {
     Trace,
@@ -60,6 +105,17 @@ pub enum CommandObjectType {
     Tree,
 }
 
+impl From<CommandObjectType> for ObjectType {
+    fn from(object_type: CommandObjectType) -> Self {
+        match object_type {
+            CommandObjectType::Blob => ObjectType::Blob,
+            CommandObjectType::Commit => ObjectType::Commit,
+            CommandObjectType::Tag => ObjectType::Tag,
+            CommandObjectType::Tree => ObjectType::Tree,
+        }
+    }
+}
+
 impl Display for CommandObjectType {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let name = match self {
@@ -72,22 +128,118 @@ impl Display for CommandObjectType {
     }
 }
 
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CommandLogFormat {
+    Oneline,
+    Short,
+    Medium,
+    Full,
+}
+
+impl Display for CommandLogFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            CommandLogFormat::Oneline => "oneline",
+            CommandLogFormat::Short => "short",
+            CommandLogFormat::Medium => "medium",
+            CommandLogFormat::Full => "full",
+        };
+        f.write_str(name)
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, PartialOrd, Ord, ValueEnum)]
+pub enum CommandDateFormat {
+    /// As `Tue May 22 23:37:03 2018 +0200`.
+    Default,
+    /// As `2018-05-22 23:37:03 +0200`.
+    Iso,
+    /// Relative to now, such as `3 days ago`.
+    Relative,
+    /// Seconds since the epoch.
+    Unix,
+}
+
+impl From<CommandDateFormat> for DateFormat {
+    fn from(format: CommandDateFormat) -> Self {
+        match format {
+            CommandDateFormat::Default => DateFormat::Default,
+            CommandDateFormat::Iso => DateFormat::Iso,
+            CommandDateFormat::Relative => DateFormat::Relative,
+            CommandDateFormat::Unix => DateFormat::Unix,
+        }
+    }
+}
+
+impl Display for CommandDateFormat {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            CommandDateFormat::Default => "default",
+            CommandDateFormat::Iso => "iso",
+            CommandDateFormat::Relative => "relative",
+            CommandDateFormat::Unix => "unix",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Subcommand)]
 pub enum Commands {
     /// Initialise a new empty repository.
     Init {
+        /// Create a repository without a worktree, with the gitdir at `path`.
+        #[arg(long)]
+        bare: bool,
+
+        /// The branch `HEAD` points at, instead of `init.defaultBranch` or master.
+        #[arg(short = 'b', long)]
+        initial_branch: Option<String>,
+
         /// Where to create the repository.
         path: PathBuf,
     },
 
     /// Provide content of repository objects.
+    ///
+    /// A tree is read through a commit or tag naming it, and a commit through a tag.
+    /// Exits with 1 if the object does not exist or, without --allow-type-mismatch, is
+    /// of another type.
     CatObject {
         /// Specify the type.
-        #[arg(value_enum)]
-        object_type: CommandObjectType,
+        #[arg(value_enum, required_unless_present_any = ["batch", "batch_check"])]
+        object_type: Option<CommandObjectType>,
 
         /// The object to display.
-        name: String,
+        #[arg(required_unless_present_any = ["batch", "batch_check"])]
+        name: Option<String>,
+
+        /// Show the object even if it is not of the requested type.
+        #[arg(long)]
+        allow_type_mismatch: bool,
+
+        /// Dump the raw contents even of an object whose type is unknown, implying --allow-type-mismatch.
+        #[arg(long)]
+        allow_unknown_type: bool,
+
+        /// Report on stderr whether the object was read from a loose file or a pack.
+        #[arg(long, conflicts_with = "allow_unknown_type")]
+        source: bool,
+
+        /// Start this many bytes into the object's data, without keeping what comes before.
+        #[arg(long, conflicts_with_all = ["allow_unknown_type", "source"])]
+        offset: Option<u64>,
+
+        /// Show at most this many bytes of the object's data.
+        #[arg(long, conflicts_with_all = ["allow_unknown_type", "source"])]
+        length: Option<u64>,
+
+        /// Read object names from stdin, printing the type, size and contents of each.
+        #[arg(long, conflicts_with_all = ["object_type", "name", "batch_check"])]
+        batch: bool,
+
+        /// Read object names from stdin, printing the type and size of each.
+        #[arg(long, conflicts_with_all = ["object_type", "name"])]
+        batch_check: bool,
 
         /// Path to repository.
         #[arg(long)]
@@ -104,6 +256,15 @@ pub enum Commands {
         #[arg(short, long)]
         write: bool,
 
+        /// The zlib compression level to store objects with, from 0 (none) to 9 (smallest).
+        #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9))]
+        compression: u32,
+
+        /// Hash the content as if it were at <PATH> in the worktree rather than where <FILE>
+        /// is, as git does to pick the filters .gitattributes gives it.
+        #[arg(long)]
+        path: Option<PathBuf>,
+
         /// Read object from <FILE>.
         file: PathBuf,
     },
@@ -114,30 +275,417 @@ pub enum Commands {
         #[arg(short, long)]
         recurse: bool,
 
+        /// Show only trees, not the blobs in them.
+        #[arg(short = 'd')]
+        trees_only: bool,
+
+        /// Show the size of blobs.
+        #[arg(short, long)]
+        long: bool,
+
+        /// Show only the paths.
+        #[arg(long)]
+        name_only: bool,
+
         /// Path to repository.
         #[arg(long)]
         repository: Option<PathBuf>,
 
         /// A tree-ish object.
         tree: String,
+
+        /// Only list these paths, or the contents of a directory ending in `/`.
+        paths: Vec<String>,
     },
 
-    /// Describe a pack file.
+    /// List the objects in a pack file, as `git verify-pack -v` does.
     LsPack {
         /// Path to repository.
         #[arg(long)]
         repository: Option<PathBuf>,
 
+        /// Finish with how many objects have each delta chain length.
+        #[arg(long)]
+        depth_histogram: bool,
+
+        /// Trust the object store rather than hashing each object read to check it,
+        /// overriding core.checkObjectContent.
+        #[arg(long)]
+        no_validate: bool,
+
+        /// A packfile sha.
+        packfile: String,
+    },
+
+    /// Check every object in a pack file.
+    ///
+    /// Exits with 1 if the pack is missing or any object in it is corrupt.
+    VerifyPack {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// Resolve objects across this many threads.
+        #[arg(long, default_value_t = 1)]
+        threads: usize,
+
         /// A packfile sha.
         packfile: String,
     },
 
+    /// Build an index for a pack file and add both to the repository.
+    IndexPack {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// The zlib compression level for delta bases added to a thin pack, from 0 (none) to 9 (smallest).
+        #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9))]
+        compression: u32,
+
+        /// The pack file to index.
+        file: PathBuf,
+    },
+
+    /// Show commit logs.
     Log {
         /// Path to repository.
         #[arg(long)]
         repository: Option<PathBuf>,
 
+        /// Shorthand for --format=oneline.
+        #[arg(long)]
+        oneline: bool,
+
+        /// Pretty-print commits in the given format.
+        #[arg(long, default_value_t = CommandLogFormat::Medium)]
+        format: CommandLogFormat,
+
+        /// How to show dates.
+        #[arg(long, default_value_t = CommandDateFormat::Default)]
+        date: CommandDateFormat,
+
+        /// Show no commit before all of its children, even when their dates say otherwise.
+        #[arg(long)]
+        topo_order: bool,
+
+        /// Choose between branches by author date rather than committer date.
+        #[arg(long, conflicts_with = "topo_order")]
+        author_date_order: bool,
+
+        /// Trust the object store rather than hashing each object read to check it,
+        /// overriding core.checkObjectContent.
+        #[arg(long)]
+        no_validate: bool,
+
         /// An object name.
         reference: String,
     },
+
+    /// Get and set repository options.
+    Config {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// List every option, with the worktree and repository config overriding the global one.
+        #[arg(short, long, conflicts_with_all = ["name", "value"])]
+        list: bool,
+
+        /// The option name, as <section>.<key>.
+        #[arg(required_unless_present = "list")]
+        name: Option<String>,
+
+        /// Set the option to <VALUE> instead of printing it.
+        value: Option<String>,
+    },
+
+    /// Show the files in the index.
+    LsFiles {
+        /// Show staged contents' mode, object name and stage number.
+        #[arg(short, long)]
+        stage: bool,
+
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+    },
+
+    /// Add file contents to the index.
+    Add {
+        /// Allow adding otherwise ignored files.
+        #[arg(short, long)]
+        force: bool,
+
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// Files or directories to add.
+        #[arg(required = true)]
+        paths: Vec<PathBuf>,
+    },
+
+    /// Show the working tree status.
+    Status {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+    },
+
+    /// Clone a repository served over dumb HTTP.
+    Clone {
+        /// The repository url.
+        url: String,
+
+        /// Where to clone to, defaulting to the last part of the url.
+        directory: Option<PathBuf>,
+    },
+
+    /// List branches, marking the one HEAD is on.
+    Branch {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// List branches, which is also what happens without any other option.
+        #[arg(short, long)]
+        list: bool,
+    },
+
+    /// List every ref, loose or packed, with the object it points at.
+    ShowRef {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// Follow each annotated tag with the object it points at, as `<tag>^{}`.
+        #[arg(short, long)]
+        dereference: bool,
+    },
+
+    /// Print every ref matching the patterns in a format of your choosing.
+    ForEachRef {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// What to print for each ref, with %(refname), %(objectname), %(objectname:short),
+        /// %(objecttype), %(authorname) and %(subject) filled in and %% for a percent sign.
+        #[arg(long, default_value = wyag::foreachref::DEFAULT_FORMAT)]
+        format: String,
+
+        /// A field to sort by, descending with a leading `-`. The last one given decides the
+        /// order first, and refs are sorted by name without any.
+        #[arg(long, value_name = "KEY")]
+        sort: Vec<String>,
+
+        /// Refs to list, either a prefix such as `refs/heads` or a glob such as `refs/tags/v*`.
+        patterns: Vec<String>,
+    },
+
+    /// Show the history of a ref.
+    Reflog {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// The ref to show.
+        #[arg(default_value = "HEAD")]
+        reference: String,
+    },
+
+    /// Find a symbolic name for a commit.
+    NameRev {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// The commit to name.
+        commit: String,
+    },
+
+    /// Describe a commit using the most recent tag reachable from it.
+    Describe {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// Use lightweight tags as well as annotated ones.
+        #[arg(long)]
+        tags: bool,
+
+        /// Abbreviate the commit name to this many hex digits, or show only the tag with 0.
+        #[arg(long, default_value_t = 7)]
+        abbrev: usize,
+
+        /// The commit to describe.
+        #[arg(default_value = "HEAD")]
+        commit: String,
+    },
+
+    /// Show the commit that last changed each line of a file.
+    Blame {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// The commit to start from.
+        #[arg(long, default_value = "HEAD")]
+        commit: String,
+
+        /// The file, relative to the top of the repository.
+        path: PathBuf,
+    },
+
+    /// List tags, or check their signatures.
+    ///
+    /// With --verify, exits with 1 unless every signature is good.
+    Tag {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// List the tags matching any of the patterns, or every tag without one, which is
+        /// also what happens without --verify.
+        #[arg(short, long)]
+        list: bool,
+
+        /// Follow each name with up to <NUM> lines of its message, or of the commit's for a
+        /// lightweight tag, one if <NUM> is left out.
+        #[arg(short = 'n', value_name = "NUM", num_args = 0..=1, default_missing_value = "1")]
+        lines: Option<usize>,
+
+        /// Check the GPG signatures of the named tags, as verify-tag does.
+        #[arg(short, long, conflicts_with_all = ["list", "lines"], requires = "names")]
+        verify: bool,
+
+        /// Glob patterns for the tag names to list, such as `v1.*`, or the tags to verify.
+        names: Vec<String>,
+    },
+
+    /// Check the GPG signature of commits.
+    ///
+    /// Exits with 1 unless every signature is good.
+    VerifyCommit {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// The commits to check.
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+
+    /// Check the GPG signature of tags.
+    ///
+    /// Exits with 1 unless every signature is good.
+    VerifyTag {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// The tags to check.
+        #[arg(required = true)]
+        tags: Vec<String>,
+    },
+
+    /// Pack loose objects and remove the packed copies.
+    Gc {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// Only remove loose objects older than this many seconds.
+        #[arg(long, default_value_t = 14 * 24 * 60 * 60)]
+        prune: u64,
+
+        /// The zlib compression level to write the pack with, from 0 (none) to 9 (smallest).
+        #[arg(long, default_value_t = 6, value_parser = clap::value_parser!(u32).range(0..=9))]
+        compression: u32,
+
+        /// Mark the pack with this checksum with a `.keep` file first, so this and
+        /// later runs leave it alone.
+        #[arg(long, value_name = "SHA")]
+        keep_pack: Vec<String>,
+    },
+
+    /// Check the object store for corrupt objects and packs.
+    ///
+    /// Exits with 1 if anything is corrupt; stray files are only warned about.
+    Fsck {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+    },
+
+    /// Count loose objects and the disk space they take.
+    CountObjects {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// Also report on packs and on loose objects that are already packed.
+        #[arg(short, long)]
+        verbose: bool,
+    },
+
+    /// List commits reachable from the given ones, newest first.
+    RevList {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// Print only how many commits, and with --objects other objects, there are.
+        #[arg(long)]
+        count: bool,
+
+        /// After the commits, list every tree and blob they reach with its path.
+        #[arg(long)]
+        objects: bool,
+
+        /// List no commit before all of its children, even when their dates say otherwise.
+        #[arg(long)]
+        topo_order: bool,
+
+        /// Choose between branches by author date rather than committer date.
+        #[arg(long, conflicts_with = "topo_order")]
+        author_date_order: bool,
+
+        /// Trust the object store rather than hashing each object read to check it,
+        /// overriding core.checkObjectContent.
+        #[arg(long)]
+        no_validate: bool,
+
+        /// Commits, or tags pointing at them, to start from.
+        #[arg(required = true)]
+        commits: Vec<String>,
+    },
+
+    /// Remove unreachable loose objects.
+    Prune {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// List the objects that would be removed without removing them.
+        #[arg(short = 'n', long)]
+        dry_run: bool,
+
+        /// Only remove objects older than this many seconds.
+        #[arg(long, default_value_t = 14 * 24 * 60 * 60)]
+        expire: u64,
+    },
+
+    /// Download objects and refs from a remote over smart HTTP.
+    #[cfg(feature = "smart-http")]
+    Fetch {
+        /// Path to repository.
+        #[arg(long)]
+        repository: Option<PathBuf>,
+
+        /// The remote to fetch from.
+        #[arg(default_value = "origin")]
+        remote: String,
+    },
 }
@@ -0,0 +1,259 @@
+use crate::gitobject::GitObject;
+use crate::gitobject::tree::LeafKind;
+use crate::http;
+use crate::repository::Repository;
+use anyhow::{Context, Result, ensure};
+use flate2::Compression;
+use hex::{ToHex, decode};
+use log::debug;
+use std::collections::HashSet;
+use std::io::Write;
+use std::path::Path;
+use std::str::from_utf8;
+
+/// Clone a repository served over the dumb HTTP protocol, where the remote
+/// is a plain file server and every file is fetched by name.
+pub fn clone(url: &str, path: &Path) -> Result<Repository> {
+    let url = url.trim_end_matches('/');
+    let reinit = Repository::new(path, true)?
+        .init()
+        .with_context(|| format!("initialising {}", path.to_string_lossy()))?;
+    ensure!(
+        !reinit,
+        "{} is already a repository",
+        path.to_string_lossy()
+    );
+    let mut repo = Repository::new(path, false)?;
+
+    let refs = http::get(&format!("{}/info/refs", url))?
+        .with_context(|| format!("{}/info/refs not found, is this a git repository?", url))?;
+    let refs = parse_refs(&refs).context("parsing info/refs")?;
+
+    let packs = http::get(&format!("{}/objects/info/packs", url))?.unwrap_or_default();
+    for line in from_utf8(&packs)
+        .context("parsing objects/info/packs")?
+        .lines()
+    {
+        let Some(pack) = line.strip_prefix("P ") else {
+            continue;
+        };
+        ensure!(pack.ends_with(".pack"), "unexpected pack name {}", pack);
+        repo.download_pack(
+            |file| {
+                debug!("downloading {}", pack);
+                let data = http::get(&format!("{}/objects/pack/{}", url, pack))?
+                    .with_context(|| format!("{} is listed but not found", pack))?;
+                Ok(file.write_all(&data)?)
+            },
+            Compression::default(),
+        )
+        .with_context(|| format!("storing {}", pack))?;
+    }
+
+    fetch_objects(&repo, url, refs.iter().map(|(sha1, _)| *sha1))?;
+
+    let message = format!("clone: from {}", url);
+    for (sha1, name) in refs.iter() {
+        if let Some(branch) = name.strip_prefix("refs/heads/") {
+            repo.update_ref(&format!("refs/remotes/origin/{}", branch), *sha1, &message)?;
+        } else if name.starts_with("refs/tags/") {
+            repo.write_ref(name, &sha1.encode_hex::<String>())?;
+        }
+    }
+
+    let head = http::get(&format!("{}/HEAD", url))?.unwrap_or_default();
+    let head = from_utf8(&head).context("parsing HEAD")?.trim();
+    if let Some(branch) = head.strip_prefix("ref: ") {
+        // point HEAD first so that creating the branch logs to both
+        repo.write_ref("HEAD", head)?;
+        if let Some((sha1, _)) = refs.iter().find(|(_, name)| name == branch) {
+            repo.update_ref(branch, *sha1, &message)?;
+        }
+    } else if !head.is_empty() {
+        let sha1 = decode(head)
+            .ok()
+            .and_then(|v| v.try_into().ok())
+            .with_context(|| format!("bad HEAD {}", head))?;
+        repo.write_ref("HEAD", head)?;
+        repo.log_ref_update("HEAD", [0; 20], sha1, &message)?;
+    }
+
+    repo.config_set("remote \"origin\"", "url", url)?;
+    repo.config_set(
+        "remote \"origin\"",
+        "fetch",
+        "+refs/heads/*:refs/remotes/origin/*",
+    )?;
+
+    Ok(repo)
+}
+
+/// Parse `info/refs`, one `<sha>\t<name>` per line, dropping peeled tags.
+fn parse_refs(data: &[u8]) -> Result<Vec<([u8; 20], String)>> {
+    from_utf8(data)?
+        .lines()
+        .filter(|line| !line.is_empty() && !line.ends_with("^{}"))
+        .map(|line| {
+            let (sha1, name) = line
+                .split_once('\t')
+                .with_context(|| format!("bad ref line {}", line))?;
+            let sha1 = decode(sha1)
+                .ok()
+                .and_then(|v| v.try_into().ok())
+                .with_context(|| format!("bad sha1 in ref line {}", line))?;
+            Ok((sha1, name.to_string()))
+        })
+        .collect()
+}
+
+/// Walk everything reachable from `tips`, downloading any object the packs
+/// did not bring in as a loose object.
+fn fetch_objects(repo: &Repository, url: &str, tips: impl Iterator<Item = [u8; 20]>) -> Result<()> {
+    // blobs are only checked for existence, everything else is read to find what it references
+    let mut pending = tips.map(|sha1| (sha1, true)).collect::<Vec<_>>();
+    let mut seen = HashSet::new();
+    while let Some((sha1, read)) = pending.pop() {
+        if !seen.insert(sha1) {
+            continue;
+        }
+        let name = sha1.encode_hex::<String>();
+        if !repo.has_object(sha1) {
+            debug!("downloading loose object {}", name);
+            let data = http::get(&format!("{}/objects/{}/{}", url, &name[..2], &name[2..]))?
+                .with_context(|| format!("object {} is missing from the remote", name))?;
+            repo.store_loose_object(sha1, &data)
+                .with_context(|| format!("storing object {}", name))?;
+        }
+        if !read {
+            continue;
+        }
+
+        let mut data = Vec::new();
+        let object_type = repo.read_object_data(sha1, &mut data)?;
+        match GitObject::new(object_type, data)? {
+            GitObject::Commit(commit) => {
+                pending.extend(commit.tree().map(|tree| (tree, true)));
+                pending.extend(commit.parents().into_iter().map(|parent| (parent, true)));
+            }
+            GitObject::Tree(tree) => {
+                for leaf in tree.leaf_iter() {
+                    let leaf_sha1 = leaf.sha1.as_slice().try_into()?;
+                    match leaf.kind() {
+                        Some(LeafKind::Tree) => pending.push((leaf_sha1, true)),
+                        Some(LeafKind::Blob | LeafKind::Symlink) => {
+                            pending.push((leaf_sha1, false))
+                        }
+                        // submodule commits live in another repository
+                        Some(LeafKind::Gitlink) | None => {}
+                    }
+                }
+            }
+            GitObject::Tag(tag) => pending.extend(tag.object().map(|object| (object, true))),
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::clone::clone;
+    use crate::gitobject::GitObject;
+    use crate::gitobject::commit::CommitObject;
+    use crate::repository::Repository;
+    use flate2::Compression;
+    use hex::ToHex;
+    use std::fs;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread;
+
+    /// Serve files under `root` over HTTP until the test exits.
+    fn serve(root: PathBuf) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines();
+                let request = lines.next().unwrap().unwrap();
+                while !lines.next().unwrap().unwrap().is_empty() {}
+                let path = request.split(' ').nth(1).unwrap().trim_start_matches('/');
+                match fs::read(root.join(path)) {
+                    Ok(body) => {
+                        write!(
+                            stream,
+                            "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                            body.len()
+                        )
+                        .unwrap();
+                        stream.write_all(&body).unwrap();
+                    }
+                    Err(_) => write!(
+                        stream,
+                        "HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+                    )
+                    .unwrap(),
+                }
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn clone_over_dumb_http() {
+        let dir = tempfile::tempdir().unwrap();
+        let remote = Repository::new(&dir.path().join("remote"), true).unwrap();
+        remote.init().unwrap();
+        let gitdir = dir.path().join("remote/.git");
+
+        // a packed history with one loose commit on top
+        let pack = "pack-2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        fs::create_dir_all(gitdir.join("objects/pack")).unwrap();
+        for ext in ["idx", "pack"] {
+            let name = format!("{}.{}", pack, ext);
+            fs::copy(
+                format!("test/pack/{}", name),
+                gitdir.join("objects/pack").join(name),
+            )
+            .unwrap();
+        }
+        let commit = "tree 73e84ce75bc418b7f9828f9a83c91721e34f384a\n\
+            parent 94cfdc807c74db921207c72b7f1d21c748ddc807\n\
+            author A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nloose\n";
+        let commit = GitObject::Commit(CommitObject::from(commit.as_bytes().to_vec()).unwrap());
+        let head = remote
+            .write_object(&commit, true, Compression::default())
+            .unwrap();
+        fs::create_dir_all(gitdir.join("info")).unwrap();
+        fs::write(
+            gitdir.join("info/refs"),
+            format!("{}\trefs/heads/master\n", head.encode_hex::<String>()),
+        )
+        .unwrap();
+        fs::create_dir_all(gitdir.join("objects/info")).unwrap();
+        fs::write(
+            gitdir.join("objects/info/packs"),
+            format!("P {}.pack\n\n", pack),
+        )
+        .unwrap();
+
+        let url = serve(gitdir);
+        let repo = clone(&url, &dir.path().join("local")).unwrap();
+
+        assert_eq!(repo.head().unwrap(), Some(head));
+        let reflog = repo.reflog("HEAD").unwrap();
+        assert_eq!(reflog.len(), 1);
+        assert_eq!(reflog[0].old, [0; 20]);
+        assert_eq!(reflog[0].new, head);
+        assert_eq!(reflog[0].message, format!("clone: from {}", url));
+        assert_eq!(repo.reflog("master").unwrap(), reflog);
+        assert_eq!(repo.reflog("origin/master").unwrap(), reflog);
+        assert_eq!(repo.log_iter(head).unwrap().count(), 13);
+        assert_eq!(
+            repo.config_get("remote \"origin\"", "url").as_deref(),
+            Some(url.as_str())
+        );
+    }
+}
@@ -0,0 +1,203 @@
+use anyhow::{Result, bail};
+
+/// A `[section]` or `[section "subsection"]` header, with the section name
+/// lowercased as git compares it and the subsection kept as written.
+#[derive(Debug, PartialEq, Eq)]
+struct Header {
+    section: String,
+    subsection: Option<String>,
+}
+
+impl Header {
+    /// Read a section as config names it in this crate, `remote "origin"`.
+    fn from_name(name: &str) -> Self {
+        match name.split_once(' ') {
+            Some((section, subsection)) => Header {
+                section: section.to_ascii_lowercase(),
+                subsection: Some(subsection.trim_matches('"').to_string()),
+            },
+            None => Header {
+                section: name.to_ascii_lowercase(),
+                subsection: None,
+            },
+        }
+    }
+
+    /// Parse a header line, or `None` if the line is not one.
+    fn parse(line: &str) -> Option<Self> {
+        let inner = line.trim().strip_prefix('[')?;
+        let inner = &inner[..inner.find(']')?];
+        Some(match inner.split_once(char::is_whitespace) {
+            Some((section, subsection)) => Header {
+                section: section.to_ascii_lowercase(),
+                subsection: Some(
+                    subsection
+                        .trim()
+                        .trim_matches('"')
+                        .replace("\\\"", "\"")
+                        .replace("\\\\", "\\"),
+                ),
+            },
+            // the deprecated `[section.subsection]` form
+            None => match inner.split_once('.') {
+                Some((section, subsection)) => Header {
+                    section: section.to_ascii_lowercase(),
+                    subsection: Some(subsection.to_ascii_lowercase()),
+                },
+                None => Header {
+                    section: inner.to_ascii_lowercase(),
+                    subsection: None,
+                },
+            },
+        })
+    }
+
+    fn write(&self) -> String {
+        match &self.subsection {
+            Some(subsection) => format!(
+                "[{} \"{}\"]\n",
+                self.section,
+                subsection.replace('\\', "\\\\").replace('"', "\\\"")
+            ),
+            None => format!("[{}]\n", self.section),
+        }
+    }
+}
+
+/// The name of the variable a line sets, if it sets one.
+fn line_key(line: &str) -> Option<&str> {
+    let line = line.trim_start();
+    let end = line
+        .find(|c: char| !(c.is_ascii_alphanumeric() || c == '-'))
+        .unwrap_or(line.len());
+    let key = &line[..end];
+    let rest = line[end..].trim_start();
+    (!key.is_empty() && (rest.is_empty() || rest.starts_with('='))).then_some(key)
+}
+
+/// Whether a value line carries on to the next, ending in an unescaped `\`.
+fn continues(line: &str) -> bool {
+    let line = line.trim_end_matches(['\n', '\r']);
+    (line.len() - line.trim_end_matches('\\').len()) % 2 == 1
+}
+
+/// Write a value as git does, quoting it when its ends are blank or it has
+/// a comment character in it.
+fn quote_value(value: &str) -> String {
+    let escaped = value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t");
+    if value.starts_with(' ') || value.ends_with(' ') || value.contains(['#', ';']) {
+        format!("\"{}\"", escaped)
+    } else {
+        escaped
+    }
+}
+
+/// Set `key` in `section` of the config `text`, changing only the line that
+/// holds it, so comments, other values and the order of the file are kept.
+/// A key missing from the section is added after the section's last line,
+/// and a missing section is added at the end of the file.
+pub(crate) fn set_value(text: &str, section: &str, key: &str, value: &str) -> Result<String> {
+    let target = Header::from_name(section);
+    let line = format!("\t{} = {}\n", key, quote_value(value));
+    let mut text = text.to_string();
+    if !text.is_empty() && !text.ends_with('\n') {
+        text.push('\n');
+    }
+    let mut lines = text.split_inclusive('\n').collect::<Vec<_>>();
+
+    let mut in_section = false;
+    // the line after the last one in the target section
+    let mut section_end = None;
+    let mut existing = Vec::new();
+    let mut i = 0;
+    while i < lines.len() {
+        let start = i;
+        if let Some(header) = Header::parse(lines[i]) {
+            in_section = header == target;
+        } else if in_section && line_key(lines[i]).is_some_and(|k| k.eq_ignore_ascii_case(key)) {
+            existing.push(start);
+        }
+        while continues(lines[i]) && i + 1 < lines.len() {
+            i += 1;
+        }
+        i += 1;
+        if in_section && !lines[start].trim().is_empty() {
+            section_end = Some(i);
+        }
+    }
+
+    match (existing.as_slice(), section_end) {
+        ([], Some(end)) => lines.insert(end, &line),
+        ([], None) => return Ok(text.clone() + &target.write() + &line),
+        ([start], _) => {
+            let mut end = *start;
+            while continues(lines[end]) && end + 1 < lines.len() {
+                end += 1;
+            }
+            lines.splice(*start..=end, [line.as_str()]);
+        }
+        _ => bail!("cannot overwrite multiple values of {}.{}", section, key),
+    }
+    Ok(lines.concat())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{continues, set_value};
+
+    #[test]
+    fn set_keeps_the_rest_of_git_config() {
+        let original = std::fs::read_to_string("test/config/git-config").unwrap();
+
+        // a new section goes at the end and nothing else changes
+        let added = set_value(&original, "user", "email", "me@x").unwrap();
+        assert_eq!(added, original.clone() + "[user]\n\temail = me@x\n");
+
+        // a new key joins the end of its section, matching the subsection's case
+        let added = set_value(&original, "branch \"Main\"", "rebase", "true").unwrap();
+        assert_eq!(
+            added,
+            original.replace(
+                "merge = refs/heads/Main\n",
+                "merge = refs/heads/Main\n\trebase = true\n"
+            )
+        );
+        let added = set_value(&original, "branch \"main\"", "rebase", "true").unwrap();
+        assert!(added.ends_with("[branch \"main\"]\n\trebase = true\n"));
+
+        // an existing key is changed in place, whatever its case
+        let changed = set_value(&original, "core", "FileMode", "false").unwrap();
+        assert_eq!(
+            changed,
+            original.replace("\tfilemode = true\n", "\tFileMode = false\n")
+        );
+        let changed = set_value(&original, "alias", "lg", "log # short").unwrap();
+        assert!(changed.contains("\tlg = \"log # short\"\n"));
+
+        // git refuses to replace every value of a multi-valued key with one
+        assert!(set_value(&original, "remote \"origin\"", "fetch", "x").is_err());
+    }
+
+    #[test]
+    fn set_replaces_continued_lines() {
+        let text = "[core]\n\tpager = less \\\n\t\t-R\n\tbare = false\n";
+        assert!(continues("\tpager = less \\\n"));
+        assert!(!continues("\tpath = C:\\\\\n"));
+        assert_eq!(
+            set_value(text, "core", "pager", "more").unwrap(),
+            "[core]\n\tpager = more\n\tbare = false\n"
+        );
+        assert_eq!(
+            set_value("", "core", "bare", "true").unwrap(),
+            "[core]\n\tbare = true\n"
+        );
+        assert_eq!(
+            set_value("[core]\n\tbare = true", "core", "x", "1").unwrap(),
+            "[core]\n\tbare = true\n\tx = 1\n"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+use std::ops::Range;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+enum EditKind {
+    Equal,
+    Delete,
+    Insert,
+}
+
+/// One line of an edit script, with the line's position in the old and new
+/// file. Inserts and deletes carry the position they happen at in the other
+/// file.
+#[derive(Debug, Clone, Copy)]
+struct Edit {
+    kind: EditKind,
+    old: usize,
+    new: usize,
+}
+
+/// Produce a unified diff of two blobs with `context` lines around each
+/// change. Identical blobs produce an empty string.
+pub fn diff_blobs(a: &[u8], b: &[u8], context: usize) -> String {
+    if a == b {
+        return String::new();
+    }
+    if a.contains(&0) || b.contains(&0) {
+        return "Binary files differ\n".to_string();
+    }
+
+    let a = split_lines(a);
+    let b = split_lines(b);
+    let edits = myers(&a, &b);
+
+    let mut out = String::new();
+    for hunk in hunks(&edits, context) {
+        let edits = &edits[hunk];
+        let old_count = edits.iter().filter(|e| e.kind != EditKind::Insert).count();
+        let new_count = edits.iter().filter(|e| e.kind != EditKind::Delete).count();
+        out.push_str(&format!(
+            "@@ -{} +{} @@\n",
+            hunk_range(edits[0].old, old_count),
+            hunk_range(edits[0].new, new_count)
+        ));
+        for edit in edits {
+            let (prefix, line) = match edit.kind {
+                EditKind::Equal => (' ', a[edit.old]),
+                EditKind::Delete => ('-', a[edit.old]),
+                EditKind::Insert => ('+', b[edit.new]),
+            };
+            out.push(prefix);
+            out.push_str(&String::from_utf8_lossy(line));
+            if !line.ends_with(b"\n") {
+                out.push_str("\n\\ No newline at end of file\n");
+            }
+        }
+    }
+    out
+}
+
+/// The lines `a` and `b` have in common, as pairs of their indexes in `a`
+/// and `b`.
+pub(crate) fn matching_lines(a: &[u8], b: &[u8]) -> Vec<(usize, usize)> {
+    myers(&split_lines(a), &split_lines(b))
+        .into_iter()
+        .filter(|e| e.kind == EditKind::Equal)
+        .map(|e| (e.old, e.new))
+        .collect()
+}
+
+fn split_lines(data: &[u8]) -> Vec<&[u8]> {
+    data.split_inclusive(|&b| b == b'\n').collect()
+}
+
+fn hunk_range(start: usize, count: usize) -> String {
+    match count {
+        0 => format!("{},0", start),
+        1 => format!("{}", start + 1),
+        _ => format!("{},{}", start + 1, count),
+    }
+}
+
+/// Group changes into ranges of the edit script, merging changes whose
+/// surrounding context would overlap.
+fn hunks(edits: &[Edit], context: usize) -> Vec<Range<usize>> {
+    let mut hunks: Vec<Range<usize>> = Vec::new();
+    for (i, _) in edits
+        .iter()
+        .enumerate()
+        .filter(|(_, e)| e.kind != EditKind::Equal)
+    {
+        let start = i.saturating_sub(context);
+        let end = (i + context + 1).min(edits.len());
+        match hunks.last_mut() {
+            Some(last) if last.end >= start => last.end = end,
+            _ => hunks.push(start..end),
+        }
+    }
+    hunks
+}
+
+/// Find a shortest edit script from `a` to `b` with Myers' O(ND) algorithm.
+fn myers(a: &[&[u8]], b: &[&[u8]]) -> Vec<Edit> {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let max = n + m;
+    let offset = max + 1;
+    let mut v = vec![0isize; 2 * max as usize + 3];
+    let mut trace = Vec::new();
+
+    'search: for d in 0..=max {
+        trace.push(v.clone());
+        for k in (-d..=d).step_by(2) {
+            let idx = (k + offset) as usize;
+            let mut x = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+                v[idx + 1]
+            } else {
+                v[idx - 1] + 1
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            v[idx] = x;
+            if x >= n && y >= m {
+                break 'search;
+            }
+        }
+    }
+
+    let mut edits = Vec::new();
+    let (mut x, mut y) = (n, m);
+    for (d, v) in trace.iter().enumerate().rev() {
+        let d = d as isize;
+        let k = x - y;
+        let idx = (k + offset) as usize;
+        let prev_k = if k == -d || (k != d && v[idx - 1] < v[idx + 1]) {
+            k + 1
+        } else {
+            k - 1
+        };
+        let prev_x = v[(prev_k + offset) as usize];
+        let prev_y = prev_x - prev_k;
+
+        while x > prev_x && y > prev_y {
+            x -= 1;
+            y -= 1;
+            edits.push(Edit {
+                kind: EditKind::Equal,
+                old: x as usize,
+                new: y as usize,
+            });
+        }
+        if d > 0 {
+            if x == prev_x {
+                edits.push(Edit {
+                    kind: EditKind::Insert,
+                    old: x as usize,
+                    new: prev_y as usize,
+                });
+            } else {
+                edits.push(Edit {
+                    kind: EditKind::Delete,
+                    old: prev_x as usize,
+                    new: y as usize,
+                });
+            }
+        }
+        x = prev_x;
+        y = prev_y;
+    }
+    edits.reverse();
+    edits
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diff::{diff_blobs, matching_lines};
+
+    #[test]
+    fn diff_modified_line() {
+        let a = b"one\ntwo\nthree\nfour\nfive\nsix\nseven\neight\n";
+        let b = b"one\ntwo\nthree\nFOUR\nfive\nsix\nseven\neight\nnine\n";
+        assert_eq!(
+            diff_blobs(a, b, 3),
+            "@@ -1,8 +1,9 @@\n one\n two\n three\n-four\n+FOUR\n five\n six\n seven\n eight\n+nine\n"
+        );
+        assert_eq!(
+            diff_blobs(a, b, 1),
+            "@@ -3,3 +3,3 @@\n three\n-four\n+FOUR\n five\n@@ -8 +8,2 @@\n eight\n+nine\n"
+        );
+    }
+
+    #[test]
+    fn diff_edge_cases() {
+        assert_eq!(diff_blobs(b"same\n", b"same\n", 3), "");
+        assert_eq!(diff_blobs(b"", b"new\n", 3), "@@ -0,0 +1 @@\n+new\n");
+        assert_eq!(
+            diff_blobs(b"a\nb", b"a\nc", 3),
+            "@@ -1,2 +1,2 @@\n a\n-b\n\\ No newline at end of file\n+c\n\\ No newline at end of file\n"
+        );
+        assert_eq!(diff_blobs(b"\0bin", b"\0other", 3), "Binary files differ\n");
+    }
+
+    #[test]
+    fn matching() {
+        assert_eq!(
+            matching_lines(b"a\nb\nc\nd\n", b"a\nx\nc\nd\ne\n"),
+            vec![(0, 0), (2, 2), (3, 3)]
+        );
+        assert_eq!(matching_lines(b"", b"a\n"), vec![]);
+    }
+}
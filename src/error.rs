@@ -0,0 +1,126 @@
+use hex::ToHex;
+use std::fmt::{Display, Formatter};
+use std::io;
+
+/// Errors from reading and resolving objects, for callers that need to tell
+/// a missing object from a corrupt one without matching on messages.
+#[derive(Debug)]
+pub enum WyagError {
+    /// No object, ref or path by the given name.
+    NotFound(String),
+    /// The object exists but could not be read back intact.
+    Corrupt {
+        sha: [u8; 20],
+        reason: String,
+    },
+    /// The object exists but is of another type than the one asked for.
+    WrongType {
+        sha: [u8; 20],
+        expected: String,
+        actual: String,
+    },
+    Io(io::Error),
+    /// A name or file that could not be parsed.
+    Parse(String),
+    /// Something this implementation does not handle, such as a repository extension.
+    Unsupported(String),
+    /// A check such as fsck or signature verification ran and found problems.
+    CheckFailed(String),
+}
+
+impl WyagError {
+    /// The exit status for a command failing with this error. As with git's
+    /// plumbing, 1 means what was asked about is missing or failed a check,
+    /// so scripts can tell it from 128, any other failure.
+    pub fn exit_code(&self) -> u8 {
+        match self {
+            WyagError::NotFound(_)
+            | WyagError::WrongType { .. }
+            | WyagError::Corrupt { .. }
+            | WyagError::CheckFailed(_) => 1,
+            WyagError::Io(_) | WyagError::Parse(_) | WyagError::Unsupported(_) => 128,
+        }
+    }
+
+    /// Classify a failure to read the object `sha`, which is known to exist,
+    /// as an io error or as corruption.
+    pub fn reading(sha: [u8; 20], err: anyhow::Error) -> Self {
+        match err.root_cause().downcast_ref::<io::Error>() {
+            Some(io_err)
+                if !matches!(
+                    io_err.kind(),
+                    io::ErrorKind::UnexpectedEof
+                        | io::ErrorKind::InvalidData
+                        | io::ErrorKind::InvalidInput
+                ) =>
+            {
+                WyagError::Io(io::Error::new(io_err.kind(), format!("{:#}", err)))
+            }
+            _ => WyagError::Corrupt {
+                sha,
+                reason: format!("{:#}", err),
+            },
+        }
+    }
+}
+
+impl Display for WyagError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            WyagError::NotFound(message)
+            | WyagError::Parse(message)
+            | WyagError::Unsupported(message)
+            | WyagError::CheckFailed(message) => f.write_str(message),
+            WyagError::Corrupt { sha, reason } => {
+                write!(
+                    f,
+                    "object {} is corrupt: {}",
+                    sha.encode_hex::<String>(),
+                    reason
+                )
+            }
+            WyagError::WrongType {
+                sha,
+                expected,
+                actual,
+            } => write!(
+                f,
+                "object {} is a {}, not a {}",
+                sha.encode_hex::<String>(),
+                actual,
+                expected
+            ),
+            WyagError::Io(err) => err.fmt(f),
+        }
+    }
+}
+
+impl std::error::Error for WyagError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            WyagError::Io(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for WyagError {
+    fn from(err: io::Error) -> Self {
+        WyagError::Io(err)
+    }
+}
+
+/// Recover a `WyagError` raised inside an `anyhow` chain, otherwise treat the
+/// failure as an io error if it was one and a parse error if not.
+impl From<anyhow::Error> for WyagError {
+    fn from(err: anyhow::Error) -> Self {
+        let err = match err.downcast::<WyagError>() {
+            Ok(err) => return err,
+            Err(err) => err,
+        };
+        match err.root_cause().downcast_ref::<io::Error>() {
+            Some(io_err) => WyagError::Io(io::Error::new(io_err.kind(), format!("{:#}", err))),
+            None => WyagError::Parse(format!("{:#}", err)),
+        }
+    }
+}
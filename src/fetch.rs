@@ -0,0 +1,319 @@
+use crate::http;
+use crate::pktline::{read_pkt, write_flush, write_pkt};
+use crate::repository::Repository;
+use anyhow::{Context, Result, bail, ensure};
+use flate2::Compression;
+use hex::{ToHex, decode};
+use log::debug;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::io::{Read, Write};
+use std::str::from_utf8;
+
+/// How many commits to offer as `have`s from each local ref.
+const HAVES_PER_REF: usize = 32;
+const MAX_HAVES: usize = 256;
+
+/// Called with each line of progress the server sends, ending in its `\r`
+/// or `\n`.
+pub type RemoteProgress<'a> = Option<&'a dyn Fn(&str)>;
+
+/// A local ref a fetch created or moved.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RefUpdate {
+    /// The ref as the remote names it, without `refs/heads/` or `refs/tags/`.
+    pub remote_name: String,
+    /// The local ref, `refs/remotes/<remote>/<branch>` or `refs/tags/<tag>`.
+    pub local_name: String,
+    pub old: Option<[u8; 20]>,
+    pub new: [u8; 20],
+}
+
+/// The update as `git fetch` reports it, such as `   1a2b3c4..5d6e7f8 main -> origin/main`.
+impl Display for RefUpdate {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.old {
+            Some(old) => write!(
+                f,
+                "   {}..{}",
+                &old.encode_hex::<String>()[..7],
+                &self.new.encode_hex::<String>()[..7]
+            )?,
+            None => f.write_str(" * [new]")?,
+        }
+        let local = self.local_name.as_str();
+        write!(
+            f,
+            " {} -> {}",
+            self.remote_name,
+            local
+                .strip_prefix("refs/remotes/")
+                .or(local.strip_prefix("refs/tags/"))
+                .unwrap_or(local)
+        )
+    }
+}
+
+/// Fetch new objects and refs from `remote` over the smart HTTP protocol,
+/// updating `refs/remotes/<remote>/*` and any tags we don't have yet, and
+/// returning the refs that changed.
+pub fn fetch(repo: &Repository, remote: &str, progress: RemoteProgress) -> Result<Vec<RefUpdate>> {
+    let url = repo
+        .config_get(&format!("remote \"{}\"", remote), "url")
+        .with_context(|| format!("remote {} has no url", remote))?;
+    let url = url.trim_end_matches('/');
+
+    let refs = advertised_refs(url).context("reading advertised refs")?;
+    let mut seen = HashSet::new();
+    let wants = refs
+        .iter()
+        .filter(|(_, name)| name.starts_with("refs/heads/") || name.starts_with("refs/tags/"))
+        .map(|(sha1, _)| *sha1)
+        .filter(|sha1| !repo.has_object(*sha1) && seen.insert(*sha1))
+        .collect::<Vec<_>>();
+
+    if !wants.is_empty() {
+        let haves = local_haves(repo)?;
+        debug!("{} wants, {} haves", wants.len(), haves.len());
+        repo.download_pack(
+            |pack| upload_pack(url, &wants, &haves, pack, progress),
+            Compression::default(),
+        )
+        .context("storing fetched pack")?;
+    }
+
+    let local = repo.refs()?;
+    let mut updates = Vec::new();
+    for (sha1, name) in refs.iter() {
+        let (local_name, short) = if let Some(branch) = name.strip_prefix("refs/heads/") {
+            (format!("refs/remotes/{}/{}", remote, branch), branch)
+        } else if let Some(tag) = name.strip_prefix("refs/tags/") {
+            (name.to_string(), tag)
+        } else {
+            continue;
+        };
+        let old = local
+            .iter()
+            .find(|(n, _)| *n == local_name)
+            .map(|(_, s)| *s);
+        if old == Some(*sha1) || (old.is_some() && name.starts_with("refs/tags/")) {
+            continue;
+        }
+        if name.starts_with("refs/tags/") {
+            repo.write_ref(&local_name, &sha1.encode_hex::<String>())?;
+        } else {
+            let message = match old {
+                Some(_) => format!("fetch {}: updating", remote),
+                None => format!("fetch {}: storing head", remote),
+            };
+            repo.update_ref(&local_name, *sha1, &message)?;
+        }
+        updates.push(RefUpdate {
+            remote_name: short.to_string(),
+            local_name,
+            old,
+            new: *sha1,
+        });
+    }
+    Ok(updates)
+}
+
+/// Read the ref advertisement from `info/refs`, dropping capabilities and peeled tags.
+fn advertised_refs(url: &str) -> Result<Vec<([u8; 20], String)>> {
+    let response = http::agent()
+        .get(&format!("{}/info/refs?service=git-upload-pack", url))
+        .call()
+        .with_context(|| format!("requesting refs from {}", url))?;
+    let mut reader = response.into_body().into_reader();
+
+    let service = read_pkt(&mut reader)?.context("empty ref advertisement")?;
+    ensure!(
+        service.starts_with(b"# service=git-upload-pack"),
+        "{} does not speak the smart HTTP protocol",
+        url
+    );
+    ensure!(
+        read_pkt(&mut reader)?.is_none(),
+        "expected flush after service line"
+    );
+
+    let mut refs = Vec::new();
+    while let Some(line) = read_pkt(&mut reader)? {
+        let line = line.split(|&b| b == 0).next().unwrap_or_default();
+        let line = from_utf8(line).context("ref advertisement is not utf8")?;
+        let (sha1, name) = line
+            .trim_end()
+            .split_once(' ')
+            .with_context(|| format!("bad ref line {}", line))?;
+        if name.ends_with("^{}") || name == "capabilities^{}" {
+            continue;
+        }
+        let sha1 = decode(sha1)
+            .ok()
+            .and_then(|v| v.try_into().ok())
+            .with_context(|| format!("bad sha1 in ref line {}", line))?;
+        refs.push((sha1, name.to_string()));
+    }
+    Ok(refs)
+}
+
+/// Offer recent history from each local ref so the server can leave it out of the pack.
+fn local_haves(repo: &Repository) -> Result<Vec<[u8; 20]>> {
+    let mut haves = Vec::new();
+    let mut seen = HashSet::new();
+    for (_, sha1) in repo.refs()? {
+        // tags may point at things other than commits, which have no history to offer
+        let Ok(log) = repo.log_iter(sha1) else {
+            continue;
+        };
+        for entry in log.take(HAVES_PER_REF) {
+            let Ok(entry) = entry else {
+                break;
+            };
+            if seen.insert(entry.sha) {
+                haves.push(entry.sha);
+            }
+        }
+        if haves.len() >= MAX_HAVES {
+            haves.truncate(MAX_HAVES);
+            break;
+        }
+    }
+    Ok(haves)
+}
+
+/// Ask `git-upload-pack` for `wants`, telling it about `haves`, and write the pack it sends
+/// to `pack` as it arrives.
+fn upload_pack(
+    url: &str,
+    wants: &[[u8; 20]],
+    haves: &[[u8; 20]],
+    pack: &mut impl Write,
+    progress: RemoteProgress,
+) -> Result<()> {
+    let mut request = Vec::new();
+    for (n, want) in wants.iter().enumerate() {
+        let capabilities = if n == 0 {
+            " side-band-64k ofs-delta thin-pack agent=wyag"
+        } else {
+            ""
+        };
+        write_pkt(
+            &mut request,
+            format!("want {}{}\n", want.encode_hex::<String>(), capabilities).as_bytes(),
+        )?;
+    }
+    write_flush(&mut request)?;
+    for have in haves {
+        write_pkt(
+            &mut request,
+            format!("have {}\n", have.encode_hex::<String>()).as_bytes(),
+        )?;
+    }
+    write_pkt(&mut request, b"done\n")?;
+
+    let response = http::agent()
+        .post(&format!("{}/git-upload-pack", url))
+        .header("Content-Type", "application/x-git-upload-pack-request")
+        .header("Accept", "application/x-git-upload-pack-result")
+        .send(&request[..])
+        .with_context(|| format!("posting to {}/git-upload-pack", url))?;
+    read_sideband(&mut response.into_body().into_reader(), pack, progress)
+}
+
+/// Demultiplex an upload-pack response, writing the pack data to `pack` and
+/// passing whole lines of progress to `progress`.
+fn read_sideband(
+    reader: &mut impl Read,
+    pack: &mut impl Write,
+    progress: RemoteProgress,
+) -> Result<()> {
+    let mut pending = Vec::new();
+    while let Some(line) = read_pkt(reader)? {
+        match line.first() {
+            Some(1) => pack.write_all(&line[1..]).context("writing pack")?,
+            Some(2) => {
+                // progress arrives in arbitrary pieces, so only pass on whole lines
+                pending.extend_from_slice(&line[1..]);
+                while let Some(end) = pending.iter().position(|&b| b == b'\r' || b == b'\n') {
+                    let line = pending.drain(..=end).collect::<Vec<_>>();
+                    if let Some(progress) = progress {
+                        progress(&String::from_utf8_lossy(&line));
+                    }
+                }
+            }
+            Some(3) => bail!("remote error: {}", String::from_utf8_lossy(&line[1..])),
+            _ if line.starts_with(b"ACK ") || line.starts_with(b"NAK") => {}
+            _ => bail!(
+                "unexpected response line {}",
+                String::from_utf8_lossy(&line)
+            ),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::fetch::{RefUpdate, read_sideband};
+    use crate::pktline::{write_flush, write_pkt};
+    use std::cell::RefCell;
+
+    #[test]
+    fn sideband_progress_goes_to_the_caller() {
+        let mut response = Vec::new();
+        write_pkt(&mut response, b"NAK\n").unwrap();
+        write_pkt(&mut response, b"\x02Counting: 1\r").unwrap();
+        write_pkt(&mut response, b"\x01PACK").unwrap();
+        write_pkt(&mut response, b"\x02Counting: 2, do").unwrap();
+        write_pkt(&mut response, b"\x02ne.\n").unwrap();
+        write_pkt(&mut response, b"\x01data").unwrap();
+        write_flush(&mut response).unwrap();
+
+        let lines = RefCell::new(Vec::new());
+        let mut pack = Vec::new();
+        read_sideband(
+            &mut &response[..],
+            &mut pack,
+            Some(&|line: &str| lines.borrow_mut().push(line.to_string())),
+        )
+        .unwrap();
+        assert_eq!(pack, b"PACKdata");
+        assert_eq!(
+            lines.into_inner(),
+            vec!["Counting: 1\r", "Counting: 2, done.\n"]
+        );
+
+        let mut pack = Vec::new();
+        read_sideband(&mut &response[..], &mut pack, None).unwrap();
+        assert_eq!(pack, b"PACKdata");
+
+        let mut error = Vec::new();
+        write_pkt(&mut error, b"\x03no such ref").unwrap();
+        let err = read_sideband(&mut &error[..], &mut Vec::new(), None).unwrap_err();
+        assert_eq!(err.to_string(), "remote error: no such ref");
+    }
+
+    #[test]
+    fn ref_update_display() {
+        let mut update = RefUpdate {
+            remote_name: "main".to_string(),
+            local_name: "refs/remotes/origin/main".to_string(),
+            old: None,
+            new: [0xbb; 20],
+        };
+        assert_eq!(update.to_string(), " * [new] main -> origin/main");
+        update.old = Some([0xaa; 20]);
+        assert_eq!(
+            update.to_string(),
+            "   aaaaaaa..bbbbbbb main -> origin/main"
+        );
+        let tag = RefUpdate {
+            remote_name: "v1".to_string(),
+            local_name: "refs/tags/v1".to_string(),
+            old: None,
+            new: [0xbb; 20],
+        };
+        assert_eq!(tag.to_string(), " * [new] v1 -> v1");
+    }
+}
@@ -0,0 +1,340 @@
+use crate::gitignore::wildmatch;
+use crate::gitobject::commit::CommitObject;
+use crate::gitobject::tag::TagObject;
+use crate::pack::BinaryObject;
+use crate::repository::Repository;
+use anyhow::{Context, Result, bail};
+use hex::ToHex;
+use std::cmp::Ordering;
+
+/// The format `for-each-ref` prints refs in without `--format`.
+pub const DEFAULT_FORMAT: &str = "%(objectname) %(objecttype)\t%(refname)";
+
+/// Something `for-each-ref` can print or sort by, written `%(<name>)`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RefField {
+    RefName,
+    ObjectName,
+    /// The first seven digits of the object name.
+    ObjectNameShort,
+    ObjectType,
+    /// The name of a commit's author, empty for any other object.
+    AuthorName,
+    /// The first paragraph of a commit or tag message, joined onto one line.
+    Subject,
+}
+
+impl RefField {
+    pub fn parse(name: &str) -> Result<Self> {
+        Ok(match name {
+            "refname" => RefField::RefName,
+            "objectname" => RefField::ObjectName,
+            "objectname:short" => RefField::ObjectNameShort,
+            "objecttype" => RefField::ObjectType,
+            "authorname" => RefField::AuthorName,
+            "subject" => RefField::Subject,
+            _ => bail!("unknown field name: {}", name),
+        })
+    }
+
+    /// Whether the field needs the object's content rather than just its name or type.
+    fn reads_content(self) -> bool {
+        matches!(self, RefField::AuthorName | RefField::Subject)
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum FormatPart {
+    Literal(String),
+    Field(RefField),
+}
+
+/// A parsed `--format`, literal text with `%(<field>)`s in it and `%%` for
+/// a percent sign.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RefFormat(Vec<FormatPart>);
+
+impl RefFormat {
+    pub fn parse(format: &str) -> Result<Self> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut rest = format;
+        while let Some(i) = rest.find('%') {
+            literal += &rest[..i];
+            rest = &rest[i..];
+            if let Some(after) = rest.strip_prefix("%%") {
+                literal.push('%');
+                rest = after;
+            } else if let Some(after) = rest.strip_prefix("%(") {
+                let end = after.find(')').context("malformed format string")?;
+                if !literal.is_empty() {
+                    parts.push(FormatPart::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(FormatPart::Field(RefField::parse(&after[..end])?));
+                rest = &after[end + 1..];
+            } else {
+                literal.push('%');
+                rest = &rest[1..];
+            }
+        }
+        literal += rest;
+        if !literal.is_empty() {
+            parts.push(FormatPart::Literal(literal));
+        }
+        Ok(Self(parts))
+    }
+
+    fn fields(&self) -> impl Iterator<Item = RefField> + '_ {
+        self.0.iter().filter_map(|part| match part {
+            FormatPart::Field(field) => Some(*field),
+            FormatPart::Literal(_) => None,
+        })
+    }
+}
+
+/// A `--sort` key, a field name with `-` in front to sort descending.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: RefField,
+    pub descending: bool,
+}
+
+impl SortKey {
+    pub fn parse(key: &str) -> Result<Self> {
+        let (descending, name) = match key.strip_prefix('-') {
+            Some(name) => (true, name),
+            None => (false, key),
+        };
+        Ok(Self {
+            field: RefField::parse(name)?,
+            descending,
+        })
+    }
+}
+
+/// A ref with what the fields say about it.
+struct RefInfo {
+    name: String,
+    sha1: [u8; 20],
+    object_type: Option<BinaryObject>,
+    author_name: String,
+    subject: String,
+}
+
+impl RefInfo {
+    fn read(repo: &Repository, name: String, sha1: [u8; 20], fields: &[RefField]) -> Result<Self> {
+        let mut info = Self {
+            name,
+            sha1,
+            object_type: None,
+            author_name: String::new(),
+            subject: String::new(),
+        };
+        if !fields.iter().any(|field| field.reads_content()) {
+            if fields.contains(&RefField::ObjectType) {
+                info.object_type = Some(
+                    repo.object_type(sha1)
+                        .with_context(|| format!("reading {}", info.name))?,
+                );
+            }
+            return Ok(info);
+        }
+        let mut data = Vec::new();
+        let object_type = repo
+            .read_object_data(sha1, &mut data)
+            .with_context(|| format!("reading {}", info.name))?;
+        let message = match object_type {
+            BinaryObject::Commit => {
+                let commit = CommitObject::from(data)?;
+                info.author_name = commit.author().map(|a| a.name).unwrap_or_default();
+                commit.message()
+            }
+            BinaryObject::Tag => TagObject::from(data)?.message(),
+            _ => None,
+        };
+        info.subject = subject(message.as_deref().unwrap_or_default());
+        info.object_type = Some(object_type);
+        Ok(info)
+    }
+
+    fn value(&self, field: RefField) -> String {
+        match field {
+            RefField::RefName => self.name.clone(),
+            RefField::ObjectName => self.sha1.encode_hex(),
+            RefField::ObjectNameShort => self.sha1.encode_hex::<String>()[..7].to_string(),
+            RefField::ObjectType => self
+                .object_type
+                .map(|t| t.name().to_string())
+                .unwrap_or_default(),
+            RefField::AuthorName => self.author_name.clone(),
+            RefField::Subject => self.subject.clone(),
+        }
+    }
+
+    fn format(&self, format: &RefFormat) -> String {
+        format
+            .0
+            .iter()
+            .map(|part| match part {
+                FormatPart::Literal(text) => text.clone(),
+                FormatPart::Field(field) => self.value(*field),
+            })
+            .collect()
+    }
+}
+
+/// The first paragraph of `message`, its lines joined with spaces.
+fn subject(message: &str) -> String {
+    message
+        .lines()
+        .skip_while(|line| line.trim().is_empty())
+        .take_while(|line| !line.trim().is_empty())
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Whether `name` is matched by `pattern`, either as a prefix ending at a
+/// `/`, so `refs/heads` matches every branch, or as a glob whose `*` does
+/// not cross a `/`.
+fn ref_matches(pattern: &str, name: &str) -> bool {
+    match name.strip_prefix(pattern.trim_end_matches('/')) {
+        Some(rest) if rest.is_empty() || rest.starts_with('/') => true,
+        _ => wildmatch(pattern.as_bytes(), name.as_bytes()),
+    }
+}
+
+/// Each ref matching any of `patterns`, or every ref without any, printed
+/// in `format`. The last of `sort` decides the order first, with the keys
+/// before it breaking ties, then the ref name.
+pub fn for_each_ref(
+    repo: &Repository,
+    patterns: &[String],
+    format: &RefFormat,
+    sort: &[SortKey],
+) -> Result<Vec<String>> {
+    let fields = format
+        .fields()
+        .chain(sort.iter().map(|key| key.field))
+        .collect::<Vec<_>>();
+    let mut refs = repo
+        .refs()?
+        .into_iter()
+        .filter(|(name, _)| patterns.is_empty() || patterns.iter().any(|p| ref_matches(p, name)))
+        .map(|(name, sha1)| RefInfo::read(repo, name, sha1, &fields))
+        .collect::<Result<Vec<_>>>()?;
+    refs.sort_by(|a, b| {
+        sort.iter()
+            .rev()
+            .map(|key| {
+                let ordering = a.value(key.field).cmp(&b.value(key.field));
+                if key.descending {
+                    ordering.reverse()
+                } else {
+                    ordering
+                }
+            })
+            .fold(Ordering::Equal, Ordering::then)
+            .then_with(|| a.name.cmp(&b.name))
+    });
+    Ok(refs.iter().map(|info| info.format(format)).collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::foreachref::{
+        DEFAULT_FORMAT, FormatPart, RefField, RefFormat, SortKey, for_each_ref, ref_matches,
+    };
+    use crate::gitobject::GitObject;
+    use crate::gitobject::commit::CommitObject;
+    use crate::gitobject::tag::TagObject;
+    use crate::gitobject::tree::TreeBuilder;
+    use crate::testutil::temp_repo;
+    use flate2::Compression;
+    use hex::ToHex;
+
+    #[test]
+    fn parse_format() {
+        let format = RefFormat::parse("%(refname) 100%% %(objectname:short)%").unwrap();
+        assert_eq!(
+            format.0,
+            vec![
+                FormatPart::Field(RefField::RefName),
+                FormatPart::Literal(" 100% ".to_string()),
+                FormatPart::Field(RefField::ObjectNameShort),
+                FormatPart::Literal("%".to_string()),
+            ]
+        );
+        assert!(RefFormat::parse(DEFAULT_FORMAT).is_ok());
+        let error = RefFormat::parse("%(bogus)").unwrap_err().to_string();
+        assert_eq!(error, "unknown field name: bogus");
+        let error = RefFormat::parse("%(refname").unwrap_err().to_string();
+        assert_eq!(error, "malformed format string");
+
+        let key = SortKey::parse("-subject").unwrap();
+        assert_eq!(key.field, RefField::Subject);
+        assert!(key.descending);
+        assert!(SortKey::parse("-").is_err());
+    }
+
+    #[test]
+    fn patterns() {
+        assert!(ref_matches("refs/heads", "refs/heads/main"));
+        assert!(ref_matches("refs/heads/", "refs/heads/main"));
+        assert!(ref_matches("refs/heads/main", "refs/heads/main"));
+        assert!(!ref_matches("refs/heads/ma", "refs/heads/main"));
+        assert!(ref_matches("refs/tags/v*", "refs/tags/v1.0"));
+        assert!(!ref_matches("refs/t*", "refs/tags/v1.0"));
+    }
+
+    #[test]
+    fn format_and_sort_refs() {
+        let (_dir, repo) = temp_repo();
+        let write = |object: GitObject| {
+            repo.write_object(&object, true, Compression::default())
+                .unwrap()
+        };
+
+        let tree = write(GitObject::Tree(TreeBuilder::new().build()));
+        let commit = format!(
+            "tree {}\nauthor Ann <a@b> 0 +0000\ncommitter Ann <a@b> 0 +0000\n\n\
+             first line\nsecond line\n\nbody\n",
+            tree.encode_hex::<String>()
+        );
+        let commit = write(GitObject::Commit(
+            CommitObject::from(commit.into_bytes()).unwrap(),
+        ));
+        let tag = format!(
+            "object {}\ntype commit\ntag v1\ntagger Ann <a@b> 0 +0000\n\nrelease\n",
+            commit.encode_hex::<String>()
+        );
+        let tag = write(GitObject::Tag(TagObject::from(tag.into_bytes()).unwrap()));
+        repo.update_ref("refs/heads/main", commit, "test").unwrap();
+        repo.update_ref("refs/tags/v1", tag, "test").unwrap();
+        repo.update_ref("refs/tags/light", commit, "test").unwrap();
+
+        let format = RefFormat::parse("%(refname) %(objecttype) %(authorname):%(subject)").unwrap();
+        assert_eq!(
+            for_each_ref(&repo, &[], &format, &[]).unwrap(),
+            vec![
+                "refs/heads/main commit Ann:first line second line",
+                "refs/tags/light commit Ann:first line second line",
+                "refs/tags/v1 tag :release",
+            ]
+        );
+
+        let sort = [
+            SortKey::parse("-refname").unwrap(),
+            SortKey::parse("objecttype").unwrap(),
+        ];
+        let format = RefFormat::parse("%(refname)").unwrap();
+        assert_eq!(
+            for_each_ref(&repo, &["refs/tags".to_string()], &format, &sort).unwrap(),
+            vec!["refs/tags/light", "refs/tags/v1"]
+        );
+        let sort = [SortKey::parse("-refname").unwrap()];
+        assert_eq!(
+            for_each_ref(&repo, &[], &format, &sort).unwrap(),
+            vec!["refs/tags/v1", "refs/tags/light", "refs/heads/main"]
+        );
+    }
+}
@@ -0,0 +1,191 @@
+use crate::gitignore::Pattern;
+use anyhow::{Context, Result};
+use std::fs::read;
+use std::path::Path;
+
+/// What the `text`, `eol` and `binary` attributes say about a path's line endings.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub(crate) enum TextAttribute {
+    #[default]
+    Unspecified,
+    /// Always text, so CRLF is stored as LF.
+    Set,
+    /// Never text, stored as it is.
+    Unset,
+    /// Text unless the content looks binary.
+    Auto,
+}
+
+impl TextAttribute {
+    /// The content as git stores it for a path with this attribute, CRLF
+    /// line endings turned into LF when the path holds text.
+    pub(crate) fn normalise(self, data: &[u8]) -> Option<Vec<u8>> {
+        let text = match self {
+            TextAttribute::Set => true,
+            TextAttribute::Auto => !looks_binary(data),
+            TextAttribute::Unspecified | TextAttribute::Unset => false,
+        };
+        if !text || !data.windows(2).any(|w| w == b"\r\n") {
+            return None;
+        }
+        let mut normalised = Vec::with_capacity(data.len());
+        let mut rest = data;
+        while let Some(i) = rest.windows(2).position(|w| w == b"\r\n") {
+            normalised.extend_from_slice(&rest[..i]);
+            normalised.push(b'\n');
+            rest = &rest[i + 2..];
+        }
+        normalised.extend_from_slice(rest);
+        Some(normalised)
+    }
+}
+
+/// Whether git's `text=auto` takes content for binary, having a NUL or a
+/// carriage return that does not end a line.
+fn looks_binary(data: &[u8]) -> bool {
+    data.iter()
+        .enumerate()
+        .any(|(i, &b)| b == 0 || (b == b'\r' && data.get(i + 1) != Some(&b'\n')))
+}
+
+/// One line of an attributes file, with the line-ending attributes it sets.
+struct Rule {
+    pattern: Pattern,
+    text: Option<TextAttribute>,
+    /// Whether `eol` is set, which makes a path text when `text` is unspecified.
+    eol: Option<bool>,
+}
+
+impl Rule {
+    fn parse(line: &[u8], base: &Path) -> Option<Self> {
+        let mut fields = line
+            .split(|b| b.is_ascii_whitespace())
+            .filter(|field| !field.is_empty());
+        let pattern = fields.next()?;
+        // negative patterns are not allowed in attributes files
+        let pattern = Pattern::parse(pattern, base).filter(|p| !p.negated)?;
+        let mut rule = Rule {
+            pattern,
+            text: None,
+            eol: None,
+        };
+        for attribute in fields {
+            match attribute {
+                b"text" => rule.text = Some(TextAttribute::Set),
+                b"-text" | b"binary" => rule.text = Some(TextAttribute::Unset),
+                b"!text" => rule.text = Some(TextAttribute::Unspecified),
+                b"text=auto" => rule.text = Some(TextAttribute::Auto),
+                _ if attribute.starts_with(b"text=") => {
+                    rule.text = Some(TextAttribute::Unspecified)
+                }
+                _ if attribute.starts_with(b"eol=") => rule.eol = Some(true),
+                b"-eol" | b"!eol" => rule.eol = Some(false),
+                _ => {}
+            }
+        }
+        Some(rule)
+    }
+}
+
+/// The line-ending attribute of a worktree path, relative to the worktree
+/// root, from every `.gitattributes` above it and `info_attributes`. Deeper
+/// files take precedence over their parents, `info_attributes` over them all,
+/// and later lines over earlier ones.
+pub(crate) fn text_attribute(
+    worktree: &Path,
+    info_attributes: &Path,
+    path: &Path,
+) -> Result<TextAttribute> {
+    let mut directories = path.ancestors().skip(1).collect::<Vec<_>>();
+    directories.reverse();
+    let mut files = directories
+        .into_iter()
+        .map(|directory| (worktree.join(directory).join(".gitattributes"), directory))
+        .collect::<Vec<_>>();
+    files.push((info_attributes.to_path_buf(), Path::new("")));
+
+    let (mut text, mut eol) = (TextAttribute::Unspecified, false);
+    for (file, base) in files {
+        for rule in read_rules(&file, base)? {
+            if !rule.pattern.matches(path, false) {
+                continue;
+            }
+            text = rule.text.unwrap_or(text);
+            eol = rule.eol.unwrap_or(eol);
+        }
+    }
+    Ok(match text {
+        TextAttribute::Unspecified if eol => TextAttribute::Set,
+        text => text,
+    })
+}
+
+fn read_rules(path: &Path, base: &Path) -> Result<Vec<Rule>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let data = read(path).with_context(|| format!("reading {}", path.to_string_lossy()))?;
+    Ok(data
+        .split(|&b| b == b'\n')
+        .map(|line| line.strip_suffix(b"\r").unwrap_or(line))
+        .filter(|line| !line.starts_with(b"#"))
+        .filter_map(|line| Rule::parse(line, base))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gitattributes::{TextAttribute, text_attribute};
+    use std::fs::{create_dir_all, write};
+    use std::path::Path;
+
+    #[test]
+    fn nested_attributes_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_dir_all(root.join("sub")).unwrap();
+        create_dir_all(root.join(".git/info")).unwrap();
+        write(
+            root.join(".gitattributes"),
+            "# line endings\n*.txt text\n*.bin binary\n*.sh eol=lf\n* text=auto\n*.md -text\n",
+        )
+        .unwrap();
+        write(root.join("sub/.gitattributes"), "*.txt !text\n").unwrap();
+        write(root.join(".git/info/attributes"), "*.bat text\n").unwrap();
+
+        let info = root.join(".git/info/attributes");
+        let attribute = |path: &str| text_attribute(root, &info, Path::new(path)).unwrap();
+        assert_eq!(attribute("a.txt"), TextAttribute::Auto);
+        assert_eq!(attribute("a.md"), TextAttribute::Unset);
+        assert_eq!(attribute("run.bat"), TextAttribute::Set);
+        assert_eq!(attribute("sub/a.txt"), TextAttribute::Unspecified);
+        assert_eq!(attribute("sub/a.md"), TextAttribute::Unset);
+
+        write(
+            root.join(".gitattributes"),
+            "*.txt text\n*.sh eol=lf\n*.bin binary\n",
+        )
+        .unwrap();
+        assert_eq!(attribute("a.txt"), TextAttribute::Set);
+        assert_eq!(attribute("a.sh"), TextAttribute::Set);
+        assert_eq!(attribute("a.bin"), TextAttribute::Unset);
+        assert_eq!(attribute("a.rs"), TextAttribute::Unspecified);
+    }
+
+    #[test]
+    fn normalise_line_endings() {
+        assert_eq!(
+            TextAttribute::Set.normalise(b"a\r\nb\rc\r\n"),
+            Some(b"a\nb\rc\n".to_vec())
+        );
+        assert_eq!(TextAttribute::Set.normalise(b"a\nb\n"), None);
+        assert_eq!(TextAttribute::Unset.normalise(b"a\r\n"), None);
+        assert_eq!(TextAttribute::Unspecified.normalise(b"a\r\n"), None);
+        assert_eq!(
+            TextAttribute::Auto.normalise(b"a\r\nb\r\n"),
+            Some(b"a\nb\n".to_vec())
+        );
+        assert_eq!(TextAttribute::Auto.normalise(b"a\r\n\0"), None);
+        assert_eq!(TextAttribute::Auto.normalise(b"a\r\nb\rc"), None);
+    }
+}
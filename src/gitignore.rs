@@ -0,0 +1,263 @@
+use anyhow::{Context, Result};
+use log::trace;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::read;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+/// A single line of an ignore file.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) struct Pattern {
+    glob: Vec<u8>,
+    pub(crate) negated: bool,
+    dir_only: bool,
+    /// Match against the path relative to `base` instead of only the file name.
+    anchored: bool,
+    /// Directory containing the ignore file, relative to the worktree.
+    base: PathBuf,
+}
+
+impl Pattern {
+    pub(crate) fn parse(line: &[u8], base: &Path) -> Option<Self> {
+        let mut line = line.strip_suffix(b"\r").unwrap_or(line);
+        while line.ends_with(b" ") && !line.ends_with(b"\\ ") {
+            line = &line[..line.len() - 1];
+        }
+        if line.is_empty() || line.starts_with(b"#") {
+            return None;
+        }
+
+        let (negated, line) = match line.strip_prefix(b"!") {
+            Some(line) => (true, line),
+            None => (false, line.strip_prefix(b"\\").unwrap_or(line)),
+        };
+        let (dir_only, line) = match line.strip_suffix(b"/") {
+            Some(line) => (true, line),
+            None => (false, line),
+        };
+        let anchored = line.contains(&b'/');
+        let glob = line.strip_prefix(b"/").unwrap_or(line).to_vec();
+        if glob.is_empty() {
+            return None;
+        }
+
+        Some(Pattern {
+            glob,
+            negated,
+            dir_only,
+            anchored,
+            base: base.to_path_buf(),
+        })
+    }
+
+    pub(crate) fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        if self.dir_only && !is_dir {
+            return false;
+        }
+        let Ok(relative) = path.strip_prefix(&self.base) else {
+            return false;
+        };
+        let relative = relative.as_os_str().as_encoded_bytes();
+        if self.anchored {
+            wildmatch(&self.glob, relative)
+        } else {
+            let name = relative.rsplit(|&b| b == b'/').next().unwrap_or(relative);
+            wildmatch(&self.glob, name)
+        }
+    }
+}
+
+/// Match `text` against a gitignore glob, where `*` and `?` do not cross
+/// directory separators but `**` does.
+pub(crate) fn wildmatch(glob: &[u8], text: &[u8]) -> bool {
+    glob_match(glob, text, true)
+}
+
+/// Match a name against a glob as `git tag --list` does, where `*`, `?` and
+/// classes match `/` like any other character.
+pub(crate) fn name_match(glob: &[u8], text: &[u8]) -> bool {
+    glob_match(glob, text, false)
+}
+
+/// Match `text` against `glob`, keeping `*`, `?` and classes from matching
+/// `/` when `pathname` is set.
+fn glob_match(glob: &[u8], text: &[u8], pathname: bool) -> bool {
+    match glob.first() {
+        None => text.is_empty(),
+        Some(b'*') if glob.starts_with(b"**") => {
+            let rest = &glob[2..];
+            if let Some(after) = rest.strip_prefix(b"/")
+                && glob_match(after, text, pathname)
+            {
+                return true;
+            }
+            (0..=text.len()).any(|i| glob_match(rest, &text[i..], pathname))
+        }
+        Some(b'*') => {
+            for i in 0..=text.len() {
+                if glob_match(&glob[1..], &text[i..], pathname) {
+                    return true;
+                }
+                if pathname && i < text.len() && text[i] == b'/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some(b'?') => {
+            text.first().is_some_and(|&c| !pathname || c != b'/')
+                && glob_match(&glob[1..], &text[1..], pathname)
+        }
+        Some(b'[') => match (text.first(), glob.iter().skip(2).position(|&b| b == b']')) {
+            (Some(&c), Some(end)) if !pathname || c != b'/' => {
+                let class = &glob[1..end + 2];
+                let (negated, class) = match class.first() {
+                    Some(b'!' | b'^') => (true, &class[1..]),
+                    _ => (false, class),
+                };
+                let mut found = false;
+                let mut i = 0;
+                while i < class.len() {
+                    if i + 2 < class.len() && class[i + 1] == b'-' {
+                        found |= class[i] <= c && c <= class[i + 2];
+                        i += 3;
+                    } else {
+                        found |= class[i] == c;
+                        i += 1;
+                    }
+                }
+                found != negated && glob_match(&glob[end + 3..], &text[1..], pathname)
+            }
+            (Some(&c), None) => c == b'[' && glob_match(&glob[1..], &text[1..], pathname),
+            _ => false,
+        },
+        Some(b'\\') if glob.len() > 1 => {
+            text.first() == Some(&glob[1]) && glob_match(&glob[2..], &text[1..], pathname)
+        }
+        Some(&c) => text.first() == Some(&c) && glob_match(&glob[1..], &text[1..], pathname),
+    }
+}
+
+/// Ignore rules for a worktree, from `.git/info/exclude` and every
+/// `.gitignore`, with deeper files taking precedence over their parents.
+pub struct GitIgnore {
+    worktree: PathBuf,
+    exclude: Vec<Pattern>,
+    directories: RefCell<HashMap<PathBuf, Rc<Vec<Pattern>>>>,
+}
+
+impl GitIgnore {
+    pub fn new(worktree: &Path, exclude_file: &Path) -> Result<Self> {
+        Ok(Self {
+            worktree: worktree.to_path_buf(),
+            exclude: read_patterns(exclude_file, Path::new("")).context("reading info/exclude")?,
+            directories: RefCell::new(HashMap::new()),
+        })
+    }
+
+    /// Whether a worktree path, relative to the worktree root, is ignored.
+    pub fn is_ignored(&self, path: &Path) -> bool {
+        // nothing below an ignored directory can be re-included
+        if path
+            .ancestors()
+            .skip(1)
+            .filter(|a| !a.as_os_str().is_empty())
+            .any(|a| self.matches(a, true))
+        {
+            return true;
+        }
+        self.matches(path, self.worktree.join(path).is_dir())
+    }
+
+    fn matches(&self, path: &Path, is_dir: bool) -> bool {
+        let mut directories = path
+            .ancestors()
+            .skip(1)
+            .map(|d| self.directory_patterns(d))
+            .collect::<Vec<_>>();
+        directories.reverse();
+
+        let matched = self
+            .exclude
+            .iter()
+            .chain(directories.iter().flat_map(|d| d.iter()))
+            .rfind(|p| p.matches(path, is_dir));
+        trace!("ignore {} matched {:?}", path.to_string_lossy(), matched);
+        matched.is_some_and(|p| !p.negated)
+    }
+
+    fn directory_patterns(&self, directory: &Path) -> Rc<Vec<Pattern>> {
+        if let Some(patterns) = self.directories.borrow().get(directory) {
+            return patterns.clone();
+        }
+        let patterns = Rc::new(
+            read_patterns(&self.worktree.join(directory).join(".gitignore"), directory)
+                .unwrap_or_default(),
+        );
+        self.directories
+            .borrow_mut()
+            .insert(directory.to_path_buf(), patterns.clone());
+        patterns
+    }
+}
+
+fn read_patterns(path: &Path, base: &Path) -> Result<Vec<Pattern>> {
+    if !path.is_file() {
+        return Ok(Vec::new());
+    }
+    let data = read(path).with_context(|| format!("reading {}", path.to_string_lossy()))?;
+    Ok(data
+        .split(|&b| b == b'\n')
+        .filter_map(|line| Pattern::parse(line, base))
+        .collect())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gitignore::{GitIgnore, name_match, wildmatch};
+    use std::fs::{create_dir_all, write};
+    use std::path::Path;
+
+    #[test]
+    fn wildmatch_globs() {
+        assert!(wildmatch(b"*.o", b"main.o"));
+        assert!(!wildmatch(b"*.o", b"src/main.o"));
+        assert!(wildmatch(b"?.txt", b"a.txt"));
+        assert!(!wildmatch(b"?.txt", b"ab.txt"));
+        assert!(wildmatch(b"**/build", b"build"));
+        assert!(wildmatch(b"**/build", b"a/b/build"));
+        assert!(wildmatch(b"logs/**", b"logs/a/b.log"));
+        assert!(wildmatch(b"a/**/b", b"a/b"));
+        assert!(wildmatch(b"a/**/b", b"a/x/y/b"));
+        assert!(wildmatch(b"[a-c]at", b"bat"));
+        assert!(!wildmatch(b"[!a-c]at", b"bat"));
+        assert!(name_match(b"v1.*", b"v1.0"));
+        assert!(name_match(b"rel*", b"release/2"));
+        assert!(name_match(b"release?2", b"release/2"));
+        assert!(!name_match(b"v1.*", b"v2.0"));
+    }
+
+    #[test]
+    fn nested_ignore_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let root = dir.path();
+        create_dir_all(root.join("sub/deep")).unwrap();
+        create_dir_all(root.join("build")).unwrap();
+        create_dir_all(root.join(".git/info")).unwrap();
+        write(root.join(".git/info/exclude"), "*.swp\n").unwrap();
+        write(root.join(".gitignore"), "*.log\n/build/\n!keep.log\n").unwrap();
+        write(root.join("sub/.gitignore"), "!*.log\nsecret\n").unwrap();
+
+        let ignore = GitIgnore::new(root, &root.join(".git/info/exclude")).unwrap();
+        assert!(ignore.is_ignored(Path::new("a.log")));
+        assert!(!ignore.is_ignored(Path::new("keep.log")));
+        assert!(!ignore.is_ignored(Path::new("sub/a.log")));
+        assert!(ignore.is_ignored(Path::new("sub/deep/secret")));
+        assert!(ignore.is_ignored(Path::new("build")));
+        assert!(ignore.is_ignored(Path::new("build/out.o")));
+        assert!(!ignore.is_ignored(Path::new("sub/build")));
+        assert!(ignore.is_ignored(Path::new("x.swp")));
+        assert!(!ignore.is_ignored(Path::new("main.rs")));
+    }
+}
@@ -1,36 +1,50 @@
-use crate::kvlm::{kvlm_parse, kvlm_serialize};
+use crate::gitobject::identity::Identity;
+use crate::kvlm::{Kvlm, kvlm_parse, kvlm_serialize};
 use anyhow::Context;
 use hex::decode;
-use ordered_hash_map::OrderedHashMap;
-use std::ops::{Deref, Range};
+use std::ops::Deref;
 use std::str::from_utf8;
 
 #[derive(Debug)]
 pub struct CommitObject {
     data: Vec<u8>,
-    kvlm: OrderedHashMap<Vec<u8>, Vec<Range<usize>>>,
+    kvlm: Kvlm,
 }
 
 impl CommitObject {
-    fn get(&self, name: &[u8]) -> impl Iterator<Item = String> {
-        self.kvlm.get(name).into_iter().flat_map(|a| {
-            a.iter().map(|v| {
-                from_utf8(&self.data[v.start..v.end])
-                    .map(|v| v.to_string())
-                    .unwrap_or("<<bad-utf8>>".to_string())
-            })
+    fn get<'a>(&'a self, name: &'a [u8]) -> impl Iterator<Item = String> + 'a {
+        self.kvlm.get(name).map(|v| {
+            from_utf8(&self.data[v.start..v.end])
+                .map(|v| v.strip_suffix('\r').unwrap_or(v).to_string())
+                .unwrap_or("<<bad-utf8>>".to_string())
         })
     }
 
-    pub fn author(&self) -> Vec<String> {
-        self.get(b"author").collect()
+    pub fn tree(&self) -> Option<[u8; 20]> {
+        self.get(b"tree")
+            .next()
+            .and_then(|s| decode(s).ok())
+            .and_then(|v| v.try_into().ok())
+    }
+
+    pub fn author(&self) -> Option<Identity> {
+        self.get(b"author")
+            .next()
+            .and_then(|s| Identity::parse(s.as_bytes()).ok())
     }
 
-    pub fn committer_timestamp(&self) -> u32 {
+    pub fn committer(&self) -> Option<Identity> {
         self.get(b"committer")
             .next()
-            .map(|s| s.parse().unwrap_or(0))
-            .unwrap_or(0)
+            .and_then(|s| Identity::parse(s.as_bytes()).ok())
+    }
+
+    pub fn committer_timestamp(&self) -> i64 {
+        self.committer().map_or(0, |c| c.timestamp)
+    }
+
+    pub fn author_timestamp(&self) -> i64 {
+        self.author().map_or(0, |a| a.timestamp)
     }
 
     pub fn message(&self) -> Option<String> {
@@ -49,6 +63,19 @@ impl CommitObject {
         Ok(Self { data, kvlm })
     }
 
+    /// The bytes a `gpgsig` header signs, which are the commit without its
+    /// signature headers, and the signature itself.
+    pub fn signed_payload(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let signature = self.kvlm.get(b"gpgsig").next()?;
+        let mut signature = self.data[signature.clone()].to_vec();
+        signature.push(b'\n');
+
+        let mut kvlm = self.kvlm.clone();
+        kvlm.remove(b"gpgsig");
+        kvlm.remove(b"gpgsig-sha256");
+        Some((kvlm_serialize(&self.data, &kvlm), signature))
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         kvlm_serialize(&self.data, &self.kvlm)
     }
@@ -16,17 +16,13 @@ pub struct DeltaObject {
 
 #[derive(Debug)]
 pub struct OffsetDeltaObject {
-    #[allow(dead_code)]
     pub offset: u64,
-    #[allow(dead_code)]
     pub delta: DeltaObject,
 }
 
 #[derive(Debug)]
 pub struct RefDeltaObject {
-    #[allow(dead_code)]
     pub reference: [u8; 20],
-    #[allow(dead_code)]
     pub delta: DeltaObject,
 }
 
@@ -46,12 +42,29 @@ impl DeltaObject {
                     .to_string()
                     + "...<<bad-utf8>>")
         );
+        ensure!(
+            reference_data.len() == self.base_size,
+            "delta base is {} bytes but the delta expects {}",
+            reference_data.len(),
+            self.base_size
+        );
         let mut result = Vec::new();
         for instr in self.instructions.iter() {
             match instr {
                 DeltaInstruction::Copy(offset, size) => {
                     trace!("copy @{} +{}", offset, size);
-                    result.extend_from_slice(&reference_data[*offset..offset + size]);
+                    let copied = offset
+                        .checked_add(*size)
+                        .and_then(|end| reference_data.get(*offset..end))
+                        .with_context(|| {
+                            format!(
+                                "delta copies {} bytes from offset {} of a {} byte base",
+                                size,
+                                offset,
+                                reference_data.len()
+                            )
+                        })?;
+                    result.extend_from_slice(copied);
                 }
                 DeltaInstruction::Insert(insert) => {
                     trace!("insert +{}", insert.len());
@@ -184,3 +197,32 @@ fn parse_delta_data(bytes: &[u8]) -> anyhow::Result<DeltaObject> {
         instructions,
     })
 }
+
+#[cfg(test)]
+mod test {
+    use crate::gitobject::delta::DeltaObject;
+
+    #[test]
+    fn copy_past_end_of_base() {
+        // base size 4, result size 4, copy 4 bytes from offset 2
+        let delta = DeltaObject::from(&[4, 4, 0b1001_0001, 2, 4]).unwrap();
+        let err = delta.rebuild(b"abcd".to_vec()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "delta copies 4 bytes from offset 2 of a 4 byte base"
+        );
+
+        let delta = DeltaObject::from(&[4, 2, 0b1001_0001, 2, 2]).unwrap();
+        assert_eq!(delta.rebuild(b"abcd".to_vec()).unwrap(), b"cd");
+    }
+
+    #[test]
+    fn base_size_mismatch() {
+        let delta = DeltaObject::from(&[3, 2, 0b1001_0001, 0, 2]).unwrap();
+        let err = delta.rebuild(b"abcd".to_vec()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "delta base is 4 bytes but the delta expects 3"
+        );
+    }
+}
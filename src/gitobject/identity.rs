@@ -0,0 +1,323 @@
+use anyhow::{Context, ensure};
+use std::fmt::{Display, Formatter};
+use std::mem::MaybeUninit;
+use std::str::from_utf8;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// An author, committer or tagger line: `Name <email> unixtime +hhmm`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Identity {
+    pub name: String,
+    pub email: String,
+    pub timestamp: i64,
+    /// The author's offset from UTC, so `+0200` is 120.
+    pub tz_offset_minutes: i32,
+}
+
+impl Identity {
+    /// `name` and `email` at the current time, in the local timezone.
+    pub fn now(name: String, email: String) -> anyhow::Result<Self> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .context("system time is before the unix epoch")?
+            .as_secs() as i64;
+        Ok(Identity {
+            name,
+            email,
+            timestamp,
+            tz_offset_minutes: local_offset_minutes(timestamp),
+        })
+    }
+
+    pub fn parse(raw: &[u8]) -> anyhow::Result<Self> {
+        let raw = from_utf8(raw).context("identity is not utf8")?;
+        let raw = raw.strip_suffix('\r').unwrap_or(raw);
+        let (name, rest) = raw
+            .split_once('<')
+            .with_context(|| format!("no email in identity {}", raw))?;
+        let (email, date) = rest
+            .rsplit_once('>')
+            .with_context(|| format!("unterminated email in identity {}", raw))?;
+        let (timestamp, tz) = date
+            .trim()
+            .split_once(' ')
+            .with_context(|| format!("no date in identity {}", raw))?;
+        let timestamp = timestamp
+            .parse()
+            .with_context(|| format!("bad timestamp in identity {}", raw))?;
+
+        ensure!(
+            tz.len() == 5
+                && (tz.starts_with('+') || tz.starts_with('-'))
+                && tz[1..].bytes().all(|b| b.is_ascii_digit()),
+            "bad timezone in identity {}",
+            raw
+        );
+        let hours: i32 = tz[1..3].parse().context("parsing timezone hours")?;
+        let minutes: i32 = tz[3..].parse().context("parsing timezone minutes")?;
+        let sign = if tz.starts_with('-') { -1 } else { 1 };
+
+        Ok(Identity {
+            name: name.trim_end().to_string(),
+            email: email.to_string(),
+            timestamp,
+            tz_offset_minutes: sign * (hours * 60 + minutes),
+        })
+    }
+
+    /// The timezone as git writes it, such as `+0200`.
+    pub fn tz(&self) -> String {
+        let sign = if self.tz_offset_minutes < 0 { '-' } else { '+' };
+        let offset = self.tz_offset_minutes.abs();
+        format!("{}{:02}{:02}", sign, offset / 60, offset % 60)
+    }
+
+    /// The date in the given format, with `now` for relative dates.
+    pub fn date(&self, format: DateFormat, now: i64) -> String {
+        // timestamps near the ends of i64 are clamped rather than overflowing
+        let local = self
+            .timestamp
+            .saturating_add(self.tz_offset_minutes as i64 * 60);
+        let (year, month, day) = civil_from_days(local.div_euclid(86400));
+        let seconds = local.rem_euclid(86400);
+        let time = format!(
+            "{:02}:{:02}:{:02}",
+            seconds / 3600,
+            seconds / 60 % 60,
+            seconds % 60
+        );
+        match format {
+            DateFormat::Default => {
+                // 1970-01-01 was a Thursday
+                let weekday = WEEKDAYS[(local.div_euclid(86400) + 4).rem_euclid(7) as usize];
+                format!(
+                    "{} {} {} {} {} {}",
+                    weekday,
+                    MONTHS[month as usize - 1],
+                    day,
+                    time,
+                    year,
+                    self.tz()
+                )
+            }
+            DateFormat::Iso => {
+                format!("{}-{:02}-{:02} {} {}", year, month, day, time, self.tz())
+            }
+            DateFormat::Relative => relative_date(now.saturating_sub(self.timestamp)),
+            DateFormat::Unix => self.timestamp.to_string(),
+        }
+    }
+}
+
+/// How `Identity::date` writes a timestamp.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum DateFormat {
+    /// As `Tue May 22 23:37:03 2018 +0200`.
+    Default,
+    /// As `2018-05-22 23:37:03 +0200`.
+    Iso,
+    /// Relative to now, such as `3 days ago`.
+    Relative,
+    /// Seconds since the epoch.
+    Unix,
+}
+
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// The proleptic Gregorian year, month and day of a count of days since
+/// 1970-01-01, after Howard Hinnant's `civil_from_days`.
+fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let day_of_era = z.rem_euclid(146097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Describe an age in seconds the way git's `--date=relative` does, rounding
+/// to the nearest unit.
+fn relative_date(age: i64) -> String {
+    fn plural(n: i64, unit: &str) -> String {
+        format!("{} {}{}", n, unit, if n == 1 { "" } else { "s" })
+    }
+
+    if age < 0 {
+        return "in the future".to_string();
+    }
+    if age < 90 {
+        return format!("{} ago", plural(age, "second"));
+    }
+    let minutes = age.saturating_add(30) / 60;
+    if minutes < 90 {
+        return format!("{} ago", plural(minutes, "minute"));
+    }
+    let hours = (minutes + 30) / 60;
+    if hours < 36 {
+        return format!("{} ago", plural(hours, "hour"));
+    }
+    let days = (hours + 12) / 24;
+    if days < 14 {
+        return format!("{} ago", plural(days, "day"));
+    }
+    if days < 70 {
+        return format!("{} ago", plural((days + 3) / 7, "week"));
+    }
+    if days < 365 {
+        return format!("{} ago", plural((days + 15) / 30, "month"));
+    }
+    if days < 1825 {
+        let total_months = (days * 12 * 2 + 365) / (365 * 2);
+        let (years, months) = (total_months / 12, total_months % 12);
+        if months > 0 {
+            return format!("{}, {} ago", plural(years, "year"), plural(months, "month"));
+        }
+        return format!("{} ago", plural(years, "year"));
+    }
+    format!("{} ago", plural((days + 183) / 365, "year"))
+}
+
+/// The local timezone's offset from UTC at `timestamp`, as the C library
+/// works it out from `TZ` or the system zone, or 0 if it cannot.
+fn local_offset_minutes(timestamp: i64) -> i32 {
+    let time = timestamp as libc::time_t;
+    let mut tm = MaybeUninit::<libc::tm>::zeroed();
+    // SAFETY: localtime_r only writes to the tm it is given, and the tm is
+    // only read once it has succeeded
+    let tm = unsafe {
+        if libc::localtime_r(&time, tm.as_mut_ptr()).is_null() {
+            return 0;
+        }
+        tm.assume_init()
+    };
+    (tm.tm_gmtoff / 60) as i32
+}
+
+impl Display for Identity {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} <{}> {} {}",
+            self.name,
+            self.email,
+            self.timestamp,
+            self.tz()
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gitobject::commit::CommitObject;
+    use crate::gitobject::identity::DateFormat;
+    use crate::gitobject::identity::Identity;
+    use crate::gitobject::tag::TagObject;
+
+    #[test]
+    fn parse() {
+        let identity =
+            Identity::parse(b"Thibault Polge <thibault@thb.lt> 1527025023 -0230").unwrap();
+        assert_eq!(identity.name, "Thibault Polge");
+        assert_eq!(identity.email, "thibault@thb.lt");
+        assert_eq!(identity.timestamp, 1527025023);
+        assert_eq!(identity.tz_offset_minutes, -150);
+        assert_eq!(identity.tz(), "-0230");
+
+        assert!(Identity::parse(b"No Email 1527025023 +0000").is_err());
+        assert!(Identity::parse(b"A <a@b> soon +0000").is_err());
+        assert!(Identity::parse(b"A <a@b> 1527025023 0200").is_err());
+        // five bytes, but not five characters
+        assert!(Identity::parse("A <a@b> 1527025023 +0é0".as_bytes()).is_err());
+        assert!(Identity::parse(b"A <a@b> 1527025023 +02x0").is_err());
+    }
+
+    #[test]
+    fn round_trip_fixtures() {
+        let mut count = 0;
+        for entry in std::fs::read_dir("test/kvlm").unwrap() {
+            let data = std::fs::read(entry.unwrap().path()).unwrap();
+            for line in data.split(|&b| b == b'\n') {
+                let Some(raw) = [&b"author "[..], b"committer ", b"tagger "]
+                    .iter()
+                    .find_map(|key| line.strip_prefix(*key))
+                else {
+                    continue;
+                };
+                let identity = Identity::parse(raw).unwrap();
+                assert_eq!(identity.to_string().as_bytes(), raw);
+                count += 1;
+            }
+        }
+        assert!(count > 0);
+    }
+
+    #[test]
+    fn commit_and_tag_identities() {
+        let commit = CommitObject::from(std::fs::read("test/kvlm/commit").unwrap()).unwrap();
+        assert_eq!(commit.committer_timestamp(), 1792106999);
+        assert_eq!(commit.author().unwrap().name, "A");
+
+        let tag = TagObject::from(std::fs::read("test/kvlm/tag-annotated").unwrap()).unwrap();
+        assert!(tag.tagger().is_some());
+    }
+
+    #[test]
+    fn dates() {
+        let identity = Identity::parse(b"A <a@b> 1527025023 +0200").unwrap();
+        let now = identity.timestamp;
+        assert_eq!(
+            identity.date(DateFormat::Default, now),
+            "Tue May 22 23:37:03 2018 +0200"
+        );
+        assert_eq!(
+            identity.date(DateFormat::Iso, now),
+            "2018-05-22 23:37:03 +0200"
+        );
+        assert_eq!(identity.date(DateFormat::Unix, now), "1527025023");
+
+        let negative = Identity::parse(b"A <a@b> 951782400 -0130").unwrap();
+        assert_eq!(
+            negative.date(DateFormat::Iso, now),
+            "2000-02-28 22:30:00 -0130"
+        );
+
+        let relative = |age| identity.date(DateFormat::Relative, now + age);
+        assert_eq!(relative(-5), "in the future");
+        assert_eq!(relative(1), "1 second ago");
+        assert_eq!(relative(89), "89 seconds ago");
+        assert_eq!(relative(90), "2 minutes ago");
+        assert_eq!(relative(3 * 3600), "3 hours ago");
+        assert_eq!(relative(3 * 86400), "3 days ago");
+        assert_eq!(relative(20 * 86400), "3 weeks ago");
+        assert_eq!(relative(100 * 86400), "3 months ago");
+        assert_eq!(relative(400 * 86400), "1 year, 1 month ago");
+        assert_eq!(relative(730 * 86400), "2 years ago");
+        assert_eq!(relative(3650 * 86400), "10 years ago");
+
+        // the ends of i64 are clamped rather than overflowing
+        for raw in [
+            format!("A <a@b> {} -1400", i64::MIN),
+            format!("A <a@b> {} +1400", i64::MAX),
+        ] {
+            let extreme = Identity::parse(raw.as_bytes()).unwrap();
+            for format in [
+                DateFormat::Default,
+                DateFormat::Iso,
+                DateFormat::Relative,
+                DateFormat::Unix,
+            ] {
+                for now in [i64::MIN, 0, i64::MAX] {
+                    extreme.date(format, now);
+                }
+            }
+        }
+    }
+}
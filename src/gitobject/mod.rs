@@ -5,15 +5,38 @@ use crate::gitobject::tag::TagObject;
 use crate::gitobject::tree::TreeObject;
 use crate::pack::BinaryObject;
 use anyhow::*;
+use hex::ToHex;
 use std::fmt::Display;
 use std::str::from_utf8;
 
 pub mod blob;
 pub mod commit;
 pub mod delta;
+pub mod identity;
 pub mod tag;
 pub mod tree;
 
+/// The type of a whole object, as opposed to a delta stored in a pack.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ObjectType {
+    Blob,
+    Commit,
+    Tag,
+    Tree,
+}
+
+impl Display for ObjectType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ObjectType::Blob => "blob",
+            ObjectType::Commit => "commit",
+            ObjectType::Tag => "tag",
+            ObjectType::Tree => "tree",
+        };
+        f.write_str(name)
+    }
+}
+
 #[derive(Debug)]
 pub enum GitObject {
     Blob(BlobObject),
@@ -35,8 +58,18 @@ impl Display for GitObject {
                 "commit {}",
                 from_utf8(&commit_object.serialize()).unwrap_or("<<BINARY>>")
             )),
+            GitObject::Tag(tag) => f.write_fmt(format_args!(
+                "tag {}",
+                from_utf8(&tag.serialize()).unwrap_or("<<BINARY>>")
+            )),
             GitObject::Tree(_) => f.write_str("tree {...}"),
-            _ => todo!(),
+            GitObject::OffsetDelta(delta) => {
+                f.write_fmt(format_args!("offsetdelta -{}", delta.offset))
+            }
+            GitObject::RefDelta(delta) => f.write_fmt(format_args!(
+                "refdelta {}",
+                delta.reference.encode_hex::<String>()
+            )),
         }
     }
 }
@@ -56,29 +89,96 @@ impl GitObject {
             BinaryObject::RefDelta(sha1) => {
                 GitObject::RefDelta(RefDeltaObject::new(sha1, &data).context("parsing ref delta")?)
             }
+            BinaryObject::Reserved(id) => bail!("object has reserved type {}", id),
         };
 
         Ok(object)
     }
 
+    pub fn to_binary_object(&self) -> BinaryObject {
+        match &self {
+            GitObject::Blob(_) => BinaryObject::Blob,
+            GitObject::Commit(_) => BinaryObject::Commit,
+            GitObject::Tree(_) => BinaryObject::Tree,
+            GitObject::Tag(_) => BinaryObject::Tag,
+            GitObject::OffsetDelta(delta) => BinaryObject::OffsetDelta(delta.offset),
+            GitObject::RefDelta(delta) => BinaryObject::RefDelta(delta.reference),
+        }
+    }
+
     pub fn name(&self) -> &'static [u8] {
         match &self {
             GitObject::Blob(_) => b"blob",
             GitObject::Commit(_) => b"commit",
             GitObject::Tree(_) => b"tree",
             GitObject::Tag(_) => b"tag",
-            _ => unimplemented!(),
+            GitObject::OffsetDelta(_) => b"offsetdelta",
+            GitObject::RefDelta(_) => b"refdelta",
         }
     }
 
-    pub fn serialize(&self) -> Vec<u8> {
-        match &self {
+    /// Serialize the object as it is stored. Deltas only exist inside packs and must be
+    /// resolved against their base first, so they are refused.
+    pub fn try_serialize(&self) -> Result<Vec<u8>> {
+        Ok(match &self {
             GitObject::Blob(blob) => blob.serialize().to_vec(),
             GitObject::Commit(commit) => commit.serialize(),
             GitObject::Tag(tag) => tag.serialize(),
             GitObject::Tree(tree) => tree.serialize(),
-            GitObject::OffsetDelta(delta) => format!("OffsetDelta({:?})", delta).into_bytes(),
-            GitObject::RefDelta(delta) => format!("RefDelta({:?})", delta).into_bytes(),
+            GitObject::OffsetDelta(_) | GitObject::RefDelta(_) => {
+                bail!("cannot serialize an unresolved delta")
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gitobject::GitObject;
+    use crate::pack::BinaryObject;
+
+    #[test]
+    fn to_binary_object_round_trips() {
+        let objects: [(BinaryObject, &[u8]); 4] = [
+            (BinaryObject::Blob, b"contents"),
+            (BinaryObject::Tree, b"100644 file\0aaaaaaaaaaaaaaaaaaaa"),
+            (
+                BinaryObject::Commit,
+                b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\nmessage\n",
+            ),
+            (
+                BinaryObject::Tag,
+                b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype tree\n\nmessage\n",
+            ),
+        ];
+        for (object_type, data) in objects {
+            let object = GitObject::new(object_type, data.to_vec()).unwrap();
+            assert_eq!(object.to_binary_object(), object_type);
+            assert_eq!(object.name(), object_type.name().as_bytes());
         }
     }
+
+    #[test]
+    fn every_object_has_a_name_and_display() {
+        let tag = GitObject::new(
+            BinaryObject::Tag,
+            b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype tree\n\nmessage\n".to_vec(),
+        )
+        .unwrap();
+        assert_eq!(
+            tag.to_string(),
+            "tag object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype tree\n\nmessage\n"
+        );
+
+        // an empty delta, from no bytes to no bytes
+        let offset = GitObject::new(BinaryObject::OffsetDelta(12), vec![0, 0]).unwrap();
+        assert_eq!(offset.name(), b"offsetdelta");
+        assert_eq!(offset.to_string(), "offsetdelta -12");
+        let reference = GitObject::new(BinaryObject::RefDelta([0xab; 20]), vec![0, 0]).unwrap();
+        assert_eq!(reference.name(), b"refdelta");
+        assert_eq!(
+            reference.to_string(),
+            format!("refdelta {}", "ab".repeat(20))
+        );
+    }
 }
@@ -1,11 +1,11 @@
-use crate::kvlm::{kvlm_parse, kvlm_serialize};
+use crate::gitobject::identity::Identity;
+use crate::kvlm::{Kvlm, kvlm_parse, kvlm_serialize};
 use anyhow::Context;
-use ordered_hash_map::OrderedHashMap;
-use std::ops::Range;
+use hex::decode;
 
 #[derive(Debug)]
 pub struct TagObject {
-    kvlm: OrderedHashMap<Vec<u8>, Vec<Range<usize>>>,
+    kvlm: Kvlm,
     pub data: Vec<u8>,
 }
 
@@ -14,6 +14,57 @@ impl TagObject {
         let (data, kvlm) = kvlm_parse(data).context("parsing tag object")?;
         Ok(Self { data, kvlm })
     }
+
+    /// The object this tag points at.
+    pub fn object(&self) -> Option<[u8; 20]> {
+        self.kvlm
+            .get(b"object")
+            .next()
+            .and_then(|v| decode(&self.data[v.start..v.end]).ok())
+            .and_then(|v| v.try_into().ok())
+    }
+
+    pub fn tagger(&self) -> Option<Identity> {
+        self.kvlm
+            .get(b"tagger")
+            .next()
+            .and_then(|v| Identity::parse(&self.data[v.start..v.end]).ok())
+    }
+
+    /// The tag message, leaving out any signature at its end.
+    pub fn message(&self) -> Option<String> {
+        let message = self.kvlm.get(b"").next()?;
+        let message = String::from_utf8_lossy(&self.data[message.start..message.end]);
+        let mut end = 0;
+        for line in message.split_inclusive('\n') {
+            if line.starts_with("-----BEGIN PGP SIGNATURE-----")
+                || line.starts_with("-----BEGIN PGP MESSAGE-----")
+            {
+                break;
+            }
+            end += line.len();
+        }
+        Some(message[..end].to_string())
+    }
+
+    /// The bytes a signature at the end of the tag message signs, which are
+    /// everything before it, and the signature itself.
+    pub fn signed_payload(&self) -> Option<(Vec<u8>, Vec<u8>)> {
+        let mut data = self.serialize();
+        let mut start = 0;
+        let mut signature_start = None;
+        for line in data.split_inclusive(|&b| b == b'\n') {
+            if line.starts_with(b"-----BEGIN PGP SIGNATURE-----")
+                || line.starts_with(b"-----BEGIN PGP MESSAGE-----")
+            {
+                signature_start = Some(start);
+            }
+            start += line.len();
+        }
+        let signature = data.split_off(signature_start?);
+        Some((data, signature))
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         kvlm_serialize(&self.data, &self.kvlm)
     }
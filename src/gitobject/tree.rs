@@ -1,8 +1,11 @@
 use anyhow::Context;
 use hex::ToHex;
 use log::debug;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::fmt::Debug;
-use std::path::PathBuf;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
 use std::str::from_utf8;
 
 #[derive(Debug)]
@@ -30,25 +33,111 @@ impl TreeObject {
     }
 
     pub(crate) fn serialize(&self) -> Vec<u8> {
-        // todo ensure leaves sorted by path
-        // see git tree.c write_index_as_tree for sorting rules
-        self.leaves
-            .iter()
+        let mut leaves = self.leaves.iter().collect::<Vec<_>>();
+        leaves.sort();
+        leaves
+            .into_iter()
             .flat_map(|l| l.serialize())
             .collect::<Vec<u8>>()
     }
 }
 
+/// Builds a single tree level from leaves added in any order. Subtrees are
+/// built separately and inserted by sha1 with mode `040000`.
+#[derive(Debug, Default)]
+pub struct TreeBuilder {
+    leaves: BTreeMap<PathBuf, TreeLeaf>,
+}
+
+impl TreeBuilder {
+    const MODES: [&'static str; 5] = ["100644", "100755", "120000", "040000", "160000"];
+
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add or replace the leaf called `path`.
+    pub fn insert(&mut self, path: &str, mode: &str, sha1: [u8; 20]) -> anyhow::Result<&mut Self> {
+        anyhow::ensure!(
+            !path.is_empty() && path != "." && path != ".." && !path.contains(['/', '\0']),
+            "invalid tree leaf name {:?}",
+            path
+        );
+        let mode = if mode.len() == 5 {
+            format!("0{}", mode)
+        } else {
+            mode.to_string()
+        };
+        anyhow::ensure!(
+            Self::MODES.contains(&mode.as_str()),
+            "invalid tree leaf mode {} for {}",
+            mode,
+            path
+        );
+
+        self.leaves.insert(
+            PathBuf::from(path),
+            TreeLeaf {
+                mode,
+                path: PathBuf::from(path),
+                sha1: sha1.to_vec(),
+            },
+        );
+        Ok(self)
+    }
+
+    pub fn remove(&mut self, path: &str) -> &mut Self {
+        self.leaves.remove(Path::new(path));
+        self
+    }
+
+    pub fn build(&self) -> TreeObject {
+        let mut leaves = self.leaves.values().cloned().collect::<Vec<_>>();
+        leaves.sort();
+        TreeObject { leaves }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum LeafKind {
+    Tree,
+    Blob,
+    Symlink,
+    /// A submodule commit, the object lives in another repository.
+    Gitlink,
+}
+
+impl LeafKind {
+    /// The object type the leaf points at.
+    pub fn object_type(&self) -> &'static str {
+        match self {
+            LeafKind::Tree => "tree",
+            LeafKind::Blob | LeafKind::Symlink => "blob",
+            LeafKind::Gitlink => "commit",
+        }
+    }
+}
+
 #[derive(PartialEq, Eq, Clone)]
 pub struct TreeLeaf {
     pub mode: String,
+    /// The name exactly as stored, git allows any bytes but `/` and NUL.
     pub path: PathBuf,
     pub sha1: Vec<u8>,
 }
 
+/// Git's tree order, comparing names bytewise as if trees had a trailing `/`.
+/// See git tree.c base_name_compare.
 impl Ord for TreeLeaf {
     fn cmp(&self, other: &Self) -> std::cmp::Ordering {
-        self.path.cmp(&other.path)
+        let name = |leaf: &TreeLeaf| {
+            let mut name = leaf.path.as_os_str().as_encoded_bytes().to_vec();
+            if leaf.mode == "040000" {
+                name.push(b'/');
+            }
+            name
+        };
+        name(self).cmp(&name(other))
     }
 }
 
@@ -70,6 +159,16 @@ impl Debug for TreeLeaf {
 }
 
 impl TreeLeaf {
+    pub fn kind(&self) -> Option<LeafKind> {
+        match self.mode.as_str() {
+            "040000" => Some(LeafKind::Tree),
+            "100644" | "100755" | "100664" => Some(LeafKind::Blob),
+            "120000" => Some(LeafKind::Symlink),
+            "160000" => Some(LeafKind::Gitlink),
+            _ => None,
+        }
+    }
+
     fn parse_one(data: &[u8]) -> anyhow::Result<(Self, usize)> {
         let x = data
             .iter()
@@ -89,7 +188,7 @@ impl TreeLeaf {
             .skip(x)
             .position(|&b| b == b'\0')
             .context("tree leaf does not contain null")?;
-        let path = PathBuf::from(from_utf8(&data[x + 1..y]).context("leaf path is not utf8")?);
+        let path = PathBuf::from(OsStr::from_bytes(&data[x + 1..y]));
         anyhow::ensure!(data.len() >= y + 21, "tree leaf truncated in sha1");
         let sha1 = data[y + 1..y + 21].to_vec();
 
@@ -106,7 +205,7 @@ impl TreeLeaf {
 
         res.extend_from_slice(mode.as_bytes());
         res.push(b' ');
-        res.extend_from_slice(self.path.to_string_lossy().as_bytes());
+        res.extend_from_slice(self.path.as_os_str().as_bytes());
         res.push(b'\0');
         res.extend_from_slice(&self.sha1);
         res
@@ -115,9 +214,11 @@ impl TreeLeaf {
 
 #[cfg(test)]
 mod test {
-    use crate::gitobject::tree::TreeLeaf;
     use crate::gitobject::tree::TreeObject;
+    use crate::gitobject::tree::{LeafKind, TreeBuilder, TreeLeaf};
     use hex::FromHex;
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
     use std::{fs::File, io::Read, path::PathBuf};
 
     #[test]
@@ -141,4 +242,103 @@ mod test {
 
         assert_eq!(tree.serialize(), buf[skip..].to_vec());
     }
+
+    #[test]
+    fn serialize_sorts_leaves() {
+        let mut f = File::open("test/tree").unwrap();
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        let skip = buf.iter().position(|&b| b == b'\0').unwrap_or(0) + 1;
+        let mut tree = TreeObject::new(&buf[skip..]).unwrap();
+        tree.leaves.reverse();
+        tree.leaves.swap(0, 2);
+
+        assert_eq!(tree.serialize(), buf[skip..].to_vec());
+    }
+
+    #[test]
+    fn trees_sort_with_trailing_slash() {
+        let leaf = |mode: &str, path: &str| TreeLeaf {
+            mode: mode.to_string(),
+            path: PathBuf::from(path),
+            sha1: vec![0; 20],
+        };
+        let mut leaves = [
+            leaf("040000", "foo"),
+            leaf("100644", "foo.txt"),
+            leaf("100644", "foo-bar"),
+            leaf("040000", "foo0"),
+        ];
+        leaves.sort();
+        assert_eq!(
+            leaves
+                .iter()
+                .map(|l| l.path.to_str().unwrap())
+                .collect::<Vec<_>>(),
+            vec!["foo-bar", "foo.txt", "foo", "foo0"]
+        );
+    }
+
+    #[test]
+    fn non_utf8_leaf_names() {
+        // "café" in Latin-1, as a tree written on such a system would hold it
+        let mut data = b"100644 caf\xe9\0".to_vec();
+        data.extend([7; 20]);
+        let tree = TreeObject::new(&data).unwrap();
+        assert_eq!(tree.leaves[0].path.as_os_str().as_bytes(), b"caf\xe9");
+        assert_eq!(
+            tree.leaves[0].path,
+            PathBuf::from(OsStr::from_bytes(b"caf\xe9"))
+        );
+        assert_eq!(tree.serialize(), data);
+    }
+
+    #[test]
+    fn leaf_kinds() {
+        let leaf = |mode: &str| TreeLeaf {
+            mode: mode.to_string(),
+            path: PathBuf::from("a"),
+            sha1: vec![0; 20],
+        };
+        assert_eq!(leaf("040000").kind(), Some(LeafKind::Tree));
+        assert_eq!(leaf("100755").kind(), Some(LeafKind::Blob));
+        assert_eq!(leaf("120000").kind(), Some(LeafKind::Symlink));
+        assert_eq!(leaf("160000").kind(), Some(LeafKind::Gitlink));
+        assert_eq!(leaf("160000").kind().unwrap().object_type(), "commit");
+        assert_eq!(leaf("170000").kind(), None);
+    }
+
+    #[test]
+    fn tree_builder() {
+        let mut f = File::open("test/tree").unwrap();
+        let mut buf = Vec::new();
+        f.read_to_end(&mut buf).unwrap();
+        let skip = buf.iter().position(|&b| b == b'\0').unwrap_or(0) + 1;
+        let tree = TreeObject::new(&buf[skip..]).unwrap();
+
+        let mut builder = TreeBuilder::new();
+        builder
+            .insert("extra", "100644", [1; 20])
+            .unwrap()
+            .remove("extra");
+        for leaf in tree.leaves.iter().rev() {
+            builder
+                .insert(
+                    leaf.path.to_str().unwrap(),
+                    &leaf.mode,
+                    leaf.sha1.as_slice().try_into().unwrap(),
+                )
+                .unwrap();
+        }
+        let built = builder.build();
+        assert_eq!(built.serialize(), buf[skip..].to_vec());
+        assert_eq!(
+            TreeObject::new(&built.serialize()).unwrap().leaves,
+            tree.leaves
+        );
+
+        assert!(builder.insert("a/b", "100644", [0; 20]).is_err());
+        assert!(builder.insert("a", "100666", [0; 20]).is_err());
+        assert!(builder.insert("a", "40000", [0; 20]).is_ok());
+    }
 }
@@ -0,0 +1,172 @@
+use anyhow::{Context, Result, bail};
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+use std::process::{Command, Stdio};
+use tempfile::NamedTempFile;
+
+/// What gpg made of a signature.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SignatureStatus {
+    Good {
+        key: String,
+        signer: String,
+    },
+    /// The signature does not match the payload, or the key has expired or
+    /// been revoked.
+    Bad {
+        key: String,
+        signer: String,
+    },
+    /// The key is not in the keyring, so the signature could not be checked.
+    UnknownKey {
+        key: String,
+    },
+}
+
+impl Display for SignatureStatus {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureStatus::Good { key, signer } => {
+                write!(f, "Good signature from {} with key {}", signer, key)
+            }
+            SignatureStatus::Bad { key, signer } => {
+                write!(f, "BAD signature from {} with key {}", signer, key)
+            }
+            SignatureStatus::UnknownKey { key } => {
+                write!(f, "Can't check signature: no public key {}", key)
+            }
+        }
+    }
+}
+
+/// Check a detached `signature` over `payload` with `program`, which takes
+/// gpg's arguments.
+pub fn verify(program: &str, payload: &[u8], signature: &[u8]) -> Result<SignatureStatus> {
+    let mut signature_file = NamedTempFile::new().context("creating signature file")?;
+    signature_file
+        .write_all(signature)
+        .context("writing signature file")?;
+
+    let mut child = Command::new(program)
+        .args(["--keyid-format=long", "--status-fd=1", "--verify"])
+        .arg(signature_file.path())
+        .arg("-")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .with_context(|| format!("running {}", program))?;
+    child
+        .stdin
+        .take()
+        .context("opening gpg stdin")?
+        .write_all(payload)
+        .context("writing payload to gpg")?;
+    let output = child.wait_with_output().context("waiting for gpg")?;
+
+    match parse_status(&String::from_utf8_lossy(&output.stdout)) {
+        Some(status) => Ok(status),
+        None => bail!(
+            "gpg could not check the signature: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    }
+}
+
+/// Read the outcome from gpg's `--status-fd` lines.
+fn parse_status(status: &str) -> Option<SignatureStatus> {
+    let mut unknown_key = None;
+    for line in status.lines() {
+        let Some(line) = line.strip_prefix("[GNUPG:] ") else {
+            continue;
+        };
+        let mut parts = line.splitn(3, ' ');
+        let keyword = parts.next().unwrap_or_default();
+        let key = parts.next().unwrap_or_default().to_string();
+        let signer = parts.next().unwrap_or_default().to_string();
+        match keyword {
+            "GOODSIG" => return Some(SignatureStatus::Good { key, signer }),
+            "BADSIG" | "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG" => {
+                return Some(SignatureStatus::Bad { key, signer });
+            }
+            "ERRSIG" | "NO_PUBKEY" => unknown_key = Some(SignatureStatus::UnknownKey { key }),
+            _ => {}
+        }
+    }
+    unknown_key
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gitobject::commit::CommitObject;
+    use crate::gitobject::tag::TagObject;
+    use crate::gpg::{SignatureStatus, parse_status};
+
+    #[test]
+    fn status_lines() {
+        let good = "[GNUPG:] NEWSIG\n\
+            [GNUPG:] KEY_CONSIDERED C705EAB8E33C6D66F8436CD51B1336171A0B9064 0\n\
+            [GNUPG:] SIG_ID abc 2018-05-22 1527025044\n\
+            [GNUPG:] GOODSIG 1B1336171A0B9064 Thibault Polge <thibault@thb.lt>\n\
+            [GNUPG:] VALIDSIG C705EAB8E33C6D66F8436CD51B1336171A0B9064 2018-05-22\n";
+        assert_eq!(
+            parse_status(good),
+            Some(SignatureStatus::Good {
+                key: "1B1336171A0B9064".to_string(),
+                signer: "Thibault Polge <thibault@thb.lt>".to_string(),
+            })
+        );
+
+        let bad = "[GNUPG:] NEWSIG\n[GNUPG:] BADSIG 1B1336171A0B9064 A <a@b>\n";
+        assert!(matches!(
+            parse_status(bad),
+            Some(SignatureStatus::Bad { .. })
+        ));
+
+        let unknown = "[GNUPG:] NEWSIG\n\
+            [GNUPG:] ERRSIG 1B1336171A0B9064 1 10 00 1527025044 9 -\n\
+            [GNUPG:] NO_PUBKEY 1B1336171A0B9064\n";
+        assert_eq!(
+            parse_status(unknown),
+            Some(SignatureStatus::UnknownKey {
+                key: "1B1336171A0B9064".to_string()
+            })
+        );
+
+        assert_eq!(parse_status("[GNUPG:] NODATA 1\n"), None);
+    }
+
+    #[test]
+    fn signed_payloads() {
+        let raw = std::fs::read("test/kvlm/commit-gpgsig").unwrap();
+        let (payload, signature) = CommitObject::from(raw.clone())
+            .unwrap()
+            .signed_payload()
+            .unwrap();
+        let raw = String::from_utf8(raw).unwrap();
+        let (headers, rest) = raw.split_once("gpgsig ").unwrap();
+        let (signature_lines, message) = rest.split_once("-----END PGP SIGNATURE-----\n").unwrap();
+        assert_eq!(
+            String::from_utf8(payload).unwrap(),
+            headers.to_string() + message
+        );
+        assert_eq!(
+            String::from_utf8(signature).unwrap(),
+            signature_lines.replace("\n ", "\n") + "-----END PGP SIGNATURE-----\n"
+        );
+
+        let raw = std::fs::read("test/kvlm/tag-signed").unwrap();
+        let (payload, signature) = TagObject::from(raw.clone())
+            .unwrap()
+            .signed_payload()
+            .unwrap();
+        assert!(signature.starts_with(b"-----BEGIN PGP SIGNATURE-----\n"));
+        assert!(payload.ends_with(b"signed tag\n"));
+        let message = TagObject::from(raw.clone()).unwrap().message().unwrap();
+        assert!(message.ends_with("signed tag\n") && !message.contains("PGP"));
+        assert_eq!([payload, signature].concat(), raw);
+
+        let raw = std::fs::read("test/kvlm/tag-annotated").unwrap();
+        assert!(TagObject::from(raw).unwrap().signed_payload().is_none());
+    }
+}
@@ -1,32 +1,73 @@
-use sha1::digest::core_api::CoreWrapper;
-use sha1::{Digest, Sha1, Sha1Core};
+use sha1::Sha1;
+use sha1::digest::{Digest, FixedOutputReset, Output};
 use std::io;
 use std::io::Read;
 
-pub struct HashingReader<T: Read> {
-    hasher: CoreWrapper<Sha1Core>,
+/// Hashes everything read through it with `D`, counting the bytes as it goes.
+pub struct DigestReader<T: Read, D: Digest> {
+    hasher: D,
+    bytes_read: u64,
     inner: T,
 }
 
-impl<T: Read> Read for HashingReader<T> {
+/// The SHA-1 reader used for objects, indexes and pack trailers.
+pub type HashingReader<T> = DigestReader<T, Sha1>;
+
+impl<T: Read, D: Digest> Read for DigestReader<T, D> {
     fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
         let res = self.inner.read(buf);
         if let Ok(size) = res {
             self.hasher.update(&buf[..size]);
+            self.bytes_read += size as u64;
         }
         res
     }
 }
 
-impl<T: Read> HashingReader<T> {
+impl<T: Read, D: Digest + FixedOutputReset> DigestReader<T, D> {
     pub fn new(inner: T) -> Self {
         Self {
-            hasher: Sha1::new(),
+            hasher: D::new(),
+            bytes_read: 0,
             inner,
         }
     }
 
+    /// The number of bytes read so far.
+    pub fn bytes_read(&self) -> u64 {
+        self.bytes_read
+    }
+
+    /// The digest of everything read so far, resetting the hasher but not the count.
+    pub fn finalize_digest(&mut self) -> Output<D> {
+        Digest::finalize_reset(&mut self.hasher)
+    }
+}
+
+impl<T: Read> HashingReader<T> {
     pub fn finalize(&mut self) -> [u8; 20] {
-        self.hasher.finalize_reset().into()
+        self.finalize_digest().into()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::hashingreader::HashingReader;
+    use hex::ToHex;
+    use std::io::Read;
+
+    #[test]
+    fn counts_and_hashes_bytes_read() {
+        let mut reader = HashingReader::new(&b"hello world"[..]);
+        let mut buf = [0; 5];
+        reader.read_exact(&mut buf).unwrap();
+        assert_eq!(reader.bytes_read(), 5);
+        assert_eq!(
+            reader.finalize().encode_hex::<String>(),
+            "aaf4c61ddcc5e8a2dabede0f3b482cd9aea9434d"
+        );
+
+        reader.read_to_end(&mut Vec::new()).unwrap();
+        assert_eq!(reader.bytes_read(), 11);
     }
 }
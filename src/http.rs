@@ -0,0 +1,95 @@
+use anyhow::{Context, Result};
+use log::debug;
+use std::time::Duration;
+use ureq::Agent;
+
+const MAX_REDIRECTS: u32 = 5;
+
+/// The most of a response body read into memory, so a server cannot make us
+/// allocate without bound.
+const MAX_BODY_SIZE: u64 = 2 << 30;
+
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(30);
+/// How long a server may take to start answering.
+const RESPONSE_TIMEOUT: Duration = Duration::from_secs(60);
+/// How long a whole response body may take, long enough for a large pack.
+const BODY_TIMEOUT: Duration = Duration::from_secs(30 * 60);
+
+/// The client every request goes through, over http or https, giving up on
+/// servers that stop answering.
+pub fn agent() -> Agent {
+    Agent::config_builder()
+        .max_redirects(MAX_REDIRECTS)
+        .user_agent("wyag")
+        .timeout_connect(Some(CONNECT_TIMEOUT))
+        .timeout_recv_response(Some(RESPONSE_TIMEOUT))
+        .timeout_recv_body(Some(BODY_TIMEOUT))
+        .build()
+        .into()
+}
+
+/// Fetch `url`, following redirects. Returns `None` if the server answers
+/// 404, which the dumb protocol uses for optional files.
+pub fn get(url: &str) -> Result<Option<Vec<u8>>> {
+    get_with_limit(url, MAX_BODY_SIZE)
+}
+
+fn get_with_limit(url: &str, limit: u64) -> Result<Option<Vec<u8>>> {
+    debug!("GET {}", url);
+    let response = match agent().get(url).call() {
+        Ok(response) => response,
+        Err(ureq::Error::StatusCode(404)) => return Ok(None),
+        Err(e) => return Err(e).with_context(|| format!("GET {} failed", url)),
+    };
+    response
+        .into_body()
+        .with_config()
+        .limit(limit)
+        .read_to_vec()
+        .with_context(|| format!("reading body of {}", url))
+        .map(Some)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::http::get_with_limit;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::thread;
+
+    /// Answer every request with `response` until the test exits.
+    fn serve(response: &'static [u8]) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                let mut stream = stream.unwrap();
+                let mut lines = BufReader::new(&stream).lines();
+                while !lines.next().unwrap().unwrap().is_empty() {}
+                stream.write_all(response).unwrap();
+            }
+        });
+        url
+    }
+
+    #[test]
+    fn body_size_is_limited() {
+        let url =
+            serve(b"HTTP/1.1 200 OK\r\nContent-Length: 10\r\nConnection: close\r\n\r\n0123456789");
+        assert_eq!(
+            get_with_limit(&url, 100).unwrap().as_deref(),
+            Some(&b"0123456789"[..])
+        );
+        assert!(get_with_limit(&url, 9).is_err());
+
+        // a length the server never sends is not allocated up front
+        let url = serve(
+            b"HTTP/1.1 200 OK\r\nContent-Length: 1099511627776\r\nConnection: close\r\n\r\nshort",
+        );
+        assert!(get_with_limit(&url, 1 << 20).is_err());
+
+        let url =
+            serve(b"HTTP/1.1 404 Not Found\r\nContent-Length: 0\r\nConnection: close\r\n\r\n");
+        assert_eq!(get_with_limit(&url, 10).unwrap(), None);
+    }
+}
@@ -0,0 +1,349 @@
+use crate::hashingreader::HashingReader;
+use crate::util::read_byte;
+use anyhow::{Context, bail, ensure};
+use hex::ToHex;
+use log::{debug, trace};
+use sha1::{Digest, Sha1};
+use std::ffi::OsString;
+use std::fs::Metadata;
+use std::io;
+use std::io::{BufReader, Read};
+use std::os::unix::ffi::OsStringExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::PathBuf;
+use std::str::from_utf8;
+
+const ENTRY_FIXED_LEN: usize = 62;
+const FLAG_EXTENDED: u16 = 0x4000;
+
+/// The staging area, as stored in `.git/index`.
+#[derive(Debug, Default)]
+pub struct Index {
+    pub version: u32,
+    pub entries: Vec<IndexEntry>,
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct IndexEntry {
+    pub ctime: (u32, u32),
+    pub mtime: (u32, u32),
+    pub dev: u32,
+    pub ino: u32,
+    pub mode: u32,
+    pub uid: u32,
+    pub gid: u32,
+    pub size: u32,
+    pub sha1: [u8; 20],
+    pub flags: u16,
+    pub extended_flags: u16,
+    pub path: PathBuf,
+}
+
+impl IndexEntry {
+    /// Build a stage 0 entry for a worktree file from its stat data.
+    pub fn new(path: PathBuf, sha1: [u8; 20], metadata: &Metadata) -> Self {
+        let mode = if metadata.is_symlink() {
+            0o120000
+        } else if metadata.mode() & 0o111 != 0 {
+            0o100755
+        } else {
+            0o100644
+        };
+        IndexEntry {
+            ctime: (metadata.ctime() as u32, metadata.ctime_nsec() as u32),
+            mtime: (metadata.mtime() as u32, metadata.mtime_nsec() as u32),
+            dev: metadata.dev() as u32,
+            ino: metadata.ino() as u32,
+            mode,
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            size: metadata.size() as u32,
+            sha1,
+            flags: 0,
+            extended_flags: 0,
+            path,
+        }
+    }
+
+    /// Whether the worktree file still has the stat data recorded when it
+    /// was staged, in which case its contents are assumed unchanged.
+    pub fn stat_matches(&self, metadata: &Metadata) -> bool {
+        let current = IndexEntry::new(self.path.clone(), self.sha1, metadata);
+        self.mtime == current.mtime
+            && self.ctime == current.ctime
+            && self.ino == current.ino
+            && self.size == current.size
+            && self.mode == current.mode
+    }
+
+    /// The merge stage, 0 for normal entries and 1-3 during a conflicted merge.
+    pub fn stage(&self) -> u16 {
+        (self.flags >> 12) & 0x3
+    }
+}
+
+impl Index {
+    /// Parse an index of `len` bytes, validating the trailing checksum.
+    pub fn new<T: Read>(reader: BufReader<T>, len: u64) -> anyhow::Result<Index> {
+        let mut reader = HashingReader::new(reader);
+
+        let (version, count) = check_header(&mut reader).context("check header")?;
+        debug!("index version {version} with {count} entries");
+
+        let mut entries = Vec::with_capacity(count as usize);
+        for n in 0..count {
+            let entry = read_entry(&mut reader, version)
+                .with_context(|| format!("reading index entry {}", n))?;
+            trace!("read index entry {}", entry.path.to_string_lossy());
+            entries.push(entry);
+        }
+
+        while reader.bytes_read() + 20 < len {
+            let mut signature = [0; 4];
+            reader
+                .read_exact(&mut signature)
+                .context("reading extension signature")?;
+            let size = read_u32be(&mut reader).context("reading extension size")?;
+            debug!(
+                "skipping index extension {} of {} bytes",
+                from_utf8(&signature).unwrap_or("<<bad-utf8>>"),
+                size
+            );
+            io::copy(&mut (&mut reader).take(size as u64), &mut io::sink())
+                .context("reading extension data")?;
+        }
+
+        let actual_sha1 = reader.finalize();
+        let mut index_sha1 = [0; 20];
+        reader
+            .read_exact(&mut index_sha1)
+            .context("reading index checksum")?;
+        ensure!(
+            actual_sha1 == index_sha1,
+            "index checksum mismatch, expected {} but calculated {}",
+            index_sha1.encode_hex::<String>(),
+            actual_sha1.encode_hex::<String>()
+        );
+
+        Ok(Index { version, entries })
+    }
+
+    /// Serialize to the `DIRC` format with entries sorted by path. Extensions
+    /// are not written, git rebuilds them as needed.
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut entries = self.entries.iter().collect::<Vec<_>>();
+        entries.sort_by(|a, b| {
+            a.path
+                .as_os_str()
+                .as_encoded_bytes()
+                .cmp(b.path.as_os_str().as_encoded_bytes())
+                .then(a.stage().cmp(&b.stage()))
+        });
+
+        let version = if entries.iter().any(|e| e.flags & FLAG_EXTENDED != 0) {
+            3
+        } else {
+            self.version.clamp(2, 3)
+        };
+
+        let mut res = Vec::new();
+        res.extend_from_slice(b"DIRC");
+        res.extend_from_slice(&version.to_be_bytes());
+        res.extend_from_slice(&(entries.len() as u32).to_be_bytes());
+        for entry in entries {
+            entry.serialize_into(&mut res);
+        }
+
+        let sha1: [u8; 20] = Sha1::digest(&res).into();
+        res.extend_from_slice(&sha1);
+        res
+    }
+}
+
+impl IndexEntry {
+    fn serialize_into(&self, res: &mut Vec<u8>) {
+        let start = res.len();
+        for n in [
+            self.ctime.0,
+            self.ctime.1,
+            self.mtime.0,
+            self.mtime.1,
+            self.dev,
+            self.ino,
+            self.mode,
+            self.uid,
+            self.gid,
+            self.size,
+        ] {
+            res.extend_from_slice(&n.to_be_bytes());
+        }
+        res.extend_from_slice(&self.sha1);
+
+        let path = self.path.as_os_str().as_encoded_bytes();
+        let flags = (self.flags & 0xf000) | path.len().min(0xfff) as u16;
+        res.extend_from_slice(&flags.to_be_bytes());
+        if flags & FLAG_EXTENDED != 0 {
+            res.extend_from_slice(&self.extended_flags.to_be_bytes());
+        }
+        res.extend_from_slice(path);
+
+        let entry_len = (res.len() - start + 8) & !7;
+        res.resize(start + entry_len, 0);
+    }
+}
+
+fn check_header<T: Read>(reader: &mut HashingReader<T>) -> anyhow::Result<(u32, u32)> {
+    let mut signature = [0; 4];
+    reader
+        .read_exact(&mut signature)
+        .context("reading signature")?;
+    ensure!(&signature == b"DIRC", "invalid index signature");
+
+    let version = read_u32be(reader).context("reading version")?;
+    if !(2..=3).contains(&version) {
+        bail!("only index versions 2 and 3 supported, index is {version}");
+    }
+
+    let count = read_u32be(reader).context("reading entry count")?;
+    Ok((version, count))
+}
+
+fn read_entry<T: Read>(reader: &mut HashingReader<T>, version: u32) -> anyhow::Result<IndexEntry> {
+    let ctime = (read_u32be(reader)?, read_u32be(reader)?);
+    let mtime = (read_u32be(reader)?, read_u32be(reader)?);
+    let dev = read_u32be(reader)?;
+    let ino = read_u32be(reader)?;
+    let mode = read_u32be(reader)?;
+    let uid = read_u32be(reader)?;
+    let gid = read_u32be(reader)?;
+    let size = read_u32be(reader)?;
+    let mut sha1 = [0; 20];
+    reader.read_exact(&mut sha1).context("reading sha1")?;
+    let flags = read_u16be(reader).context("reading flags")?;
+
+    let mut fixed_len = ENTRY_FIXED_LEN;
+    let extended_flags = if flags & FLAG_EXTENDED != 0 {
+        ensure!(version >= 3, "extended flags in version {version} index");
+        fixed_len += 2;
+        read_u16be(reader).context("reading extended flags")?
+    } else {
+        0
+    };
+
+    let mut path = Vec::new();
+    loop {
+        match read_byte(reader).context("reading path")? {
+            0 => break,
+            b => path.push(b),
+        }
+    }
+    // paths are bytes, which need not be utf8 on unix
+    let path = PathBuf::from(OsString::from_vec(path));
+
+    // entries are NUL padded to a multiple of eight bytes, with at least one NUL
+    let entry_len = (fixed_len + path.as_os_str().len() + 8) & !7;
+    let padding = entry_len - fixed_len - path.as_os_str().len() - 1;
+    let mut pad = [0; 8];
+    reader
+        .read_exact(&mut pad[..padding])
+        .context("reading entry padding")?;
+
+    Ok(IndexEntry {
+        ctime,
+        mtime,
+        dev,
+        ino,
+        mode,
+        uid,
+        gid,
+        size,
+        sha1,
+        flags,
+        extended_flags,
+        path,
+    })
+}
+
+fn read_u32be<T: Read>(reader: &mut T) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    reader.read_exact(&mut buf)?;
+    Ok(u32::from_be_bytes(buf))
+}
+
+fn read_u16be<T: Read>(reader: &mut T) -> io::Result<u16> {
+    let mut buf = [0; 2];
+    reader.read_exact(&mut buf)?;
+    Ok(u16::from_be_bytes(buf))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::index::Index;
+    use hex::FromHex;
+    use std::ffi::OsStr;
+    use std::fs::File;
+    use std::io::BufReader;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::PathBuf;
+
+    #[test]
+    fn read_index() {
+        let f = File::open("test/index").unwrap();
+        let len = f.metadata().unwrap().len();
+        let index = Index::new(BufReader::new(f), len).unwrap();
+
+        assert_eq!(index.version, 2);
+        assert_eq!(
+            index
+                .entries
+                .iter()
+                .map(|e| e.path.clone())
+                .collect::<Vec<_>>(),
+            vec![
+                PathBuf::from("README.md"),
+                PathBuf::from("src/a-rather-long-file-name.rs"),
+                PathBuf::from("src/main.rs"),
+            ]
+        );
+        assert_eq!(index.entries[0].mode, 0o100644);
+        assert_eq!(
+            index.entries[0].sha1,
+            <[u8; 20]>::from_hex("ce013625030ba8dba906f756967f9e9ca394464a").unwrap()
+        );
+    }
+
+    #[test]
+    fn serialize_index() {
+        let f = File::open("test/index").unwrap();
+        let len = f.metadata().unwrap().len();
+        let mut index = Index::new(BufReader::new(f), len).unwrap();
+        index.entries.reverse();
+
+        let data = index.serialize();
+        let reread = Index::new(BufReader::new(data.as_slice()), data.len() as u64).unwrap();
+
+        index.entries.reverse();
+        assert_eq!(reread.version, 2);
+        assert_eq!(reread.entries, index.entries);
+    }
+
+    #[test]
+    fn non_utf8_path() {
+        let f = File::open("test/index").unwrap();
+        let len = f.metadata().unwrap().len();
+        let mut index = Index::new(BufReader::new(f), len).unwrap();
+        let mut entry = index.entries[0].clone();
+        entry.path = PathBuf::from(OsStr::from_bytes(b"caf\xe9"));
+        index.entries.push(entry.clone());
+
+        let data = index.serialize();
+        let reread = Index::new(BufReader::new(data.as_slice()), data.len() as u64).unwrap();
+        assert_eq!(reread.entries.len(), 4);
+        let reread = reread
+            .entries
+            .iter()
+            .find(|e| e.path == entry.path)
+            .unwrap();
+        assert_eq!(reread.sha1, entry.sha1);
+    }
+}
@@ -1,28 +1,49 @@
 use anyhow::{Context, anyhow};
 use log::trace;
-use ordered_hash_map::OrderedHashMap;
 use std::ops::Range;
 use std::str::from_utf8;
 
-type Kvlm = OrderedHashMap<Vec<u8>, Vec<Range<usize>>>;
+/// The headers of a commit or tag in the order they were written, one entry
+/// per value so a key repeated around other keys keeps its place, then the
+/// message under the empty key.
+#[derive(Clone, Debug, Default)]
+pub struct Kvlm(Vec<(Vec<u8>, Range<usize>)>);
+
+impl Kvlm {
+    /// Every value of `key`, in order.
+    pub fn get<'a>(&'a self, key: &'a [u8]) -> impl Iterator<Item = &'a Range<usize>> + 'a {
+        self.0.iter().filter(move |(k, _)| k == key).map(|(_, v)| v)
+    }
+
+    /// Drop every value of `key`.
+    pub fn remove(&mut self, key: &[u8]) {
+        self.0.retain(|(k, _)| k != key);
+    }
+}
 
 pub fn kvlm_parse(mut raw: Vec<u8>) -> anyhow::Result<(Vec<u8>, Kvlm)> {
-    let map = OrderedHashMap::new();
+    let map = Kvlm::default();
     let map = kvlm_parse_rec(&mut raw, map, 0).context("parsing kvlm")?;
     Ok((raw, map))
 }
 
 fn kvlm_parse_rec(raw: &mut Vec<u8>, mut map: Kvlm, i: usize) -> anyhow::Result<Kvlm> {
-    if raw.len() == i {
+    if i >= raw.len() {
         return Ok(map);
     }
-    if raw[i] == b'\n' {
-        let range = i + 1..raw.len();
+    // the message follows the first blank line, which may be CRLF terminated
+    let blank = match &raw[i..] {
+        [b'\n', ..] => Some(1),
+        [b'\r', b'\n', ..] => Some(2),
+        _ => None,
+    };
+    if let Some(blank) = blank {
+        let range = i + blank..raw.len();
         trace!(
             "using final value [{}]",
             from_utf8(&raw[range.clone()]).unwrap_or("<<bad-utf8>>")
         );
-        map.insert(Vec::new(), vec![range]);
+        map.0.push((Vec::new(), range));
         return Ok(map);
     }
 
@@ -41,7 +62,7 @@ fn kvlm_parse_rec(raw: &mut Vec<u8>, mut map: Kvlm, i: usize) -> anyhow::Result<
         end += raw[end..]
             .iter()
             .position(|&b| b == b'\n')
-            .unwrap_or(raw.len() - end - 1);
+            .unwrap_or(raw.len() - end);
 
         if end + 1 >= raw.len() || raw[end + 1] != b' ' {
             break;
@@ -57,16 +78,12 @@ fn kvlm_parse_rec(raw: &mut Vec<u8>, mut map: Kvlm, i: usize) -> anyhow::Result<
     );
 
     let i = end + 1;
-    if let Some(v) = map.get_mut(&key) {
-        v.push(value);
-    } else {
-        map.insert(key, vec![value]);
-    }
+    map.0.push((key, value));
 
     kvlm_parse_rec(raw, map, i)
 }
 
-fn kvlm_clean_value(vec: &mut Vec<u8>, range: Range<usize>) -> usize {
+fn kvlm_clean_value(vec: &mut [u8], range: Range<usize>) -> usize {
     if range.start >= vec.len() {
         return range.start;
     }
@@ -90,32 +107,27 @@ fn kvlm_clean_value(vec: &mut Vec<u8>, range: Range<usize>) -> usize {
     i
 }
 
-pub fn kvlm_serialize(data: &Vec<u8>, map: &OrderedHashMap<Vec<u8>, Vec<Range<usize>>>) -> Vec<u8> {
+pub fn kvlm_serialize(data: &[u8], map: &Kvlm) -> Vec<u8> {
+    let mut v = Vec::new();
     let mut rest = None;
-    let mut v: Vec<u8> = map
-        .iter()
-        .filter_map(|(k, v)| {
-            if k.is_empty() {
-                rest = Some(v);
-                None
-            } else {
-                let start = k.iter();
-                let end = v.iter().flat_map(|v| {
-                    data[v.start..v.end]
-                        .split(|&b| b == b'\n')
-                        .flat_map(|v| b" ".iter().chain(v.iter()).chain(b"\n"))
-                });
-                Some(start.chain(end).copied())
-            }
-        })
-        .flatten()
-        .collect::<Vec<_>>();
+    for (k, value) in map.0.iter() {
+        if k.is_empty() {
+            rest = Some(value);
+            continue;
+        }
+        // every value repeats the key, continuation lines are indented
+        v.extend_from_slice(k);
+        for line in data[value.start..value.end].split(|&b| b == b'\n') {
+            v.push(b' ');
+            v.extend_from_slice(line);
+            v.push(b'\n');
+        }
+    }
 
     if let Some(rest) = rest {
-        v.push(b'\n');
-        for b in rest.iter() {
-            v.extend_from_slice(&data[b.start..b.end]);
-        }
+        let crlf = rest.start >= 2 && &data[rest.start - 2..rest.start] == b"\r\n";
+        v.extend_from_slice(if crlf { b"\r\n" } else { b"\n" });
+        v.extend_from_slice(&data[rest.start..rest.end]);
     }
 
     trace!(
@@ -130,9 +142,8 @@ pub fn kvlm_serialize(data: &Vec<u8>, map: &OrderedHashMap<Vec<u8>, Vec<Range<us
 
 #[cfg(test)]
 mod tests {
-    use super::{kvlm_parse, kvlm_serialize};
+    use super::{Kvlm, kvlm_parse, kvlm_serialize};
     use log::debug;
-    use ordered_hash_map::OrderedHashMap;
     use std::ops::Range;
     use std::{collections::HashMap, str::from_utf8};
 
@@ -164,19 +175,19 @@ Create first draft"#;
         let (kvlm, map) = kvlm_parse(kvlm).unwrap();
         assert_bytes_eq(
             &kvlm,
-            map.get(&b"tree"[..]),
+            map.get(b"tree"),
             vec![&b"29ff16c9c14e2652b22f8b78bb08a5a07930c147"[..]],
             "tree",
         );
         assert_bytes_eq(
             &kvlm,
-            map.get(&b"parent"[..]),
+            map.get(b"parent"),
             vec![&b"206941306e8a8af65b66eaaaea388a7ae24d49a0"[..]],
             "parent",
         );
         assert_bytes_eq(
             &kvlm,
-            map.get(&b"gpgsig"[..]),
+            map.get(b"gpgsig"),
             vec![
                 br#"-----BEGIN PGP SIGNATURE-----
 
@@ -200,7 +211,7 @@ Q52UWybBzpaP9HEd4XnR+HuQ4k2K0ns2KgNImsNvIyFwbpMUyUWLMPimaV1DWUXo
         );
         assert_bytes_eq(
             &kvlm,
-            map.get(b"".as_slice()),
+            map.get(b""),
             vec![b"Create first draft".as_slice()],
             "comment",
         );
@@ -213,7 +224,7 @@ Q52UWybBzpaP9HEd4XnR+HuQ4k2K0ns2KgNImsNvIyFwbpMUyUWLMPimaV1DWUXo
         let ser = kvlm_serialize(&kvlm, &map);
         assert_bytes_eq(
             &kvlm,
-            map.get(b"".as_slice()),
+            map.get(b""),
             vec![b"Create first draft".as_slice()],
             "comment",
         );
@@ -224,38 +235,166 @@ Q52UWybBzpaP9HEd4XnR+HuQ4k2K0ns2KgNImsNvIyFwbpMUyUWLMPimaV1DWUXo
         );
     }
 
-    fn readable_map(
-        data: &Vec<u8>,
-        map: &OrderedHashMap<Vec<u8>, Vec<Range<usize>>>,
-    ) -> HashMap<String, Vec<String>> {
-        map.clone()
-            .into_iter()
-            .map(|(k, v)| {
-                (
-                    from_utf8(&k).unwrap().to_string(),
-                    v.iter()
-                        .map(|v| from_utf8(&data[v.start..v.end]).unwrap().to_string())
-                        .collect::<Vec<String>>(),
-                )
-            })
-            .collect::<HashMap<String, Vec<String>>>()
+    #[test]
+    fn test_crlf_round_trip() {
+        let raw = b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\r\n\
+            author A <a@b> 1527025023 +0200\r\n\
+            gpgsig line one\r\n line two\r\n\
+            \r\n\
+            Subject\r\n\r\nBody\r\n"
+            .to_vec();
+        let (data, map) = kvlm_parse(raw.clone()).unwrap();
+        assert_bytes_eq(
+            &data,
+            map.get(b""),
+            vec![b"Subject\r\n\r\nBody\r\n".as_slice()],
+            "message",
+        );
+        assert_bytes_eq(
+            &data,
+            map.get(b"gpgsig"),
+            vec![b"line one\r\nline two\r".as_slice()],
+            "gpgsig",
+        );
+        assert_eq!(kvlm_serialize(&data, &map), raw);
+    }
+
+    #[test]
+    fn test_message_round_trip() {
+        for raw in [
+            &b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n\nSubject\n\nFirst paragraph.\n\nSecond.\n"[..],
+            b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n\nTrailing blank line\n\n",
+            b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n\n\nLeading blank line\n",
+            b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n\n",
+            b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n",
+        ] {
+            let (data, map) = kvlm_parse(raw.to_vec()).unwrap();
+            assert_eq!(
+                from_utf8(&kvlm_serialize(&data, &map)).unwrap(),
+                from_utf8(raw).unwrap()
+            );
+        }
+
+        let (data, map) =
+            kvlm_parse(b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147".to_vec()).unwrap();
+        assert_bytes_eq(
+            &data,
+            map.get(b"tree"),
+            vec![b"29ff16c9c14e2652b22f8b78bb08a5a07930c147".as_slice()],
+            "tree without newline",
+        );
     }
 
-    fn assert_bytes_eq(
-        raw: &Vec<u8>,
-        actual: Option<&Vec<Range<usize>>>,
+    #[test]
+    fn test_corpus_round_trip() {
+        for entry in std::fs::read_dir("test/kvlm").unwrap() {
+            let path = entry.unwrap().path();
+            let raw = std::fs::read(&path).unwrap();
+            let (data, map) = kvlm_parse(raw.clone()).unwrap();
+            assert_eq!(
+                from_utf8(&kvlm_serialize(&data, &map)).unwrap(),
+                from_utf8(&raw).unwrap(),
+                "{}",
+                path.to_string_lossy()
+            );
+        }
+    }
+
+    #[test]
+    fn test_mergetags() {
+        let raw = std::fs::read("test/kvlm/commit-octopus-mergetags").unwrap();
+        let (data, map) = kvlm_parse(raw).unwrap();
+        assert_eq!(
+            map.0
+                .iter()
+                .map(|(k, _)| from_utf8(k).unwrap())
+                .collect::<Vec<_>>(),
+            vec![
+                "tree",
+                "parent",
+                "parent",
+                "parent",
+                "author",
+                "committer",
+                "mergetag",
+                "mergetag",
+                ""
+            ]
+        );
+
+        let mergetags = map.get(b"mergetag").collect::<Vec<_>>();
+        assert_eq!(mergetags.len(), 2);
+        for (range, (object, tag)) in mergetags.into_iter().zip([
+            ("79002e18acb2273c552abeba01bd61302d3be31c", "t1"),
+            ("02962a33e73863f2999119f1c7cc973456f7bb3c", "t2"),
+        ]) {
+            let (tag_data, tag_map) = kvlm_parse(data[range.clone()].to_vec()).unwrap();
+            assert_bytes_eq(
+                &tag_data,
+                tag_map.get(b"object"),
+                vec![object.as_bytes()],
+                "mergetag object",
+            );
+            assert_bytes_eq(
+                &tag_data,
+                tag_map.get(b"tag"),
+                vec![tag.as_bytes()],
+                "mergetag tag",
+            );
+        }
+    }
+
+    #[test]
+    fn test_interleaved_keys_keep_their_order() {
+        let raw = b"tree 29ff16c9c14e2652b22f8b78bb08a5a07930c147\n\
+            parent 206941306e8a8af65b66eaaaea388a7ae24d49a0\n\
+            author A <a@b> 0 +0000\n\
+            parent 79002e18acb2273c552abeba01bd61302d3be31c\n\
+            x-extra one\n\
+            committer A <a@b> 0 +0000\n\
+            x-extra two\n\
+            \n\
+            message\n"
+            .to_vec();
+        let (data, map) = kvlm_parse(raw.clone()).unwrap();
+        assert_eq!(kvlm_serialize(&data, &map), raw);
+        assert_bytes_eq(
+            &data,
+            map.get(b"parent"),
+            vec![
+                b"206941306e8a8af65b66eaaaea388a7ae24d49a0".as_slice(),
+                b"79002e18acb2273c552abeba01bd61302d3be31c".as_slice(),
+            ],
+            "parent",
+        );
+    }
+
+    fn readable_map(data: &[u8], map: &Kvlm) -> HashMap<String, Vec<String>> {
+        let mut readable = HashMap::<String, Vec<String>>::new();
+        for (k, v) in map.0.iter() {
+            readable
+                .entry(from_utf8(k).unwrap().to_string())
+                .or_default()
+                .push(from_utf8(&data[v.start..v.end]).unwrap().to_string());
+        }
+        readable
+    }
+
+    fn assert_bytes_eq<'a>(
+        raw: &[u8],
+        actual: impl Iterator<Item = &'a Range<usize>>,
         expected: Vec<&[u8]>,
         msg: &str,
     ) {
+        let actual = actual.collect::<Vec<_>>();
         assert!(
-            actual.is_some(),
+            !actual.is_empty(),
             "{}: {}",
             msg,
             "value does not exist in map"
         );
         actual
-            .unwrap()
-            .iter()
+            .into_iter()
             .zip(expected)
             .for_each(|(actual, expected)| {
                 assert_eq!(
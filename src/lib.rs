@@ -0,0 +1,44 @@
+//! Read and write git repositories: loose objects, packs and their indexes,
+//! refs, the index file, and cloning and fetching over http.
+
+mod bitmap;
+pub mod blame;
+pub mod clone;
+mod config;
+pub mod diff;
+pub mod error;
+#[cfg(feature = "smart-http")]
+pub mod fetch;
+pub mod foreachref;
+mod gitattributes;
+mod gitignore;
+pub mod gitobject;
+pub mod gpg;
+mod hashingreader;
+mod http;
+pub mod index;
+mod kvlm;
+pub mod logiterator;
+pub mod objectstore;
+pub mod pack;
+pub mod packindex;
+// delimiters are only used by protocol v2, and nothing but fetch reads packets yet
+#[allow(dead_code)]
+mod pktline;
+pub mod reflog;
+pub mod repository;
+pub mod status;
+#[cfg(test)]
+mod testutil;
+mod util;
+
+pub use error::WyagError;
+pub use gitobject::blob::BlobObject;
+pub use gitobject::commit::CommitObject;
+pub use gitobject::identity::Identity;
+pub use gitobject::tag::TagObject;
+pub use gitobject::tree::TreeObject;
+pub use gitobject::{GitObject, ObjectType};
+pub use pack::{BinaryObject, Pack};
+pub use packindex::PackIndex;
+pub use repository::Repository;
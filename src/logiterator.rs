@@ -1,15 +1,16 @@
 use crate::gitobject::commit::CommitObject;
-use crate::pack::BinaryObject;
-use crate::repository::Repository;
-use anyhow::{Context, Result, ensure};
+use crate::gitobject::identity::Identity;
+use crate::objectstore::{ObjectStore, read_commit};
+use anyhow::{Context, Result};
 use hex::ToHex;
-use log::error;
 use std::cmp::Ordering;
 use std::cmp::Ordering::Equal;
 use std::collections::{BinaryHeap, HashMap, HashSet};
 use std::rc::Rc;
 
-struct HeapItem(u32, [u8; 20]);
+/// A commit in a history walk, ordered by the walk's date so the most
+/// recent commit is popped from a heap first.
+pub(crate) struct HeapItem(pub(crate) i64, pub(crate) [u8; 20]);
 
 impl Eq for HeapItem {}
 
@@ -31,55 +32,196 @@ impl Ord for HeapItem {
     }
 }
 
+pub struct LogEntry {
+    pub sha: [u8; 20],
+    pub author: Option<Identity>,
+    pub committer: Option<Identity>,
+    pub message: String,
+    pub parents: Vec<[u8; 20]>,
+}
+
+/// Which date a history walk shows the most recent commit by, when more than
+/// one commit could come next.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum DateOrder {
+    #[default]
+    Committer,
+    /// Keeps rebased or cherry-picked commits where they were first written.
+    Author,
+}
+
+impl DateOrder {
+    fn timestamp(&self, commit: &CommitObject) -> i64 {
+        match self {
+            DateOrder::Committer => commit.committer_timestamp(),
+            DateOrder::Author => commit.author_timestamp(),
+        }
+    }
+}
+
 pub struct LogIterator<'a> {
-    repository: &'a Repository,
+    store: &'a dyn ObjectStore,
+    order: DateOrder,
     current: BinaryHeap<HeapItem>,
     seen: HashSet<[u8; 20]>,
-    cache: HashMap<[u8; 20], Rc<CommitObject>>,
+    shallow: HashSet<[u8; 20]>,
+    cache: CommitCache<'a>,
+    /// For a topological walk, the commits still to show, worked out up
+    /// front and held last first.
+    topo: Option<Vec<[u8; 20]>>,
 }
 
 impl LogIterator<'_> {
     fn read_commit(&mut self, sha1: [u8; 20]) -> Result<Rc<CommitObject>> {
-        if let Some(cached) = self.cache.get(&sha1) {
+        self.cache.read(sha1)
+    }
+
+    /// The parents the walk follows from `sha1`.
+    fn parents(&self, sha1: [u8; 20], commit: &CommitObject) -> Vec<[u8; 20]> {
+        // the parents of a shallow commit were never fetched
+        if self.shallow.contains(&sha1) {
+            Vec::new()
+        } else {
+            self.store.parents(sha1, commit)
+        }
+    }
+
+    fn entry(&mut self, sha1: [u8; 20]) -> Result<LogEntry> {
+        let commit = self.read_commit(sha1)?;
+        Ok(LogEntry {
+            sha: sha1,
+            author: commit.author(),
+            committer: commit.committer(),
+            message: commit.message().unwrap_or_default(),
+            parents: self.parents(sha1, &commit),
+        })
+    }
+}
+
+/// Reads commits for history walks, keeping every commit it has parsed.
+pub(crate) struct CommitCache<'a> {
+    store: &'a dyn ObjectStore,
+    commits: HashMap<[u8; 20], Rc<CommitObject>>,
+}
+
+impl<'a> CommitCache<'a> {
+    pub(crate) fn new(store: &'a dyn ObjectStore) -> Self {
+        Self {
+            store,
+            commits: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn read(&mut self, sha1: [u8; 20]) -> Result<Rc<CommitObject>> {
+        if let Some(cached) = self.commits.get(&sha1) {
             return Ok(cached.clone());
         }
 
-        let mut data = Vec::new();
-        let object_type = self
-            .repository
-            .read_object_data(sha1, &mut data)
+        let commit = read_commit(self.store, sha1)
             .with_context(|| format!("iterating log {}", sha1.encode_hex::<String>()))?;
-        ensure!(
-            object_type == BinaryObject::Commit,
-            "expected commit, received {}",
-            object_type.name()
-        );
-        let rc = Rc::new(CommitObject::from(data)?);
-        self.cache.insert(sha1, rc.clone());
+        let rc = Rc::new(commit);
+        self.commits.insert(sha1, rc.clone());
         Ok(rc)
     }
 }
 
 impl<'a> LogIterator<'a> {
-    pub fn new(repository: &'a Repository, sha1: [u8; 20]) -> Result<Self> {
+    pub fn new(store: &'a dyn ObjectStore, sha1: [u8; 20]) -> Result<Self> {
+        Self::from_tips(store, &[sha1])
+    }
+
+    /// Walk the history of every commit in `tips` at once, each commit
+    /// appearing once however many tips reach it.
+    pub fn from_tips(store: &'a dyn ObjectStore, tips: &[[u8; 20]]) -> Result<Self> {
+        Self::by_date(store, tips, DateOrder::Committer)
+    }
+
+    /// Walk the history of `tips`, showing the most recent commit by `order`
+    /// whenever there is a choice.
+    pub fn by_date(
+        store: &'a dyn ObjectStore,
+        tips: &[[u8; 20]],
+        order: DateOrder,
+    ) -> Result<Self> {
         let mut res = Self {
-            repository,
+            store,
+            order,
             current: BinaryHeap::new(),
             seen: HashSet::new(),
-            cache: HashMap::new(),
+            shallow: store.shallow()?,
+            cache: CommitCache::new(store),
+            topo: None,
         };
 
-        let commit = res.read_commit(sha1)?;
-        res.current
-            .push(HeapItem(commit.committer_timestamp(), sha1));
+        for &sha1 in tips {
+            let commit = res.read_commit(sha1)?;
+            res.current.push(HeapItem(order.timestamp(&commit), sha1));
+        }
+        Ok(res)
+    }
+
+    /// Walk the history of `tips` so that no commit comes before all of its
+    /// children, however skewed their clocks. Like `git log --topo-order`,
+    /// once a merge is shown the history of its last parent comes first.
+    pub fn topo_order(store: &'a dyn ObjectStore, tips: &[[u8; 20]]) -> Result<Self> {
+        let mut res = Self::from_tips(store, tips)?;
+
+        // count each commit's children within the history being walked
+        let mut children = HashMap::new();
+        let mut pending = tips.to_vec();
+        while let Some(sha1) = pending.pop() {
+            if !res.seen.insert(sha1) {
+                continue;
+            }
+            children.entry(sha1).or_insert(0);
+            let commit = res.read_commit(sha1)?;
+            for parent in res.parents(sha1, &commit) {
+                // as in a date ordered walk, parents that cannot be read are left out
+                if res.read_commit(parent).is_err() {
+                    continue;
+                }
+                *children.entry(parent).or_insert(0) += 1;
+                pending.push(parent);
+            }
+        }
+
+        // Kahn's algorithm, starting from the newest tips nobody else reaches
+        let mut ready = Vec::new();
+        while let Some(HeapItem(_, sha1)) = res.current.pop() {
+            if children[&sha1] == 0 && !ready.contains(&sha1) {
+                ready.push(sha1);
+            }
+        }
+        ready.reverse();
+        let mut order = Vec::with_capacity(children.len());
+        while let Some(sha1) = ready.pop() {
+            order.push(sha1);
+            let commit = res.read_commit(sha1)?;
+            for parent in res.parents(sha1, &commit) {
+                let Some(count) = children.get_mut(&parent) else {
+                    continue;
+                };
+                *count -= 1;
+                if *count == 0 {
+                    ready.push(parent);
+                }
+            }
+        }
+        order.reverse();
+        res.topo = Some(order);
         Ok(res)
     }
 }
 
 impl Iterator for LogIterator<'_> {
-    type Item = Result<String>;
+    type Item = Result<LogEntry>;
 
     fn next(&mut self) -> Option<Self::Item> {
+        if let Some(topo) = &mut self.topo {
+            let sha1 = topo.pop()?;
+            return Some(self.entry(sha1));
+        }
+
         let mut current;
         loop {
             HeapItem(_, current) = self.current.pop()?;
@@ -88,40 +230,174 @@ impl Iterator for LogIterator<'_> {
             }
         }
 
-        let res = {
-            let commit = match self.read_commit(current) {
-                Ok(data) => data,
-                Err(e) => return Some(Err(e)),
-            };
-
-            let line = format!(
-                "{} {}: {}",
-                current.encode_hex::<String>(),
-                commit
-                    .author()
-                    .first()
-                    .unwrap_or(&"<<no author>>".to_string()),
-                commit.message().unwrap_or("".to_string())
-            )
-            .replace("\n", " ");
-
-            if commit.parents().len() > 1 {
-                error!(
-                    "commit with many parents! {} {}",
-                    current.encode_hex::<String>(),
-                    commit.parents().len()
-                );
+        let entry = match self.entry(current) {
+            Ok(entry) => entry,
+            Err(e) => return Some(Err(e)),
+        };
+        for &next_sha1 in entry.parents.iter() {
+            // merges can reach the same ancestor through several parents
+            if self.seen.contains(&next_sha1) {
+                continue;
             }
-
-            for next_sha1 in commit.parents() {
-                if let Ok(next_commit) = self.read_commit(next_sha1) {
-                    self.current
-                        .push(HeapItem(next_commit.committer_timestamp(), next_sha1));
-                }
+            if let Ok(next_commit) = self.read_commit(next_sha1) {
+                self.current
+                    .push(HeapItem(self.order.timestamp(&next_commit), next_sha1));
             }
+        }
+        Some(Ok(entry))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::logiterator::{DateOrder, LogIterator};
+    use crate::objectstore::MemoryStore;
+    use crate::repository::Repository;
+    use crate::testutil::{temp_repo, write_commit, write_history};
+    use hex::ToHex;
+
+    #[test]
+    fn topo_order_with_skewed_clocks() {
+        let store = MemoryStore::new();
+        let commit = |name: &str, parents: &[[u8; 20]], time: i64| {
+            write_commit(&store, parents, time, time, name)
+        };
+
+        // the side branch was committed on a machine whose clock was behind
+        let x = commit("x", &[], 50);
+        let b = commit("b", &[x], 1);
+        let b2 = commit("b2", &[b], 2);
+        let a = commit("a", &[x], 100);
+        let a2 = commit("a2", &[a], 110);
+        let m = commit("m", &[a2, b2], 200);
 
-            line
+        let walk = |log: LogIterator| {
+            log.map(|entry| entry.unwrap().message.trim().to_string())
+                .collect::<Vec<_>>()
         };
-        Some(Ok(res))
+        assert_eq!(
+            walk(LogIterator::new(&store, m).unwrap()),
+            ["m", "a2", "a", "x", "b2", "b"]
+        );
+        assert_eq!(
+            walk(LogIterator::topo_order(&store, &[m]).unwrap()),
+            ["m", "b2", "b", "a2", "a", "x"]
+        );
+        assert_eq!(
+            walk(LogIterator::topo_order(&store, &[a2, b2]).unwrap()),
+            ["a2", "a", "b2", "b", "x"]
+        );
+    }
+
+    #[test]
+    fn author_date_order() {
+        let store = MemoryStore::new();
+        let commit = |name: &str, parent: Option<[u8; 20]>, authored: i64, committed: i64| {
+            write_commit(&store, parent.as_slice(), authored, committed, name)
+        };
+
+        // written before `main` but rebased onto the base after it
+        let base = commit("base", None, 1, 1);
+        let main = commit("main", Some(base), 10, 10);
+        let rebased = commit("rebased", Some(base), 5, 20);
+
+        let walk = |order| {
+            LogIterator::by_date(&store, &[main, rebased], order)
+                .unwrap()
+                .map(|entry| entry.unwrap().message.trim().to_string())
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(walk(DateOrder::Committer), ["rebased", "main", "base"]);
+        assert_eq!(walk(DateOrder::Author), ["main", "rebased", "base"]);
+
+        let entry = LogIterator::new(&store, rebased)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(entry.author.unwrap().timestamp, 5);
+        assert_eq!(entry.committer.unwrap().timestamp, 20);
+    }
+
+    #[test]
+    fn log_stops_at_shallow_commits() {
+        let (dir, repo) = temp_repo();
+        let history = write_history(&repo, 3);
+
+        // cut the root off as `clone --depth 2` would
+        let root = history[0].encode_hex::<String>();
+        std::fs::remove_file(
+            dir.path()
+                .join(".git/objects")
+                .join(&root[..2])
+                .join(&root[2..]),
+        )
+        .unwrap();
+        std::fs::write(
+            dir.path().join(".git/shallow"),
+            format!("{}\n", history[1].encode_hex::<String>()),
+        )
+        .unwrap();
+
+        let log = repo
+            .log_iter(history[2])
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            log.iter().map(|e| e.sha).collect::<Vec<_>>(),
+            vec![history[2], history[1]]
+        );
+        assert!(log[1].parents.is_empty());
+        assert_eq!(repo.reachable_count(&[history[2]]).unwrap(), 2);
+
+        let err = repo
+            .read_object_data(history[0], &mut Vec::new())
+            .unwrap_err();
+        assert!(err.to_string().contains("shallow boundary"));
+    }
+
+    #[test]
+    fn grafts_and_replacements_change_history() {
+        let (dir, repo) = temp_repo();
+        let commit = |parent: Option<[u8; 20]>, message: &str| {
+            write_commit(&repo, parent.as_slice(), 0, 0, message)
+        };
+        let a = commit(None, "a");
+        let b = commit(Some(a), "b");
+        let c = commit(Some(b), "c");
+        let other = commit(None, "other");
+        let replacement = commit(Some(a), "replaced b");
+
+        // graft the other root in place of a, and replace b
+        std::fs::create_dir_all(dir.path().join(".git/info")).unwrap();
+        std::fs::write(
+            dir.path().join(".git/info/grafts"),
+            format!(
+                "# comment\n{} {}\n",
+                b.encode_hex::<String>(),
+                other.encode_hex::<String>()
+            ),
+        )
+        .unwrap();
+        repo.write_ref(
+            &format!("refs/replace/{}", b.encode_hex::<String>()),
+            &replacement.encode_hex::<String>(),
+        )
+        .unwrap();
+
+        let repo = Repository::new(dir.path(), false).unwrap();
+        let log = repo
+            .log_iter(c)
+            .unwrap()
+            .collect::<anyhow::Result<Vec<_>>>()
+            .unwrap();
+        assert_eq!(
+            log.iter().map(|e| e.sha).collect::<Vec<_>>(),
+            vec![c, b, other]
+        );
+        assert_eq!(log[1].message, "replaced b\n");
+        assert_eq!(log[1].parents, vec![other]);
+        assert_eq!(repo.reachable_count(&[c]).unwrap(), 3);
     }
 }
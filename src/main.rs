@@ -1,81 +1,919 @@
-use crate::gitobject::GitObject;
 use anyhow::Context;
 use clap::Parser;
-use cli::{Cli, CommandObjectType, Commands};
+use cli::{Cli, CommandLogFormat, Commands, Output};
+use flate2::Compression;
 use hex::ToHex;
 use logger::SimpleLogger;
-use repository::Repository;
 use std::{
-    io::Write,
+    cell::Cell,
+    collections::BTreeMap,
+    io::{BufRead, IsTerminal, Write},
+    os::unix::ffi::OsStrExt,
     path::{Path, PathBuf},
+    process::ExitCode,
+    time::{Duration, SystemTime, UNIX_EPOCH},
 };
+use wyag::foreachref::{RefFormat, SortKey};
+use wyag::gitobject::identity::DateFormat;
+use wyag::gpg::SignatureStatus;
+use wyag::logiterator::{DateOrder, LogEntry, LogIterator};
+use wyag::repository::{DeltaBase, FsckProblem, LsTreeFormat};
+use wyag::status::Status;
+use wyag::{BinaryObject, Identity, ObjectType, Repository, WyagError};
 
 mod cli;
-mod gitobject;
-mod hashingreader;
-mod kvlm;
 mod logger;
-mod logiterator;
-mod pack;
-mod packindex;
-mod repository;
-mod util;
 
 static LOGGER: SimpleLogger = SimpleLogger;
 
-fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+/// The exit status for bad arguments, the same as for other fatal errors.
+const USAGE_EXIT_CODE: u8 = 128;
+
+fn main() -> ExitCode {
+    let cli = match Cli::try_parse() {
+        Ok(cli) => cli,
+        Err(e) => {
+            let _ = e.print();
+            // --help and --version end up here too
+            return if e.use_stderr() {
+                ExitCode::from(USAGE_EXIT_CODE)
+            } else {
+                ExitCode::SUCCESS
+            };
+        }
+    };
 
     log::set_logger(&LOGGER)
         .map(|()| log::set_max_level(cli.log_level.filter()))
         .expect("failed to set logger");
 
+    match run(cli) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code(&e))
+        }
+    }
+}
+
+/// The exit status the outermost `WyagError` behind `err` calls for, or 128
+/// when there is none.
+fn exit_code(err: &anyhow::Error) -> u8 {
+    err.downcast_ref::<WyagError>()
+        .or_else(|| err.chain().find_map(|e| e.downcast_ref::<WyagError>()))
+        .map_or(128, WyagError::exit_code)
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    let output = cli.output();
     match cli.command {
-        Commands::Init { path } => init(path),
+        Commands::Init {
+            bare,
+            initial_branch,
+            path,
+        } => init(path, bare, initial_branch),
         Commands::CatObject {
             object_type,
             name,
+            allow_type_mismatch,
+            allow_unknown_type,
+            source,
+            offset,
+            length,
+            batch,
+            batch_check,
             repository,
-        } => read_object(repository.unwrap_or(PathBuf::from(".")), object_type, name),
-        Commands::HashObject { _type, write, file } => hash_object(_type, file, write),
+        } => match (object_type, name) {
+            (Some(object_type), Some(name)) if !batch && !batch_check => read_object(
+                repository.unwrap_or(PathBuf::from(".")),
+                object_type.into(),
+                name,
+                allow_type_mismatch,
+                allow_unknown_type,
+                source,
+                (offset.is_some() || length.is_some())
+                    .then(|| (offset.unwrap_or(0), length.unwrap_or(u64::MAX))),
+            ),
+            _ => read_objects(repository.unwrap_or(PathBuf::from(".")), batch),
+        },
+        Commands::HashObject {
+            _type,
+            write,
+            compression,
+            path,
+            file,
+        } => hash_object(
+            _type.into(),
+            file,
+            path.as_deref(),
+            write,
+            Compression::new(compression),
+        ),
         Commands::LsTree {
             recurse,
+            trees_only,
+            long,
+            name_only,
             tree,
+            paths,
             repository,
-        } => ls_tree(&repository.unwrap_or(PathBuf::new()), tree, recurse),
+        } => ls_tree(
+            &repository.unwrap_or(PathBuf::new()),
+            tree,
+            &paths,
+            recurse,
+            trees_only,
+            if name_only {
+                LsTreeFormat::NameOnly
+            } else if long {
+                LsTreeFormat::Long
+            } else {
+                LsTreeFormat::Default
+            },
+            output,
+        ),
         Commands::LsPack {
             repository,
+            depth_histogram,
+            no_validate,
+            packfile,
+        } => ls_pack(
+            &repository.unwrap_or(PathBuf::new()),
+            packfile,
+            depth_histogram,
+            no_validate,
+            output,
+        ),
+        Commands::VerifyPack {
+            repository,
+            threads,
             packfile,
-        } => ls_pack(&repository.unwrap_or(PathBuf::new()), packfile),
+        } => verify_pack(&repository.unwrap_or(PathBuf::new()), packfile, threads),
+        Commands::IndexPack {
+            repository,
+            compression,
+            file,
+        } => index_pack(
+            &repository.unwrap_or(PathBuf::new()),
+            file,
+            Compression::new(compression),
+        ),
         Commands::Log {
             repository,
+            oneline,
+            format,
+            date,
+            topo_order,
+            author_date_order,
+            no_validate,
+            reference,
+        } => log(
+            repository.unwrap_or(PathBuf::new()),
             reference,
-        } => log(repository.unwrap_or(PathBuf::new()), reference),
+            if oneline {
+                CommandLogFormat::Oneline
+            } else {
+                format
+            },
+            date.into(),
+            history_order(topo_order, author_date_order),
+            no_validate,
+        ),
+        Commands::Config {
+            repository,
+            list,
+            name,
+            value,
+        } => match name {
+            Some(name) if !list => config(repository.unwrap_or(PathBuf::new()), name, value),
+            _ => config_list(repository.unwrap_or(PathBuf::new())),
+        },
+        Commands::LsFiles { stage, repository } => {
+            ls_files(&repository.unwrap_or(PathBuf::new()), stage)
+        }
+        Commands::Add {
+            force,
+            repository,
+            paths,
+        } => add(&repository.unwrap_or(PathBuf::new()), paths, force),
+        Commands::Status { repository } => status(&repository.unwrap_or(PathBuf::new()), output),
+        Commands::Clone { url, directory } => clone(url, directory, output),
+        Commands::Branch {
+            repository,
+            list: _,
+        } => branch(&repository.unwrap_or(PathBuf::new()), output),
+        Commands::ShowRef {
+            repository,
+            dereference,
+        } => show_ref(&repository.unwrap_or(PathBuf::new()), dereference, output),
+        Commands::ForEachRef {
+            repository,
+            format,
+            sort,
+            patterns,
+        } => for_each_ref(
+            &repository.unwrap_or(PathBuf::new()),
+            &format,
+            &sort,
+            &patterns,
+        ),
+        Commands::Reflog {
+            repository,
+            reference,
+        } => reflog(&repository.unwrap_or(PathBuf::new()), &reference),
+        Commands::NameRev { repository, commit } => {
+            name_rev(&repository.unwrap_or(PathBuf::new()), &commit)
+        }
+        Commands::Describe {
+            repository,
+            tags,
+            abbrev,
+            commit,
+        } => describe(&repository.unwrap_or(PathBuf::new()), &commit, tags, abbrev),
+        Commands::Blame {
+            repository,
+            commit,
+            path,
+        } => blame(&repository.unwrap_or(PathBuf::new()), &commit, &path),
+        Commands::Tag {
+            repository,
+            list,
+            lines,
+            verify,
+            names,
+        } => {
+            let repository = repository.unwrap_or(PathBuf::new());
+            if verify {
+                verify_signatures(&repository, &names, ObjectType::Tag, output)
+            } else if list || lines.is_some() || names.is_empty() {
+                tag_list(&repository, &names, lines)
+            } else {
+                anyhow::bail!("creating tags is not supported, use --list to list them")
+            }
+        }
+        Commands::VerifyCommit {
+            repository,
+            commits,
+        } => verify_signatures(
+            &repository.unwrap_or(PathBuf::new()),
+            &commits,
+            ObjectType::Commit,
+            output,
+        ),
+        Commands::VerifyTag { repository, tags } => verify_signatures(
+            &repository.unwrap_or(PathBuf::new()),
+            &tags,
+            ObjectType::Tag,
+            output,
+        ),
+        Commands::Gc {
+            repository,
+            prune,
+            compression,
+            keep_pack,
+        } => {
+            let repo = Repository::find(&repository.unwrap_or(PathBuf::new()))
+                .context("loading repository")?;
+            for pack in keep_pack.iter() {
+                repo.keep_pack(pack)?;
+            }
+            let (packed, removed) =
+                repo.gc(Duration::from_secs(prune), Compression::new(compression))?;
+            if !output.quiet {
+                println!(
+                    "packed {} objects, removed {} loose objects",
+                    packed, removed
+                );
+            }
+            Ok(())
+        }
+        Commands::Fsck { repository } => fsck(&repository.unwrap_or(PathBuf::new()), output),
+        Commands::CountObjects {
+            repository,
+            verbose,
+        } => count_objects(&repository.unwrap_or(PathBuf::new()), verbose),
+        Commands::RevList {
+            repository,
+            count,
+            objects,
+            topo_order,
+            author_date_order,
+            no_validate,
+            commits,
+        } => rev_list(
+            &repository.unwrap_or(PathBuf::new()),
+            &commits,
+            count,
+            objects,
+            history_order(topo_order, author_date_order),
+            no_validate,
+        ),
+        Commands::Prune {
+            repository,
+            dry_run,
+            expire,
+        } => prune(
+            &repository.unwrap_or(PathBuf::new()),
+            Duration::from_secs(expire),
+            dry_run,
+        ),
+        #[cfg(feature = "smart-http")]
+        Commands::Fetch { repository, remote } => {
+            let repo = Repository::find(&repository.unwrap_or(PathBuf::new()))
+                .context("loading repository")?;
+            let progress = |line: &str| eprint!("remote: {}", line);
+            let progress: wyag::fetch::RemoteProgress =
+                if output.quiet { None } else { Some(&progress) };
+            let updates = wyag::fetch::fetch(&repo, &remote, progress)
+                .with_context(|| format!("fetching from {}", remote))?;
+            if !output.quiet {
+                for update in updates {
+                    eprintln!("{}", update);
+                }
+            }
+            Ok(())
+        }
     }
 }
 
-fn ls_pack(path: &Path, packfile: String) -> anyhow::Result<()> {
-    let repository = Repository::find(path)
+fn clone(url: String, directory: Option<PathBuf>, output: Output) -> anyhow::Result<()> {
+    let directory = match directory {
+        Some(directory) => directory,
+        None => {
+            let name = url
+                .trim_end_matches('/')
+                .rsplit('/')
+                .next()
+                .unwrap_or_default();
+            let name = name.strip_suffix(".git").unwrap_or(name);
+            anyhow::ensure!(
+                !name.is_empty(),
+                "cannot guess a directory name from {}",
+                url
+            );
+            PathBuf::from(name)
+        }
+    };
+    if !output.quiet {
+        eprintln!("Cloning into '{}'...", directory.to_string_lossy());
+    }
+    wyag::clone::clone(&url, &directory).with_context(|| format!("cloning {}", url))?;
+    Ok(())
+}
+
+/// List branches, or with `--porcelain` print `<*| > <sha> <name>` for each,
+/// like `git branch --format='%(HEAD) %(objectname) %(refname:short)'`.
+fn branch(path: &Path, output: Output) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let current = repo.symbolic_ref("HEAD")?;
+    if current.is_none()
+        && !output.porcelain
+        && let Some(head) = repo.head()?
+    {
+        println!("* (HEAD detached at {})", &head.encode_hex::<String>()[..7]);
+    }
+    for (name, sha1) in repo.branches()? {
+        let is_current = current
+            .as_deref()
+            .and_then(|c| c.strip_prefix("refs/heads/"))
+            == Some(name.as_str());
+        let marker = if is_current { '*' } else { ' ' };
+        if output.porcelain {
+            print!(
+                "{} {} {}{}",
+                marker,
+                sha1.encode_hex::<String>(),
+                name,
+                output.terminator()
+            );
+        } else {
+            println!("{} {}", marker, name);
+        }
+    }
+    Ok(())
+}
+
+fn show_ref(path: &Path, dereference: bool, output: Output) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    for (name, sha1) in repo.refs()? {
+        print!(
+            "{} {}{}",
+            sha1.encode_hex::<String>(),
+            name,
+            output.terminator()
+        );
+        if dereference && repo.object_type(sha1)? == BinaryObject::Tag {
+            let target = repo
+                .peel_tags(sha1)
+                .with_context(|| format!("peeling {}", name))?;
+            print!(
+                "{} {}^{{}}{}",
+                target.encode_hex::<String>(),
+                name,
+                output.terminator()
+            );
+        }
+    }
+    Ok(())
+}
+
+fn reflog(path: &Path, name: &str) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let entries = repo.reflog(name)?;
+    for (n, entry) in entries.iter().rev().enumerate() {
+        println!(
+            "{} {}@{{{}}}: {}",
+            &entry.new.encode_hex::<String>()[..7],
+            name,
+            n,
+            entry.message
+        );
+    }
+    Ok(())
+}
+
+fn name_rev(path: &Path, name: &str) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let sha1 = repo
+        .find_object(name)
+        .with_context(|| format!("finding object {}", name))?;
+    let sha1 = repo.peel_to_commit(sha1)?;
+    match repo.name_rev(sha1)? {
+        Some(rev) => println!("{} {}", name, rev),
+        None => println!("{} undefined", name),
+    }
+    Ok(())
+}
+
+fn describe(path: &Path, name: &str, tags: bool, abbrev: usize) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let sha1 = repo
+        .find_object(name)
+        .with_context(|| format!("finding object {}", name))?;
+    let sha1 = repo.peel_to_commit(sha1)?;
+    let sha = sha1.encode_hex::<String>();
+    let (tag, depth) = repo
+        .describe(sha1, tags)?
+        .with_context(|| format!("No tags can describe '{}'", sha))?;
+    if depth == 0 || abbrev == 0 {
+        println!("{}", tag);
+    } else {
+        println!("{}-{}-g{}", tag, depth, &sha[..abbrev.clamp(4, 40)]);
+    }
+    Ok(())
+}
+
+fn blame(path: &Path, name: &str, file: &Path) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let sha1 = repo
+        .find_object(name)
+        .with_context(|| format!("finding object {}", name))?;
+    let sha1 = repo.peel_to_commit(sha1)?;
+    let lines = wyag::blame::blame(&repo, sha1, file)
+        .with_context(|| format!("blaming {}", file.to_string_lossy()))?;
+
+    let name_width = lines
+        .iter()
+        .map(|l| l.author.as_ref().map_or(0, |a| a.name.chars().count()))
+        .max()
+        .unwrap_or(0);
+    let number_width = lines.len().to_string().len();
+    for (n, line) in lines.iter().enumerate() {
+        let (name, date) = match &line.author {
+            Some(author) => (author.name.as_str(), author.date(DateFormat::Iso, 0)),
+            None => ("", String::new()),
+        };
+        let content = String::from_utf8_lossy(&line.content);
+        println!(
+            "{} ({:<name_width$} {} {:>number_width$}) {}",
+            &line.commit.encode_hex::<String>()[..8],
+            name,
+            date,
+            n + 1,
+            content.strip_suffix('\n').unwrap_or(&content)
+        );
+    }
+    Ok(())
+}
+
+fn tag_list(path: &Path, patterns: &[String], lines: Option<usize>) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    for (name, sha1) in repo.tags(patterns)? {
+        let Some(lines) = lines else {
+            println!("{}", name);
+            continue;
+        };
+        let message = repo
+            .tag_message(sha1)
+            .with_context(|| format!("reading tag {}", name))?;
+        let mut message = message.lines().take(lines);
+        println!("{:<15} {}", name, message.next().unwrap_or_default());
+        for line in message {
+            println!("    {}", line);
+        }
+    }
+    Ok(())
+}
+
+fn for_each_ref(
+    path: &Path,
+    format: &str,
+    sort: &[String],
+    patterns: &[String],
+) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let format = RefFormat::parse(format)?;
+    let sort = sort
+        .iter()
+        .map(|key| SortKey::parse(key))
+        .collect::<anyhow::Result<Vec<_>>>()?;
+    for line in wyag::foreachref::for_each_ref(&repo, patterns, &format, &sort)? {
+        println!("{}", line);
+    }
+    Ok(())
+}
+
+fn verify_signatures(
+    path: &Path,
+    names: &[String],
+    object_type: ObjectType,
+    output: Output,
+) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let mut all_good = true;
+    for name in names {
+        let sha1 = repo
+            .find_object(name)
+            .with_context(|| format!("finding object {}", name))?;
+        let status = repo
+            .verify_signature(sha1, object_type)
+            .with_context(|| format!("verifying {}", name))?;
+        let good = matches!(status, SignatureStatus::Good { .. });
+        all_good &= good;
+        if !good || !output.quiet {
+            eprintln!("{}: {}", name, status);
+        }
+    }
+    if !all_good {
+        return Err(WyagError::CheckFailed("signature verification failed".to_string()).into());
+    }
+    Ok(())
+}
+
+fn fsck(path: &Path, output: Output) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let mut errors = 0;
+    for problem in repo.fsck().context("checking objects")? {
+        if matches!(problem, FsckProblem::Garbage(_)) {
+            if !output.quiet {
+                eprintln!("warning: {}", problem);
+            }
+        } else {
+            eprintln!("error: {}", problem);
+            errors += 1;
+        }
+    }
+    if errors > 0 {
+        return Err(WyagError::CheckFailed(format!("{} corrupt objects or packs", errors)).into());
+    }
+    Ok(())
+}
+
+fn count_objects(path: &Path, verbose: bool) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let counts = repo.count_objects().context("counting objects")?;
+    if !verbose {
+        println!("{} objects, {} kilobytes", counts.count, counts.size / 1024);
+        return Ok(());
+    }
+    println!("count: {}", counts.count);
+    println!("size: {}", counts.size / 1024);
+    println!("in-pack: {}", counts.in_pack);
+    println!("packs: {}", counts.packs);
+    println!("size-pack: {}", counts.size_pack / 1024);
+    println!("prune-packable: {}", counts.prune_packable);
+    println!("garbage: {}", counts.garbage);
+    println!("size-garbage: {}", counts.size_garbage / 1024);
+    Ok(())
+}
+
+fn prune(path: &Path, expire: Duration, dry_run: bool) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let pruned = repo.prune(expire, dry_run)?;
+    if dry_run {
+        for sha1 in pruned {
+            let mut data = Vec::new();
+            let object_type = repo.read_object_data(sha1, &mut data)?;
+            println!("{} {}", sha1.encode_hex::<String>(), object_type.name());
+        }
+    }
+    Ok(())
+}
+
+fn status(path: &Path, output: Output) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let status = Status::new(&repo).context("reading status")?;
+    if output.porcelain || output.null_terminated {
+        print_porcelain_status(&repo, &status, output)?;
+        return Ok(());
+    }
+
+    let mut sections = Vec::new();
+    if !status.staged.is_empty() {
+        let mut section = "Changes to be committed:\n".to_string();
+        for (change, path) in status.staged.iter() {
+            section.push_str(&format!(
+                "\t{:<12}{}\n",
+                format!("{}:", change),
+                repo.quote_path(path)
+            ));
+        }
+        sections.push(section);
+    }
+    if !status.unstaged.is_empty() {
+        let mut section = "Changes not staged for commit:\n".to_string();
+        for (change, path) in status.unstaged.iter() {
+            section.push_str(&format!(
+                "\t{:<12}{}\n",
+                format!("{}:", change),
+                repo.quote_path(path)
+            ));
+        }
+        sections.push(section);
+    }
+    if !status.untracked.is_empty() {
+        let mut section = "Untracked files:\n".to_string();
+        for path in status.untracked.iter() {
+            section.push_str(&format!("\t{}\n", repo.quote_path(path)));
+        }
+        sections.push(section);
+    }
+
+    if sections.is_empty() {
+        println!("nothing to commit, working tree clean");
+    } else {
+        print!("{}", sections.join("\n"));
+    }
+    Ok(())
+}
+
+/// Print `XY <path>` for each changed path, as `git status --porcelain` does,
+/// where X is the staged change and Y the unstaged one, then `?? <path>` for
+/// each untracked path.
+fn print_porcelain_status(
+    repo: &Repository,
+    status: &Status,
+    output: Output,
+) -> std::io::Result<()> {
+    // -z passes paths through as bytes, whether they are utf8 or not, and
+    // otherwise paths with spaces are quoted too
+    let show = |path: &Path| {
+        if output.null_terminated {
+            return path.as_os_str().as_bytes().to_vec();
+        }
+        let quoted = repo.quote_path(path);
+        if !quoted.starts_with('"') && quoted.contains(' ') {
+            format!("\"{}\"", quoted).into_bytes()
+        } else {
+            quoted.into_bytes()
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    let mut changes = BTreeMap::new();
+    for (change, path) in status.staged.iter() {
+        changes.entry(path).or_insert([' ', ' '])[0] = change.code();
+    }
+    for (change, path) in status.unstaged.iter() {
+        changes.entry(path).or_insert([' ', ' '])[1] = change.code();
+    }
+    for (path, [staged, unstaged]) in changes {
+        write!(stdout, "{}{} ", staged, unstaged)?;
+        stdout.write_all(&show(path))?;
+        write!(stdout, "{}", output.terminator())?;
+    }
+    for path in status.untracked.iter() {
+        stdout.write_all(b"?? ")?;
+        stdout.write_all(&show(path))?;
+        write!(stdout, "{}", output.terminator())?;
+    }
+    Ok(())
+}
+
+fn add(path: &Path, paths: Vec<PathBuf>, force: bool) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    repo.add(&paths, force).context("adding files")
+}
+
+fn ls_files(path: &Path, stage: bool) -> anyhow::Result<()> {
+    let repo = Repository::find(path).context("loading repository")?;
+    let index = repo.read_index().context("reading index")?;
+    for entry in index.entries.iter() {
+        if stage {
+            println!(
+                "{:06o} {} {}\t{}",
+                entry.mode,
+                entry.sha1.encode_hex::<String>(),
+                entry.stage(),
+                repo.quote_path(&entry.path)
+            );
+        } else {
+            println!("{}", repo.quote_path(&entry.path));
+        }
+    }
+    Ok(())
+}
+
+fn config(repository: PathBuf, name: String, value: Option<String>) -> anyhow::Result<()> {
+    let mut repo = Repository::find(&repository)
+        .with_context(|| format!("finding repository at {}", repository.to_string_lossy()))?;
+    let (section, key) = split_config_name(&name)?;
+    match value {
+        Some(value) => repo
+            .config_set(&section, key, &value)
+            .with_context(|| format!("setting {}", name)),
+        None => {
+            let value = repo
+                .config_get(&section, key)
+                .with_context(|| format!("{} is not set", name))?;
+            println!("{}", value);
+            Ok(())
+        }
+    }
+}
+
+fn config_list(repository: PathBuf) -> anyhow::Result<()> {
+    let repo = Repository::find(&repository)
+        .with_context(|| format!("finding repository at {}", repository.to_string_lossy()))?;
+    for (section, key, value) in repo.config_list() {
+        let section = match section.split_once(' ') {
+            Some((section, subsection)) => format!("{}.{}", section, subsection.trim_matches('"')),
+            None => section,
+        };
+        println!("{}.{}={}", section, key, value);
+    }
+    Ok(())
+}
+
+/// Split a config name such as `user.name` or `remote.origin.url` into the
+/// ini section (`user`, `remote "origin"`) and key.
+fn split_config_name(name: &str) -> anyhow::Result<(String, &str)> {
+    let (section, key) = name
+        .rsplit_once('.')
+        .with_context(|| format!("key does not contain a section: {}", name))?;
+    anyhow::ensure!(
+        !section.is_empty() && !key.is_empty(),
+        "invalid key: {}",
+        name
+    );
+    let section = match section.split_once('.') {
+        Some((section, subsection)) => format!("{} \"{}\"", section, subsection),
+        None => section.to_string(),
+    };
+    Ok((section, key))
+}
+
+fn ls_pack(
+    path: &Path,
+    packfile: String,
+    depth_histogram: bool,
+    no_validate: bool,
+    output: Output,
+) -> anyhow::Result<()> {
+    let mut repository = Repository::find(path)
         .with_context(|| format!("loading repository at {}", path.to_string_lossy()))?;
+    if no_validate {
+        repository.set_check_object_content(false);
+    }
+    let progress = if output.quiet {
+        None
+    } else {
+        progress_reporter("Reading objects")
+    };
     let objects = repository
-        .read_packfile(&packfile)
+        .list_pack(&packfile, progress.as_deref())
         .with_context(|| format!("reading packfile {}", packfile))?;
-    for (object_type, data) in objects.into_iter() {
-        println!("object: {}", GitObject::new(object_type, data)?);
+    let mut depths = BTreeMap::new();
+    for object in &objects {
+        print!(
+            "{} {:<6} {} {} {}",
+            object.sha1.encode_hex::<String>(),
+            object.object_type.name(),
+            object.size,
+            object.packed_size,
+            object.offset
+        );
+        match object.delta {
+            Some((depth, DeltaBase::Ref(base) | DeltaBase::Offset(_, base))) => {
+                println!(" {} {}", depth, base.encode_hex::<String>())
+            }
+            None => println!(),
+        }
+        *depths
+            .entry(object.delta.map_or(0, |(depth, _)| depth))
+            .or_insert(0) += 1;
+    }
+    if depth_histogram {
+        for (depth, count) in depths {
+            let objects = if count == 1 { "object" } else { "objects" };
+            match depth {
+                0 => println!("non delta: {} {}", count, objects),
+                _ => println!("chain length = {}: {} {}", depth, count, objects),
+            }
+        }
     }
     Ok(())
 }
 
-fn ls_tree(path: &Path, tree: String, recurse: bool) -> anyhow::Result<()> {
+fn verify_pack(path: &Path, packfile: String, threads: usize) -> anyhow::Result<()> {
+    let repository = Repository::find(path)
+        .with_context(|| format!("loading repository at {}", path.to_string_lossy()))?;
+    let objects = repository
+        .verify_pack(&packfile, threads)
+        .context(WyagError::CheckFailed(format!(
+            "verifying packfile {}",
+            packfile
+        )))?;
+    for (sha1, object_type, size) in objects.iter() {
+        println!(
+            "{} {} {}",
+            sha1.encode_hex::<String>(),
+            object_type.name(),
+            size
+        );
+    }
+    println!("pack-{}.pack: ok", packfile);
+    Ok(())
+}
+
+fn index_pack(path: &Path, file: PathBuf, compression: Compression) -> anyhow::Result<()> {
+    let repository = Repository::find(path)
+        .with_context(|| format!("loading repository at {}", path.to_string_lossy()))?;
+    let data =
+        std::fs::read(&file).with_context(|| format!("reading pack {}", file.to_string_lossy()))?;
+    let pack_sha1 = repository
+        .index_pack(&data, compression)
+        .with_context(|| format!("indexing pack {}", file.to_string_lossy()))?;
+    println!("{}", pack_sha1.encode_hex::<String>());
+    Ok(())
+}
+
+/// Render progress to stderr, only when a user is watching stdout.
+fn progress_reporter(label: &str) -> Option<Box<dyn Fn(usize, usize) + '_>> {
+    if !std::io::stdout().is_terminal() {
+        return None;
+    }
+    let last_percent = Cell::new(None);
+    Some(Box::new(move |done, total| {
+        let percent = done * 100 / total.max(1);
+        if last_percent.replace(Some(percent)) != Some(percent) || done == total {
+            eprint!("\r{}: {}% ({}/{})", label, percent, done, total);
+        }
+        if done == total {
+            eprintln!(", done.");
+        }
+    }))
+}
+
+fn ls_tree(
+    path: &Path,
+    tree: String,
+    pathspecs: &[String],
+    recurse: bool,
+    trees_only: bool,
+    format: LsTreeFormat,
+    output: Output,
+) -> anyhow::Result<()> {
     let repo = Repository::find(path).context("loading repository")?;
-    repo.ls_tree(&tree, recurse, Path::new("."))
-        .context("reading tree")
+    let lines = repo
+        .ls_tree(
+            &tree,
+            recurse,
+            trees_only,
+            format,
+            !output.null_terminated,
+            Path::new(""),
+            pathspecs,
+        )
+        .context("reading tree")?;
+    // paths are written as bytes, which -z passes through whatever they are
+    let mut stdout = std::io::stdout().lock();
+    for line in lines {
+        stdout.write_all(&line)?;
+        write!(stdout, "{}", output.terminator())?;
+    }
+    Ok(())
 }
 
-fn hash_object(_type: CommandObjectType, file: PathBuf, write: bool) -> anyhow::Result<()> {
+fn hash_object(
+    _type: ObjectType,
+    file: PathBuf,
+    path: Option<&Path>,
+    write: bool,
+    compression: Compression,
+) -> anyhow::Result<()> {
     let repo = Repository::find(Path::new(".")).context("loading repository")?;
     let sha1 = repo
-        .object_hash(&file, _type, write)
+        .object_hash(&file, _type, path, write, compression)
         .context("hashing file")?;
     println!("{}", sha1.encode_hex::<String>());
     Ok(())
@@ -83,39 +921,294 @@ fn hash_object(_type: CommandObjectType, file: PathBuf, write: bool) -> anyhow::
 
 fn read_object(
     repository: PathBuf,
-    _object_type: CommandObjectType,
+    object_type: ObjectType,
     name: String,
+    allow_type_mismatch: bool,
+    allow_unknown_type: bool,
+    source: bool,
+    range: Option<(u64, u64)>,
 ) -> anyhow::Result<()> {
     let repo = Repository::find(&repository)
         .with_context(|| format!("loading repository at {}", repository.to_string_lossy()))?;
-    let sha1 = repo
+    let mut sha1 = repo
         .find_object(&name)
         .with_context(|| format!("finding object {}", name))?;
+    // like git, follow commits and tags to the object type asked for; when
+    // that fails the type check below reports the mismatch
+    if !allow_type_mismatch && !allow_unknown_type {
+        let peeled = match object_type {
+            ObjectType::Tree => repo.peel_to_tree(sha1).ok(),
+            ObjectType::Commit => repo.peel_to_commit(sha1).ok(),
+            ObjectType::Blob | ObjectType::Tag => None,
+        };
+        sha1 = peeled.unwrap_or(sha1);
+    }
     let mut data = Vec::new();
-    repo.read_object_data(sha1, &mut data)
-        .with_context(|| format!("reading object {}", sha1.encode_hex::<String>()))?;
+    if allow_unknown_type {
+        repo.read_raw_object(sha1, &mut data).map(|_| ())
+    } else {
+        let read = match range {
+            // only the range is held, which matters for large blobs
+            Some((offset, len)) => repo
+                .read_object_range(sha1, offset, len, &mut data)
+                .map(|(actual, _)| (actual, None)),
+            None => repo
+                .read_object_with_source(sha1, &mut data)
+                .map(|(actual, from)| (actual, Some(from))),
+        };
+        read.and_then(|(actual, from)| {
+            if source && let Some(from) = from {
+                eprintln!("{} {}", sha1.encode_hex::<String>(), from);
+            }
+            if allow_type_mismatch || actual.name() == object_type.to_string() {
+                Ok(())
+            } else {
+                Err(WyagError::WrongType {
+                    sha: sha1,
+                    expected: object_type.to_string(),
+                    actual: actual.name(),
+                })
+            }
+        })
+    }
+    .with_context(|| format!("reading object {}", sha1.encode_hex::<String>()))?;
     std::io::stdout()
         .write_all(&data)
         .context("writing serialized object to stdout")?;
     Ok(())
 }
 
-fn log(repository: PathBuf, name: String) -> anyhow::Result<()> {
+/// Print `<sha1> <type> <size>` for each object named on stdin, followed by
+/// the contents when `contents` is set.
+fn read_objects(repository: PathBuf, contents: bool) -> anyhow::Result<()> {
     let repo = Repository::find(&repository)
+        .with_context(|| format!("loading repository at {}", repository.to_string_lossy()))?;
+    let mut out = std::io::stdout().lock();
+    for line in std::io::stdin().lock().lines() {
+        let name = line.context("reading object name from stdin")?;
+        let Ok(sha1) = repo.find_object(&name) else {
+            writeln!(out, "{} missing", name)?;
+            out.flush()?;
+            continue;
+        };
+        let sha = sha1.encode_hex::<String>();
+        if contents {
+            let mut data = Vec::new();
+            match repo.read_object_data(sha1, &mut data) {
+                Ok(object_type) => {
+                    writeln!(out, "{} {} {}", sha, object_type.name(), data.len())?;
+                    out.write_all(&data)?;
+                    writeln!(out)?;
+                }
+                Err(_) => writeln!(out, "{} missing", name)?,
+            }
+        } else {
+            match repo.object_info(sha1) {
+                Ok((object_type, size)) => {
+                    writeln!(out, "{} {} {}", sha, object_type.name(), size)?
+                }
+                Err(_) => writeln!(out, "{} missing", name)?,
+            }
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+fn log(
+    repository: PathBuf,
+    name: String,
+    format: CommandLogFormat,
+    date: DateFormat,
+    order: HistoryOrder,
+    no_validate: bool,
+) -> anyhow::Result<()> {
+    let mut repo = Repository::find(&repository)
         .with_context(|| format!("finding repository at {}", repository.to_string_lossy()))?;
+    if no_validate {
+        repo.set_check_object_content(false);
+    }
     let sha1 = repo
         .find_object(&name)
         .with_context(|| format!("finding object {}", name))?;
-    for msg in repo.log_iter(sha1)? {
-        println!("{}", msg.context("reading logs")?);
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs() as i64);
+    for entry in walk_history(&repo, &[sha1], order)? {
+        print!(
+            "{}",
+            format_log_entry(&entry.context("reading logs")?, format, date, now)
+        );
     }
     Ok(())
 }
 
-fn init(path: PathBuf) -> anyhow::Result<()> {
-    let repo = Repository::new(&path, true)
-        .with_context(|| format!("finding repository at {}", path.to_string_lossy()))?;
-    repo.init().context("initialising repository")?;
-    println!("Created repository at: {}", repo.worktree.to_string_lossy());
+/// How log and rev-list order the commits they show.
+enum HistoryOrder {
+    Date(DateOrder),
+    Topological,
+}
+
+fn history_order(topo_order: bool, author_date_order: bool) -> HistoryOrder {
+    if topo_order {
+        HistoryOrder::Topological
+    } else if author_date_order {
+        HistoryOrder::Date(DateOrder::Author)
+    } else {
+        HistoryOrder::Date(DateOrder::Committer)
+    }
+}
+
+fn walk_history<'a>(
+    repo: &'a Repository,
+    tips: &[[u8; 20]],
+    order: HistoryOrder,
+) -> anyhow::Result<LogIterator<'a>> {
+    match order {
+        HistoryOrder::Date(order) => LogIterator::by_date(repo, tips, order),
+        HistoryOrder::Topological => LogIterator::topo_order(repo, tips),
+    }
+}
+
+/// Print the commits reachable from `names`, then with `objects` any tags
+/// named and the trees and blobs the commits reach, as `<sha> <path>`.
+fn rev_list(
+    path: &Path,
+    names: &[String],
+    count: bool,
+    objects: bool,
+    order: HistoryOrder,
+    no_validate: bool,
+) -> anyhow::Result<()> {
+    let mut repo = Repository::find(path).context("loading repository")?;
+    if no_validate {
+        repo.set_check_object_content(false);
+    }
+    let mut tips = Vec::new();
+    let mut tags = Vec::new();
+    for name in names {
+        let sha1 = repo
+            .find_object(name)
+            .with_context(|| format!("finding object {}", name))?;
+        let commit = repo.peel_to_commit(sha1)?;
+        if commit != sha1 {
+            tags.push((sha1, PathBuf::from(name)));
+        }
+        tips.push(commit);
+    }
+    // only commits are counted, so a pack bitmap can answer without the walk
+    if count && !objects {
+        println!(
+            "{}",
+            repo.reachable_count(&tips).context("counting commits")?
+        );
+        return Ok(());
+    }
+
+    let mut lines = Vec::new();
+    let mut commits = Vec::new();
+    for entry in walk_history(&repo, &tips, order)? {
+        let entry = entry.context("walking history")?;
+        lines.push(entry.sha.encode_hex::<String>());
+        commits.push(entry.sha);
+    }
+    if objects {
+        let listed = tags
+            .into_iter()
+            .chain(repo.commit_objects(&commits).context("listing objects")?);
+        for (sha1, path) in listed {
+            lines.push(format!(
+                "{} {}",
+                sha1.encode_hex::<String>(),
+                repo.quote_path(&path)
+            ));
+        }
+    }
+
+    if count {
+        println!("{}", lines.len());
+    } else {
+        for line in lines {
+            println!("{}", line);
+        }
+    }
+    Ok(())
+}
+
+fn format_log_entry(
+    entry: &LogEntry,
+    format: CommandLogFormat,
+    date: DateFormat,
+    now: i64,
+) -> String {
+    let sha = entry.sha.encode_hex::<String>();
+    let title = entry.message.lines().next().unwrap_or("");
+    if format == CommandLogFormat::Oneline {
+        return format!("{} {}\n", &sha[..7], title);
+    }
+
+    let mut out = format!("commit {}\n", sha);
+    if entry.parents.len() > 1 {
+        let parents = entry
+            .parents
+            .iter()
+            .map(|p| p.encode_hex::<String>()[..7].to_string())
+            .collect::<Vec<_>>();
+        out.push_str(&format!("Merge: {}\n", parents.join(" ")));
+    }
+    let name = |identity: &Option<Identity>, missing: &str| match identity {
+        Some(identity) => format!("{} <{}>", identity.name, identity.email),
+        None => missing.to_string(),
+    };
+    out.push_str(&format!(
+        "Author: {}\n",
+        name(&entry.author, "<<no author>>")
+    ));
+    match format {
+        CommandLogFormat::Medium => {
+            if let Some(author) = &entry.author {
+                out.push_str(&format!("Date:   {}\n", author.date(date, now)))
+            }
+        }
+        CommandLogFormat::Full => out.push_str(&format!(
+            "Commit: {}\n",
+            name(&entry.committer, "<<no committer>>")
+        )),
+        _ => {}
+    }
+    out.push('\n');
+
+    if format == CommandLogFormat::Short {
+        out.push_str(&format!("    {}\n", title));
+    } else {
+        for line in entry.message.trim_end().lines() {
+            out.push_str(&format!("    {}\n", line));
+        }
+    }
+    out.push('\n');
+    out
+}
+
+fn init(path: PathBuf, bare: bool, initial_branch: Option<String>) -> anyhow::Result<()> {
+    let repo = if bare {
+        Repository::new_bare(&path, true)
+    } else {
+        Repository::new(&path, true)
+    }
+    .with_context(|| format!("finding repository at {}", path.to_string_lossy()))?;
+    let initial_branch = initial_branch
+        .or_else(|| repo.config_get("init", "defaultbranch"))
+        .unwrap_or_else(|| "master".to_string());
+    let reinit = repo
+        .init_with_branch(&initial_branch)
+        .context("initialising repository")?;
+    if reinit {
+        println!(
+            "Reinitialized existing repository at: {}",
+            repo.worktree.to_string_lossy()
+        );
+    } else {
+        println!("Created repository at: {}", repo.worktree.to_string_lossy());
+    }
     Ok(())
 }
@@ -0,0 +1,241 @@
+use crate::gitobject::GitObject;
+use crate::gitobject::commit::CommitObject;
+use crate::pack::{BinaryObject, Pack};
+use crate::repository::Repository;
+use crate::util::hash_raw_object;
+use anyhow::{Context, Result, bail, ensure};
+use flate2::Compression;
+use hex::ToHex;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::{BufReader, Cursor};
+
+/// An object's type, with any delta resolved, and its data.
+pub type StoredObject = (BinaryObject, Vec<u8>);
+
+/// Somewhere objects are kept, addressed by sha. History walks go through
+/// this rather than the filesystem, so they can run over other stores.
+pub trait ObjectStore {
+    fn read(&self, sha1: [u8; 20]) -> Result<StoredObject>;
+
+    /// Store an object, returning its sha.
+    fn write(&self, object: &GitObject) -> Result<[u8; 20]>;
+
+    fn contains(&self, sha1: [u8; 20]) -> bool;
+
+    /// The parents a history walk should follow from `commit`, which a
+    /// repository may override with grafts.
+    fn parents(&self, _sha1: [u8; 20], commit: &CommitObject) -> Vec<[u8; 20]> {
+        commit.parents()
+    }
+
+    /// The commits whose parents the store was never given.
+    fn shallow(&self) -> Result<HashSet<[u8; 20]>> {
+        Ok(HashSet::new())
+    }
+}
+
+impl ObjectStore for Repository {
+    fn read(&self, sha1: [u8; 20]) -> Result<StoredObject> {
+        let mut data = Vec::new();
+        let object_type = self.read_object_data(sha1, &mut data)?;
+        Ok((object_type, data))
+    }
+
+    fn write(&self, object: &GitObject) -> Result<[u8; 20]> {
+        self.write_object(object, true, Compression::default())
+    }
+
+    fn contains(&self, sha1: [u8; 20]) -> bool {
+        self.has_object(sha1)
+    }
+
+    fn parents(&self, sha1: [u8; 20], commit: &CommitObject) -> Vec<[u8; 20]> {
+        Repository::parents(self, sha1, commit)
+    }
+
+    fn shallow(&self) -> Result<HashSet<[u8; 20]>> {
+        Repository::shallow(self)
+    }
+}
+
+/// Objects and refs held in memory, for tests and for work that never needs
+/// to reach the disk.
+#[derive(Default)]
+pub struct MemoryStore {
+    objects: RefCell<HashMap<[u8; 20], StoredObject>>,
+    refs: RefCell<BTreeMap<String, [u8; 20]>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// A store holding every object in the pack `data`, which must not be thin.
+    pub fn from_pack(data: &[u8]) -> Result<Self> {
+        let pack = Pack::new(BufReader::new(Cursor::new(data))).context("opening pack")?;
+        let objects = pack
+            .resolve_all()
+            .context("reading pack")?
+            .into_iter()
+            .map(|(sha1, object_type, data)| (sha1, (object_type, data)))
+            .collect();
+        Ok(Self {
+            objects: RefCell::new(objects),
+            refs: RefCell::default(),
+        })
+    }
+
+    /// Point the ref `name`, such as `refs/heads/main`, at `sha1`.
+    pub fn set_ref(&self, name: &str, sha1: [u8; 20]) {
+        self.refs.borrow_mut().insert(name.to_string(), sha1);
+    }
+
+    pub fn get_ref(&self, name: &str) -> Option<[u8; 20]> {
+        self.refs.borrow().get(name).copied()
+    }
+
+    /// Every ref, sorted by name.
+    pub fn refs(&self) -> Vec<(String, [u8; 20])> {
+        self.refs
+            .borrow()
+            .iter()
+            .map(|(name, sha1)| (name.clone(), *sha1))
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.objects.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.objects.borrow().is_empty()
+    }
+}
+
+impl ObjectStore for MemoryStore {
+    fn read(&self, sha1: [u8; 20]) -> Result<StoredObject> {
+        self.objects
+            .borrow()
+            .get(&sha1)
+            .cloned()
+            .with_context(|| format!("object {} not found", sha1.encode_hex::<String>()))
+    }
+
+    fn write(&self, object: &GitObject) -> Result<[u8; 20]> {
+        let object_type = object.to_binary_object();
+        ensure!(!object_type.is_delta(), "cannot store a delta on its own");
+        let data = object.try_serialize()?;
+        let sha1 = hash_raw_object(object.name(), &data);
+        self.objects.borrow_mut().insert(sha1, (object_type, data));
+        Ok(sha1)
+    }
+
+    fn contains(&self, sha1: [u8; 20]) -> bool {
+        self.objects.borrow().contains_key(&sha1)
+    }
+}
+
+/// Read `sha1` from `store`, failing unless it is a commit.
+pub fn read_commit(store: &dyn ObjectStore, sha1: [u8; 20]) -> Result<CommitObject> {
+    let (object_type, data) = store.read(sha1)?;
+    if object_type != BinaryObject::Commit {
+        bail!(
+            "expected commit {}, received {}",
+            sha1.encode_hex::<String>(),
+            object_type.name()
+        );
+    }
+    CommitObject::from(data)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::gitobject::GitObject;
+    use crate::gitobject::blob::BlobObject;
+    use crate::logiterator::LogIterator;
+    use crate::objectstore::{MemoryStore, ObjectStore, read_commit};
+    use crate::pack::BinaryObject;
+    use crate::testutil::{temp_repo, write_history};
+    use flate2::Compression;
+    use hex::ToHex;
+
+    #[test]
+    fn memory_store() {
+        let store = MemoryStore::new();
+        assert!(store.is_empty());
+
+        let blob = GitObject::Blob(BlobObject::from(b"in memory".to_vec()));
+        let sha1 = store.write(&blob).unwrap();
+        assert_eq!(
+            sha1.encode_hex::<String>(),
+            "43c6cb029b9f8a95d6c683d7966fe8789e082511"
+        );
+        assert!(store.contains(sha1));
+        assert_eq!(
+            store.read(sha1).unwrap(),
+            (BinaryObject::Blob, b"in memory".to_vec())
+        );
+        assert!(store.read([0; 20]).is_err());
+        assert!(read_commit(&store, sha1).is_err());
+
+        let history = write_history(&store, 3);
+        assert_eq!(store.len(), 5);
+        let log = LogIterator::new(&store, history[2])
+            .unwrap()
+            .map(|entry| entry.unwrap().sha)
+            .collect::<Vec<_>>();
+        assert_eq!(log, history.iter().rev().copied().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn memory_store_from_pack() {
+        let name = "pack-2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        let pack = std::fs::read(format!("test/pack/{}.pack", name)).unwrap();
+        let store = MemoryStore::from_pack(&pack).unwrap();
+        assert_eq!(store.len(), 36);
+
+        // the same objects a repository reads from the pack and its index
+        let (_dir, repo) = temp_repo();
+        repo.index_pack(&pack, Compression::default()).unwrap();
+        let objects = repo
+            .verify_pack("2c61a5470c8319d5ff6cf64199aa714f18414c26", 1)
+            .unwrap();
+        for (sha1, object_type, _) in objects {
+            let (stored_type, data) = store.read(sha1).unwrap();
+            assert_eq!(stored_type, object_type);
+            assert_eq!(data, ObjectStore::read(&repo, sha1).unwrap().1);
+        }
+
+        assert!(store.refs().is_empty());
+        let commit = store
+            .objects
+            .borrow()
+            .iter()
+            .find(|(_, (object_type, _))| *object_type == BinaryObject::Commit)
+            .map(|(sha1, _)| *sha1)
+            .unwrap();
+        store.set_ref("refs/heads/main", commit);
+        assert_eq!(store.refs(), vec![("refs/heads/main".to_string(), commit)]);
+        assert_eq!(store.get_ref("refs/heads/other"), None);
+        let start = store.get_ref("refs/heads/main").unwrap();
+        assert!(LogIterator::new(&store, start).unwrap().all(|e| e.is_ok()));
+    }
+
+    #[test]
+    fn stores_agree() {
+        let (_dir, repo) = temp_repo();
+        let memory = MemoryStore::new();
+
+        let history = write_history(&repo, 2);
+        assert_eq!(write_history(&memory, 2), history);
+        for sha1 in history {
+            assert!(ObjectStore::contains(&repo, sha1));
+            assert_eq!(
+                ObjectStore::read(&repo, sha1).unwrap(),
+                memory.read(sha1).unwrap()
+            );
+        }
+    }
+}
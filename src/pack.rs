@@ -1,28 +1,59 @@
 extern crate sha1;
 
-use crate::util::parse_offset_delta;
-use anyhow::{Context, Result};
-use flate2::bufread::ZlibDecoder;
+use crate::gitobject::delta::DeltaObject;
+use crate::hashingreader::HashingReader;
+use crate::packindex::{PackIndex, PackIndexItem};
+use crate::util::{delta_base_offset, get_sha1, parse_offset_delta, validate_sha1};
+use anyhow::{Context, Result, bail, ensure};
+use flate2::bufread::{ZlibDecoder, ZlibEncoder};
+use flate2::{Compression, Decompress, FlushDecompress, Status};
+use hex::ToHex;
 use log::debug;
+use sha1::{Digest, Sha1};
 use std::cell::RefCell;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::File;
 use std::io;
-use std::io::{BufReader, Read};
+use std::io::{BufRead, BufReader, Read, Write};
 use std::io::{Seek, SeekFrom};
+use std::os::unix::fs::FileExt;
+use std::thread;
+
+/// Optional callback told how many of the total items have been processed.
+pub type Progress<'a> = Option<&'a dyn Fn(usize, usize)>;
+
+/// An object's sha1, its resolved type and its undeltified data.
+pub type ResolvedObject = ([u8; 20], BinaryObject, Vec<u8>);
+
+/// An entry for a pack index, with the CRC32 of the object's packed bytes.
+pub type PackIndexEntry = (PackIndexItem, u32);
+
+/// Index entries, the pack checksum and any delta bases missing from a thin pack.
+pub type ThinPackIndex = (Vec<PackIndexEntry>, [u8; 20], Vec<[u8; 20]>);
 
 pub struct Pack<T: Read + Seek> {
     reader: RefCell<BufReader<T>>,
+    inflater: RefCell<Decompress>,
+    entries: usize,
 }
 
 impl<T: Read + Seek> Pack<T> {
     pub fn new(reader: BufReader<T>) -> Result<Pack<T>> {
-        let pack = Pack {
+        let mut pack = Pack {
             reader: RefCell::new(reader),
+            inflater: RefCell::new(Decompress::new(true)),
+            entries: 0,
         };
-        pack.check_header().context("check header")?;
+        pack.entries = pack.check_header().context("check header")?;
         Ok(pack)
     }
 
-    pub fn read_all(&self) -> Result<Vec<(BinaryObject, Vec<u8>)>> {
+    /// The number of objects the pack header says it holds.
+    pub fn object_count(&self) -> usize {
+        self.entries
+    }
+
+    pub fn read_all(&self, progress: Progress) -> Result<Vec<(BinaryObject, Vec<u8>)>> {
         {
             self.reader
                 .borrow_mut()
@@ -37,19 +68,256 @@ impl<T: Read + Seek> Pack<T> {
         for n in 0..entries {
             debug!("reading entry {}", n);
             let mut data = Vec::new();
-            let object_type = read_data(&mut self.reader.borrow_mut(), &mut data)?;
+            let object_type = read_data(
+                &mut self.reader.borrow_mut(),
+                &mut self.inflater.borrow_mut(),
+                &mut data,
+            )?;
             result.push((object_type, data));
+            if let Some(progress) = progress {
+                progress(n + 1, entries);
+            }
         }
 
         Ok(result)
     }
 
-    pub fn read_object_data_at(&self, offset: u64, data: &mut Vec<u8>) -> Result<BinaryObject> {
+    /// Read the object at `offset` into `data`, which is cleared first so its allocation is
+    /// reused across calls.
+    pub fn read_object_data_into(&self, offset: u64, data: &mut Vec<u8>) -> Result<BinaryObject> {
+        let mut reader = self.reader.borrow_mut();
+        reader
+            .seek(SeekFrom::Start(offset))
+            .with_context(|| format!("reading object at offset {}", offset))?;
+        read_data(&mut reader, &mut self.inflater.borrow_mut(), data)
+    }
+
+    /// The type of the entry at `offset`, reading no further than its header and a delta's
+    /// base, so none of its data is inflated.
+    pub fn read_type_at(&self, offset: u64) -> Result<BinaryObject> {
+        let mut reader = self.reader.borrow_mut();
+        reader
+            .seek(SeekFrom::Start(offset))
+            .with_context(|| format!("reading object at offset {}", offset))?;
+        Ok(read_entry_header(&mut reader)?.0)
+    }
+
+    /// Write up to `len` bytes of the data of the object at `offset` to `out`, starting
+    /// `skip` bytes in, inflating no further than that. Returns the object's type and how
+    /// many bytes were written, or `None` for a delta, which needs its base to be read.
+    pub fn copy_object_range<W: Write>(
+        &self,
+        offset: u64,
+        skip: u64,
+        len: u64,
+        out: &mut W,
+    ) -> Result<Option<(BinaryObject, u64)>> {
         let mut reader = self.reader.borrow_mut();
         reader
             .seek(SeekFrom::Start(offset))
             .with_context(|| format!("reading object at offset {}", offset))?;
-        read_data(&mut reader, data)
+        let (object_type, size) = read_entry_header(&mut reader)?;
+        if object_type.is_delta() {
+            return Ok(None);
+        }
+        let mut data = ZlibDecoder::new(&mut *reader).take(size as u64);
+        io::copy(&mut (&mut data).take(skip), &mut io::sink()).context("inflating object")?;
+        let written = io::copy(&mut data.take(len), out).context("inflating object")?;
+        Ok(Some((object_type, written)))
+    }
+
+    /// Read every object in the pack with its deltas resolved, one after another.
+    /// Like `index_entries`, this refuses thin packs.
+    pub fn resolve_all(&self) -> Result<Vec<ResolvedObject>> {
+        let (entries, _) = self.index_entries()?;
+        let mut raw = HashMap::with_capacity(entries.len());
+        let mut offsets = HashMap::with_capacity(entries.len());
+        for (PackIndexItem(sha1, offset), _) in entries.iter() {
+            let mut data = Vec::new();
+            let object_type = self.read_object_data_into(*offset, &mut data)?;
+            raw.insert(*offset, (object_type, data));
+            offsets.insert(*sha1, *offset);
+        }
+
+        let base_offset = |offset: u64, object_type: BinaryObject| -> Result<Option<u64>> {
+            Ok(match object_type {
+                BinaryObject::OffsetDelta(delta_offset) => {
+                    Some(delta_base_offset(offset, delta_offset)?)
+                }
+                BinaryObject::RefDelta(reference) => Some(offsets[&reference]),
+                _ => None,
+            })
+        };
+        let mut bases = HashSet::new();
+        for (offset, (object_type, _)) in raw.iter() {
+            bases.extend(base_offset(*offset, *object_type)?);
+        }
+
+        let mut read = |offset: u64| raw_entry(&raw, offset);
+        let mut cache = BaseCache::new(BASE_CACHE_LIMIT);
+        entries
+            .iter()
+            .map(|(PackIndexItem(sha1, offset), _)| {
+                let (object_type, data) =
+                    resolve(*offset, &mut read, &bases, &base_offset, &mut cache)?;
+                Ok((*sha1, object_type, data))
+            })
+            .collect()
+    }
+
+    /// Scan the pack as `index-pack` does, returning each object's sha1 and offset with the
+    /// CRC32 of its packed bytes, plus the pack's own checksum once it has been verified.
+    /// Deltas must have their bases in the pack, so thin packs are refused.
+    pub fn index_entries(&self) -> Result<(Vec<PackIndexEntry>, [u8; 20])> {
+        let (entries, pack_sha1, missing) = self.index_thin_entries()?;
+        if let Some(base) = missing.first() {
+            bail!(
+                "delta base {} is not in this pack",
+                base.encode_hex::<String>()
+            );
+        }
+        Ok((entries, pack_sha1))
+    }
+
+    /// Index a pack that may be thin, with ref deltas against objects it does not
+    /// contain. Those bases are returned instead of failing, and the entries are
+    /// only complete when there are none.
+    pub fn index_thin_entries(&self) -> Result<ThinPackIndex> {
+        let pack_sha1 = self
+            .validate_checksum()
+            .context("verifying pack checksum")?;
+        {
+            self.reader
+                .borrow_mut()
+                .seek(SeekFrom::Start(0))
+                .context("read from start of pack")?;
+        }
+        let entries = self.check_header().context("check header")?;
+
+        let mut raw = HashMap::with_capacity(entries);
+        let mut crc32s = HashMap::with_capacity(entries);
+        let mut bases = HashSet::new();
+        {
+            let mut reader = self.reader.borrow_mut();
+            let mut inflater = self.inflater.borrow_mut();
+            for n in 0..entries {
+                let offset = reader.stream_position()?;
+                let mut data = Vec::new();
+                let object_type = read_data(&mut reader, &mut inflater, &mut data)
+                    .with_context(|| format!("reading entry {} at {}", n, offset))?;
+                let end = reader.stream_position()?;
+
+                let mut packed = vec![0; (end - offset) as usize];
+                reader.seek(SeekFrom::Start(offset))?;
+                reader.read_exact(&mut packed)?;
+                crc32s.insert(offset, crc32fast::hash(&packed));
+
+                if let BinaryObject::OffsetDelta(delta_offset) = object_type {
+                    bases.insert(delta_base_offset(offset, delta_offset)?);
+                }
+                raw.insert(offset, (object_type, data));
+            }
+        }
+
+        // ref deltas can only be resolved once their base's sha1 is known, so keep
+        // resolving until a round makes no progress
+        let mut offsets: HashMap<[u8; 20], u64> = HashMap::with_capacity(entries);
+        let mut pending = raw.keys().copied().collect::<Vec<_>>();
+        let mut read = |offset: u64| raw_entry(&raw, offset);
+        let mut cache = BaseCache::new(BASE_CACHE_LIMIT);
+        while !pending.is_empty() {
+            let base_offset = |offset: u64, object_type: BinaryObject| -> Result<Option<u64>> {
+                Ok(match object_type {
+                    BinaryObject::OffsetDelta(delta_offset) => {
+                        Some(delta_base_offset(offset, delta_offset)?)
+                    }
+                    BinaryObject::RefDelta(reference) => match offsets.get(&reference) {
+                        Some(offset) => Some(*offset),
+                        None => bail!(
+                            "delta base {} is not in this pack",
+                            reference.encode_hex::<String>()
+                        ),
+                    },
+                    _ => None,
+                })
+            };
+            let mut resolved = Vec::new();
+            let mut unresolved = Vec::new();
+            let mut error = None;
+            for offset in pending {
+                match resolve(offset, &mut read, &bases, &base_offset, &mut cache) {
+                    Ok((object_type, data)) => {
+                        resolved.push((get_sha1(object_type, &data), offset));
+                    }
+                    Err(e) => {
+                        error = Some(e);
+                        unresolved.push(offset);
+                    }
+                }
+            }
+            if resolved.is_empty()
+                && let Some(error) = error
+            {
+                let mut missing = unresolved
+                    .iter()
+                    .filter_map(|offset| match raw[offset].0 {
+                        BinaryObject::RefDelta(base) if !offsets.contains_key(&base) => Some(base),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>();
+                if missing.is_empty() {
+                    return Err(error);
+                }
+                missing.sort();
+                missing.dedup();
+                return Ok((Vec::new(), pack_sha1, missing));
+            }
+            offsets.extend(resolved);
+            pending = unresolved;
+        }
+
+        let items = offsets
+            .into_iter()
+            .map(|(sha1, offset)| (PackIndexItem(sha1, offset), crc32s[&offset]))
+            .collect();
+        Ok((items, pack_sha1, Vec::new()))
+    }
+
+    /// The pack's checksum, as its trailing 20 bytes record it. Nothing is
+    /// checked; see `validate_checksum`.
+    pub fn sha(&self) -> Result<[u8; 20]> {
+        let mut reader = self.reader.borrow_mut();
+        let len = reader.seek(SeekFrom::End(0))?;
+        ensure!(len >= 32, "pack is too short");
+        reader.seek(SeekFrom::End(-20))?;
+        let mut sha1 = [0; 20];
+        reader
+            .read_exact(&mut sha1)
+            .context("reading pack trailer")?;
+        Ok(sha1)
+    }
+
+    /// Hash everything before the trailer and check it against the trailer,
+    /// returning the checksum. This reads the whole pack, where reading an
+    /// object only catches corruption in that object.
+    pub fn validate_checksum(&self) -> Result<[u8; 20]> {
+        let mut reader = self.reader.borrow_mut();
+        let len = reader.seek(SeekFrom::End(0))?;
+        ensure!(len >= 32, "pack is too short");
+        reader.seek(SeekFrom::Start(0))?;
+
+        let mut hashing = HashingReader::new((&mut *reader).take(len - 20));
+        io::copy(&mut hashing, &mut io::sink())?;
+        let actual = hashing.finalize();
+        let mut expected = [0; 20];
+        reader.read_exact(&mut expected)?;
+        ensure!(
+            actual == expected,
+            "pack checksum mismatch, expected {} but calculated {}",
+            expected.encode_hex::<String>(),
+            actual.encode_hex::<String>()
+        );
+        Ok(actual)
     }
 
     fn check_header(&self) -> Result<usize> {
@@ -83,18 +351,298 @@ impl<T: Read + Seek> Pack<T> {
     }
 }
 
+impl Pack<File> {
+    /// Read and resolve every object listed in `index`, spread across `threads`
+    /// workers, returning each object's sha1, type and size in sha1 order.
+    ///
+    /// Each worker reads the pack through its own positioned reader, so they never share a
+    /// file offset. Only entry headers are read up front, to learn which entries are bases;
+    /// objects are then rebuilt one at a time, with each worker keeping a bounded cache of
+    /// the bases it has rebuilt, so memory does not grow with the pack.
+    pub(crate) fn verify_objects_parallel(
+        &self,
+        index: &PackIndex,
+        threads: usize,
+    ) -> Result<Vec<([u8; 20], BinaryObject, usize)>> {
+        let reader = self.reader.borrow();
+        let file = reader.get_ref();
+        // in pack order, so each worker meets a delta's bases shortly before it
+        let mut items = index.iter().collect::<Vec<_>>();
+        items.sort_unstable_by_key(|PackIndexItem(_, offset)| *offset);
+        let chunk_size = items.len().div_ceil(threads.max(1)).max(1);
+        let cache_limit = BASE_CACHE_LIMIT / threads.max(1);
+
+        let offsets: HashMap<[u8; 20], u64> = items
+            .iter()
+            .map(|PackIndexItem(sha1, offset)| (*sha1, *offset))
+            .collect();
+        let base_offset = |offset: u64, object_type: BinaryObject| -> Result<Option<u64>> {
+            Ok(match object_type {
+                BinaryObject::OffsetDelta(delta_offset) => {
+                    Some(delta_base_offset(offset, delta_offset)?)
+                }
+                BinaryObject::RefDelta(reference) => match offsets.get(&reference) {
+                    Some(offset) => Some(*offset),
+                    None => bail!(
+                        "delta base {} is not in this pack",
+                        reference.encode_hex::<String>()
+                    ),
+                },
+                _ => None,
+            })
+        };
+
+        let mut bases = HashSet::new();
+        {
+            let mut reader = BufReader::new(PositionedReader::new(file));
+            for PackIndexItem(_, offset) in items.iter() {
+                reader.seek(SeekFrom::Start(*offset))?;
+                let (object_type, _) = read_entry_header(&mut reader)
+                    .with_context(|| format!("reading object at {}", offset))?;
+                bases.extend(base_offset(*offset, object_type)?);
+            }
+        }
+
+        thread::scope(|scope| {
+            let workers = items
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    let (bases, base_offset) = (&bases, &base_offset);
+                    scope.spawn(move || {
+                        let mut reader = BufReader::new(PositionedReader::new(file));
+                        let mut inflater = Decompress::new(true);
+                        let mut read = |offset: u64| {
+                            reader.seek(SeekFrom::Start(offset))?;
+                            let mut data = Vec::new();
+                            let object_type = read_data(&mut reader, &mut inflater, &mut data)
+                                .with_context(|| format!("reading object at {}", offset))?;
+                            Ok((object_type, data))
+                        };
+                        let mut cache = BaseCache::new(cache_limit);
+                        chunk
+                            .iter()
+                            .map(|PackIndexItem(sha1, offset)| {
+                                let (object_type, data) =
+                                    resolve(*offset, &mut read, bases, base_offset, &mut cache)?;
+                                validate_sha1(*sha1, object_type, &data)
+                                    .with_context(|| format!("resolving object at {}", offset))?;
+                                Ok((*sha1, object_type, data.len()))
+                            })
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .map(|worker| worker.join().expect("pack resolver thread panicked"))
+                .collect::<Result<Vec<_>>>()
+        })
+        .map(|results| {
+            let mut results = results.into_iter().flatten().collect::<Vec<_>>();
+            results.sort_unstable_by_key(|(sha1, _, _)| *sha1);
+            results
+        })
+    }
+}
+
+/// A delta chain deeper than this is taken to be corrupt; git writes none deeper than 4095.
+const MAX_DELTA_DEPTH: usize = 10_000;
+
+/// The bytes of rebuilt delta bases kept while resolving a pack, git's default
+/// `core.deltaBaseCacheLimit`, shared between any workers.
+const BASE_CACHE_LIMIT: usize = 96 << 20;
+
+/// Rebuilt delta bases by offset, dropping the least recently used once they hold more
+/// than `limit` bytes.
+struct BaseCache {
+    /// Each base with the tick it was last used at.
+    entries: HashMap<u64, (BinaryObject, Vec<u8>, u64)>,
+    /// Offsets by the tick they were last used at, oldest first.
+    order: BTreeMap<u64, u64>,
+    tick: u64,
+    size: usize,
+    limit: usize,
+}
+
+impl BaseCache {
+    fn new(limit: usize) -> Self {
+        BaseCache {
+            entries: HashMap::new(),
+            order: BTreeMap::new(),
+            tick: 0,
+            size: 0,
+            limit,
+        }
+    }
+
+    fn get(&mut self, offset: u64) -> Option<(BinaryObject, Vec<u8>)> {
+        let (object_type, data, used) = self.entries.get_mut(&offset)?;
+        self.order.remove(used);
+        self.tick += 1;
+        *used = self.tick;
+        self.order.insert(self.tick, offset);
+        Some((*object_type, data.clone()))
+    }
+
+    fn insert(&mut self, offset: u64, object_type: BinaryObject, data: &[u8]) {
+        if data.len() > self.limit || self.entries.contains_key(&offset) {
+            return;
+        }
+        self.tick += 1;
+        self.size += data.len();
+        self.entries
+            .insert(offset, (object_type, data.to_vec(), self.tick));
+        self.order.insert(self.tick, offset);
+        while self.size > self.limit
+            && let Some((_, oldest)) = self.order.pop_first()
+        {
+            if let Some((_, data, _)) = self.entries.remove(&oldest) {
+                self.size -= data.len();
+            }
+        }
+    }
+}
+
+/// Rebuild the object at `offset`, following its deltas down to a whole object or a
+/// cached base and applying them back up. Entries that are `bases` of other deltas are
+/// cached as they are rebuilt. The chain is walked without recursion, and one that loops
+/// or runs deeper than `MAX_DELTA_DEPTH` is an error.
+fn resolve(
+    offset: u64,
+    read: &mut dyn FnMut(u64) -> Result<(BinaryObject, Vec<u8>)>,
+    bases: &HashSet<u64>,
+    base_offset: &dyn Fn(u64, BinaryObject) -> Result<Option<u64>>,
+    cache: &mut BaseCache,
+) -> Result<(BinaryObject, Vec<u8>)> {
+    let mut deltas = Vec::new();
+    let mut seen = HashSet::new();
+    let mut current = offset;
+    let (object_type, mut data) = loop {
+        if let Some(resolved) = cache.get(current) {
+            break resolved;
+        }
+        let (object_type, data) = read(current)?;
+        let Some(base) = base_offset(current, object_type)? else {
+            if bases.contains(&current) {
+                cache.insert(current, object_type, &data);
+            }
+            break (object_type, data);
+        };
+        ensure!(
+            seen.insert(current),
+            "delta at offset {} is its own base",
+            current
+        );
+        ensure!(
+            deltas.len() < MAX_DELTA_DEPTH,
+            "delta chain from offset {} is deeper than {}",
+            offset,
+            MAX_DELTA_DEPTH
+        );
+        deltas.push((current, data));
+        current = base;
+    };
+    for (delta_offset, delta) in deltas.into_iter().rev() {
+        data = DeltaObject::from(&delta)
+            .context("reading delta data")?
+            .rebuild(data)
+            .context("rebuilding delta")?;
+        if bases.contains(&delta_offset) {
+            cache.insert(delta_offset, object_type, &data);
+        }
+    }
+    Ok((object_type, data))
+}
+
+/// A copy of an entry read earlier, for `resolve` to read from memory.
+fn raw_entry(
+    raw: &HashMap<u64, (BinaryObject, Vec<u8>)>,
+    offset: u64,
+) -> Result<(BinaryObject, Vec<u8>)> {
+    raw.get(&offset)
+        .cloned()
+        .with_context(|| format!("no object at offset {}", offset))
+}
+
+/// Reads a file at its own position without moving the file's shared offset.
+struct PositionedReader<'a> {
+    file: &'a File,
+    position: u64,
+}
+
+impl<'a> PositionedReader<'a> {
+    fn new(file: &'a File) -> Self {
+        PositionedReader { file, position: 0 }
+    }
+}
+
+impl Read for PositionedReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.file.read_at(buf, self.position)?;
+        self.position += read as u64;
+        Ok(read)
+    }
+}
+
+impl Seek for PositionedReader<'_> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let position = match pos {
+            SeekFrom::Start(position) => Some(position),
+            SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+            SeekFrom::End(delta) => self.file.metadata()?.len().checked_add_signed(delta),
+        };
+        self.position = position.ok_or_else(|| {
+            io::Error::new(io::ErrorKind::InvalidInput, "seek to a negative position")
+        })?;
+        Ok(self.position)
+    }
+}
+
+/// Inflate one zlib stream of `size` bytes into `bytes`, reusing both the buffer and the
+/// inflater. The whole stream is consumed, including its checksum, so the reader is left at
+/// the start of the next entry.
 fn read_compressed<T: Read>(
     reader: &mut BufReader<T>,
+    inflater: &mut Decompress,
     size: usize,
     bytes: &mut Vec<u8>,
 ) -> io::Result<()> {
-    debug!("reading compressed: {}", bytes.len());
+    debug!("reading compressed: {}", size);
+    bytes.clear();
     bytes.resize(size, 0);
-    let mut z = ZlibDecoder::new(reader);
-    z.read_exact(bytes)
+    inflater.reset(true);
+    loop {
+        let input = reader.fill_buf()?;
+        if input.is_empty() {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (read_before, written) = (inflater.total_in(), inflater.total_out() as usize);
+        let status = inflater
+            .decompress(input, &mut bytes[written..], FlushDecompress::None)
+            .map_err(io::Error::other)?;
+        let read = (inflater.total_in() - read_before) as usize;
+        reader.consume(read);
+        match status {
+            Status::StreamEnd => break,
+            Status::BufError if read == 0 => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "object is larger than its header says",
+                ));
+            }
+            _ => {}
+        }
+    }
+    if inflater.total_out() as usize != size {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "object is smaller than its header says",
+        ));
+    }
+    Ok(())
 }
 
-#[derive(Copy, Clone, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub enum BinaryObject {
     Blob,
     Commit,
@@ -102,6 +650,9 @@ pub enum BinaryObject {
     Tree,
     OffsetDelta(u64),
     RefDelta([u8; 20]),
+    /// A type id git does not use, 0 or 5, kept so a pack holding one can
+    /// still be listed.
+    Reserved(u8),
 }
 
 impl BinaryObject {
@@ -122,13 +673,31 @@ impl BinaryObject {
             BinaryObject::Tree => "tree",
             BinaryObject::OffsetDelta(_) => "offsetdelta",
             BinaryObject::RefDelta(_) => "refdelta",
+            BinaryObject::Reserved(id) => return format!("reserved{}", id),
         }
         .to_string()
     }
 }
 
-pub fn read_data<T: Read>(reader: &mut BufReader<T>, data: &mut Vec<u8>) -> Result<BinaryObject> {
+pub(crate) fn read_data<T: Read>(
+    reader: &mut BufReader<T>,
+    inflater: &mut Decompress,
+    data: &mut Vec<u8>,
+) -> Result<BinaryObject> {
     debug!("reading object");
+    let (object_type, size) = read_entry_header(reader)?;
+    read_compressed(reader, inflater, size, data).with_context(|| {
+        format!(
+            "reading compressed object data for type: {}",
+            object_type.name()
+        )
+    })?;
+    Ok(object_type)
+}
+
+/// Read an entry's type, with a delta's base, and the size of its inflated data, leaving
+/// `reader` at the start of the compressed data.
+fn read_entry_header<T: Read>(reader: &mut BufReader<T>) -> Result<(BinaryObject, usize)> {
     let mut read = [0; 1];
     reader
         .read_exact(&mut read)
@@ -143,6 +712,7 @@ pub fn read_data<T: Read>(reader: &mut BufReader<T>, data: &mut Vec<u8>) -> Resu
             reader
                 .read_exact(&mut read)
                 .context("reading object size")?;
+            anyhow::ensure!(shift < usize::BITS, "object size is too large");
             size |= (read[0] as usize & 0x7f) << shift;
             shift += 7;
         }
@@ -160,18 +730,76 @@ pub fn read_data<T: Read>(reader: &mut BufReader<T>, data: &mut Vec<u8>) -> Resu
         0b111 => {
             BinaryObject::RefDelta(read_sha1(reader).context("reading ref delta reference sha1")?)
         }
-        _ => anyhow::bail!("unexpected object type {}", type_id),
+        _ => BinaryObject::Reserved(type_id),
     };
 
     debug!("read object {}, size: {}", object_type.name(), size);
+    Ok((object_type, size))
+}
 
-    read_compressed(reader, size, data).with_context(|| {
-        format!(
-            "reading compressed object data for type: {}",
-            object_type.name()
-        )
-    })?;
-    Ok(object_type)
+/// Append an entry for `data` to a pack being written. Offset deltas are not
+/// supported, as their base offset depends on where the entry is written.
+pub fn write_entry(
+    out: &mut Vec<u8>,
+    object_type: BinaryObject,
+    data: &[u8],
+    compression: Compression,
+) -> Result<()> {
+    let type_id = match object_type {
+        BinaryObject::Commit => 0b001,
+        BinaryObject::Tree => 0b010,
+        BinaryObject::Blob => 0b011,
+        BinaryObject::Tag => 0b100,
+        BinaryObject::RefDelta(_) => 0b111,
+        BinaryObject::OffsetDelta(_) => bail!("cannot write offset deltas"),
+        BinaryObject::Reserved(id) => bail!("cannot write objects of reserved type {}", id),
+    };
+    let mut size = data.len();
+    let mut byte = (type_id << 4) | (size & 0xf) as u8;
+    size >>= 4;
+    while size > 0 {
+        out.push(byte | 0b1000_0000);
+        byte = (size & 0x7f) as u8;
+        size >>= 7;
+    }
+    out.push(byte);
+    if let BinaryObject::RefDelta(base) = object_type {
+        out.extend_from_slice(&base);
+    }
+    ZlibEncoder::new(data, compression)
+        .read_to_end(out)
+        .context("compressing pack entry")?;
+    Ok(())
+}
+
+/// Complete a thin pack by appending its external delta bases as whole objects,
+/// updating the object count and trailing checksum to match.
+pub fn append_objects(
+    pack: &[u8],
+    objects: &[(BinaryObject, Vec<u8>)],
+    compression: Compression,
+) -> Result<Vec<u8>> {
+    ensure!(pack.len() >= 32, "pack is too short");
+    let mut res = pack[..pack.len() - 20].to_vec();
+    let count = u32::from_be_bytes(res[8..12].try_into()?) as usize + objects.len();
+    res[8..12].copy_from_slice(&u32::try_from(count)?.to_be_bytes());
+    for (object_type, data) in objects {
+        write_entry(&mut res, *object_type, data, compression)?;
+    }
+    let checksum = Sha1::digest(&res);
+    res.extend_from_slice(&checksum);
+    Ok(res)
+}
+
+/// Write a pack holding each of `objects` whole, without deltas.
+pub fn write_pack(
+    objects: &[(BinaryObject, Vec<u8>)],
+    compression: Compression,
+) -> Result<Vec<u8>> {
+    let mut empty = b"PACK\0\0\0\x02\0\0\0\0".to_vec();
+    let checksum = Sha1::digest(&empty);
+    empty.extend_from_slice(&checksum);
+    append_objects(&empty, objects, compression)
 }
 
 fn read_sha1<T: Read>(reader: &mut BufReader<T>) -> Result<[u8; 20]> {
@@ -179,3 +807,94 @@ fn read_sha1<T: Read>(reader: &mut BufReader<T>) -> Result<[u8; 20]> {
     reader.read_exact(&mut sha1ref).context("reading sha1")?;
     Ok(sha1ref)
 }
+
+#[cfg(test)]
+mod test {
+    use crate::pack::{BaseCache, BinaryObject, Pack, write_entry};
+    use crate::packindex::{PackIndex, PackIndexItem, write_index};
+    use flate2::Compression;
+    use sha1::{Digest, Sha1};
+    use std::fs::File;
+    use std::io::{BufReader, Cursor, Seek, Write};
+
+    #[test]
+    fn verify_objects_parallel_matches_resolve_all() {
+        let id = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        let path = format!("test/pack/pack-{}", id);
+        let pack = Pack::new(BufReader::new(
+            File::open(format!("{}.pack", path)).unwrap(),
+        ))
+        .unwrap();
+        let index =
+            PackIndex::new(BufReader::new(File::open(format!("{}.idx", path)).unwrap())).unwrap();
+
+        let mut expected = pack
+            .resolve_all()
+            .unwrap()
+            .into_iter()
+            .map(|(sha1, object_type, data)| (sha1, object_type, data.len()))
+            .collect::<Vec<_>>();
+        expected.sort_by_key(|(sha1, _, _)| *sha1);
+        for threads in [1, 3] {
+            let mut verified = pack.verify_objects_parallel(&index, threads).unwrap();
+            verified.sort_by_key(|(sha1, _, _)| *sha1);
+            assert_eq!(verified, expected);
+        }
+    }
+
+    #[test]
+    fn delta_cycle_is_an_error() {
+        // two ref deltas, each the other's base
+        let (a, b) = ([1; 20], [2; 20]);
+        let delta = [1, 1, 0b1001_0000, 1];
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x02".to_vec();
+        let a_offset = pack.len() as u64;
+        write_entry(
+            &mut pack,
+            BinaryObject::RefDelta(b),
+            &delta,
+            Compression::default(),
+        )
+        .unwrap();
+        let b_offset = pack.len() as u64;
+        write_entry(
+            &mut pack,
+            BinaryObject::RefDelta(a),
+            &delta,
+            Compression::default(),
+        )
+        .unwrap();
+        let checksum: [u8; 20] = Sha1::digest(&pack).into();
+        pack.extend_from_slice(&checksum);
+
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&pack).unwrap();
+        file.rewind().unwrap();
+        let pack = Pack::new(BufReader::new(file)).unwrap();
+        let index = write_index(
+            vec![
+                (PackIndexItem(a, a_offset), 0),
+                (PackIndexItem(b, b_offset), 0),
+            ],
+            checksum,
+        );
+        let index = PackIndex::new(BufReader::new(Cursor::new(index))).unwrap();
+        let err = pack.verify_objects_parallel(&index, 2).unwrap_err();
+        assert!(err.to_string().contains("is its own base"), "{:#}", err);
+    }
+
+    #[test]
+    fn base_cache_evicts_least_recently_used() {
+        let mut cache = BaseCache::new(10);
+        cache.insert(1, BinaryObject::Blob, &[0; 4]);
+        cache.insert(2, BinaryObject::Blob, &[0; 4]);
+        assert!(cache.get(1).is_some());
+        cache.insert(3, BinaryObject::Blob, &[0; 4]);
+        assert!(cache.get(2).is_none());
+        assert!(cache.get(1).is_some() && cache.get(3).is_some());
+        // anything over the limit is never kept
+        cache.insert(4, BinaryObject::Blob, &[0; 11]);
+        assert!(cache.get(4).is_none());
+        assert_eq!(cache.size, 8);
+    }
+}
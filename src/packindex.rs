@@ -1,22 +1,26 @@
 use crate::hashingreader::HashingReader;
+use crate::pack::PackIndexEntry;
 use anyhow::{Context, bail, ensure};
 use hex::ToHex;
 use log::{debug, info, trace};
-use std::cmp::Ordering;
+use sha1::{Digest, Sha1};
+use std::cell::OnceCell;
 use std::io;
 use std::io::{BufReader, Read};
+use std::ops::Range;
 
 pub struct PackIndex {
-    #[allow(dead_code)]
     fanout: [u32; 256],
     hashes: Vec<[u8; 20]>,
-    #[allow(dead_code)]
     crc32: Vec<u32>,
     offsets: Vec<u32>,
     offsets64: Vec<u64>,
     pack_sha1: [u8; 20],
     #[allow(dead_code)]
     index_sha1: [u8; 20],
+    /// Positions in the index sorted by pack offset, from a `.rev` file or
+    /// worked out the first time they are needed.
+    reverse: OnceCell<Vec<u32>>,
 }
 
 impl PackIndex {
@@ -58,6 +62,7 @@ impl PackIndex {
             offsets64,
             pack_sha1,
             index_sha1,
+            reverse: OnceCell::new(),
         })
     }
 
@@ -65,39 +70,124 @@ impl PackIndex {
         self.pack_sha1
     }
 
-    #[allow(dead_code)]
+    pub fn object_count(&self) -> usize {
+        self.hashes.len()
+    }
+
     pub fn find(&self, sha1: [u8; 20]) -> Option<u64> {
         let index = self.search_hash(sha1)?;
+        Some(self.offset_at(index))
+    }
+
+    /// The CRC32 of `sha1`'s bytes as stored in the pack.
+    pub fn crc32(&self, sha1: [u8; 20]) -> Option<u32> {
+        Some(self.crc32[self.search_hash(sha1)?])
+    }
 
-        let offset = self.offsets[index];
+    fn offset_at(&self, position: usize) -> u64 {
+        let offset = self.offsets[position];
         if offset & 0x8000_0000 != 0 {
             let i: usize = (offset & 0x7fff_ffff) as usize;
-            return Some(self.offsets64[i]);
+            return self.offsets64[i];
         }
+        offset as u64
+    }
+
+    /// Read the pack's `.rev` file, listing index positions in pack order,
+    /// so that offsets can be looked up without sorting every object first.
+    pub fn read_reverse_index<T: Read>(&self, reader: BufReader<T>) -> anyhow::Result<()> {
+        let mut reader = HashingReader::new(reader);
+        let mut header = [0; 12];
+        reader.read_exact(&mut header).context("reading header")?;
+        ensure!(&header[..4] == b"RIDX", "invalid reverse index header");
+        ensure!(
+            header[4..8] == 1u32.to_be_bytes(),
+            "only version 1 supported"
+        );
+        ensure!(
+            header[8..12] == 1u32.to_be_bytes(),
+            "only sha1 reverse indexes are supported"
+        );
+
+        let count = self.hashes.len();
+        let positions = read_n_u32be(&mut reader, count).context("reading positions")?;
+        ensure!(
+            positions.iter().all(|&p| (p as usize) < count),
+            "reverse index position out of range"
+        );
+        let pack_sha1 = read_hash(&mut reader).context("reading pack sha1")?;
+        ensure!(
+            pack_sha1 == self.pack_sha1,
+            "reverse index is for pack {}",
+            pack_sha1.encode_hex::<String>()
+        );
+        let actual = reader.finalize();
+        let expected = read_hash(&mut reader).context("reading reverse index sha1")?;
+        ensure!(actual == expected, "reverse index checksum does not match");
+
+        // the index may already have worked the order out for itself
+        let _ = self.reverse.set(positions);
+        Ok(())
+    }
+
+    fn reverse(&self) -> &[u32] {
+        self.reverse.get_or_init(|| {
+            let mut positions = (0..self.hashes.len() as u32).collect::<Vec<_>>();
+            positions.sort_by_key(|&p| self.offset_at(p as usize));
+            positions
+        })
+    }
+
+    /// The object stored at `offset` in the pack.
+    pub fn sha_at_offset(&self, offset: u64) -> Option<[u8; 20]> {
+        let reverse = self.reverse();
+        let i = reverse
+            .binary_search_by_key(&offset, |&p| self.offset_at(p as usize))
+            .ok()?;
+        Some(self.hashes[reverse[i] as usize])
+    }
+
+    /// Every object in the order it is stored in the pack.
+    pub fn iter_by_offset(&self) -> impl Iterator<Item = PackIndexItem> + '_ {
+        self.reverse().iter().map(|&p| {
+            let p = p as usize;
+            PackIndexItem(self.hashes[p], self.offset_at(p))
+        })
+    }
 
-        Some(offset as u64)
+    /// Every object in pack order with the bytes it takes up, running to the
+    /// next object or, for the last, to the trailing checksum of a pack
+    /// `pack_len` bytes long.
+    pub fn packed_ranges(&self, pack_len: u64) -> Vec<(PackIndexItem, Range<u64>)> {
+        let entries = self.iter_by_offset().collect::<Vec<_>>();
+        let ends = entries
+            .iter()
+            .skip(1)
+            .map(|PackIndexItem(_, offset)| *offset)
+            .chain([pack_len.saturating_sub(20)])
+            .collect::<Vec<_>>();
+        entries
+            .into_iter()
+            .zip(ends)
+            .map(|(item, end)| {
+                let start = item.1;
+                (item, start..end)
+            })
+            .collect()
     }
 
     fn search_hash(&self, sha1: [u8; 20]) -> Option<usize> {
-        assert_eq!(sha1.len(), 20);
-        let mut left = if sha1[0] == 0 {
-            0
-        } else {
-            self.fanout[sha1[0] as usize - 1] as usize + 1
+        // the fanout counts the hashes up to and including each first byte
+        let start = match sha1[0] {
+            0 => 0,
+            b => self.fanout[b as usize - 1] as usize,
         };
-        let mut right = self.fanout[sha1[0] as usize] as usize;
-        while left <= right {
-            let i = (right - left) / 2 + left;
-            match self.hashes[i].as_slice().cmp(&sha1) {
-                Ordering::Less => left = i + 1,
-                Ordering::Greater => right = i - 1,
-                Ordering::Equal => return Some(i),
-            }
-        }
-        None
+        let end = self.fanout[sha1[0] as usize] as usize;
+        let bucket = self.hashes.get(start..end)?;
+        bucket.binary_search(&sha1).ok().map(|i| start + i)
     }
 
-    pub fn iter(&self) -> PackIndexIterator {
+    pub fn iter(&self) -> PackIndexIterator<'_> {
         PackIndexIterator {
             index: self,
             item: 0,
@@ -105,6 +195,49 @@ impl PackIndex {
     }
 }
 
+/// Serialize a version 2 pack index listing `entries`, each with the CRC32 of its packed
+/// bytes, for the pack whose checksum is `pack_sha1`.
+pub fn write_index(mut entries: Vec<PackIndexEntry>, pack_sha1: [u8; 20]) -> Vec<u8> {
+    entries.sort_by_key(|(PackIndexItem(sha1, _), _)| *sha1);
+
+    let mut res = Vec::new();
+    res.extend_from_slice(b"\xff\x74\x4f\x63");
+    res.extend_from_slice(&2u32.to_be_bytes());
+    let mut fanout = [0u32; 256];
+    for (PackIndexItem(sha1, _), _) in entries.iter() {
+        fanout[sha1[0] as usize] += 1;
+    }
+    let mut count = 0;
+    for n in fanout {
+        count += n;
+        res.extend_from_slice(&count.to_be_bytes());
+    }
+    for (PackIndexItem(sha1, _), _) in entries.iter() {
+        res.extend_from_slice(sha1);
+    }
+    for (_, crc32) in entries.iter() {
+        res.extend_from_slice(&crc32.to_be_bytes());
+    }
+    // offsets that don't fit in 31 bits go in a trailing table of 64 bit offsets
+    let mut offsets64 = Vec::new();
+    for (PackIndexItem(_, offset), _) in entries.iter() {
+        let offset = if *offset < 0x8000_0000 {
+            *offset as u32
+        } else {
+            offsets64.push(*offset);
+            0x8000_0000 | (offsets64.len() - 1) as u32
+        };
+        res.extend_from_slice(&offset.to_be_bytes());
+    }
+    for offset in offsets64 {
+        res.extend_from_slice(&offset.to_be_bytes());
+    }
+    res.extend_from_slice(&pack_sha1);
+    let index_sha1: [u8; 20] = Sha1::digest(&res).into();
+    res.extend_from_slice(&index_sha1);
+    res
+}
+
 fn check_header<T: Read>(reader: &mut HashingReader<T>) -> anyhow::Result<()> {
     {
         let mut header = [0; 4];
@@ -182,11 +315,7 @@ impl Iterator for PackIndexIterator<'_> {
             return None;
         }
 
-        let offset = if self.index.offsets[self.item] & (1u32 << 31) == 0 {
-            self.index.offsets[self.item] as u64
-        } else {
-            self.index.offsets64[(self.index.offsets[self.item] ^ (1u32 << 31)) as usize]
-        };
+        let offset = self.index.offset_at(self.item);
         let hash = self.index.hashes[self.item];
         self.item += 1;
         Some(PackIndexItem(hash, offset))
@@ -194,3 +323,73 @@ impl Iterator for PackIndexIterator<'_> {
 }
 
 pub struct PackIndexItem(pub [u8; 20], pub u64);
+
+#[cfg(test)]
+mod test {
+    use crate::packindex::{PackIndex, PackIndexItem};
+    use std::fs::File;
+    use std::io::BufReader;
+
+    const PACK: &str = "test/pack/pack-2c61a5470c8319d5ff6cf64199aa714f18414c26";
+
+    fn open() -> PackIndex {
+        PackIndex::new(BufReader::new(File::open(format!("{}.idx", PACK)).unwrap())).unwrap()
+    }
+
+    #[test]
+    fn reverse_index() {
+        let rev = std::fs::read(format!("{}.rev", PACK)).unwrap();
+        let from_rev = open();
+        from_rev
+            .read_reverse_index(BufReader::new(rev.as_slice()))
+            .unwrap();
+        let built = open();
+        assert_eq!(from_rev.reverse.get().unwrap(), built.reverse());
+
+        for PackIndexItem(sha1, offset) in built.iter() {
+            assert_eq!(built.sha_at_offset(offset), Some(sha1));
+            assert_eq!(from_rev.sha_at_offset(offset), Some(sha1));
+        }
+        assert_eq!(built.sha_at_offset(13), None);
+        let offsets = built
+            .iter_by_offset()
+            .map(|PackIndexItem(_, offset)| offset)
+            .collect::<Vec<_>>();
+        assert_eq!(offsets.len(), 36);
+        assert!(offsets.is_sorted());
+
+        let read = |rev: &[u8]| open().read_reverse_index(BufReader::new(rev));
+        let mut swapped = rev.clone();
+        swapped[12..20].rotate_left(4);
+        assert!(read(&swapped).is_err());
+        let mut other_pack = rev.clone();
+        let end = other_pack.len() - 40;
+        other_pack[end] ^= 1;
+        assert!(read(&other_pack).is_err());
+        assert!(read(&rev[..rev.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn packed_ranges_cover_pack() {
+        let pack = std::fs::read(format!("{}.pack", PACK)).unwrap();
+        let index = open();
+        let ranges = index.packed_ranges(pack.len() as u64);
+        assert_eq!(ranges.len(), index.object_count());
+
+        // right after the header, each object running up to the next and
+        // the last up to the trailing checksum
+        let mut expected_start = 12;
+        for (PackIndexItem(sha1, offset), range) in ranges {
+            assert_eq!(range.start, offset);
+            assert_eq!(range.start, expected_start);
+            assert!(range.start < range.end);
+            let packed = &pack[range.start as usize..range.end as usize];
+            assert_eq!(index.crc32(sha1), Some(crc32fast::hash(packed)));
+            assert_eq!(index.find(sha1), Some(offset));
+            expected_start = range.end;
+        }
+        assert_eq!(expected_start, pack.len() as u64 - 20);
+        assert_eq!(index.find([0; 20]), None);
+        assert_eq!(index.crc32([0xff; 20]), None);
+    }
+}
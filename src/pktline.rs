@@ -0,0 +1,129 @@
+use anyhow::{Context, Result, ensure};
+use std::io::{Read, Write};
+use std::str::from_utf8;
+
+/// The longest packet allowed, including its four byte length.
+pub const MAX_PKT_LEN: usize = 65520;
+
+/// A pkt-line packet, including the special zero length packets.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Packet {
+    Data(Vec<u8>),
+    /// `0000`, ending a message.
+    Flush,
+    /// `0001`, separating sections in protocol v2.
+    Delim,
+    /// `0002`, ending a response in stateless protocol v2.
+    ResponseEnd,
+}
+
+/// Read one packet of any kind.
+pub fn read_packet<R: Read>(reader: &mut R) -> Result<Packet> {
+    let mut len = [0; 4];
+    reader
+        .read_exact(&mut len)
+        .context("reading pkt-line length")?;
+    let len = from_utf8(&len)
+        .ok()
+        .and_then(|len| usize::from_str_radix(len, 16).ok())
+        .with_context(|| {
+            format!(
+                "invalid pkt-line length {:?}",
+                String::from_utf8_lossy(&len)
+            )
+        })?;
+    match len {
+        0 => return Ok(Packet::Flush),
+        1 => return Ok(Packet::Delim),
+        2 => return Ok(Packet::ResponseEnd),
+        _ => {}
+    }
+    ensure!(len >= 4, "invalid pkt-line length {}", len);
+    ensure!(len <= MAX_PKT_LEN, "pkt-line of {} bytes is too long", len);
+    let mut data = vec![0; len - 4];
+    reader.read_exact(&mut data).context("reading pkt-line")?;
+    Ok(Packet::Data(data))
+}
+
+/// Read one data packet, returning `None` for a flush.
+pub fn read_pkt<R: Read>(reader: &mut R) -> Result<Option<Vec<u8>>> {
+    match read_packet(reader)? {
+        Packet::Data(data) => Ok(Some(data)),
+        Packet::Flush => Ok(None),
+        packet => anyhow::bail!("unexpected {:?} packet", packet),
+    }
+}
+
+pub fn write_pkt<W: Write>(writer: &mut W, data: &[u8]) -> Result<()> {
+    ensure!(
+        data.len() + 4 <= MAX_PKT_LEN,
+        "{} bytes is too long for a pkt-line",
+        data.len()
+    );
+    write!(writer, "{:04x}", data.len() + 4)?;
+    writer.write_all(data)?;
+    Ok(())
+}
+
+pub fn write_flush<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(b"0000")?;
+    Ok(())
+}
+
+pub fn write_delim<W: Write>(writer: &mut W) -> Result<()> {
+    writer.write_all(b"0001")?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::pktline::{
+        MAX_PKT_LEN, Packet, read_packet, read_pkt, write_delim, write_flush, write_pkt,
+    };
+
+    #[test]
+    fn round_trip() {
+        let mut buf = Vec::new();
+        write_pkt(&mut buf, b"want abc\n").unwrap();
+        write_delim(&mut buf).unwrap();
+        write_pkt(&mut buf, b"").unwrap();
+        write_flush(&mut buf).unwrap();
+        assert_eq!(buf, b"000dwant abc\n000100040000");
+
+        let mut reader = &buf[..];
+        assert_eq!(read_pkt(&mut reader).unwrap(), Some(b"want abc\n".to_vec()));
+        assert_eq!(read_packet(&mut reader).unwrap(), Packet::Delim);
+        assert_eq!(read_pkt(&mut reader).unwrap(), Some(vec![]));
+        assert_eq!(read_pkt(&mut reader).unwrap(), None);
+        assert!(read_pkt(&mut reader).is_err());
+    }
+
+    #[test]
+    fn special_packets() {
+        assert_eq!(read_packet(&mut &b"0002"[..]).unwrap(), Packet::ResponseEnd);
+        assert!(read_pkt(&mut &b"0001"[..]).is_err());
+        assert!(read_pkt(&mut &b"0003"[..]).is_err());
+        assert!(read_pkt(&mut &b"00zz"[..]).is_err());
+    }
+
+    #[test]
+    fn oversized_packets() {
+        let mut buf = Vec::new();
+        assert!(write_pkt(&mut buf, &vec![b'a'; MAX_PKT_LEN - 4]).is_ok());
+        assert!(write_pkt(&mut Vec::new(), &vec![b'a'; MAX_PKT_LEN - 3]).is_err());
+        assert_eq!(
+            read_pkt(&mut &buf[..]).unwrap().unwrap().len(),
+            MAX_PKT_LEN - 4
+        );
+
+        let mut buf = b"fff1".to_vec();
+        buf.resize(0xfff1, b'a');
+        assert!(read_pkt(&mut &buf[..]).is_err());
+    }
+
+    #[test]
+    fn truncated_packet() {
+        assert!(read_pkt(&mut &b"0009abc"[..]).is_err());
+        assert!(read_pkt(&mut &b"00"[..]).is_err());
+    }
+}
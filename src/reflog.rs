@@ -0,0 +1,79 @@
+use anyhow::{Context, Result};
+use hex::{ToHex, decode};
+use std::fmt::{Display, Formatter};
+
+/// One line of a reflog, recording a ref moving from `old` to `new`.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ReflogEntry {
+    pub old: [u8; 20],
+    pub new: [u8; 20],
+    /// Who made the change, as `Name <email> unixtime tz`.
+    pub identity: String,
+    pub message: String,
+}
+
+impl ReflogEntry {
+    /// Parse a `<old> <new> <identity>\t<message>` line.
+    pub fn parse(line: &str) -> Result<Self> {
+        let (head, message) = line.split_once('\t').unwrap_or((line, ""));
+        let sha1 = |s: Option<&str>| -> Result<[u8; 20]> {
+            s.and_then(|s| decode(s).ok())
+                .and_then(|v| v.try_into().ok())
+                .with_context(|| format!("bad sha1 in reflog line {}", line))
+        };
+        let mut parts = head.splitn(3, ' ');
+        Ok(ReflogEntry {
+            old: sha1(parts.next())?,
+            new: sha1(parts.next())?,
+            identity: parts.next().unwrap_or_default().to_string(),
+            message: message.to_string(),
+        })
+    }
+}
+
+impl Display for ReflogEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // a newline in the message would start a new entry
+        let message = self.message.lines().next().unwrap_or_default();
+        write!(
+            f,
+            "{} {} {}\t{}",
+            self.old.encode_hex::<String>(),
+            self.new.encode_hex::<String>(),
+            self.identity,
+            message
+        )
+    }
+}
+
+/// Parse a whole reflog file, oldest entry first.
+pub fn parse_reflog(data: &str) -> Result<Vec<ReflogEntry>> {
+    data.lines()
+        .filter(|line| !line.is_empty())
+        .map(ReflogEntry::parse)
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use crate::reflog::{ReflogEntry, parse_reflog};
+
+    #[test]
+    fn round_trip() {
+        let line = format!(
+            "{} {} A U Thor <a@b> 1700000000 +0000\tclone: from http://x/y",
+            "0".repeat(40),
+            "ab".repeat(20)
+        );
+        let entry = ReflogEntry::parse(&line).unwrap();
+        assert_eq!(entry.old, [0; 20]);
+        assert_eq!(entry.new, [0xab; 20]);
+        assert_eq!(entry.identity, "A U Thor <a@b> 1700000000 +0000");
+        assert_eq!(entry.message, "clone: from http://x/y");
+        assert_eq!(entry.to_string(), line);
+
+        let entries = parse_reflog(&format!("{}\n{}\n", line, line)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(parse_reflog("not a reflog\n").is_err());
+    }
+}
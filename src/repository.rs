@@ -1,17 +1,27 @@
 extern crate sha1;
 
-use crate::cli::CommandObjectType;
-use crate::gitobject::GitObject;
+use crate::bitmap::{Bitmap, PackBitmap};
+use crate::config;
+use crate::error::WyagError;
+use crate::gitattributes::{TextAttribute, text_attribute};
+use crate::gitignore::{GitIgnore, name_match};
 use crate::gitobject::blob::BlobObject;
+use crate::gitobject::commit::CommitObject;
 use crate::gitobject::delta::DeltaObject;
-use crate::gitobject::tree::TreeObject;
+use crate::gitobject::identity::Identity;
+use crate::gitobject::tag::TagObject;
+use crate::gitobject::tree::{LeafKind, TreeLeaf, TreeObject};
+use crate::gitobject::{GitObject, ObjectType};
+use crate::gpg::{self, SignatureStatus};
 use crate::hashingreader::HashingReader;
-use crate::logiterator::LogIterator;
+use crate::index::{Index, IndexEntry};
+use crate::logiterator::{CommitCache, LogIterator};
 use crate::pack::BinaryObject::{Blob, Commit, Tag, Tree};
-use crate::pack::{BinaryObject, Pack};
-use crate::packindex::{PackIndex, PackIndexItem};
+use crate::pack::{BinaryObject, Pack, PackIndexEntry, Progress, append_objects, write_pack};
+use crate::packindex::{PackIndex, PackIndexItem, write_index};
+use crate::reflog::{ReflogEntry, parse_reflog};
 use crate::repository::ObjectLocation::{ObjectFile, PackFile};
-use crate::util::validate_sha1;
+use crate::util::{delta_base_offset, hash_raw_object, quote_path, read_byte, validate_sha1};
 use BinaryObject::{OffsetDelta, RefDelta};
 use anyhow::{Context, Result, bail, ensure};
 use bytes::{Buf, Bytes};
@@ -19,30 +29,182 @@ use configparser::ini::Ini;
 use flate2::Compression;
 use flate2::bufread::{ZlibDecoder, ZlibEncoder};
 use hex::{ToHex, decode};
-use log::{debug, trace};
+use log::{debug, trace, warn};
+use sha1::{Digest, Sha1};
 use std::cell::RefCell;
 use std::cmp::Ordering;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::fmt::{Display, Formatter};
 use std::io::sink;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
 use std::rc::Rc;
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use std::{
     fs::{File, create_dir_all},
     io,
-    io::{BufReader, BufWriter, Read, Write},
-    path::{Path, PathBuf},
+    io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Component, Path, PathBuf},
     str::from_utf8,
 };
 use tempfile::NamedTempFile;
 
 type PackRef = Rc<Pack<File>>;
 
+/// An object as it is stored in a pack.
+pub struct PackedObject {
+    pub sha1: [u8; 20],
+    /// The type once any delta is resolved.
+    pub object_type: BinaryObject,
+    /// The inflated size of the entry, which for a delta is the delta itself.
+    pub size: usize,
+    /// The bytes the entry takes up in the pack, including its header.
+    pub packed_size: u64,
+    pub offset: u64,
+    /// For a delta, the length of its delta chain down to a whole object and its base.
+    pub delta: Option<(usize, DeltaBase)>,
+}
+
+/// Where a packed delta's base is, as recorded in the pack.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DeltaBase {
+    /// The offset of the base in the same pack, and the object found there.
+    Offset(u64, [u8; 20]),
+    Ref([u8; 20]),
+}
+
+/// Where an object was read from.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ObjectSource {
+    Loose(PathBuf),
+    Packed {
+        pack: [u8; 20],
+        offset: u64,
+    },
+    /// The empty tree or blob, which is made up when the repository lacks it.
+    Synthesized,
+}
+
+impl Display for ObjectSource {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ObjectSource::Loose(path) => write!(f, "loose {}", path.to_string_lossy()),
+            ObjectSource::Packed { pack, offset } => {
+                write!(f, "pack-{}.pack at {}", pack.encode_hex::<String>(), offset)
+            }
+            ObjectSource::Synthesized => write!(f, "synthesized"),
+        }
+    }
+}
+
+/// Object totals as `git count-objects -v` reports them. Sizes are in bytes.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct ObjectCounts {
+    /// Loose objects.
+    pub count: usize,
+    /// Disk space taken by loose objects.
+    pub size: u64,
+    /// Objects in packs, counting an object once per pack it is in.
+    pub in_pack: usize,
+    pub packs: usize,
+    /// Bytes taken by packs and their indexes.
+    pub size_pack: u64,
+    /// Loose objects that are also packed, which `prune-packed` would remove.
+    pub prune_packable: usize,
+    /// Files among the loose objects that are not objects.
+    pub garbage: usize,
+    pub size_garbage: u64,
+}
+
+/// How `ls-tree` prints each entry.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum LsTreeFormat {
+    Default,
+    /// Include the size of blobs.
+    Long,
+    /// Only the paths.
+    NameOnly,
+}
+
+/// Something `fsck` found wrong with the object store.
+#[derive(Debug, PartialEq, Eq)]
+pub enum FsckProblem {
+    /// A file among the loose objects that is not one, which is only worth
+    /// a warning.
+    Garbage(PathBuf),
+    /// A loose object that cannot be read or does not hash to its name.
+    Corrupt([u8; 20], String),
+    /// A pack that fails verification, named by its checksum.
+    BadPack(String, String),
+}
+
+impl Display for FsckProblem {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            FsckProblem::Garbage(path) => {
+                write!(f, "garbage found: {}", path.to_string_lossy())
+            }
+            FsckProblem::Corrupt(sha1, error) => {
+                write!(f, "{}: {}", sha1.encode_hex::<String>(), error)
+            }
+            FsckProblem::BadPack(pack, error) => write!(f, "pack-{}.pack: {}", pack, error),
+        }
+    }
+}
+
+/// How many packs are kept open when `core.maxOpenPacks` is not set.
+const DEFAULT_MAX_OPEN_PACKS: usize = 32;
+
+/// The open packs, least recently used first. Evicting a pack only drops the
+/// cache's reference, so its file stays open until any reader holding it is done.
+struct PackCache {
+    packs: VecDeque<([u8; 20], PackRef)>,
+    limit: usize,
+}
+
+impl PackCache {
+    fn new(limit: usize) -> Self {
+        PackCache {
+            packs: VecDeque::new(),
+            limit: limit.max(1),
+        }
+    }
+
+    fn get(&mut self, id: [u8; 20]) -> Option<PackRef> {
+        let i = self.packs.iter().position(|(pack_id, _)| *pack_id == id)?;
+        let entry = self.packs.remove(i)?;
+        let pack = entry.1.clone();
+        self.packs.push_back(entry);
+        Some(pack)
+    }
+
+    fn insert(&mut self, id: [u8; 20], pack: PackRef) {
+        self.packs.push_back((id, pack));
+        while self.packs.len() > self.limit {
+            self.packs.pop_front();
+        }
+    }
+}
+
 pub struct Repository {
     pub worktree: PathBuf,
     gitdir: PathBuf,
-    #[allow(dead_code)]
     conf: Option<Ini>,
-    pack_cache: RefCell<HashMap<[u8; 20], PackRef>>,
+    global_conf: Option<Ini>,
+    /// `config.worktree`, read when `extensions.worktreeConfig` is set.
+    worktree_conf: Option<Ini>,
+    pack_cache: RefCell<PackCache>,
     global_index: RefCell<Option<GlobalIndex>>,
+    /// Commits whose parents are overridden by `info/grafts`.
+    grafts: HashMap<[u8; 20], Vec<[u8; 20]>>,
+    /// Objects substituted by `refs/replace/<sha>`.
+    replacements: HashMap<[u8; 20], [u8; 20]>,
+    /// Whether objects are hashed as they are read to check they match their
+    /// sha, from `core.checkObjectContent`.
+    check_object_content: bool,
 }
 
 struct GlobalIndex {
@@ -51,7 +213,99 @@ struct GlobalIndex {
     locations: Vec<ObjectLocation>,
 }
 
+/// The merged pack indexes, saved between runs so they are only rebuilt when
+/// the packs change.
+const GLOBAL_INDEX_CACHE: &str = "wyag-global-index";
+
 impl GlobalIndex {
+    /// Build the index from `(object, pack, offset)` triples. An object listed
+    /// more than once keeps its first location.
+    fn new(mut items: Vec<([u8; 20], [u8; 20], u64)>) -> GlobalIndex {
+        // the sort is stable, so the first of each run of duplicates is kept
+        items.sort_by_key(|(hash, _, _)| *hash);
+        items.dedup_by_key(|(hash, _, _)| *hash);
+
+        let mut result = GlobalIndex {
+            fanout: [0u32; 256],
+            hashes: Vec::with_capacity(items.len()),
+            locations: Vec::with_capacity(items.len()),
+        };
+        for (hash, pack, offset) in items.into_iter() {
+            result.fanout[hash[0] as usize] += 1;
+            result.hashes.push(hash);
+            result.locations.push(PackFile(pack, offset));
+        }
+        for i in 1..result.fanout.len() {
+            result.fanout[i] += result.fanout[i - 1];
+        }
+        result
+    }
+
+    /// Serialize for the cache file: a `WGIX` header, `key`, the pack ids,
+    /// then each object with the number of its pack and its offset there.
+    fn to_bytes(&self, key: [u8; 20]) -> Vec<u8> {
+        let mut packs = Vec::new();
+        let mut pack_numbers = HashMap::new();
+        for location in self.locations.iter() {
+            if let PackFile(pack, _) = location {
+                pack_numbers.entry(*pack).or_insert_with(|| {
+                    packs.push(*pack);
+                    packs.len() as u32 - 1
+                });
+            }
+        }
+
+        let mut out = Vec::with_capacity(32 + packs.len() * 20 + self.hashes.len() * 32);
+        out.extend_from_slice(b"WGIX");
+        out.extend_from_slice(&key);
+        out.extend_from_slice(&(packs.len() as u32).to_be_bytes());
+        packs.iter().for_each(|pack| out.extend_from_slice(pack));
+        out.extend_from_slice(&(self.hashes.len() as u32).to_be_bytes());
+        for (hash, location) in self.hashes.iter().zip(self.locations.iter()) {
+            let PackFile(pack, offset) = location else {
+                unreachable!("the global index only holds packed objects");
+            };
+            out.extend_from_slice(hash);
+            out.extend_from_slice(&pack_numbers[pack].to_be_bytes());
+            out.extend_from_slice(&offset.to_be_bytes());
+        }
+        out
+    }
+
+    /// Read a cache file, or `None` if it is malformed or was written for
+    /// packs other than those `key` describes.
+    fn from_bytes(mut data: &[u8], key: [u8; 20]) -> Option<GlobalIndex> {
+        fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+            let (head, rest) = data.split_at_checked(n)?;
+            *data = rest;
+            Some(head)
+        }
+        let take_u32 = |data: &mut &[u8]| Some(u32::from_be_bytes(take(data, 4)?.try_into().ok()?));
+
+        if take(&mut data, 4)? != b"WGIX" || take(&mut data, 20)? != key {
+            return None;
+        }
+        let packs = (0..take_u32(&mut data)?)
+            .map(|_| take(&mut data, 20)?.try_into().ok())
+            .collect::<Option<Vec<[u8; 20]>>>()?;
+        let count = take_u32(&mut data)? as usize;
+        if data.len() != count * 32 {
+            return None;
+        }
+        let items = data
+            .chunks_exact(32)
+            .map(|item| {
+                let pack = *packs.get(u32::from_be_bytes(item[20..24].try_into().ok()?) as usize)?;
+                Some((
+                    item[..20].try_into().ok()?,
+                    pack,
+                    u64::from_be_bytes(item[24..].try_into().ok()?),
+                ))
+            })
+            .collect::<Option<Vec<_>>>()?;
+        Some(GlobalIndex::new(items))
+    }
+
     pub fn search(&self, sha1: [u8; 20]) -> Option<ObjectLocation> {
         let mut left = if sha1[0] == 0 {
             0
@@ -59,11 +313,11 @@ impl GlobalIndex {
             self.fanout[sha1[0] as usize - 1]
         } as usize;
         let mut right = self.fanout[sha1[0] as usize] as usize;
-        while left <= right {
+        while left < right {
             let i = (right - left) / 2 + left;
             match self.hashes[i].as_slice().cmp(&sha1) {
                 Ordering::Less => left = i + 1,
-                Ordering::Greater => right = i - 1,
+                Ordering::Greater => right = i,
                 Ordering::Equal => return Some(self.locations[i]),
             }
         }
@@ -72,33 +326,46 @@ impl GlobalIndex {
 }
 
 impl Repository {
+    /// The tree with no entries, `4b825dc642cb6eb9a060e54bf8d69288fbee4904`.
+    pub const EMPTY_TREE_SHA1: [u8; 20] = [
+        0x4b, 0x82, 0x5d, 0xc6, 0x42, 0xcb, 0x6e, 0xb9, 0xa0, 0x60, 0xe5, 0x4b, 0xf8, 0xd6, 0x92,
+        0x88, 0xfb, 0xee, 0x49, 0x04,
+    ];
+    /// The blob with no contents, `e69de29bb2d1d6434b8b29ae775ad8c2e48c5391`.
+    pub const EMPTY_BLOB_SHA1: [u8; 20] = [
+        0xe6, 0x9d, 0xe2, 0x9b, 0xb2, 0xd1, 0xd6, 0x43, 0x4b, 0x8b, 0x29, 0xae, 0x77, 0x5a, 0xd8,
+        0xc2, 0xe4, 0x8c, 0x53, 0x91,
+    ];
+
     pub fn new(path: &Path, force: bool) -> Result<Self> {
-        let gitdir = path.join(".git");
+        if !path.join(".git").exists() && is_bare_gitdir(path) {
+            return Self::new_bare(path, force);
+        }
+        Self::open(path, path.join(".git"), force)
+    }
+
+    /// A repository with no worktree, whose gitdir is `path` itself.
+    pub fn new_bare(path: &Path, force: bool) -> Result<Self> {
+        Self::open(path, path.to_path_buf(), force)
+    }
+
+    fn open(path: &Path, gitdir: PathBuf, force: bool) -> Result<Self> {
         let config_file = gitdir.join("config");
 
         debug!("constructing repo");
 
         let conf = if config_file.is_file() {
-            let conf = {
-                let file = File::open(config_file).context("opening config file")?;
-                let mut reader = BufReader::new(file);
-
-                let mut conf = Ini::new();
-                let mut config = String::new();
-                reader
-                    .read_to_string(&mut config)
-                    .context("reading config file")?;
-                if let Err(e) = conf.read(config) {
-                    bail!("error parsing config contents: {}", e);
-                }
-                conf
-            };
+            let conf = read_config(&config_file).context("reading repository config")?;
 
             let vers = conf
                 .get("core", "repositoryformatversion")
                 .context("version string does not exist")?;
             let vers = vers.parse::<i32>().context("parsing repository version")?;
-            anyhow::ensure!(vers == 0, "Unsupported repositoryformatversion: {}", vers);
+            match vers {
+                0 => {}
+                1 => check_extensions(&conf)?,
+                _ => bail!("Unsupported repositoryformatversion: {}", vers),
+            }
 
             Some(conf)
         } else {
@@ -109,15 +376,87 @@ impl Repository {
             bail!("config file does not exist");
         }
 
-        trace!("constructed");
+        let worktree_conf = match &conf {
+            Some(conf)
+                if conf.getboolcoerce("extensions", "worktreeconfig") == Ok(Some(true))
+                    && gitdir.join("config.worktree").is_file() =>
+            {
+                Some(
+                    read_config(&gitdir.join("config.worktree"))
+                        .context("reading worktree config")?,
+                )
+            }
+            _ => None,
+        };
+
+        let global_conf =
+            std::env::var_os("HOME").and_then(|home| read_global_config(home.as_ref()));
 
-        Ok(Self {
+        let mut repo = Self {
             worktree: path.into(),
             gitdir,
             conf,
-            pack_cache: RefCell::new(HashMap::new()),
+            global_conf,
+            worktree_conf,
+            pack_cache: RefCell::new(PackCache::new(DEFAULT_MAX_OPEN_PACKS)),
             global_index: RefCell::new(None),
-        })
+            grafts: HashMap::new(),
+            replacements: HashMap::new(),
+            check_object_content: true,
+        };
+        if let Some(limit) = repo.config_get("core", "maxopenpacks") {
+            let limit = limit.parse().context("parsing core.maxOpenPacks")?;
+            repo.pack_cache = RefCell::new(PackCache::new(limit));
+        }
+        repo.check_object_content = repo
+            .config_get("core", "checkobjectcontent")
+            .is_none_or(|v| v != "false");
+        if repo.conf.is_some() {
+            repo.grafts = repo.read_grafts().context("reading grafts")?;
+            repo.replacements = repo
+                .refs()?
+                .into_iter()
+                .filter_map(|(name, target)| {
+                    let original = name.strip_prefix("refs/replace/")?;
+                    let original = decode(original).ok()?.try_into().ok()?;
+                    Some((original, target))
+                })
+                .collect();
+        }
+
+        trace!("constructed");
+
+        Ok(repo)
+    }
+
+    /// Parse `info/grafts`, where each line is a commit followed by the parents it should have.
+    fn read_grafts(&self) -> Result<HashMap<[u8; 20], Vec<[u8; 20]>>> {
+        let path = self.repo_path(Path::new("info/grafts"));
+        if !path.is_file() {
+            return Ok(HashMap::new());
+        }
+        std::fs::read_to_string(&path)?
+            .lines()
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(|line| {
+                let mut shas = line.split_whitespace().map(|sha1| {
+                    decode(sha1)
+                        .ok()
+                        .and_then(|sha1| sha1.try_into().ok())
+                        .with_context(|| format!("bad sha1 in graft {}", line))
+                });
+                let commit = shas.next().context("empty graft")??;
+                Ok((commit, shas.collect::<Result<_>>()?))
+            })
+            .collect()
+    }
+
+    /// The parents of `commit`, as overridden by any graft.
+    pub fn parents(&self, sha1: [u8; 20], commit: &CommitObject) -> Vec<[u8; 20]> {
+        match self.grafts.get(&sha1) {
+            Some(parents) => parents.clone(),
+            None => commit.parents(),
+        }
     }
 
     pub fn find(orig: &Path) -> Result<Self> {
@@ -129,7 +468,7 @@ impl Repository {
                 .join(orig)
         };
 
-        while !path.join(".git").is_dir() {
+        while !path.join(".git").is_dir() && !is_bare_gitdir(path) {
             path = path
                 .parent()
                 .with_context(|| format!("{} is not a repository!", orig.to_string_lossy()))?;
@@ -139,6 +478,12 @@ impl Repository {
             .with_context(|| format!("loading repository at {}", path.to_string_lossy()))
     }
 
+    /// Skip hashing objects as they are read, trusting the store to hold what
+    /// each sha names, or check them again. This overrides `core.checkObjectContent`.
+    pub fn set_check_object_content(&mut self, check: bool) {
+        self.check_object_content = check;
+    }
+
     /// Compute path under repo gitdir
     fn repo_path(&self, path: &Path) -> PathBuf {
         self.gitdir.join(path)
@@ -162,7 +507,113 @@ impl Repository {
         Some(file_path)
     }
 
-    pub fn init(&self) -> Result<()> {
+    /// Look up a config value, preferring `config.worktree`, then the
+    /// repository config, then the global `~/.gitconfig`.
+    pub fn config_get(&self, section: &str, key: &str) -> Option<String> {
+        [&self.worktree_conf, &self.conf, &self.global_conf]
+            .into_iter()
+            .flatten()
+            .find_map(|conf| conf.get(section, key))
+    }
+
+    /// Every config value as `(section, key, value)`, with the same precedence
+    /// as `config_get`.
+    pub fn config_list(&self) -> Vec<(String, String, String)> {
+        let mut values = BTreeMap::new();
+        for conf in [&self.global_conf, &self.conf, &self.worktree_conf]
+            .into_iter()
+            .flatten()
+        {
+            for (section, keys) in conf.get_map_ref() {
+                for (key, value) in keys {
+                    values.insert(
+                        (section.clone(), key.clone()),
+                        value.clone().unwrap_or_default(),
+                    );
+                }
+            }
+        }
+        values
+            .into_iter()
+            .map(|((section, key), value)| (section, key, value))
+            .collect()
+    }
+
+    /// Set a value in the repository config and write it back to disk.
+    pub fn config_set(&mut self, section: &str, key: &str, value: &str) -> Result<()> {
+        let path = self
+            .repo_file(Path::new("config"), true)
+            .context("could not create path to config file")?;
+
+        // only the line being set is touched, as configparser would drop
+        // comments, repeated keys and the case of subsections on writing
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e).context("reading config file"),
+        };
+        let text = config::set_value(&text, section, key, value)?;
+        let file = NamedTempFile::new_in(&self.gitdir).context("creating temp config file")?;
+        BufWriter::new(&file)
+            .write_all(text.as_bytes())
+            .context("writing config file")?;
+        file.persist(path).context("replacing config file")?;
+        self.conf = Some(parse_config(text)?);
+        Ok(())
+    }
+
+    /// Build the author and committer identities for new objects.
+    /// `GIT_AUTHOR_*` and `GIT_COMMITTER_*` environment variables take
+    /// precedence over `user.name`/`user.email`.
+    pub fn user_identity(&self) -> Result<(Identity, Identity)> {
+        let identity = |role: &str, label: &str| -> Result<Identity> {
+            let lookup = |field: &str| {
+                std::env::var(format!("GIT_{}_{}", role, field.to_uppercase()))
+                    .ok()
+                    .or_else(|| self.config_get("user", field))
+                    .filter(|v| !v.is_empty())
+            };
+            match (lookup("name"), lookup("email")) {
+                (Some(name), Some(email)) => Identity::now(name, email),
+                _ => bail!(
+                    "{} identity unknown\n\n\
+                     *** Please tell me who you are.\n\n\
+                     Run\n\n  \
+                     wyag config user.email \"you@example.com\"\n  \
+                     wyag config user.name \"Your Name\"\n\n\
+                     to set your account's default identity.",
+                    label
+                ),
+            }
+        };
+
+        Ok((
+            identity("AUTHOR", "Author")?,
+            identity("COMMITTER", "Committer")?,
+        ))
+    }
+
+    pub fn is_bare(&self) -> bool {
+        self.gitdir == self.worktree
+    }
+
+    pub fn init(&self) -> Result<bool> {
+        self.init_with_branch("master")
+    }
+
+    /// Create the repository with `HEAD` pointing at the unborn `initial_branch`.
+    /// Running it on an existing repository only adds what is missing, leaving
+    /// `HEAD` and the config alone, and returns true.
+    pub fn init_with_branch(&self, initial_branch: &str) -> Result<bool> {
+        ensure!(
+            !initial_branch.is_empty()
+                && !initial_branch.contains("..")
+                && !initial_branch.starts_with('-')
+                && !initial_branch.contains(|c: char| c.is_whitespace() || c.is_control()),
+            "'{}' is not a valid branch name",
+            initial_branch
+        );
+        let reinit = self.gitdir.join("HEAD").is_file();
         if self.worktree.exists() {
             anyhow::ensure!(
                 self.worktree.is_dir(),
@@ -170,7 +621,7 @@ impl Repository {
                 self.worktree.to_string_lossy()
             );
 
-            if self.gitdir.exists() {
+            if self.gitdir.exists() && !reinit {
                 anyhow::ensure!(
                     self.gitdir.is_dir(),
                     "{} exists and is not a directory!",
@@ -206,11 +657,14 @@ impl Repository {
                 "description",
                 "Unnamed repository; edit this file 'description' to name the repository.\n",
             ),
-            ("HEAD", "ref: refs/heads/master\n"),
-            ("config", &default_config().writes()),
+            ("HEAD", &format!("ref: refs/heads/{}\n", initial_branch)),
+            ("config", &default_config(self.is_bare()).writes()),
         ];
 
         for (f, contents) in files {
+            if reinit && self.gitdir.join(f).exists() {
+                continue;
+            }
             let file = File::create_new(self.gitdir.join(f))
                 .with_context(|| format!("creating file {}", f))?;
             BufWriter::new(file)
@@ -218,10 +672,16 @@ impl Repository {
                 .with_context(|| format!("writing file contents {}", f))?;
         }
 
-        Ok(())
+        Ok(reinit)
     }
 
     fn read_object_file_data(&self, sha1: [u8; 20], data: &mut Vec<u8>) -> Result<BinaryObject> {
+        let type_name = self.read_raw_object_file(sha1, data)?;
+        object_type_from_name(&type_name)
+    }
+
+    /// Read a loose object, returning its type word without interpreting it.
+    fn read_raw_object_file(&self, sha1: [u8; 20], data: &mut Vec<u8>) -> Result<Vec<u8>> {
         let path = self
             .object_file_path(sha1)
             .with_context(|| format!("Could not load object {}", sha1.encode_hex::<String>()))?;
@@ -233,35 +693,14 @@ impl Repository {
 
         let mut file = File::open(path).context("opening object file")?;
         let mut decoder = ZlibDecoder::new(BufReader::new(&mut file));
-        let mut raw = [0; 64];
-        decoder
-            .read_exact(&mut raw)
-            .context("reading object file")?;
-        trace!(
-            "first part: {}",
-            from_utf8(&raw).unwrap_or_else(|e| from_utf8(&raw[..e.valid_up_to()]).unwrap())
-        );
-        let (object_type, raw) = raw.split_at(
-            raw.iter()
-                .position(|&b| b == b' ')
-                .context("expected space")?,
-        );
-        let (size, raw) =
-            raw[1..].split_at(raw.iter().position(|&b| b == 0).context("expected null")?);
-        trace!(
-            "reading size... [{}]",
-            from_utf8(&size[..size.len() - 1]).unwrap_or("<<bad-utf8>>")
+        let (type_name, size) =
+            read_raw_object_header(&mut decoder).context("reading object header")?;
+        debug!(
+            "type = '{}' size = {}",
+            String::from_utf8_lossy(&type_name),
+            size
         );
-        let size = from_utf8(&size[..size.len() - 1])
-            .context("parsing size as utf8")?
-            .parse::<usize>()
-            .context("parsing size as usize")?;
 
-        data.extend_from_slice(raw);
-        trace!(
-            "remaining [[{}]]",
-            from_utf8(data).unwrap_or_else(|e| from_utf8(&data[..e.valid_up_to()]).unwrap())
-        );
         decoder.read_to_end(data).context("reading object")?;
         trace!(
             "fully read [[{}]]",
@@ -271,30 +710,22 @@ impl Repository {
         ensure!(
             size == data.len(),
             "object corrupt: size {} does not match expected {}",
+            data.len(),
             size,
-            raw.len(),
-        );
-        debug!(
-            "type = '{}' size = {}",
-            from_utf8(object_type)
-                .map(|b| b.to_string())
-                .unwrap_or_else(|_| format!("hex={}", object_type.encode_hex::<String>())),
-            size
         );
 
-        let object_type = match object_type {
-            b"blob" => Blob,
-            b"commit" => Commit,
-            b"tree" => Tree,
-            b"tag" => Tag,
-            _ => unimplemented!(
-                "unexpected type {}",
-                from_utf8(object_type).unwrap_or("<<invalid utf8>>")
-            ),
-        };
-
-        validate_sha1(sha1, object_type, data).context("validating object sha1")?;
-        Ok(object_type)
+        if !self.check_object_content {
+            return Ok(type_name);
+        }
+        let result = hash_raw_object(&type_name, data);
+        ensure!(
+            result == sha1,
+            "sha1 did not validate for object {} with type {}, received {}",
+            sha1.encode_hex::<String>(),
+            String::from_utf8_lossy(&type_name),
+            result.encode_hex::<String>(),
+        );
+        Ok(type_name)
     }
 
     fn object_file_path(&self, sha1: [u8; 20]) -> Option<PathBuf> {
@@ -304,46 +735,81 @@ impl Repository {
     }
 
     fn init_global_index(&self) -> Result<()> {
-        let index_iter = self
-            .repo_path(Path::new("objects/pack"))
-            .read_dir()?
+        let pack_dir = self.repo_path(Path::new("objects/pack"));
+        // a fresh repository has no pack directory until something is packed
+        let mut index_paths = pack_dir
+            .read_dir()
+            .into_iter()
+            .flatten()
             .filter_map(|p| {
-                if let Ok(p) = p {
-                    if let Some(name) = p.file_name().to_str() {
-                        let path = p.path();
-                        if name.starts_with("pack-") && name.ends_with(".idx") && path.is_file() {
-                            debug!("found pack: {name}");
-                            if let Ok(value) = self.open_index(&path) {
-                                return Some(value);
-                            }
-                        }
+                if let Ok(p) = p
+                    && let Some(name) = p.file_name().to_str()
+                {
+                    let path = p.path();
+                    if name.starts_with("pack-") && name.ends_with(".idx") && path.is_file() {
+                        debug!("found pack: {name}");
+                        return Some(path);
                     }
                 }
                 None
-            });
+            })
+            .collect::<Vec<_>>();
+        index_paths.sort();
+
+        // the cache is only good for exactly the index files it was built from
+        let mut key = Sha1::new();
+        let mut indexes = Vec::with_capacity(index_paths.len());
+        for path in index_paths.iter() {
+            let metadata = path.metadata().context("reading pack index metadata")?;
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+                .unwrap_or_default();
+            key.update(path.file_name().unwrap_or_default().as_encoded_bytes());
+            key.update(metadata.len().to_be_bytes());
+            key.update(modified.as_nanos().to_be_bytes());
+            let kept = path.with_extension("keep").is_file();
+            key.update([kept as u8]);
+            indexes.push((kept, modified, path));
+        }
+        let key: [u8; 20] = key.finalize().into();
+
+        let cache_path = self.repo_path(Path::new(GLOBAL_INDEX_CACHE));
+        if let Ok(data) = std::fs::read(&cache_path)
+            && let Some(cached) = GlobalIndex::from_bytes(&data, key)
+        {
+            debug!("loaded global index from cache");
+            self.global_index.replace(Some(cached));
+            return Ok(());
+        }
 
+        // kept packs, then newest first, so an object in several packs is
+        // found in the one gc will not touch, or else the newest
+        indexes.sort_by_key(|(kept, modified, _)| (!kept, std::cmp::Reverse(*modified)));
         let mut all_items = Vec::new();
-        for index in index_iter {
+        for index in indexes
+            .iter()
+            .filter_map(|(_, _, p)| self.open_index(p).ok())
+        {
             index
                 .iter()
                 .map(|PackIndexItem(hash, offset)| (hash, index.id(), offset))
                 .for_each(|item| all_items.push(item));
         }
-        all_items.sort_by(|(hash1, _, _), (hash2, _, _)| hash1.cmp(hash2));
+        let result = GlobalIndex::new(all_items);
 
-        let mut result = GlobalIndex {
-            fanout: [0u32; 256],
-            hashes: Vec::with_capacity(all_items.len()),
-            locations: Vec::with_capacity(all_items.len()),
-        };
-        let mut hash_prefix = 0u8;
-        for (i, (hash, pack, offset)) in all_items.into_iter().enumerate() {
-            while hash_prefix < hash[0] {
-                result.fanout[hash_prefix as usize] = (i - 1) as u32;
-                hash_prefix += 1;
+        if !index_paths.is_empty() {
+            // a read-only repository can still be read, just without the cache
+            let written = NamedTempFile::new_in(&self.gitdir)
+                .and_then(|mut file| {
+                    file.write_all(&result.to_bytes(key))?;
+                    file.persist(&cache_path).map_err(|e| e.error)
+                })
+                .map(drop);
+            if let Err(e) = written {
+                debug!("could not cache global index: {e}");
             }
-            result.hashes.push(hash);
-            result.locations.push(PackFile(pack, offset));
         }
 
         self.global_index.replace(Some(result));
@@ -352,29 +818,35 @@ impl Repository {
     }
 
     fn find_object_location(&self, sha1: [u8; 20]) -> Option<ObjectLocation> {
+        self.object_locations(sha1).into_iter().next()
+    }
+
+    /// Every copy of an object, packed before loose as git looks for them.
+    fn object_locations(&self, sha1: [u8; 20]) -> Vec<ObjectLocation> {
+        let mut locations = Vec::new();
         {
             let global_index = self.global_index.borrow();
             if global_index.is_none() {
                 drop(global_index);
-                self.init_global_index().ok()?;
+                if let Err(e) = self.init_global_index() {
+                    debug!("could not build global index: {e:#}");
+                }
             }
         }
         {
             let global = self.global_index.borrow();
-            let global = global.iter().next()?;
-            let location = global.search(sha1);
-            if location.is_some() {
-                return location;
+            if let Some(global) = global.as_ref() {
+                locations.extend(global.search(sha1));
             }
         }
 
-        if let Some(path) = self.object_file_path(sha1) {
-            if path.is_file() {
-                return Some(ObjectFile);
-            }
+        if let Some(path) = self.object_file_path(sha1)
+            && path.is_file()
+        {
+            locations.push(ObjectFile);
         }
 
-        None
+        locations
     }
 
     fn open_index(&self, path: &Path) -> Result<PackIndex> {
@@ -382,22 +854,208 @@ impl Repository {
             .with_context(|| format!("opening pack index file {}", path.to_string_lossy()))?;
         let index = PackIndex::new(BufReader::new(file))
             .with_context(|| format!("opening pack index file {}", path.to_string_lossy()))?;
+        // without a usable .rev the index sorts its offsets when first asked
+        let rev_path = path.with_extension("rev");
+        if let Ok(file) = File::open(&rev_path)
+            && let Err(e) = index.read_reverse_index(BufReader::new(file))
+        {
+            warn!("ignoring {}: {:#}", rev_path.to_string_lossy(), e);
+        }
         Ok(index)
     }
 
-    pub fn read_object_data(&self, sha1: [u8; 20], data: &mut Vec<u8>) -> Result<BinaryObject> {
-        let location = self
-            .find_object_location(sha1)
-            .context("Failed to find object")?;
-        self.read_object_from_location(sha1, location, data)
-            .context("reading object from location")
+    pub fn read_object_data(
+        &self,
+        sha1: [u8; 20],
+        data: &mut Vec<u8>,
+    ) -> Result<BinaryObject, WyagError> {
+        Ok(self.read_object_with_source(sha1, data)?.0)
     }
 
-    fn open_pack(&self, id: [u8; 20]) -> Result<Rc<Pack<File>>> {
-        let value = {
-            let cache = self.pack_cache.borrow();
-            cache.get(&id).cloned()
+    /// Read an object and say where it came from. A copy that fails to read
+    /// is passed over for the next, so a stale pack entry or a corrupt loose
+    /// file does not hide a good copy elsewhere.
+    pub fn read_object_with_source(
+        &self,
+        sha1: [u8; 20],
+        data: &mut Vec<u8>,
+    ) -> Result<(BinaryObject, ObjectSource), WyagError> {
+        let sha1 = self.replacements.get(&sha1).copied().unwrap_or(sha1);
+        self.read_stored_object(sha1, data)
+    }
+
+    /// Read the object stored under `sha1` itself, not following `refs/replace`,
+    /// as gc, prune and fsck must so they keep the objects that were replaced.
+    fn read_stored_object(
+        &self,
+        sha1: [u8; 20],
+        data: &mut Vec<u8>,
+    ) -> Result<(BinaryObject, ObjectSource), WyagError> {
+        let result = self.read_first_copy(sha1, data);
+        // a concurrent gc may have removed a pack since the global index was
+        // built, so look again once in case the object has moved to another
+        if result.is_err()
+            && let Some(pack) = self.object_locations(sha1).iter().find_map(|l| match l {
+                PackFile(pack, _) if !self.pack_path(*pack).is_file() => Some(*pack),
+                _ => None,
+            })
+        {
+            debug!(
+                "pack-{} has gone, rebuilding the global index",
+                pack.encode_hex::<String>()
+            );
+            self.global_index.replace(None);
+            return self.read_first_copy(sha1, data);
+        }
+        result
+    }
+
+    /// Read the first copy of `sha1` that can be read, in the order of `object_locations`.
+    fn read_first_copy(
+        &self,
+        sha1: [u8; 20],
+        data: &mut Vec<u8>,
+    ) -> Result<(BinaryObject, ObjectSource), WyagError> {
+        let locations = self.object_locations(sha1);
+        if locations.is_empty() {
+            // git never stores the empty tree, so commits may refer to it
+            // without the repository having it
+            if let Some(object_type) = empty_object_type(sha1) {
+                data.clear();
+                return Ok((object_type, ObjectSource::Synthesized));
+            }
+            return Err(self.missing_object(sha1));
+        }
+
+        let mut first_error = None;
+        for location in locations {
+            data.clear();
+            match self.read_object_from_location(sha1, location, data) {
+                Ok(object_type) => {
+                    let source = match location {
+                        ObjectFile => {
+                            ObjectSource::Loose(self.object_file_path(sha1).unwrap_or_default())
+                        }
+                        PackFile(pack, offset) => ObjectSource::Packed { pack, offset },
+                    };
+                    return Ok((object_type, source));
+                }
+                Err(e) => {
+                    warn!("could not read {}: {:#}", sha1.encode_hex::<String>(), e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        let e = first_error.expect("at least one location was tried");
+        Err(WyagError::reading(
+            sha1,
+            e.context("reading object from location"),
+        ))
+    }
+
+    /// The type of an object, reading only a loose object's header or a packed
+    /// entry's type, and for a delta the type of the object at the end of its
+    /// chain of bases, so nothing is inflated past the headers.
+    pub fn object_type(&self, sha1: [u8; 20]) -> Result<BinaryObject> {
+        let sha1 = self.replacements.get(&sha1).copied().unwrap_or(sha1);
+        let locations = self.object_locations(sha1);
+        if locations.is_empty() {
+            return match empty_object_type(sha1) {
+                Some(object_type) => Ok(object_type),
+                None => Err(self.missing_object(sha1).into()),
+            };
+        }
+
+        let mut first_error = None;
+        for location in locations {
+            match self.location_object_type(sha1, location) {
+                Ok(object_type) => return Ok(object_type),
+                Err(e) => {
+                    warn!("could not read {}: {:#}", sha1.encode_hex::<String>(), e);
+                    first_error.get_or_insert(e);
+                }
+            }
+        }
+        Err(first_error
+            .expect("at least one location was tried")
+            .context(format!("reading type of {}", sha1.encode_hex::<String>())))
+    }
+
+    fn location_object_type(
+        &self,
+        sha1: [u8; 20],
+        location: ObjectLocation,
+    ) -> Result<BinaryObject> {
+        let (pack, mut offset) = match location {
+            ObjectFile => {
+                let path = self.object_file_path(sha1).with_context(|| {
+                    format!("Could not load object {}", sha1.encode_hex::<String>())
+                })?;
+                let file = File::open(path).context("opening object file")?;
+                let mut decoder = ZlibDecoder::new(BufReader::new(file));
+                let (type_name, _) =
+                    read_raw_object_header(&mut decoder).context("reading object header")?;
+                return object_type_from_name(&type_name);
+            }
+            PackFile(pack, offset) => (pack, offset),
         };
+        let packfile = self
+            .open_pack(pack)
+            .with_context(|| format!("opening pack {}", pack.encode_hex::<String>()))?;
+        loop {
+            match packfile.read_type_at(offset)? {
+                OffsetDelta(delta_offset) => offset = delta_base_offset(offset, delta_offset)?,
+                RefDelta(base) => return self.object_type(base),
+                object_type => return Ok(object_type),
+            }
+        }
+    }
+
+    fn missing_object(&self, sha1: [u8; 20]) -> WyagError {
+        let sha = sha1.encode_hex::<String>();
+        match self.shallow() {
+            Ok(shallow) if !shallow.is_empty() => WyagError::NotFound(format!(
+                "Failed to find object {}, it may be beyond the shallow boundary",
+                sha
+            )),
+            _ => WyagError::NotFound(format!("Failed to find object {}", sha)),
+        }
+    }
+
+    /// Read an object without requiring a type git knows, returning the type
+    /// word from its header. Only loose objects can have unknown types; packed
+    /// ones are read as usual.
+    pub fn read_raw_object(&self, sha1: [u8; 20], data: &mut Vec<u8>) -> Result<String, WyagError> {
+        let sha1 = self.replacements.get(&sha1).copied().unwrap_or(sha1);
+        match self.find_object_location(sha1) {
+            Some(ObjectFile) => self
+                .read_raw_object_file(sha1, data)
+                .map(|type_name| String::from_utf8_lossy(&type_name).into_owned())
+                .map_err(|e| WyagError::reading(sha1, e)),
+            _ => Ok(self.read_object_data(sha1, data)?.name()),
+        }
+    }
+
+    /// Read an object, failing if it is not of the `expected` type.
+    pub fn read_object_data_as(
+        &self,
+        sha1: [u8; 20],
+        expected: ObjectType,
+        data: &mut Vec<u8>,
+    ) -> Result<BinaryObject, WyagError> {
+        let object_type = self.read_object_data(sha1, data)?;
+        if object_type.name() != expected.to_string() {
+            return Err(WyagError::WrongType {
+                sha: sha1,
+                expected: expected.to_string(),
+                actual: object_type.name(),
+            });
+        }
+        Ok(object_type)
+    }
+
+    fn open_pack(&self, id: [u8; 20]) -> Result<Rc<Pack<File>>> {
+        let value = self.pack_cache.borrow_mut().get(id);
         let pack = match value {
             None => {
                 let packfile_name = format!("pack-{}.pack", id.encode_hex::<String>());
@@ -405,7 +1063,23 @@ impl Repository {
                 let pack = match self.repo_file(&packfile_path, false) {
                     Some(packfile_path) => {
                         let file = File::open(packfile_path).context("opening packfile file")?;
-                        Pack::new(BufReader::new(file)).context("opening packfile")?
+                        let pack = Pack::new(BufReader::new(file)).context("opening packfile")?;
+                        // off by default, as it reads the whole pack on first use
+                        if self
+                            .config_get("core", "verifypackchecksum")
+                            .is_some_and(|v| v == "true")
+                        {
+                            let sha1 = pack.validate_checksum().with_context(|| {
+                                format!("verifying pack-{}.pack", id.encode_hex::<String>())
+                            })?;
+                            ensure!(
+                                sha1 == id,
+                                "pack-{}.pack has checksum {}",
+                                id.encode_hex::<String>(),
+                                sha1.encode_hex::<String>()
+                            );
+                        }
+                        pack
                     }
                     None => bail!("Failed to load packfile"),
                 };
@@ -418,6 +1092,15 @@ impl Repository {
         Ok(pack)
     }
 
+    /// Where the pack `id` is, whether or not it is there.
+    fn pack_path(&self, id: [u8; 20]) -> PathBuf {
+        self.repo_path(
+            &Path::new("objects")
+                .join("pack")
+                .join(format!("pack-{}.pack", id.encode_hex::<String>())),
+        )
+    }
+
     fn read_object_from_location(
         &self,
         sha1: [u8; 20],
@@ -438,7 +1121,7 @@ impl Repository {
                     .with_context(|| format!("opening pack {}", pack.encode_hex::<String>()))?;
                 let object_type =
                     packfile
-                        .read_object_data_at(offset, data)
+                        .read_object_data_into(offset, data)
                         .with_context(|| {
                             format!(
                                 "reading object {} from packfile {} at {}",
@@ -455,6 +1138,9 @@ impl Repository {
                         data.len()
                     );
 
+                    if !self.check_object_content {
+                        return Ok(object_type);
+                    }
                     validate_sha1(sha1, object_type, data).with_context(|| {
                         format!(
                             "reading {} from pack {} at {}",
@@ -478,6 +1164,9 @@ impl Repository {
                     })?;
                 data.truncate(0);
                 data.extend_from_slice(&unpacked_data);
+                if !self.check_object_content {
+                    return Ok(object_type);
+                }
                 validate_sha1(sha1, object_type, data).with_context(|| {
                     format!(
                         "reading {} from pack {} at {} - unpacked",
@@ -502,9 +1191,9 @@ impl Repository {
         let (reference_type, reference_data) = match object_type {
             OffsetDelta(delta_offset) => {
                 let mut reference_data = Vec::new();
-                let reference_offset = offset - delta_offset;
+                let reference_offset = delta_base_offset(offset, delta_offset)?;
                 let reference_type = packfile
-                    .read_object_data_at(reference_offset, &mut reference_data)
+                    .read_object_data_into(reference_offset, &mut reference_data)
                     .context("reading object in packfile")?;
 
                 if !reference_type.is_delta() {
@@ -560,70 +1249,201 @@ impl Repository {
         ))
     }
 
-    pub fn find_object(&self, name: &str) -> Result<[u8; 20]> {
-        if let Ok(hash) = decode(name) {
-            if let Ok(hash) = hash.try_into() {
-                return Ok(hash);
+    pub fn find_object(&self, name: &str) -> Result<[u8; 20], WyagError> {
+        if let Some((rev, path)) = name.split_once(':') {
+            if rev.is_empty() {
+                let index = self.read_index().context("reading index")?;
+                return index
+                    .entries
+                    .iter()
+                    .find(|e| e.stage() == 0 && e.path == Path::new(path))
+                    .map(|e| e.sha1)
+                    .ok_or_else(|| {
+                        WyagError::NotFound(format!("path {} is not in the index", path))
+                    });
             }
+            let sha1 = self.find_object(rev)?;
+            return Ok(self
+                .object_at_path(sha1, Path::new(path))
+                .with_context(|| format!("resolving path {} in {}", path, rev))?);
         }
 
-        if let Some(buf) = self.repo_file(&Path::new("refs").join("heads").join(name), false) {
-            if buf.is_file() {
-                let mut ref_contents = String::new();
-                File::open(buf)
-                    .context("opening object file")?
-                    .read_to_string(&mut ref_contents)
-                    .context("reading object")?;
-                let ref_contents = ref_contents.trim_end_matches([' ', '\t', '\n', '\r']);
-                return if let Some(ref_contents) = ref_contents.strip_prefix("ref: ") {
-                    self.find_object(ref_contents)
-                } else {
-                    let sha1_decode: Result<[u8; 20], _> = match decode(ref_contents) {
-                        Ok(sha1) => sha1.try_into(),
-                        _ => bail!(
-                            "Failed to decode reference file contents: '{}'",
-                            ref_contents
-                        ),
-                    };
-                    match sha1_decode {
-                        Ok(result) => Ok(result),
-                        _ => bail!("sha1 has incorrect length"),
-                    }
-                };
-            }
+        if let Some(rev) = name.strip_suffix('}')
+            && let Some((rev, n)) = rev.rsplit_once("@{")
+            && let Ok(n) = n.parse::<usize>()
+        {
+            return Ok(self.reflog_entry(rev, n)?);
         }
 
-        bail!("reference does not exist: {}", name)
-    }
+        if let Ok(hash) = decode(name)
+            && let Ok(hash) = hash.try_into()
+        {
+            return Ok(hash);
+        }
 
-    pub fn write_object(&self, obj: &GitObject, write: bool) -> Result<[u8; 20]> {
-        let mut bytes = {
-            let serialized = obj.serialize();
+        if let Some((_, sha1)) = self.resolve_ref(name)? {
+            return Ok(sha1);
+        }
 
-            let bytes = Bytes::from_iter(
-                obj.name()
-                    .iter()
-                    .chain(b" ")
-                    .chain(serialized.len().to_string().as_bytes())
-                    .chain(b"\0")
-                    .chain(&serialized)
-                    .copied(),
-            )
-            .reader();
-            HashingReader::new(bytes)
-        };
+        Err(WyagError::NotFound(format!(
+            "reference does not exist: {}",
+            name
+        )))
+    }
 
-        let sha1 = if write {
-            let file = NamedTempFile::new().context("creating temp file")?;
+    /// Find the ref a short name refers to, trying the same places in the
+    /// same order as git: `name`, `refs/name`, `refs/tags/name`,
+    /// `refs/heads/name`, `refs/remotes/name` and `refs/remotes/name/HEAD`.
+    /// The first that exists wins; a warning is logged if others exist too,
+    /// unless `core.warnAmbiguousRefs` is false.
+    pub fn resolve_ref(&self, name: &str) -> Result<Option<(String, [u8; 20])>> {
+        let mut candidates = Vec::new();
+        // git only looks for names outside refs/ directly under the git dir
+        // when they look like HEAD, FETCH_HEAD and the like
+        if name.starts_with("refs/") || name.bytes().all(|b| b.is_ascii_uppercase() || b == b'_') {
+            candidates.push(name.to_string());
+        }
+        candidates.extend([
+            format!("refs/{}", name),
+            format!("refs/tags/{}", name),
+            format!("refs/heads/{}", name),
+            format!("refs/remotes/{}", name),
+            format!("refs/remotes/{}/HEAD", name),
+        ]);
+
+        let warn = self
+            .config_get("core", "warnambiguousrefs")
+            .is_none_or(|v| v != "false");
+        let mut found: Option<(String, [u8; 20])> = None;
+        for candidate in candidates {
+            let Some(sha1) = self.read_ref(Path::new(&candidate))? else {
+                continue;
+            };
+            match &found {
+                None if !warn => return Ok(Some((candidate, sha1))),
+                None => found = Some((candidate, sha1)),
+                Some((first, _)) => {
+                    warn!("refname '{}' is ambiguous, using {}", name, first);
+                    break;
+                }
+            }
+        }
+        Ok(found)
+    }
+
+    /// Resolve the commit HEAD points at, or `None` on an unborn branch.
+    pub fn head(&self) -> Result<Option<[u8; 20]>> {
+        self.read_ref(Path::new("HEAD")).context("reading HEAD")
+    }
+
+    /// The commits listed in `shallow`, whose parents were left out of a
+    /// shallow clone and must be treated as if they had none.
+    pub fn shallow(&self) -> Result<HashSet<[u8; 20]>> {
+        let Some(path) = self.repo_file(Path::new("shallow"), false) else {
+            return Ok(HashSet::new());
+        };
+        if !path.is_file() {
+            return Ok(HashSet::new());
+        }
+        std::fs::read_to_string(&path)
+            .context("reading shallow")?
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| {
+                decode(line)
+                    .ok()
+                    .and_then(|sha1| sha1.try_into().ok())
+                    .with_context(|| format!("bad sha1 in shallow: {}", line))
+            })
+            .collect()
+    }
+
+    /// Read a ref file under the gitdir, following symbolic refs. Returns
+    /// `None` if the ref, or the ref it points to, does not exist.
+    fn read_ref(&self, path: &Path) -> Result<Option<[u8; 20]>> {
+        let buf = match self.repo_file(path, false) {
+            Some(buf) if buf.is_file() => buf,
+            _ => {
+                let name = path.to_string_lossy();
+                return Ok(self.packed_refs()?.get(name.as_ref()).copied());
+            }
+        };
+
+        let mut ref_contents = String::new();
+        File::open(buf)
+            .context("opening ref file")?
+            .read_to_string(&mut ref_contents)
+            .context("reading ref")?;
+        let ref_contents = ref_contents.trim_end_matches([' ', '\t', '\n', '\r']);
+        if let Some(ref_contents) = ref_contents.strip_prefix("ref: ") {
+            return self.read_ref(Path::new(ref_contents));
+        }
+
+        let sha1_decode: Result<[u8; 20], _> = match decode(ref_contents) {
+            Ok(sha1) => sha1.try_into(),
+            _ => bail!(
+                "Failed to decode reference file contents: '{}'",
+                ref_contents
+            ),
+        };
+        match sha1_decode {
+            Ok(result) => Ok(Some(result)),
+            _ => bail!("sha1 has incorrect length"),
+        }
+    }
+
+    /// Hash, and optionally store, an object. `compression` only changes how
+    /// the object is stored, never its sha1.
+    pub fn write_object(
+        &self,
+        obj: &GitObject,
+        write: bool,
+        compression: Compression,
+    ) -> Result<[u8; 20]> {
+        let serialized = obj.try_serialize()?;
+        self.write_object_stream(
+            obj.name(),
+            serialized.len() as u64,
+            &serialized[..],
+            write,
+            compression,
+        )
+    }
+
+    /// Hash, and optionally store, an object whose `len` bytes of data are read from `data`,
+    /// without holding the whole object in memory.
+    fn write_object_stream<R: Read>(
+        &self,
+        name: &[u8],
+        len: u64,
+        data: R,
+        write: bool,
+        compression: Compression,
+    ) -> Result<[u8; 20]> {
+        let header = Bytes::from_iter(
+            name.iter()
+                .chain(b" ")
+                .chain(len.to_string().as_bytes())
+                .chain(b"\0")
+                .copied(),
+        );
+        let expected = header.len() as u64 + len;
+        let mut bytes = HashingReader::new(header.reader().chain(data.take(len)));
+
+        let sha1 = if write {
+            let file = NamedTempFile::new_in(&self.gitdir).context("creating temp file")?;
 
-            let mut encoder = ZlibEncoder::new(BufReader::new(&mut bytes), Compression::default());
+            let mut encoder = ZlibEncoder::new(BufReader::new(&mut bytes), compression);
             io::copy(&mut encoder, &mut BufWriter::new(&file))
                 .context("copying compressed data to object file")?;
+            ensure!(
+                bytes.bytes_read() == expected,
+                "object data changed while hashing"
+            );
 
             let sha1 = bytes.finalize();
             let sha1_hex = sha1.encode_hex::<String>();
 
-            let path = file.path();
             let new_path = self
                 .repo_file(
                     &Path::new("objects")
@@ -632,115 +1452,4110 @@ impl Repository {
                     true,
                 )
                 .context("could not create path to object file")?;
-            std::fs::rename(path, new_path)?;
+            if new_path.is_file() {
+                // the sha says the contents are the same, so only check the
+                // header in case the existing file is damaged
+                self.check_existing_object(&new_path, name, len)
+                    .with_context(|| format!("object {} already exists", sha1_hex))?;
+                return Ok(sha1);
+            }
+            self.persist(file, &new_path)
+                .context("moving object file into place")?;
+            // objects never change once written, so git makes them read-only
+            #[cfg(unix)]
+            std::fs::set_permissions(&new_path, std::fs::Permissions::from_mode(0o444))
+                .context("making object file read-only")?;
 
             sha1
         } else {
             io::copy(&mut bytes, &mut sink()).context("")?;
+            ensure!(
+                bytes.bytes_read() == expected,
+                "object data changed while hashing"
+            );
             bytes.finalize()
         };
 
         Ok(sha1)
     }
 
-    pub fn object_hash(
-        &self,
-        path: &Path,
-        object_type: CommandObjectType,
-        write: bool,
-    ) -> Result<[u8; 20]> {
-        let data = {
-            let mut file = File::open(path)?;
-            let mut buf = Vec::new();
-            file.read_to_end(&mut buf)?;
-            buf
-        };
+    /// Check that the loose object at `path` has the type and size it should,
+    /// and touch it so that `prune` treats it as newly written.
+    fn check_existing_object(&self, path: &Path, name: &[u8], len: u64) -> Result<()> {
+        let file = File::open(path).context("opening object file")?;
+        let (type_name, size) =
+            read_raw_object_header(&mut ZlibDecoder::new(BufReader::new(&file)))
+                .context("reading object header, the object file may be corrupt")?;
+        ensure!(
+            type_name == name && size as u64 == len,
+            "object file is a {} of {} bytes rather than a {} of {}, it may be corrupt",
+            String::from_utf8_lossy(&type_name),
+            size,
+            String::from_utf8_lossy(name),
+            len
+        );
+        if let Err(e) = file.set_modified(SystemTime::now()) {
+            debug!("could not freshen {}: {}", path.to_string_lossy(), e);
+        }
+        Ok(())
+    }
 
-        let obj = match object_type {
-            CommandObjectType::Blob => GitObject::Blob(BlobObject::from(data)),
-            _ => todo!(),
-        };
+    /// Whether the object is in this repository, loose or packed.
+    pub fn has_object(&self, sha1: [u8; 20]) -> bool {
+        empty_object_type(sha1).is_some() || self.find_object_location(sha1).is_some()
+    }
 
-        self.write_object(&obj, write)
+    /// Store a loose object exactly as another repository had it on disk, checking that its
+    /// contents match `sha1`.
+    pub(crate) fn store_loose_object(&self, sha1: [u8; 20], compressed: &[u8]) -> Result<()> {
+        let mut decoder = ZlibDecoder::new(compressed);
+        let (object_type, size) =
+            read_object_header(&mut decoder).context("reading object header")?;
+        let mut data = Vec::with_capacity(size);
+        decoder.read_to_end(&mut data).context("reading object")?;
+        ensure!(
+            size == data.len(),
+            "object corrupt: size {} does not match expected {}",
+            data.len(),
+            size,
+        );
+        validate_sha1(sha1, object_type, &data).context("validating object sha1")?;
+        self.write_object_stream(
+            object_type.name().as_bytes(),
+            size as u64,
+            &data[..],
+            true,
+            Compression::default(),
+        )?;
+        Ok(())
     }
 
-    pub fn read_packfile(&self, packfile_sha: &str) -> Result<Vec<(BinaryObject, Vec<u8>)>> {
+    /// Store a pack or pack index file named `pack-<sha>.pack` or `pack-<sha>.idx`.
+    pub(crate) fn store_pack(&self, name: &str, data: &[u8]) -> Result<()> {
+        let sha = name
+            .strip_prefix("pack-")
+            .and_then(|n| n.strip_suffix(".pack").or_else(|| n.strip_suffix(".idx")))
+            .with_context(|| format!("{} is not a pack file name", name))?;
+        ensure!(
+            sha.len() == 40 && decode(sha).is_ok(),
+            "{} is not a pack file name",
+            name
+        );
+
         let path = self
-            .repo_file(
-                &Path::new("objects")
-                    .join("pack")
-                    .join(format!("pack-{}.pack", packfile_sha)),
-                false,
-            )
-            .context("Packfile does not exist")?;
+            .repo_mkdir(&Path::new("objects").join("pack"))
+            .context("could not create pack directory")?
+            .join(name);
+        let mut file = NamedTempFile::new_in(&self.gitdir).context("creating temp file")?;
+        file.write_all(data).context("writing pack")?;
+        self.persist(file, &path).context("replacing pack")?;
 
-        let reader = BufReader::new(File::open(path)?);
-        Pack::new(reader)?.read_all()
+        // the next lookup rebuilds the index with this pack in it
+        self.global_index.replace(None);
+        Ok(())
     }
 
-    pub fn ls_tree(&self, reference: &str, recurse: bool, path: &Path) -> Result<()> {
-        trace!("finding object {}", reference);
-        let sha1 = self.find_object(reference)?;
-        trace!("reading object {}", sha1.encode_hex::<String>());
+    /// Index a pack received in full, as `index-pack` does, and store it alongside its new
+    /// index. A thin pack is first completed with its delta bases from this repository.
+    /// Returns the pack's checksum, which names both files.
+    pub fn index_pack(&self, data: &[u8], compression: Compression) -> Result<[u8; 20]> {
+        let pack = Pack::new(BufReader::new(io::Cursor::new(data))).context("opening pack")?;
+        let (entries, pack_sha1, missing) = pack.index_thin_entries().context("indexing pack")?;
+        if missing.is_empty() {
+            return self.store_indexed_pack(data, entries, pack_sha1);
+        }
 
-        let mut data = Vec::new();
-        let object = match self.read_object_data(sha1, &mut data)? {
-            Tree => TreeObject::new(&data)?,
-            _ => bail!("object not a tree"),
+        debug!("completing thin pack with {} bases", missing.len());
+        let bases = missing
+            .iter()
+            .map(|base| {
+                let mut data = Vec::new();
+                let object_type = self.read_object_data(*base, &mut data).with_context(|| {
+                    format!(
+                        "thin pack delta base {} is not in the repository",
+                        base.encode_hex::<String>()
+                    )
+                })?;
+                Ok((object_type, data))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let data = append_objects(data, &bases, compression)?;
+        let pack = Pack::new(BufReader::new(io::Cursor::new(&data))).context("opening pack")?;
+        let (entries, pack_sha1) = pack.index_entries().context("indexing completed pack")?;
+        self.store_indexed_pack(&data, entries, pack_sha1)
+    }
+
+    /// Receive a pack as `download` writes it, spooling it to a temporary file, and store it
+    /// the way `index_pack` does. Nothing is named `pack-*` until the trailing checksum has
+    /// been checked and the pack indexed, and the index is moved into place after the pack,
+    /// so an interrupted or truncated transfer leaves no pack for a lookup to open.
+    pub fn download_pack(
+        &self,
+        download: impl FnOnce(&mut File) -> Result<()>,
+        compression: Compression,
+    ) -> Result<[u8; 20]> {
+        let mut file = NamedTempFile::new_in(&self.gitdir).context("creating temp file")?;
+        download(file.as_file_mut()).context("downloading pack")?;
+
+        let len = file.as_file_mut().seek(SeekFrom::End(0))?;
+        ensure!(len >= 32, "downloaded pack is truncated");
+        file.as_file_mut().rewind()?;
+        let mut reader = HashingReader::new(BufReader::new(file.as_file_mut().take(len - 20)));
+        io::copy(&mut reader, &mut sink()).context("reading downloaded pack")?;
+        let actual = reader.finalize();
+        let mut expected = [0; 20];
+        file.as_file_mut().read_exact(&mut expected)?;
+        ensure!(
+            actual == expected,
+            "downloaded pack is truncated or corrupt, its trailer is {} but its data hashes to {}",
+            expected.encode_hex::<String>(),
+            actual.encode_hex::<String>()
+        );
+
+        let pack = Pack::new(BufReader::new(file.reopen()?)).context("opening pack")?;
+        let (entries, pack_sha1, missing) = pack.index_thin_entries().context("indexing pack")?;
+        if !missing.is_empty() {
+            // completing a thin pack rewrites it, which is done in memory
+            let mut data = Vec::new();
+            file.as_file_mut().rewind()?;
+            file.read_to_end(&mut data)?;
+            return self.index_pack(&data, compression);
+        }
+
+        let name = format!("pack-{}", pack_sha1.encode_hex::<String>());
+        let pack_dir = self
+            .repo_mkdir(&Path::new("objects").join("pack"))
+            .context("could not create pack directory")?;
+        let mut index = NamedTempFile::new_in(&self.gitdir).context("creating temp file")?;
+        index
+            .write_all(&write_index(entries, pack_sha1))
+            .context("writing pack index")?;
+        self.persist(file, &pack_dir.join(format!("{}.pack", name)))
+            .context("storing pack")?;
+        self.persist(index, &pack_dir.join(format!("{}.idx", name)))
+            .context("storing pack index")?;
+
+        self.global_index.replace(None);
+        Ok(pack_sha1)
+    }
+
+    fn store_indexed_pack(
+        &self,
+        data: &[u8],
+        entries: Vec<PackIndexEntry>,
+        pack_sha1: [u8; 20],
+    ) -> Result<[u8; 20]> {
+        let name = format!("pack-{}", pack_sha1.encode_hex::<String>());
+        self.store_pack(&format!("{}.pack", name), data)?;
+        self.store_pack(&format!("{}.idx", name), &write_index(entries, pack_sha1))?;
+        Ok(pack_sha1)
+    }
+
+    /// Every loose object in the object directory, sorted.
+    pub fn loose_objects(&self) -> Result<Vec<[u8; 20]>> {
+        Ok(self
+            .loose_object_entries()?
+            .into_iter()
+            .filter_map(|entry| entry.ok())
+            .collect())
+    }
+
+    /// Every file in the `xx/` fan-out directories of the object store: the
+    /// object it holds, or its path if it is not named with the remaining 38
+    /// hex digits of one, such as a temporary file left by an interrupted
+    /// write. Objects come first, sorted. `pack/`, `info/` and the like are
+    /// not read, and the fan-out directories are shared out between threads.
+    pub fn loose_object_entries(&self) -> Result<Vec<Result<[u8; 20], PathBuf>>> {
+        let Ok(dirs) = self.repo_path(Path::new("objects")).read_dir() else {
+            return Ok(Vec::new());
         };
+        let mut shards = Vec::new();
+        for dir in dirs {
+            let dir = dir.context("reading objects directory")?;
+            if dir.file_name().to_str().is_some_and(|name| is_hex(name, 2))
+                && dir.file_type().is_ok_and(|t| t.is_dir())
+            {
+                shards.push(dir.path());
+            }
+        }
 
-        trace!("iterating leaf {}", path.to_string_lossy());
+        let threads = thread::available_parallelism().map_or(1, |n| n.get());
+        let chunk_size = shards.len().div_ceil(threads).max(1);
+        let mut entries = thread::scope(|scope| {
+            let workers = shards
+                .chunks(chunk_size)
+                .map(|chunk| {
+                    scope.spawn(move || {
+                        chunk
+                            .iter()
+                            .map(|dir| read_loose_shard(dir))
+                            .collect::<Result<Vec<_>>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .map(|worker| {
+                    worker
+                        .join()
+                        .expect("object directory reader thread panicked")
+                })
+                .collect::<Result<Vec<_>>>()
+        })?
+        .into_iter()
+        .flatten()
+        .flatten()
+        .collect::<Vec<_>>();
+        entries.sort();
+        Ok(entries)
+    }
 
-        for item in object.leaf_iter() {
-            let _type = match &item.mode[..2] {
-                "04" => "tree",
-                "10" | "12" => "blob",
-                "16" => "commit",
-                _ => bail!(
-                    "weird TreeLeaf mode {} on {}",
-                    &item.mode[..2],
-                    item.path.to_string_lossy()
-                ),
-            };
+    /// Check that every loose object reads back and hashes to its name and
+    /// that every pack verifies, returning what is wrong. Objects are not
+    /// checked for being reachable or for pointing at objects that exist.
+    pub fn fsck(&self) -> Result<Vec<FsckProblem>> {
+        let mut problems = Vec::new();
+        for entry in self.loose_object_entries()? {
+            match entry {
+                Ok(sha1) => {
+                    let mut data = Vec::new();
+                    if let Err(e) = self.read_object_from_location(sha1, ObjectFile, &mut data) {
+                        problems.push(FsckProblem::Corrupt(sha1, format!("{:#}", e)));
+                    }
+                }
+                Err(path) => problems.push(FsckProblem::Garbage(path)),
+            }
+        }
 
-            if recurse && _type == "tree" {
-                self.ls_tree(
-                    &item.sha1.encode_hex::<String>(),
-                    recurse,
-                    &path.join(&item.path),
+        let pack_dir = self.repo_path(Path::new("objects/pack"));
+        let mut packs = pack_dir
+            .read_dir()
+            .into_iter()
+            .flatten()
+            .filter_map(|entry| {
+                let name = entry.ok()?.file_name().into_string().ok()?;
+                Some(
+                    name.strip_prefix("pack-")?
+                        .strip_suffix(".idx")?
+                        .to_string(),
                 )
-                .with_context(|| {
-                    format!("Failed to descend tree in {}", item.path.to_string_lossy())
-                })?;
-            } else {
-                trace!(
-                    "{} {} {} {}",
-                    item.mode,
-                    _type,
-                    item.sha1.encode_hex::<String>(),
-                    path.join(&item.path).to_string_lossy()
+            })
+            .collect::<Vec<_>>();
+        packs.sort();
+        for pack in packs {
+            if let Err(e) = self.verify_pack(&pack, 1) {
+                problems.push(FsckProblem::BadPack(pack, format!("{:#}", e)));
+            }
+        }
+        Ok(problems)
+    }
+
+    /// Tally the loose and packed objects, as `git count-objects -v` does.
+    pub fn count_objects(&self) -> Result<ObjectCounts> {
+        let mut counts = ObjectCounts::default();
+        for entry in self.loose_object_entries()? {
+            let sha1 = match entry {
+                Ok(sha1) => sha1,
+                Err(path) => {
+                    counts.garbage += 1;
+                    counts.size_garbage += path.metadata().map_or(0, |m| m.blocks() * 512);
+                    continue;
+                }
+            };
+            let Some(path) = self.object_file_path(sha1) else {
+                continue;
+            };
+            counts.count += 1;
+            counts.size += path.metadata().context("reading loose object")?.blocks() * 512;
+            if self
+                .object_locations(sha1)
+                .iter()
+                .any(|l| matches!(l, PackFile(..)))
+            {
+                counts.prune_packable += 1;
+            }
+        }
+
+        let pack_dir = self.repo_path(Path::new("objects/pack"));
+        for entry in pack_dir.read_dir().into_iter().flatten() {
+            let path = entry.context("reading pack directory")?.path();
+            let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+                continue;
+            };
+            if !name.starts_with("pack-") || !name.ends_with(".idx") {
+                continue;
+            }
+            let pack_path = path.with_extension("pack");
+            let Ok(pack_metadata) = pack_path.metadata() else {
+                continue;
+            };
+            counts.packs += 1;
+            counts.in_pack += self.open_index(&path)?.object_count();
+            counts.size_pack += pack_metadata.len() + path.metadata()?.len();
+        }
+        Ok(counts)
+    }
+
+    /// Every object reachable from HEAD, the refs and their reflogs, along
+    /// with everything staged in the index.
+    pub fn reachable_objects(&self) -> Result<HashSet<[u8; 20]>> {
+        let refs = self.refs()?;
+        let mut pending = refs.iter().map(|(_, sha1)| *sha1).collect::<Vec<_>>();
+        pending.extend(self.head()?);
+        for name in refs.iter().map(|(name, _)| name.as_str()).chain(["HEAD"]) {
+            if let Ok(entries) = self.reflog(name) {
+                pending.extend(
+                    entries
+                        .iter()
+                        .map(|e| e.new)
+                        .filter(|sha1| *sha1 != [0; 20]),
+                );
+            }
+        }
+
+        let shallow = self.shallow()?;
+        let mut seen = self
+            .read_index()?
+            .entries
+            .iter()
+            .map(|entry| entry.sha1)
+            .collect::<HashSet<_>>();
+        while let Some(sha1) = pending.pop() {
+            if !seen.insert(sha1) {
+                continue;
+            }
+            let mut data = Vec::new();
+            let (object_type, _) = self
+                .read_stored_object(sha1, &mut data)
+                .with_context(|| format!("reading object {}", sha1.encode_hex::<String>()))?;
+            match GitObject::new(object_type, data)? {
+                GitObject::Commit(commit) => {
+                    pending.extend(commit.tree());
+                    if !shallow.contains(&sha1) {
+                        pending.extend(self.parents(sha1, &commit));
+                    }
+                }
+                GitObject::Tree(tree) => {
+                    for leaf in tree.leaf_iter() {
+                        let leaf_sha1 = leaf.sha1.as_slice().try_into()?;
+                        match leaf.kind() {
+                            Some(LeafKind::Tree) => pending.push(leaf_sha1),
+                            // blobs reference nothing, so there is no need to read them
+                            Some(LeafKind::Blob | LeafKind::Symlink) => {
+                                seen.insert(leaf_sha1);
+                            }
+                            Some(LeafKind::Gitlink) | None => {}
+                        }
+                    }
+                }
+                GitObject::Tag(tag) => pending.extend(tag.object()),
+                _ => {}
+            }
+        }
+        Ok(seen)
+    }
+
+    /// Pack the reachable loose objects into a new pack, then delete the loose
+    /// copies of those older than `grace`. Objects a kept pack already holds
+    /// are not packed again, only their loose copies removed. Returns how many
+    /// objects were packed and how many loose files were removed.
+    pub fn gc(&self, grace: Duration, compression: Compression) -> Result<(usize, usize)> {
+        let reachable = self
+            .reachable_objects()
+            .context("finding reachable objects")?;
+        let kept = self.kept_packs()?;
+        let (kept_loose, loose) = self
+            .loose_objects()?
+            .into_iter()
+            .filter(|sha1| reachable.contains(sha1))
+            .partition::<Vec<_>, _>(|sha1| {
+                self.object_locations(*sha1)
+                    .iter()
+                    .any(|l| matches!(l, PackFile(pack, _) if kept.contains(pack)))
+            });
+        let mut packed = Vec::new();
+        if !loose.is_empty() {
+            let objects = loose
+                .iter()
+                .map(|sha1| {
+                    let mut data = Vec::new();
+                    let (object_type, _) = self.read_stored_object(*sha1, &mut data)?;
+                    Ok((object_type, data))
+                })
+                .collect::<Result<Vec<_>>>()?;
+            let pack_sha1 = self.index_pack(&write_pack(&objects, compression)?, compression)?;
+            debug!(
+                "packed {} loose objects into pack-{}",
+                loose.len(),
+                pack_sha1.encode_hex::<String>()
+            );
+            // only loose copies the new pack is known to hold are removed
+            let index = self.open_index(&self.pack_path(pack_sha1).with_extension("idx"))?;
+            packed = loose
+                .iter()
+                .copied()
+                .filter(|sha1| index.find(*sha1).is_some())
+                .collect();
+            ensure!(
+                packed.len() == loose.len(),
+                "pack-{} is missing {} of the objects packed into it",
+                pack_sha1.encode_hex::<String>(),
+                loose.len() - packed.len()
+            );
+        }
+
+        let removed = self.remove_loose_objects(&[packed, kept_loose].concat(), grace, false)?;
+        Ok((loose.len(), removed.len()))
+    }
+
+    /// The packs marked with a `pack-<sha>.keep` file, which gc leaves as they are.
+    pub fn kept_packs(&self) -> Result<HashSet<[u8; 20]>> {
+        let pack_dir = self.repo_path(Path::new("objects/pack"));
+        let mut kept = HashSet::new();
+        for entry in pack_dir.read_dir().into_iter().flatten() {
+            let entry = entry.context("reading pack directory")?;
+            if let Some(name) = entry.file_name().to_str()
+                && let Some(sha1) = name
+                    .strip_prefix("pack-")
+                    .and_then(|n| n.strip_suffix(".keep"))
+                && is_hex(sha1, 40)
+            {
+                kept.insert(
+                    decode(sha1)?
+                        .try_into()
+                        .expect("40 hex digits are 20 bytes"),
                 );
             }
         }
+        Ok(kept)
+    }
+
+    /// Mark the pack `packfile_sha` with a `.keep` file, so gc never repacks or
+    /// removes it.
+    pub fn keep_pack(&self, packfile_sha: &str) -> Result<()> {
+        ensure!(
+            is_hex(packfile_sha, 40),
+            "{} is not a pack checksum",
+            packfile_sha
+        );
+        let path = self.repo_path(
+            &Path::new("objects")
+                .join("pack")
+                .join(format!("pack-{}.pack", packfile_sha)),
+        );
+        ensure!(path.is_file(), "pack-{} does not exist", packfile_sha);
+        std::fs::write(path.with_extension("keep"), "")
+            .with_context(|| format!("marking pack-{} as kept", packfile_sha))
+    }
+
+    /// Delete the loose objects that nothing reaches and that are older than
+    /// `grace`, or only list them if `dry_run` is set.
+    pub fn prune(&self, grace: Duration, dry_run: bool) -> Result<Vec<[u8; 20]>> {
+        let reachable = self
+            .reachable_objects()
+            .context("finding reachable objects")?;
+        let unreachable = self
+            .loose_objects()?
+            .into_iter()
+            .filter(|sha1| !reachable.contains(sha1))
+            .collect::<Vec<_>>();
+        self.remove_loose_objects(&unreachable, grace, dry_run)
+    }
+
+    /// Remove the loose files of `objects` last modified before `grace` ago,
+    /// returning the objects removed, or that would be with `dry_run`.
+    fn remove_loose_objects(
+        &self,
+        objects: &[[u8; 20]],
+        grace: Duration,
+        dry_run: bool,
+    ) -> Result<Vec<[u8; 20]>> {
+        let cutoff = SystemTime::now() - grace;
+        let mut removed = Vec::new();
+        for sha1 in objects.iter() {
+            let Some(path) = self.object_file_path(*sha1) else {
+                continue;
+            };
+            let modified = path.metadata().and_then(|m| m.modified())?;
+            if modified > cutoff {
+                continue;
+            }
+            removed.push(*sha1);
+            if dry_run {
+                continue;
+            }
+            std::fs::remove_file(&path)
+                .with_context(|| format!("removing {}", path.to_string_lossy()))?;
+            if let Some(dir) = path.parent() {
+                // only succeeds once the directory is empty
+                let _ = std::fs::remove_dir(dir);
+            }
+        }
+        Ok(removed)
+    }
+
+    /// The refs git has moved out of their own files into `packed-refs`.
+    /// Loose refs of the same name take precedence over these.
+    fn packed_refs(&self) -> Result<BTreeMap<String, [u8; 20]>> {
+        let path = self.repo_path(Path::new("packed-refs"));
+        if !path.is_file() {
+            return Ok(BTreeMap::new());
+        }
+        std::fs::read_to_string(&path)
+            .context("reading packed-refs")?
+            .lines()
+            // `^` lines hold the commit the annotated tag above them peels to
+            .filter(|line| !line.is_empty() && !line.starts_with('#') && !line.starts_with('^'))
+            .map(|line| {
+                let (sha, name) = line
+                    .split_once(' ')
+                    .with_context(|| format!("bad line in packed-refs: {}", line))?;
+                let sha1 = decode(sha)
+                    .ok()
+                    .and_then(|sha1| sha1.try_into().ok())
+                    .with_context(|| format!("bad sha1 in packed-refs: {}", line))?;
+                Ok((name.to_string(), sha1))
+            })
+            .collect()
+    }
+
+    /// Every ref under `refs/`, loose or packed, sorted by name, with the sha
+    /// it resolves to.
+    pub fn refs(&self) -> Result<Vec<(String, [u8; 20])>> {
+        let mut refs = Vec::new();
+        let mut pending = vec![PathBuf::from("refs")];
+        while let Some(dir) = pending.pop() {
+            let Ok(entries) = self.repo_path(&dir).read_dir() else {
+                continue;
+            };
+            for entry in entries {
+                let entry = entry.context("reading refs directory")?;
+                let path = dir.join(entry.file_name());
+                if entry.file_type()?.is_dir() {
+                    pending.push(path);
+                } else if let Some(sha1) = self.read_ref(&path)? {
+                    refs.push((path.to_string_lossy().into_owned(), sha1));
+                }
+            }
+        }
+        for (name, sha1) in self.packed_refs()? {
+            if !self.repo_path(Path::new(&name)).is_file() {
+                refs.push((name, sha1));
+            }
+        }
+        refs.sort();
+        Ok(refs)
+    }
+
+    /// The local branches, named without `refs/heads/`, with their tips.
+    pub fn branches(&self) -> Result<Vec<(String, [u8; 20])>> {
+        Ok(self
+            .refs()?
+            .into_iter()
+            .filter_map(|(name, sha1)| Some((name.strip_prefix("refs/heads/")?.to_string(), sha1)))
+            .collect())
+    }
+
+    /// The tags, named without `refs/tags/`, whose names match any of
+    /// `patterns`, or every tag without any, sorted by name.
+    pub fn tags(&self, patterns: &[String]) -> Result<Vec<(String, [u8; 20])>> {
+        let mut tags = self
+            .refs()?
+            .into_iter()
+            .filter_map(|(name, sha1)| Some((name.strip_prefix("refs/tags/")?.to_string(), sha1)))
+            .filter(|(name, _)| {
+                patterns.is_empty()
+                    || patterns
+                        .iter()
+                        .any(|pattern| name_match(pattern.as_bytes(), name.as_bytes()))
+            })
+            .collect::<Vec<_>>();
+        tags.sort();
+        Ok(tags)
+    }
+
+    /// The message of the annotated tag `sha1`, without its signature, or
+    /// for a lightweight tag the message of the commit it points at. Tags
+    /// of anything else have none.
+    pub fn tag_message(&self, sha1: [u8; 20]) -> Result<String> {
+        let mut data = Vec::new();
+        Ok(match self.read_object_data(sha1, &mut data)? {
+            Tag => TagObject::from(data)?.message(),
+            Commit => CommitObject::from(data)?.message(),
+            _ => None,
+        }
+        .unwrap_or_default())
+    }
+
+    /// The ref that `name` points to, if it is a symbolic ref. A detached
+    /// HEAD holds a sha instead, and gives `None`.
+    pub fn symbolic_ref(&self, name: &str) -> Result<Option<String>> {
+        let path = self.repo_path(Path::new(name));
+        if !path.is_file() {
+            return Ok(None);
+        }
+        let contents = std::fs::read_to_string(&path).context("reading ref")?;
+        Ok(contents
+            .trim_end()
+            .strip_prefix("ref: ")
+            .map(|target| target.to_string()))
+    }
 
+    /// Point `name` at `new` and record the change in the reflog. Like
+    /// `git update-ref`, updating a symbolic ref such as HEAD moves the
+    /// branch it points to, and both are logged.
+    pub fn update_ref(&self, name: &str, new: [u8; 20], message: &str) -> Result<()> {
+        let head = self.symbolic_ref("HEAD")?;
+        let target = match self.symbolic_ref(name)? {
+            Some(target) => target,
+            None => name.to_string(),
+        };
+        let old = self.read_ref(Path::new(&target))?.unwrap_or([0; 20]);
+        self.write_ref(&target, &new.encode_hex::<String>())?;
+        self.log_ref_update(&target, old, new, message)?;
+        if target != name || head.as_deref() == Some(name) {
+            self.log_ref_update("HEAD", old, new, message)?;
+        }
         Ok(())
     }
 
-    pub fn log_iter(&self, sha1: [u8; 20]) -> Result<LogIterator> {
-        LogIterator::new(self, sha1)
+    /// Append an entry to the reflog of `name`. As with git's default
+    /// `core.logAllRefUpdates`, only HEAD, branches and remote-tracking
+    /// branches are logged.
+    pub(crate) fn log_ref_update(
+        &self,
+        name: &str,
+        old: [u8; 20],
+        new: [u8; 20],
+        message: &str,
+    ) -> Result<()> {
+        if name != "HEAD" && !name.starts_with("refs/heads/") && !name.starts_with("refs/remotes/")
+        {
+            return Ok(());
+        }
+        let identity = match self.user_identity() {
+            Ok((_, committer)) => committer,
+            Err(_) => Identity::now("unknown".to_string(), "unknown".to_string())?,
+        }
+        .to_string();
+        let entry = ReflogEntry {
+            old,
+            new,
+            identity,
+            message: message.to_string(),
+        };
+        let path = self
+            .repo_file(&Path::new("logs").join(name), true)
+            .with_context(|| format!("could not create reflog for {}", name))?;
+        let mut file = File::options()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("opening reflog for {}", name))?;
+        writeln!(file, "{}", entry).context("writing reflog")?;
+        Ok(())
     }
-}
 
-fn default_config() -> Ini {
-    let mut ini = Ini::new();
-    ini.setstr("core", "repositoryformatversion", Some("0"));
-    ini.setstr("core", "filemode", Some("false"));
-    ini.setstr("core", "bare", Some("false"));
-    ini
-}
+    /// Read the reflog of `name`, oldest entry first. Short names are looked
+    /// up as branches and then as remote-tracking branches.
+    pub fn reflog(&self, name: &str) -> Result<Vec<ReflogEntry>> {
+        let candidates = if name == "HEAD" || name.starts_with("refs/") {
+            vec![name.to_string()]
+        } else {
+            vec![
+                format!("refs/heads/{}", name),
+                format!("refs/remotes/{}", name),
+            ]
+        };
+        let path = candidates
+            .iter()
+            .map(|name| self.repo_path(&Path::new("logs").join(name)))
+            .find(|path| path.is_file())
+            .ok_or_else(|| WyagError::NotFound(format!("no reflog for {}", name)))?;
+        let data = std::fs::read_to_string(&path)
+            .with_context(|| format!("reading reflog for {}", name))?;
+        parse_reflog(&data).with_context(|| format!("parsing reflog for {}", name))
+    }
 
-#[derive(PartialEq, Copy, Clone)]
-enum ObjectLocation {
-    ObjectFile,
-    PackFile([u8; 20], u64),
+    /// Resolve `<name>@{n}`, the value `name` had `n` updates ago. An empty
+    /// name means the current branch, as in `@{1}`.
+    fn reflog_entry(&self, name: &str, n: usize) -> Result<[u8; 20]> {
+        let name = match name {
+            "" => match self.symbolic_ref("HEAD")? {
+                Some(branch) => branch,
+                None => "HEAD".to_string(),
+            },
+            name => name.to_string(),
+        };
+        let entries = self.reflog(&name)?;
+        ensure!(
+            n < entries.len(),
+            WyagError::NotFound(format!(
+                "log for {} only has {} entries",
+                name,
+                entries.len()
+            ))
+        );
+        Ok(entries[entries.len() - 1 - n].new)
+    }
+
+    /// Point a ref, or HEAD, at `target`, which is either a sha or `ref: <name>`.
+    pub fn write_ref(&self, name: &str, target: &str) -> Result<()> {
+        let path = Path::new(name);
+        ensure!(
+            (name == "HEAD" || name.starts_with("refs/"))
+                && path.components().all(|c| matches!(c, Component::Normal(_))),
+            "invalid ref name {}",
+            name
+        );
+        let path = self
+            .repo_file(path, true)
+            .with_context(|| format!("could not create ref {}", name))?;
+        let mut file = NamedTempFile::new_in(&self.gitdir).context("creating temp file")?;
+        writeln!(file, "{}", target).context("writing ref")?;
+        self.persist(file, &path).context("replacing ref")?;
+        Ok(())
+    }
+
+    /// `path` quoted for line-based output, with non-ASCII bytes escaped
+    /// unless `core.quotePath` is false.
+    pub fn quote_path(&self, path: &Path) -> String {
+        let quote_non_ascii = self
+            .config_get("core", "quotepath")
+            .is_none_or(|v| v != "false");
+        quote_path(path, quote_non_ascii)
+    }
+
+    /// Move a finished temporary file to `path`. Unless `core.fsyncObjectFiles`
+    /// is false, the file is flushed to disk first and its directory after, so
+    /// a crash cannot leave an empty or missing file behind a rename that
+    /// appeared to succeed.
+    fn persist(&self, file: NamedTempFile, path: &Path) -> Result<()> {
+        let fsync = self
+            .config_get("core", "fsyncobjectfiles")
+            .is_none_or(|v| v != "false");
+        if fsync {
+            file.as_file().sync_all().context("syncing file")?;
+        }
+        file.persist(path).map_err(|e| e.error)?;
+        if fsync && let Some(dir) = path.parent() {
+            File::open(dir)
+                .and_then(|dir| dir.sync_all())
+                .with_context(|| format!("syncing {}", dir.to_string_lossy()))?;
+        }
+        Ok(())
+    }
+
+    /// Hash the file at `path` as an object of `object_type`. A blob is
+    /// hashed as if it were at `attribute_path` in the worktree, if given,
+    /// with its line endings normalised as the attributes there say.
+    pub fn object_hash(
+        &self,
+        path: &Path,
+        object_type: ObjectType,
+        attribute_path: Option<&Path>,
+        write: bool,
+        compression: Compression,
+    ) -> Result<[u8; 20]> {
+        let file = File::open(path)?;
+        let len = file.metadata()?.len();
+
+        let object_type = match object_type {
+            ObjectType::Blob => {
+                let text = match attribute_path {
+                    Some(attribute_path) => self.text_attribute(attribute_path)?,
+                    None => TextAttribute::Unspecified,
+                };
+                if text != TextAttribute::Unspecified && text != TextAttribute::Unset {
+                    let mut data = Vec::with_capacity(len as usize);
+                    BufReader::new(file).read_to_end(&mut data)?;
+                    let data = text.normalise(&data).unwrap_or(data);
+                    return self.write_object_stream(
+                        b"blob",
+                        data.len() as u64,
+                        data.as_slice(),
+                        write,
+                        compression,
+                    );
+                }
+                // other blobs are streamed so files larger than memory can be hashed
+                return self.write_object_stream(
+                    b"blob",
+                    len,
+                    BufReader::new(file),
+                    write,
+                    compression,
+                );
+            }
+            ObjectType::Commit => Commit,
+            ObjectType::Tag => Tag,
+            ObjectType::Tree => Tree,
+        };
+        // anything else is checked first so a malformed object is refused, as git does,
+        // but the file's own bytes are what get hashed
+        let mut data = Vec::with_capacity(len as usize);
+        BufReader::new(file).read_to_end(&mut data)?;
+        let valid = match GitObject::new(object_type, data.clone()) {
+            Ok(GitObject::Commit(commit)) => {
+                commit.tree().is_some() && commit.author().is_some() && commit.committer().is_some()
+            }
+            Ok(GitObject::Tag(tag)) => tag.object().is_some(),
+            Ok(_) => true,
+            Err(_) => false,
+        };
+        ensure!(valid, "corrupt {} file", object_type.name());
+        self.write_object_stream(
+            object_type.name().as_bytes(),
+            len,
+            data.as_slice(),
+            write,
+            compression,
+        )
+    }
+
+    /// List every object in a pack in the order it is stored, as `git verify-pack -v`
+    /// does, resolving deltas to find each object's sha1 and type.
+    pub fn list_pack(&self, packfile_sha: &str, progress: Progress) -> Result<Vec<PackedObject>> {
+        let path = self
+            .repo_file(
+                &Path::new("objects")
+                    .join("pack")
+                    .join(format!("pack-{}.pack", packfile_sha)),
+                false,
+            )
+            .context("Packfile does not exist")?;
+        let pack_len = std::fs::metadata(&path)?.len();
+        let pack = Pack::new(BufReader::new(File::open(&path)?))?;
+        // a pack without its index is indexed in memory, which means
+        // resolving every object in it first
+        let index = match self.open_index(&path.with_extension("idx")) {
+            Ok(index) => index,
+            Err(_) => {
+                let (entries, pack_sha1) = pack.index_entries().context("indexing pack")?;
+                PackIndex::new(BufReader::new(io::Cursor::new(write_index(
+                    entries, pack_sha1,
+                ))))?
+            }
+        };
+        let pack_sha1 = index.id();
+        let entries = index.packed_ranges(pack_len);
+
+        let mut bases = HashMap::new();
+        let mut objects = Vec::with_capacity(entries.len());
+        for (n, (PackIndexItem(sha1, offset), range)) in entries.iter().enumerate() {
+            let mut data = Vec::new();
+            let base = match pack.read_object_data_into(*offset, &mut data)? {
+                OffsetDelta(delta) => {
+                    let base_offset = delta_base_offset(*offset, delta)?;
+                    let base_sha1 = index.sha_at_offset(base_offset).with_context(|| {
+                        format!("no object at delta base offset {}", base_offset)
+                    })?;
+                    bases.insert(*sha1, base_sha1);
+                    Some(DeltaBase::Offset(base_offset, base_sha1))
+                }
+                RefDelta(base) => {
+                    bases.insert(*sha1, base);
+                    Some(DeltaBase::Ref(base))
+                }
+                _ => None,
+            };
+            let size = data.len();
+
+            data.clear();
+            let object_type =
+                self.read_object_from_location(*sha1, PackFile(pack_sha1, *offset), &mut data)?;
+            objects.push(PackedObject {
+                sha1: *sha1,
+                object_type,
+                size,
+                packed_size: range.end - range.start,
+                offset: *offset,
+                delta: base.map(|base| (0, base)),
+            });
+            if let Some(progress) = progress {
+                progress(n + 1, entries.len());
+            }
+        }
+
+        for object in objects.iter_mut() {
+            let Some((depth, _)) = object.delta.as_mut() else {
+                continue;
+            };
+            let mut current = object.sha1;
+            while let Some(&next) = bases.get(&current) {
+                ensure!(*depth < bases.len(), "delta chain loops");
+                *depth += 1;
+                current = next;
+            }
+        }
+        Ok(objects)
+    }
+
+    /// Resolve and check the sha1 of every object in a pack, using `threads` workers when more
+    /// than one is asked for. Returns each object's sha1, type and size in index order.
+    pub fn verify_pack(
+        &self,
+        packfile_sha: &str,
+        threads: usize,
+    ) -> Result<Vec<([u8; 20], BinaryObject, usize)>> {
+        let pack_path = Path::new("objects").join("pack");
+        let index_path = self
+            .repo_file(&pack_path.join(format!("pack-{}.idx", packfile_sha)), false)
+            .context("Pack index does not exist")?;
+        let index = self.open_index(&index_path)?;
+
+        let path = self
+            .repo_file(
+                &pack_path.join(format!("pack-{}.pack", packfile_sha)),
+                false,
+            )
+            .context("Packfile does not exist")?;
+        let pack = Pack::new(BufReader::new(File::open(&path)?))?;
+        // a truncated pack or an index left over from another pack
+        ensure!(
+            pack.object_count() == index.object_count(),
+            "pack-{}.pack has {} objects but its index lists {}",
+            packfile_sha,
+            pack.object_count(),
+            index.object_count()
+        );
+
+        // the bytes stored for each object, walked in pack order
+        let pack_len = std::fs::metadata(&path)?.len();
+        let mut reader = BufReader::new(File::open(&path)?);
+        reader.seek(SeekFrom::Start(12))?;
+        let mut packed = Vec::new();
+        for (PackIndexItem(sha1, offset), range) in index.packed_ranges(pack_len) {
+            ensure!(
+                range.start < range.end && reader.stream_position()? == offset,
+                "object {} at offset {} overlaps another",
+                sha1.encode_hex::<String>(),
+                offset
+            );
+            packed.resize((range.end - range.start) as usize, 0);
+            reader.read_exact(&mut packed)?;
+            ensure!(
+                index.crc32(sha1) == Some(crc32fast::hash(&packed)),
+                "crc32 of object {} does not match the index",
+                sha1.encode_hex::<String>()
+            );
+        }
+        let pack_sha1 = pack.validate_checksum()?;
+        ensure!(
+            pack_sha1 == index.id(),
+            "pack-{}.pack has checksum {} but its index is for {}",
+            packfile_sha,
+            pack_sha1.encode_hex::<String>(),
+            index.id().encode_hex::<String>()
+        );
+
+        if threads > 1 {
+            return pack.verify_objects_parallel(&index, threads);
+        }
+
+        index
+            .iter()
+            .map(|PackIndexItem(sha1, offset)| {
+                let mut data = Vec::new();
+                let object_type =
+                    self.read_object_from_location(sha1, PackFile(index.id(), offset), &mut data)?;
+                Ok((sha1, object_type, data.len()))
+            })
+            .collect()
+    }
+
+    /// List the entries of a tree, one line each, in the style of `git ls-tree`.
+    /// With `trees_only` blobs are left out, though trees are still recursed into.
+    /// Given `pathspecs`, only the entries at those paths are listed, or the
+    /// contents of a tree when its path ends in `/`.
+    #[allow(clippy::too_many_arguments)]
+    pub fn ls_tree(
+        &self,
+        reference: &str,
+        recurse: bool,
+        trees_only: bool,
+        format: LsTreeFormat,
+        quote_paths: bool,
+        path: &Path,
+        pathspecs: &[String],
+    ) -> Result<Vec<Vec<u8>>> {
+        trace!("finding object {}", reference);
+        let sha1 = self.peel_to_tree(self.find_object(reference)?)?;
+        trace!("reading object {}", sha1.encode_hex::<String>());
+
+        let mut data = Vec::new();
+        let object = match self.read_object_data(sha1, &mut data)? {
+            Tree => TreeObject::new(&data)?,
+            _ => bail!("object not a tree"),
+        };
+
+        trace!("iterating leaf {}", path.to_string_lossy());
+
+        let mut lines = Vec::new();
+        for item in object.leaf_iter() {
+            let kind = item.kind().with_context(|| {
+                format!(
+                    "weird TreeLeaf mode {} on {}",
+                    item.mode,
+                    item.path.to_string_lossy()
+                )
+            })?;
+            let item_path = path.join(&item.path);
+            let item_sha1 = item.sha1.encode_hex::<String>();
+            let descend = |pathspecs: &[String]| {
+                self.ls_tree(
+                    &item_sha1,
+                    recurse,
+                    trees_only,
+                    format,
+                    quote_paths,
+                    &item_path,
+                    pathspecs,
+                )
+                .with_context(|| {
+                    format!("Failed to descend tree in {}", item.path.to_string_lossy())
+                })
+            };
+
+            match match_pathspecs(&item_path, kind == LeafKind::Tree, pathspecs) {
+                PathspecMatch::Outside => continue,
+                PathspecMatch::Ancestor => {
+                    lines.extend(descend(pathspecs)?);
+                    continue;
+                }
+                PathspecMatch::Contents => {
+                    lines.extend(descend(&[])?);
+                    continue;
+                }
+                PathspecMatch::Entry => {}
+            }
+
+            if recurse && kind == LeafKind::Tree {
+                if trees_only {
+                    lines.push(self.ls_tree_line(item, kind, &item_path, format, quote_paths)?);
+                }
+                lines.extend(descend(&[])?);
+                continue;
+            }
+            if trees_only && kind != LeafKind::Tree {
+                continue;
+            }
+            lines.push(self.ls_tree_line(item, kind, &item_path, format, quote_paths)?);
+        }
+
+        Ok(lines)
+    }
+
+    fn ls_tree_line(
+        &self,
+        item: &TreeLeaf,
+        kind: LeafKind,
+        path: &Path,
+        format: LsTreeFormat,
+        quote_paths: bool,
+    ) -> Result<Vec<u8>> {
+        let sha1 = item.sha1.encode_hex::<String>();
+        // without quoting the path's bytes are kept as they are, utf8 or not
+        let path = if quote_paths {
+            self.quote_path(path).into_bytes()
+        } else {
+            path.as_os_str().as_bytes().to_vec()
+        };
+        let prefix = match format {
+            LsTreeFormat::NameOnly => String::new(),
+            LsTreeFormat::Long => {
+                // trees and gitlinks have no size, and a gitlink's commit lives in the submodule
+                let size = match kind {
+                    LeafKind::Blob | LeafKind::Symlink => self
+                        .object_info(item.sha1.as_slice().try_into()?)?
+                        .1
+                        .to_string(),
+                    LeafKind::Tree | LeafKind::Gitlink => "-".to_string(),
+                };
+                format!(
+                    "{} {} {} {:>7}\t",
+                    item.mode,
+                    kind.object_type(),
+                    sha1,
+                    size
+                )
+            }
+            // gitlinks are printed without reading, their commit lives in the submodule
+            LsTreeFormat::Default => format!("{} {} {}\t", item.mode, kind.object_type(), sha1),
+        };
+        Ok([prefix.as_bytes(), &path].concat())
+    }
+
+    /// The type and size of an object's data. Loose objects only have their header
+    /// read, packed objects are read in full since a delta's size depends on its base.
+    pub fn object_info(&self, sha1: [u8; 20]) -> Result<(BinaryObject, usize), WyagError> {
+        let sha1 = self.replacements.get(&sha1).copied().unwrap_or(sha1);
+        if let Some(ObjectFile) = self.find_object_location(sha1) {
+            let path = self
+                .object_file_path(sha1)
+                .ok_or_else(|| self.missing_object(sha1))?;
+            let file = File::open(path)?;
+            let mut decoder = ZlibDecoder::new(BufReader::new(file));
+            return read_object_header(&mut decoder)
+                .context("reading object header")
+                .map_err(|e| WyagError::reading(sha1, e));
+        }
+        let mut data = Vec::new();
+        let object_type = self.read_object_data(sha1, &mut data)?;
+        Ok((object_type, data.len()))
+    }
+
+    /// Write `len` bytes of an object's data, starting `offset` bytes in, to `out`, or
+    /// fewer if the object ends first. Returns the object's type and how many bytes were
+    /// written. A loose object, or a packed one stored whole, is inflated only as far as
+    /// the range goes and never held in memory, so the range is not checked against the
+    /// object's sha; a delta is read and checked in full first.
+    pub fn read_object_range(
+        &self,
+        sha1: [u8; 20],
+        offset: u64,
+        len: u64,
+        out: &mut impl Write,
+    ) -> Result<(BinaryObject, u64), WyagError> {
+        let sha1 = self.replacements.get(&sha1).copied().unwrap_or(sha1);
+        let streamed = match self.find_object_location(sha1) {
+            Some(ObjectFile) => {
+                let path = self
+                    .object_file_path(sha1)
+                    .ok_or_else(|| self.missing_object(sha1))?;
+                let mut decoder = ZlibDecoder::new(BufReader::new(File::open(path)?));
+                let (object_type, size) = read_object_header(&mut decoder)
+                    .context("reading object header")
+                    .map_err(|e| WyagError::reading(sha1, e))?;
+                let mut data = decoder.take(size as u64);
+                io::copy(&mut (&mut data).take(offset), &mut sink())?;
+                Some((object_type, io::copy(&mut data.take(len), out)?))
+            }
+            Some(PackFile(pack, pack_offset)) => self
+                .open_pack(pack)
+                .and_then(|pack| pack.copy_object_range(pack_offset, offset, len, out))
+                .map_err(|e| WyagError::reading(sha1, e))?,
+            None => None,
+        };
+        if let Some(streamed) = streamed {
+            return Ok(streamed);
+        }
+
+        let mut data = Vec::new();
+        let object_type = self.read_object_data(sha1, &mut data)?;
+        let start = data.len().min(offset.try_into().unwrap_or(usize::MAX));
+        let end = data
+            .len()
+            .min(start.saturating_add(len.try_into().unwrap_or(usize::MAX)));
+        out.write_all(&data[start..end])?;
+        Ok((object_type, (end - start) as u64))
+    }
+
+    /// Read the staging area, an absent index is treated as empty.
+    pub fn read_index(&self) -> Result<Index> {
+        let path = self.repo_path(Path::new("index"));
+        if !path.is_file() {
+            return Ok(Index::default());
+        }
+
+        let file = File::open(&path)
+            .with_context(|| format!("opening index file {}", path.to_string_lossy()))?;
+        let len = file.metadata().context("reading index metadata")?.len();
+        Index::new(BufReader::new(file), len)
+            .with_context(|| format!("reading index file {}", path.to_string_lossy()))
+    }
+
+    /// Replace the staging area with `index`.
+    pub fn write_index(&self, index: &Index) -> Result<()> {
+        let path = self
+            .repo_file(Path::new("index"), true)
+            .context("could not create path to index file")?;
+        let file = NamedTempFile::new_in(&self.gitdir).context("creating temp index file")?;
+        BufWriter::new(&file)
+            .write_all(&index.serialize())
+            .context("writing index file")?;
+        file.persist(path).context("replacing index file")?;
+        Ok(())
+    }
+
+    /// The line-ending attribute `.gitattributes` files give `path`, a path
+    /// relative to the current directory. Paths outside the worktree have none.
+    fn text_attribute(&self, path: &Path) -> Result<TextAttribute> {
+        let worktree = std::path::absolute(&self.worktree).context("resolving worktree")?;
+        let path = std::path::absolute(path)
+            .with_context(|| format!("resolving {}", path.to_string_lossy()))?;
+        match path.strip_prefix(&worktree) {
+            Ok(relative) if !self.is_bare() => text_attribute(
+                &worktree,
+                &self.repo_path(Path::new("info/attributes")),
+                relative,
+            ),
+            _ => Ok(TextAttribute::Unspecified),
+        }
+    }
+
+    /// Ignore rules for the worktree.
+    pub(crate) fn gitignore(&self) -> Result<GitIgnore> {
+        GitIgnore::new(&self.worktree, &self.repo_path(Path::new("info/exclude")))
+    }
+
+    /// Stage files, or every file below a directory, into the index. Ignored
+    /// files are skipped unless `force` is set.
+    pub fn add(&self, paths: &[PathBuf], force: bool) -> Result<()> {
+        ensure!(!self.is_bare(), "add must be run in a worktree");
+        let mut index = self.read_index().context("reading index")?;
+        let ignore = self.gitignore().context("reading ignore rules")?;
+        let worktree = std::path::absolute(&self.worktree).context("resolving worktree")?;
+        for path in paths {
+            let path = std::path::absolute(path)
+                .with_context(|| format!("resolving {}", path.to_string_lossy()))?;
+            ensure!(
+                path.starts_with(&worktree),
+                "{} is outside repository at {}",
+                path.to_string_lossy(),
+                worktree.to_string_lossy()
+            );
+            let relative = path.strip_prefix(&worktree)?;
+            ensure!(
+                force || relative.as_os_str().is_empty() || !ignore.is_ignored(relative),
+                "{} is ignored by one of your .gitignore files, use --force to add it",
+                relative.to_string_lossy()
+            );
+            let ignore = if force { None } else { Some(&ignore) };
+            self.add_path(&mut index, ignore, &worktree, &path)
+                .with_context(|| format!("adding {}", path.to_string_lossy()))?;
+        }
+        self.write_index(&index).context("writing index")
+    }
+
+    /// Hash a worktree file as a blob, storing a symlink's target rather than
+    /// the file it points to.
+    pub fn hash_path(&self, path: &Path, write: bool) -> Result<[u8; 20]> {
+        let metadata = path
+            .symlink_metadata()
+            .with_context(|| format!("reading metadata of {}", path.to_string_lossy()))?;
+        if metadata.is_symlink() {
+            let target = std::fs::read_link(path).context("reading symlink")?;
+            let blob = BlobObject::from(target.as_os_str().as_encoded_bytes().to_vec());
+            self.write_object(&GitObject::Blob(blob), write, Compression::default())
+        } else {
+            self.object_hash(path, ObjectType::Blob, None, write, Compression::default())
+        }
+    }
+
+    /// Flatten a tree, or the tree of a commit, into its blob and gitlink
+    /// entries keyed by path.
+    pub fn tree_entries(&self, sha1: [u8; 20]) -> Result<BTreeMap<PathBuf, (u32, [u8; 20])>> {
+        let mut entries = BTreeMap::new();
+        self.collect_tree_entries(sha1, Path::new(""), &mut entries)?;
+        Ok(entries)
+    }
+
+    fn collect_tree_entries(
+        &self,
+        sha1: [u8; 20],
+        prefix: &Path,
+        entries: &mut BTreeMap<PathBuf, (u32, [u8; 20])>,
+    ) -> Result<()> {
+        let mut data = Vec::new();
+        let tree = match self.read_object_data(sha1, &mut data)? {
+            Tree => TreeObject::new(&data)?,
+            Commit => {
+                let tree = CommitObject::from(data)?
+                    .tree()
+                    .context("commit has no tree")?;
+                return self.collect_tree_entries(tree, prefix, entries);
+            }
+            object_type => bail!("expected tree, received {}", object_type.name()),
+        };
+
+        for leaf in tree.leaf_iter() {
+            let mode = u32::from_str_radix(&leaf.mode, 8)
+                .with_context(|| format!("parsing tree leaf mode {}", leaf.mode))?;
+            let leaf_sha1: [u8; 20] = leaf
+                .sha1
+                .as_slice()
+                .try_into()
+                .context("tree leaf sha1 has incorrect length")?;
+            let path = prefix.join(&leaf.path);
+            if mode == 0o040000 {
+                self.collect_tree_entries(leaf_sha1, &path, entries)
+                    .with_context(|| format!("reading tree {}", path.to_string_lossy()))?;
+            } else {
+                entries.insert(path, (mode, leaf_sha1));
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the object at `path` within a tree, or within the tree of a commit.
+    pub fn object_at_path(&self, sha1: [u8; 20], path: &Path) -> Result<[u8; 20]> {
+        let mut current = self.peel_to_tree(sha1)?;
+        let mut walked = PathBuf::new();
+        let mut kind = LeafKind::Tree;
+        for component in path.components() {
+            let name = match component {
+                Component::Normal(name) => name,
+                Component::CurDir => continue,
+                _ => bail!("invalid path component in {}", path.to_string_lossy()),
+            };
+            ensure!(
+                kind == LeafKind::Tree,
+                "{} is not a tree",
+                walked.to_string_lossy()
+            );
+
+            let mut data = Vec::new();
+            let tree = match self.read_object_data(current, &mut data)? {
+                Tree => TreeObject::new(&data)?,
+                object_type => bail!(
+                    "{} is a {}, not a tree",
+                    walked.to_string_lossy(),
+                    object_type.name()
+                ),
+            };
+            walked.push(name);
+            let leaf = tree
+                .leaf_iter()
+                .find(|leaf| leaf.path.as_os_str() == name)
+                .ok_or_else(|| {
+                    WyagError::NotFound(format!("path {} does not exist", walked.to_string_lossy()))
+                })?;
+            kind = leaf.kind().with_context(|| {
+                format!(
+                    "weird TreeLeaf mode {} on {}",
+                    leaf.mode,
+                    walked.to_string_lossy()
+                )
+            })?;
+            current = leaf
+                .sha1
+                .as_slice()
+                .try_into()
+                .context("tree leaf sha1 has incorrect length")?;
+        }
+        Ok(current)
+    }
+
+    /// Follow annotated tags to the first object that is not one, so any other
+    /// object is returned unchanged.
+    pub fn peel_tags(&self, mut sha1: [u8; 20]) -> Result<[u8; 20]> {
+        while self.object_type(sha1)? == Tag {
+            let mut data = Vec::new();
+            self.read_object_data(sha1, &mut data)?;
+            sha1 = TagObject::from(data)?
+                .object()
+                .context("tag has no object")?;
+        }
+        Ok(sha1)
+    }
+
+    /// Follow annotated tags until reaching a commit.
+    pub fn peel_to_commit(&self, mut sha1: [u8; 20]) -> Result<[u8; 20]> {
+        loop {
+            let mut data = Vec::new();
+            match self.read_object_data(sha1, &mut data)? {
+                Commit => return Ok(sha1),
+                Tag => {
+                    sha1 = TagObject::from(data)?
+                        .object()
+                        .context("tag has no object")?
+                }
+                object_type => bail!(
+                    "{} is a {}, not a commit-ish",
+                    sha1.encode_hex::<String>(),
+                    object_type.name()
+                ),
+            }
+        }
+    }
+
+    /// Resolve a commit, or a tag pointing at one, to its tree. Trees are
+    /// returned unchanged.
+    pub fn peel_to_tree(&self, mut sha1: [u8; 20]) -> Result<[u8; 20]> {
+        loop {
+            let mut data = Vec::new();
+            match self.read_object_data(sha1, &mut data)? {
+                Tree => return Ok(sha1),
+                Commit => {
+                    return CommitObject::from(data)?
+                        .tree()
+                        .context("commit has no tree");
+                }
+                Tag => {
+                    sha1 = TagObject::from(data)?
+                        .object()
+                        .context("tag has no object")?
+                }
+                object_type => bail!(
+                    "{} is a {}, not a tree-ish",
+                    sha1.encode_hex::<String>(),
+                    object_type.name()
+                ),
+            }
+        }
+    }
+
+    fn add_path(
+        &self,
+        index: &mut Index,
+        ignore: Option<&GitIgnore>,
+        worktree: &Path,
+        path: &Path,
+    ) -> Result<()> {
+        let metadata = path
+            .symlink_metadata()
+            .with_context(|| format!("pathspec {} did not match", path.to_string_lossy()))?;
+        let relative = path.strip_prefix(worktree)?.to_path_buf();
+
+        if metadata.is_dir() {
+            if path == self.gitdir || path.file_name().is_some_and(|n| n == ".git") {
+                return Ok(());
+            }
+            let mut children = path
+                .read_dir()
+                .with_context(|| format!("reading directory {}", path.to_string_lossy()))?
+                .map(|entry| entry.map(|e| e.path()))
+                .collect::<io::Result<Vec<_>>>()
+                .context("reading directory entry")?;
+            children.sort();
+            for child in children {
+                if ignore.is_some_and(|i| i.is_ignored(child.strip_prefix(worktree).unwrap())) {
+                    trace!("skipping ignored {}", child.to_string_lossy());
+                    continue;
+                }
+                self.add_path(index, ignore, worktree, &child)?;
+            }
+            // stage deletions of tracked files that are gone from the directory
+            index.entries.retain(|e| {
+                !e.path.starts_with(&relative) || worktree.join(&e.path).symlink_metadata().is_ok()
+            });
+            return Ok(());
+        }
+
+        let sha1 = self.hash_path(path, true)?;
+        trace!(
+            "staging {} as {}",
+            relative.to_string_lossy(),
+            sha1.encode_hex::<String>()
+        );
+
+        // a file replaces any entries for the same path, a directory it used
+        // to be, or a file where one of its parent directories now is
+        index.entries.retain(|e| {
+            !(e.path == relative || e.path.starts_with(&relative) || relative.starts_with(&e.path))
+        });
+        index
+            .entries
+            .push(IndexEntry::new(relative, sha1, &metadata));
+        Ok(())
+    }
+
+    pub fn log_iter(&self, sha1: [u8; 20]) -> Result<LogIterator<'_>> {
+        LogIterator::new(self, sha1)
+    }
+
+    /// The trees and blobs reachable from `commits`, each once with the path
+    /// it was first found at, in the order `git rev-list --objects` lists
+    /// them. Root trees have an empty path and submodule commits are left out.
+    pub fn commit_objects(&self, commits: &[[u8; 20]]) -> Result<Vec<([u8; 20], PathBuf)>> {
+        let mut seen = HashSet::new();
+        let mut objects = Vec::new();
+        for &sha1 in commits {
+            let mut data = Vec::new();
+            self.read_object_data_as(sha1, ObjectType::Commit, &mut data)?;
+            let tree = CommitObject::from(data)?
+                .tree()
+                .context("commit has no tree")?;
+            self.tree_objects(tree, PathBuf::new(), &mut seen, &mut objects)
+                .with_context(|| format!("listing objects of {}", sha1.encode_hex::<String>()))?;
+        }
+        Ok(objects)
+    }
+
+    fn tree_objects(
+        &self,
+        sha1: [u8; 20],
+        path: PathBuf,
+        seen: &mut HashSet<[u8; 20]>,
+        objects: &mut Vec<([u8; 20], PathBuf)>,
+    ) -> Result<()> {
+        if !seen.insert(sha1) {
+            return Ok(());
+        }
+        let mut data = Vec::new();
+        self.read_object_data_as(sha1, ObjectType::Tree, &mut data)?;
+        objects.push((sha1, path.clone()));
+        for leaf in TreeObject::new(&data)?.leaf_iter() {
+            let leaf_sha1 = leaf.sha1.as_slice().try_into()?;
+            match leaf.kind() {
+                Some(LeafKind::Tree) => {
+                    self.tree_objects(leaf_sha1, path.join(&leaf.path), seen, objects)?
+                }
+                Some(LeafKind::Blob | LeafKind::Symlink) => {
+                    if seen.insert(leaf_sha1) {
+                        objects.push((leaf_sha1, path.join(&leaf.path)));
+                    }
+                }
+                Some(LeafKind::Gitlink) => {}
+                None => bail!(
+                    "weird TreeLeaf mode {} on {}",
+                    leaf.mode,
+                    path.join(&leaf.path).to_string_lossy()
+                ),
+            }
+        }
+        Ok(())
+    }
+
+    /// Find the most recent tag reachable from `sha1`, returning its name and
+    /// how many commits were walked past before reaching it. Only annotated
+    /// tags are considered unless `lightweight` is set.
+    pub fn describe(&self, sha1: [u8; 20], lightweight: bool) -> Result<Option<(String, usize)>> {
+        // commit -> (is lightweight, tag name), keeping annotated tags over lightweight ones
+        let mut tags: HashMap<[u8; 20], (bool, String)> = HashMap::new();
+        for (name, tip) in self.refs()? {
+            let Some(tag) = name.strip_prefix("refs/tags/") else {
+                continue;
+            };
+            let mut data = Vec::new();
+            let is_lightweight = match self.read_object_data(tip, &mut data)? {
+                Tag => false,
+                _ if lightweight => true,
+                _ => continue,
+            };
+            let Ok(commit) = self.peel_to_commit(tip) else {
+                continue;
+            };
+            let candidate = (is_lightweight, tag.to_string());
+            if tags
+                .get(&commit)
+                .is_none_or(|existing| candidate < *existing)
+            {
+                tags.insert(commit, candidate);
+            }
+        }
+        if tags.is_empty() {
+            return Ok(None);
+        }
+
+        for (depth, entry) in self.log_iter(sha1)?.enumerate() {
+            if let Some((_, name)) = tags.get(&entry?.sha) {
+                return Ok(Some((name.clone(), depth)));
+            }
+        }
+        Ok(None)
+    }
+
+    /// Check the signature on the commit or tag `sha1` with `gpg.program`,
+    /// or gpg if that is not set.
+    pub fn verify_signature(
+        &self,
+        sha1: [u8; 20],
+        expected: ObjectType,
+    ) -> Result<SignatureStatus> {
+        let mut data = Vec::new();
+        self.read_object_data_as(sha1, expected, &mut data)?;
+        let signed = match expected {
+            ObjectType::Commit => CommitObject::from(data)?.signed_payload(),
+            ObjectType::Tag => TagObject::from(data)?.signed_payload(),
+            _ => bail!("only commits and tags can be signed"),
+        };
+        let (payload, signature) = signed
+            .with_context(|| format!("no signature found in {}", sha1.encode_hex::<String>()))?;
+        let program = self
+            .config_get("gpg", "program")
+            .unwrap_or("gpg".to_string());
+        gpg::verify(&program, &payload, &signature)
+    }
+
+    /// Name `sha1` relative to the closest ref it is a first-parent ancestor
+    /// of, as `<ref>~<n>`. Tags win over branches at the same distance.
+    pub fn name_rev(&self, sha1: [u8; 20]) -> Result<Option<String>> {
+        let shallow = self.shallow()?;
+        let mut cache = CommitCache::new(self);
+        // (distance, whether the ref is not a tag, name)
+        let mut best: Option<(usize, bool, String)> = None;
+        for (name, tip) in self.refs()? {
+            // tags may point at trees or blobs
+            let Ok(mut current) = self.peel_to_commit(tip) else {
+                continue;
+            };
+            let is_tag = name.starts_with("refs/tags/");
+            let mut distance = 0;
+            loop {
+                if best
+                    .as_ref()
+                    .is_some_and(|best| (distance, !is_tag) >= (best.0, best.1))
+                {
+                    break;
+                }
+                if current == sha1 {
+                    best = Some((distance, !is_tag, name));
+                    break;
+                }
+                if shallow.contains(&current) {
+                    break;
+                }
+                let commit = cache.read(current)?;
+                let Some(&parent) = self.parents(current, &commit).first() else {
+                    break;
+                };
+                current = parent;
+                distance += 1;
+            }
+        }
+
+        Ok(best.map(|(distance, _, name)| {
+            let name = if let Some(branch) = name.strip_prefix("refs/heads/") {
+                branch.to_string()
+            } else {
+                name.strip_prefix("refs/").unwrap_or(&name).to_string()
+            };
+            match distance {
+                0 => name,
+                distance => format!("{}~{}", name, distance),
+            }
+        }))
+    }
+
+    /// The first `.bitmap` in the pack directory, if there is one.
+    fn open_bitmap(&self) -> Result<Option<PackBitmap>> {
+        let pack_dir = self.repo_path(Path::new("objects/pack"));
+        let bitmap = pack_dir.read_dir().into_iter().flatten().find_map(|p| {
+            let path = p.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            (name.starts_with("pack-") && name.ends_with(".bitmap")).then_some(path)
+        });
+        let Some(path) = bitmap else {
+            return Ok(None);
+        };
+        debug!("found bitmap: {}", path.to_string_lossy());
+        let index = self.open_index(&path.with_extension("idx"))?;
+        let file = File::open(&path)
+            .with_context(|| format!("opening bitmap {}", path.to_string_lossy()))?;
+        let bitmap = PackBitmap::new(BufReader::new(file), &index)
+            .with_context(|| format!("reading bitmap {}", path.to_string_lossy()))?;
+        Ok(Some(bitmap))
+    }
+
+    /// Count the commits reachable from any of `tips`, including themselves.
+    /// With a pack bitmap the walk stops at the first commits the bitmap
+    /// covers, otherwise every commit is visited. Grafts and replacements
+    /// change history the bitmap was written without, so they turn it off.
+    pub fn reachable_count(&self, tips: &[[u8; 20]]) -> Result<usize> {
+        let bitmap = if self.grafts.is_empty() && self.replacements.is_empty() {
+            self.open_bitmap()?
+        } else {
+            None
+        };
+        let Some(bitmap) = bitmap else {
+            let mut count = 0;
+            for entry in LogIterator::from_tips(self, tips)? {
+                entry?;
+                count += 1;
+            }
+            return Ok(count);
+        };
+
+        let shallow = self.shallow()?;
+        let mut reachable = Bitmap::default();
+        let mut outside = HashSet::new();
+        let mut pending = tips.to_vec();
+        while let Some(sha1) = pending.pop() {
+            if let Some(commits) = bitmap.reachable(sha1) {
+                reachable.or(commits);
+                continue;
+            }
+            match bitmap.position(sha1) {
+                Some(position) if reachable.get(position) => continue,
+                Some(position) => reachable.set(position),
+                None if !outside.insert(sha1) => continue,
+                None => {}
+            }
+            if shallow.contains(&sha1) {
+                continue;
+            }
+            let mut data = Vec::new();
+            self.read_object_data_as(sha1, ObjectType::Commit, &mut data)?;
+            pending.extend(self.parents(sha1, &CommitObject::from(data)?));
+        }
+        Ok(reachable.count_and(bitmap.commits()) + outside.len())
+    }
+}
+
+/// How a set of `ls-tree` pathspecs applies to a tree entry.
+#[derive(PartialEq, Eq, PartialOrd, Ord)]
+enum PathspecMatch {
+    Outside,
+    /// A tree on the way to a pathspec, to be walked but not listed.
+    Ancestor,
+    Entry,
+    /// A tree named by a pathspec ending in `/`, whose children are listed.
+    Contents,
+}
+
+fn match_pathspecs(path: &Path, is_tree: bool, pathspecs: &[String]) -> PathspecMatch {
+    if pathspecs.is_empty() {
+        return PathspecMatch::Entry;
+    }
+    pathspecs
+        .iter()
+        .map(|pathspec| {
+            let base = Path::new(pathspec.trim_end_matches('/'));
+            if path == base {
+                if is_tree && pathspec.ends_with('/') {
+                    PathspecMatch::Contents
+                } else {
+                    PathspecMatch::Entry
+                }
+            } else if is_tree && base.starts_with(path) {
+                PathspecMatch::Ancestor
+            } else {
+                PathspecMatch::Outside
+            }
+        })
+        .max()
+        .unwrap_or(PathspecMatch::Outside)
+}
+
+/// Whether `name` is `len` lowercase hex digits, as object names are written.
+fn is_hex(name: &str, len: usize) -> bool {
+    name.len() == len && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The loose objects in one fan-out directory of the object store, and the
+/// paths of files there that are not objects.
+fn read_loose_shard(dir: &Path) -> Result<Vec<Result<[u8; 20], PathBuf>>> {
+    let prefix = dir.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let mut objects = Vec::new();
+    for file in dir
+        .read_dir()
+        .with_context(|| format!("reading {}", dir.to_string_lossy()))?
+    {
+        let file = file.context("reading objects directory")?;
+        if let Some(rest) = file.file_name().to_str()
+            && is_hex(rest, 38)
+            && let Ok(sha1) = decode(format!("{}{}", prefix, rest))
+            && let Ok(sha1) = sha1.try_into()
+        {
+            objects.push(Ok(sha1));
+        } else {
+            objects.push(Err(file.path()));
+        }
+    }
+    Ok(objects)
+}
+
+/// The type of the empty tree or empty blob, which are readable whether or
+/// not they were ever stored.
+fn empty_object_type(sha1: [u8; 20]) -> Option<BinaryObject> {
+    match sha1 {
+        Repository::EMPTY_TREE_SHA1 => Some(Tree),
+        Repository::EMPTY_BLOB_SHA1 => Some(Blob),
+        _ => None,
+    }
+}
+
+/// Read the `<type> <size>\0` header of a loose object one byte at a time,
+/// leaving the reader at the start of the object data.
+fn read_object_header<T: Read>(reader: &mut T) -> Result<(BinaryObject, usize)> {
+    let (type_name, size) = read_raw_object_header(reader)?;
+    Ok((object_type_from_name(&type_name)?, size))
+}
+
+fn object_type_from_name(type_name: &[u8]) -> Result<BinaryObject> {
+    Ok(match type_name {
+        b"blob" => Blob,
+        b"commit" => Commit,
+        b"tree" => Tree,
+        b"tag" => Tag,
+        _ => bail!(
+            "unknown object type {}",
+            from_utf8(type_name).unwrap_or("<<invalid utf8>>")
+        ),
+    })
+}
+
+/// Read a loose object's `<type> <size>\0` header, leaving the type word as is.
+fn read_raw_object_header<T: Read>(reader: &mut T) -> Result<(Vec<u8>, usize)> {
+    let mut header = Vec::new();
+    loop {
+        match read_byte(reader).context("reading object header")? {
+            0 => break,
+            b => header.push(b),
+        }
+        ensure!(header.len() <= 32, "object header too long");
+    }
+    trace!("header [{}]", from_utf8(&header).unwrap_or("<<bad-utf8>>"));
+
+    let space = header
+        .iter()
+        .position(|&b| b == b' ')
+        .context("expected space")?;
+    let size = from_utf8(&header[space + 1..])
+        .context("parsing size as utf8")?
+        .parse::<usize>()
+        .context("parsing size as usize")?;
+    header.truncate(space);
+    Ok((header, size))
+}
+
+fn read_config(path: &Path) -> Result<Ini> {
+    let file = File::open(path)
+        .with_context(|| format!("opening config file {}", path.to_string_lossy()))?;
+    let mut reader = BufReader::new(file);
+
+    let mut config = String::new();
+    reader
+        .read_to_string(&mut config)
+        .context("reading config file")?;
+    parse_config(config)
+}
+
+/// Read `~/.gitconfig` from `home`, warning rather than failing when it is
+/// broken, as it should not stop every repository from opening.
+fn read_global_config(home: &Path) -> Option<Ini> {
+    let path = home.join(".gitconfig");
+    if !path.is_file() {
+        return None;
+    }
+    read_config(&path)
+        .inspect_err(|e| warn!("ignoring global config: {:#}", e))
+        .ok()
+}
+
+fn parse_config(config: String) -> Result<Ini> {
+    let mut conf = Ini::new();
+    if let Err(e) = conf.read(config) {
+        bail!("error parsing config contents: {}", e);
+    }
+    Ok(conf)
+}
+
+fn default_config(bare: bool) -> Ini {
+    let mut ini = Ini::new();
+    ini.setstr("core", "repositoryformatversion", Some("0"));
+    ini.setstr("core", "filemode", Some("false"));
+    ini.setstr("core", "bare", Some(&bare.to_string()));
+    ini
+}
+
+/// Version 1 repositories list the extensions a reader must understand to
+/// use them safely, so refuse any that we don't.
+fn check_extensions(conf: &Ini) -> Result<()> {
+    let Some(extensions) = conf.get_map_ref().get("extensions") else {
+        return Ok(());
+    };
+    for (name, value) in extensions {
+        let value = value.as_deref().unwrap_or_default();
+        match name.as_str() {
+            "noop" | "worktreeconfig" => {}
+            "objectformat" => ensure!(
+                value.eq_ignore_ascii_case("sha1"),
+                WyagError::Unsupported(format!("Unsupported object format: {}", value))
+            ),
+            _ => bail!(WyagError::Unsupported(format!(
+                "Unsupported repository extension: {}",
+                name
+            ))),
+        }
+    }
+    Ok(())
+}
+
+/// Whether `path` looks like the gitdir of a bare repository.
+fn is_bare_gitdir(path: &Path) -> bool {
+    path.join("HEAD").is_file() && path.join("objects").is_dir() && path.join("refs").is_dir()
+}
+
+#[derive(PartialEq, Copy, Clone)]
+enum ObjectLocation {
+    ObjectFile,
+    PackFile([u8; 20], u64),
+}
+
+#[cfg(test)]
+mod test {
+    use crate::diff::diff_blobs;
+    use crate::error::WyagError;
+    use crate::gitobject::blob::BlobObject;
+    use crate::gitobject::commit::CommitObject;
+    use crate::gitobject::tag::TagObject;
+    use crate::gitobject::tree::{TreeBuilder, TreeObject};
+    use crate::gitobject::{GitObject, ObjectType};
+    use crate::logiterator::LogIterator;
+    use crate::pack::{BinaryObject, Pack, write_entry, write_pack};
+    use crate::repository::ObjectLocation::PackFile;
+    use crate::repository::{
+        DeltaBase, FsckProblem, GLOBAL_INDEX_CACHE, GlobalIndex, LsTreeFormat, ObjectSource,
+        Repository, read_global_config,
+    };
+    use crate::status::{Change, Status};
+    use crate::testutil::{temp_repo, write_annotated_tag, write_history, write_nested_commit};
+    use crate::util::hash_raw_object;
+    use anyhow::bail;
+    use flate2::Compression;
+    use hex::{ToHex, decode};
+    use sha1::{Digest, Sha1};
+    use std::collections::HashSet;
+    use std::fs::File;
+    use std::io::{BufReader, Cursor, Write};
+    use std::path::{Path, PathBuf};
+    use std::rc::Rc;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn read_tiny_object() {
+        let (_dir, repo) = temp_repo();
+
+        for contents in [&b"a"[..], b""] {
+            let blob = GitObject::Blob(BlobObject::from(contents.to_vec()));
+            let sha1 = repo
+                .write_object(&blob, true, Compression::default())
+                .unwrap();
+            let mut data = Vec::new();
+            let object_type = repo.read_object_data(sha1, &mut data).unwrap();
+            assert!(object_type == BinaryObject::Blob);
+            assert_eq!(data, contents);
+        }
+    }
+
+    #[test]
+    fn write_existing_object() {
+        let (_dir, repo) = temp_repo();
+
+        let blob = GitObject::Blob(BlobObject::from(b"twice".to_vec()));
+        let sha1 = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap();
+        let path = repo.object_file_path(sha1).unwrap();
+        let written = std::fs::read(&path).unwrap();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        File::open(&path).unwrap().set_modified(old).unwrap();
+
+        // a different compression level would change the file, were it rewritten
+        assert_eq!(
+            repo.write_object(&blob, true, Compression::best()).unwrap(),
+            sha1
+        );
+        assert_eq!(std::fs::read(&path).unwrap(), written);
+        assert!(path.metadata().unwrap().modified().unwrap() > old);
+
+        // a file holding some other object is reported rather than trusted
+        let other = GitObject::Blob(BlobObject::from(b"another".to_vec()));
+        let other = repo
+            .write_object(&other, true, Compression::default())
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        std::fs::copy(repo.object_file_path(other).unwrap(), &path).unwrap();
+        let err = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("may be corrupt"), "{:#}", err);
+    }
+
+    #[test]
+    fn writes_without_fsync() {
+        let (dir, mut repo) = temp_repo();
+
+        for fsync in ["true", "false"] {
+            repo.config_set("core", "fsyncObjectFiles", fsync).unwrap();
+            let blob = GitObject::Blob(BlobObject::from(fsync.as_bytes().to_vec()));
+            let sha1 = repo
+                .write_object(&blob, true, Compression::default())
+                .unwrap();
+            repo.write_ref("refs/heads/master", &sha1.encode_hex::<String>())
+                .unwrap();
+            assert_eq!(repo.find_object("master").unwrap(), sha1);
+            assert!(repo.has_object(sha1));
+        }
+        // nothing is left behind in the gitdir
+        assert!(
+            std::fs::read_dir(dir.path().join(".git"))
+                .unwrap()
+                .all(|e| !e.unwrap().file_name().to_string_lossy().starts_with(".tmp"))
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn written_objects_are_read_only() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let (_dir, repo) = temp_repo();
+
+        let blob = GitObject::Blob(BlobObject::from(b"read only".to_vec()));
+        let sha1 = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap();
+        let path = repo.object_file_path(sha1).unwrap();
+        assert_eq!(path.metadata().unwrap().permissions().mode() & 0o777, 0o444);
+
+        // writing it again leaves the existing file alone
+        assert_eq!(
+            repo.write_object(&blob, true, Compression::default())
+                .unwrap(),
+            sha1
+        );
+        assert!(path.metadata().unwrap().permissions().readonly());
+    }
+
+    #[test]
+    fn empty_objects() {
+        let (_dir, repo) = temp_repo();
+        let empty_blob = decode("e69de29bb2d1d6434b8b29ae775ad8c2e48c5391").unwrap();
+        let empty_tree = decode("4b825dc642cb6eb9a060e54bf8d69288fbee4904").unwrap();
+        assert_eq!(empty_blob, Repository::EMPTY_BLOB_SHA1);
+        assert_eq!(empty_tree, Repository::EMPTY_TREE_SHA1);
+        let empty_tree: [u8; 20] = empty_tree.try_into().unwrap();
+
+        // the empty tree can be read before anything has written it
+        assert!(repo.has_object(empty_tree));
+        let mut data = b"stale".to_vec();
+        assert_eq!(
+            repo.read_object_data(empty_tree, &mut data).unwrap(),
+            BinaryObject::Tree
+        );
+        assert!(data.is_empty());
+        assert_eq!(
+            repo.object_info(empty_tree).unwrap(),
+            (BinaryObject::Tree, 0)
+        );
+
+        let blob = GitObject::Blob(BlobObject::from(Vec::new()));
+        assert_eq!(
+            repo.write_object(&blob, true, Compression::default())
+                .unwrap()[..],
+            empty_blob
+        );
+        let tree = GitObject::Tree(TreeBuilder::new().build());
+        assert_eq!(
+            repo.write_object(&tree, true, Compression::default())
+                .unwrap(),
+            empty_tree
+        );
+        for (sha1, object_type) in [
+            (empty_blob.clone().try_into().unwrap(), BinaryObject::Blob),
+            (empty_tree, BinaryObject::Tree),
+        ] {
+            let mut data = Vec::new();
+            assert_eq!(repo.read_object_data(sha1, &mut data).unwrap(), object_type);
+            assert!(data.is_empty());
+            assert_eq!(repo.object_info(sha1).unwrap(), (object_type, 0));
+        }
+
+        // and read back from a pack
+        let (_dir, repo) = temp_repo();
+        let pack = write_pack(&[(BinaryObject::Blob, Vec::new())], Compression::default()).unwrap();
+        repo.index_pack(&pack, Compression::default()).unwrap();
+        let mut data = Vec::new();
+        let sha1 = empty_blob.try_into().unwrap();
+        assert_eq!(
+            repo.read_object_data(sha1, &mut data).unwrap(),
+            BinaryObject::Blob
+        );
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn diff_against_empty_tree() {
+        let (dir, repo) = temp_repo();
+
+        // a root commit of an empty directory, as commit-tree writes it
+        let commit = format!(
+            "tree {}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nempty\n",
+            Repository::EMPTY_TREE_SHA1.encode_hex::<String>()
+        );
+        let commit = GitObject::Commit(CommitObject::from(commit.into_bytes()).unwrap());
+        let commit = repo
+            .write_object(&commit, true, Compression::default())
+            .unwrap();
+        repo.write_ref("refs/heads/master", &commit.encode_hex::<String>())
+            .unwrap();
+        assert!(repo.tree_entries(commit).unwrap().is_empty());
+
+        std::fs::write(dir.path().join("a"), "hello\n").unwrap();
+        repo.add(&[dir.path().join("a")], false).unwrap();
+        let status = Status::new(&repo).unwrap();
+        assert_eq!(status.staged, vec![(Change::Added, PathBuf::from("a"))]);
+
+        let mut empty = b"stale".to_vec();
+        assert_eq!(
+            repo.read_object_data(Repository::EMPTY_BLOB_SHA1, &mut empty)
+                .unwrap(),
+            BinaryObject::Blob
+        );
+        assert_eq!(diff_blobs(&empty, b"hello\n", 3), "@@ -0,0 +1 @@\n+hello\n");
+    }
+
+    #[test]
+    fn read_object_as_checks_type() {
+        let (_dir, repo) = temp_repo();
+
+        let blob = GitObject::Blob(BlobObject::from(b"contents".to_vec()));
+        let blob_sha1 = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap();
+        let mut tree = TreeBuilder::new();
+        tree.insert("file", "100644", blob_sha1).unwrap();
+        let tree = GitObject::Tree(tree.build());
+        let tree_sha1 = repo
+            .write_object(&tree, true, Compression::default())
+            .unwrap();
+        let commit = format!(
+            "tree {}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nmessage\n",
+            tree_sha1.encode_hex::<String>()
+        );
+        let commit = GitObject::Commit(CommitObject::from(commit.into_bytes()).unwrap());
+        let commit_sha1 = repo
+            .write_object(&commit, true, Compression::default())
+            .unwrap();
+        let tag = format!(
+            "object {}\ntype commit\ntag v1\ntagger A <a@b> 0 +0000\n\nmessage\n",
+            commit_sha1.encode_hex::<String>()
+        );
+        let tag = GitObject::Tag(TagObject::from(tag.into_bytes()).unwrap());
+        let tag_sha1 = repo
+            .write_object(&tag, true, Compression::default())
+            .unwrap();
+
+        let objects = [
+            (ObjectType::Blob, blob_sha1),
+            (ObjectType::Tree, tree_sha1),
+            (ObjectType::Commit, commit_sha1),
+            (ObjectType::Tag, tag_sha1),
+        ];
+        for (stored, sha1) in objects {
+            for (requested, _) in objects {
+                let mut data = Vec::new();
+                let result = repo.read_object_data_as(sha1, requested, &mut data);
+                if requested == stored {
+                    assert!(result.is_ok(), "{} as {}", stored, requested);
+                } else {
+                    let message = result.unwrap_err().to_string();
+                    assert!(
+                        message.ends_with(&format!("is a {}, not a {}", stored, requested)),
+                        "{}",
+                        message
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn read_unknown_object_type() {
+        let (_dir, repo) = temp_repo();
+
+        let sha1 = repo
+            .write_object_stream(b"bogus", 3, &b"abc"[..], true, Compression::default())
+            .unwrap();
+        let err = repo.read_object_data(sha1, &mut Vec::new()).unwrap_err();
+        assert!(matches!(err, WyagError::Corrupt { .. }), "{:?}", err);
+        assert!(
+            err.to_string().contains("unknown object type bogus"),
+            "{}",
+            err
+        );
+
+        let mut data = Vec::new();
+        assert_eq!(repo.read_raw_object(sha1, &mut data).unwrap(), "bogus");
+        assert_eq!(data, b"abc");
+
+        let blob = GitObject::Blob(BlobObject::from(b"known".to_vec()));
+        let blob = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap();
+        let mut data = Vec::new();
+        assert_eq!(repo.read_raw_object(blob, &mut data).unwrap(), "blob");
+        assert_eq!(data, b"known");
+    }
+
+    #[test]
+    fn compression_levels_share_shas() {
+        let contents = b"compressible ".repeat(100);
+        let blob = GitObject::Blob(BlobObject::from(contents.clone()));
+        let mut loose_sizes = Vec::new();
+        let mut shas = Vec::new();
+        for level in [
+            Compression::none(),
+            Compression::new(6),
+            Compression::best(),
+        ] {
+            let (dir, repo) = temp_repo();
+
+            let sha1 = repo.write_object(&blob, true, level).unwrap();
+            let sha = sha1.encode_hex::<String>();
+            let path = dir
+                .path()
+                .join(".git/objects")
+                .join(&sha[..2])
+                .join(&sha[2..]);
+            loose_sizes.push(std::fs::metadata(path).unwrap().len());
+            shas.push(sha1);
+
+            let mut data = Vec::new();
+            repo.read_object_data(sha1, &mut data).unwrap();
+            assert_eq!(data, contents);
+
+            let pack = write_pack(&[(BinaryObject::Blob, b"packed ".repeat(100))], level).unwrap();
+            repo.index_pack(&pack, level).unwrap();
+            let packed = GitObject::Blob(BlobObject::from(b"packed ".repeat(100)));
+            let packed = repo.write_object(&packed, false, level).unwrap();
+            let mut data = Vec::new();
+            repo.read_object_data(packed, &mut data).unwrap();
+            assert_eq!(data, b"packed ".repeat(100));
+        }
+        assert!(shas.iter().all(|&sha1| sha1 == shas[0]));
+        assert!(loose_sizes[0] > loose_sizes[1] && loose_sizes[1] >= loose_sizes[2]);
+    }
+
+    #[test]
+    fn hash_object_types() {
+        let (dir, repo) = temp_repo();
+
+        // shas from git hash-object
+        let objects: [(ObjectType, BinaryObject, &[u8], &str); 4] = [
+            (
+                ObjectType::Blob,
+                BinaryObject::Blob,
+                b"hello\n",
+                "ce013625030ba8dba906f756967f9e9ca394464a",
+            ),
+            (
+                ObjectType::Tree,
+                BinaryObject::Tree,
+                b"100644 file\0aaaaaaaaaaaaaaaaaaaa",
+                "3f60a3872311e51bc6c8bb1752fb06a49a9fcbbd",
+            ),
+            (
+                ObjectType::Commit,
+                BinaryObject::Commit,
+                b"tree 4b825dc642cb6eb9a060e54bf8d69288fbee4904\n\
+                  author A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nmsg\n",
+                "451424af2a9b5b83dadfa2c57d906dd53a07399a",
+            ),
+            (
+                ObjectType::Tag,
+                BinaryObject::Tag,
+                b"object 4b825dc642cb6eb9a060e54bf8d69288fbee4904\ntype tree\n\
+                  tag v1\ntagger A <a@b> 0 +0000\n\nmsg\n",
+                "d4df28866b0a161d51b82989665f9cd3f26d7700",
+            ),
+        ];
+        let path = dir.path().join("object");
+        for (command_type, object_type, contents, sha) in objects {
+            std::fs::write(&path, contents).unwrap();
+            let sha1 = repo
+                .object_hash(&path, command_type, None, true, Compression::default())
+                .unwrap();
+            assert_eq!(sha1.encode_hex::<String>(), sha);
+            let mut data = Vec::new();
+            assert_eq!(repo.read_object_data(sha1, &mut data).unwrap(), object_type);
+            assert_eq!(data, contents);
+        }
+
+        std::fs::write(&path, b"not an object").unwrap();
+        for command_type in [ObjectType::Tree, ObjectType::Commit, ObjectType::Tag] {
+            let err = repo
+                .object_hash(&path, command_type, None, false, Compression::default())
+                .unwrap_err();
+            assert_eq!(err.to_string(), format!("corrupt {} file", command_type));
+        }
+    }
+
+    #[test]
+    fn hash_object_as_if_at_path() {
+        let (dir, repo) = temp_repo();
+        std::fs::write(dir.path().join(".gitattributes"), "*.txt text\n").unwrap();
+        let file = dir.path().join("crlf");
+        std::fs::write(&file, b"a\r\nb\r\n").unwrap();
+
+        let hash = |path: Option<&Path>| {
+            repo.object_hash(&file, ObjectType::Blob, path, false, Compression::default())
+                .unwrap()
+                .encode_hex::<String>()
+        };
+        // shas from git hash-object --path
+        assert_eq!(hash(None), "c30dea8a3641ea99b125d04d599d843712292759");
+        assert_eq!(
+            hash(Some(&dir.path().join("a.bin"))),
+            "c30dea8a3641ea99b125d04d599d843712292759"
+        );
+        assert_eq!(
+            hash(Some(&dir.path().join("a.txt"))),
+            "422c2b7ab3b3c668038da977e4e93a5fc623169c"
+        );
+    }
+
+    #[test]
+    fn write_object_refuses_deltas() {
+        let (_dir, repo) = temp_repo();
+
+        // base size 0, result size 1, insert one literal byte
+        let delta = GitObject::new(BinaryObject::OffsetDelta(12), vec![0, 1, 1, b'a']).unwrap();
+        assert!(
+            repo.write_object(&delta, true, Compression::default())
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn reserved_object_type() {
+        let (_dir, repo) = temp_repo();
+
+        let mut pack = write_pack(
+            &[(BinaryObject::Blob, b"future".to_vec())],
+            Compression::default(),
+        )
+        .unwrap();
+        // give the entry type id 5, which git reserves, and fix up the checksum
+        pack[12] = (pack[12] & 0b1000_1111) | (5 << 4);
+        let end = pack.len() - 20;
+        let checksum: [u8; 20] = Sha1::digest(&pack[..end]).into();
+        pack[end..].copy_from_slice(&checksum);
+        let id = checksum.encode_hex::<String>();
+        repo.store_pack(&format!("pack-{}.pack", id), &pack)
+            .unwrap();
+
+        let objects = repo.list_pack(&id, None).unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].object_type, BinaryObject::Reserved(5));
+        assert_eq!(objects[0].object_type.name(), "reserved5");
+        assert_eq!(objects[0].size, 6);
+        assert!(!objects[0].object_type.is_delta());
+        assert!(GitObject::new(BinaryObject::Reserved(5), b"future".to_vec()).is_err());
+    }
+
+    #[test]
+    fn index_pack_matches_git() {
+        let (dir, repo) = temp_repo();
+        let name = "pack-2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        let pack = std::fs::read(format!("test/pack/{}.pack", name)).unwrap();
+
+        let pack_sha1 = repo.index_pack(&pack, Compression::default()).unwrap();
+
+        assert_eq!(
+            pack_sha1.encode_hex::<String>(),
+            "2c61a5470c8319d5ff6cf64199aa714f18414c26"
+        );
+        let index = dir.path().join(format!(".git/objects/pack/{}.idx", name));
+        assert_eq!(
+            std::fs::read(index).unwrap(),
+            std::fs::read(format!("test/pack/{}.idx", name)).unwrap()
+        );
+
+        let mut truncated = pack.clone();
+        truncated.truncate(pack.len() - 1);
+        assert!(repo.index_pack(&truncated, Compression::default()).is_err());
+    }
+
+    #[test]
+    fn verify_pack_in_parallel() {
+        let (dir, repo) = temp_repo();
+        let pack_dir = dir.path().join(".git/objects/pack");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        let id = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        for ext in ["idx", "pack"] {
+            let name = format!("pack-{}.{}", id, ext);
+            std::fs::copy(format!("test/pack/{}", name), pack_dir.join(name)).unwrap();
+        }
+
+        let serial = repo.verify_pack(id, 1).unwrap();
+        let parallel = repo.verify_pack(id, 4).unwrap();
+        assert_eq!(serial.len(), 36);
+        assert!(
+            serial
+                .iter()
+                .zip(parallel.iter())
+                .all(|(a, b)| a.0 == b.0 && a.1 == b.1 && a.2 == b.2)
+        );
+
+        // a damaged object is caught by its crc32 before anything is inflated
+        let pack_path = pack_dir.join(format!("pack-{}.pack", id));
+        let mut pack = std::fs::read(&pack_path).unwrap();
+        pack[100] ^= 0xff;
+        std::fs::write(&pack_path, &pack).unwrap();
+        let err = repo.verify_pack(id, 1).unwrap_err().to_string();
+        assert!(err.contains("does not match the index"), "{}", err);
+        pack[100] ^= 0xff;
+
+        pack[8..12].copy_from_slice(&37u32.to_be_bytes());
+        std::fs::write(&pack_path, pack).unwrap();
+        let err = repo.verify_pack(id, 1).unwrap_err().to_string();
+        assert!(
+            err.contains("has 37 objects but its index lists 36"),
+            "{}",
+            err
+        );
+    }
+
+    #[test]
+    fn pack_checksum_catches_flipped_byte() {
+        let (dir, repo) = temp_repo();
+        let pack_dir = dir.path().join(".git/objects/pack");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        let id = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        for ext in ["idx", "pack"] {
+            let name = format!("pack-{}.{}", id, ext);
+            std::fs::copy(format!("test/pack/{}", name), pack_dir.join(name)).unwrap();
+        }
+        let commit: [u8; 20] = decode("94cfdc807c74db921207c72b7f1d21c748ddc807")
+            .unwrap()
+            .try_into()
+            .unwrap();
+
+        let mut data = std::fs::read(pack_dir.join(format!("pack-{}.pack", id))).unwrap();
+        let pack = Pack::new(BufReader::new(Cursor::new(&data))).unwrap();
+        assert_eq!(pack.sha().unwrap().encode_hex::<String>(), id);
+        assert_eq!(pack.validate_checksum().unwrap().encode_hex::<String>(), id);
+
+        // the last object, which no object read below goes near
+        let last = data.len() - 30;
+        data[last] ^= 0xff;
+        let pack = Pack::new(BufReader::new(Cursor::new(&data))).unwrap();
+        assert_eq!(pack.sha().unwrap().encode_hex::<String>(), id);
+        let err = pack.validate_checksum().unwrap_err().to_string();
+        assert!(err.contains("checksum mismatch"), "{}", err);
+        std::fs::write(pack_dir.join(format!("pack-{}.pack", id)), &data).unwrap();
+
+        // objects elsewhere in the pack read fine unless the pack is checked on open
+        assert!(repo.read_object_data(commit, &mut Vec::new()).is_ok());
+        let mut repo = Repository::new(dir.path(), false).unwrap();
+        repo.config_set("core", "verifyPackChecksum", "true")
+            .unwrap();
+        let err = repo.read_object_data(commit, &mut Vec::new()).unwrap_err();
+        assert!(
+            format!("{:#}", err).contains("checksum mismatch"),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn read_survives_pack_replaced_underneath() {
+        let (dir, repo) = temp_repo();
+        let id = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        let pack = std::fs::read(format!("test/pack/pack-{}.pack", id)).unwrap();
+        repo.index_pack(&pack, Compression::default()).unwrap();
+        let commit: [u8; 20] = decode("94cfdc807c74db921207c72b7f1d21c748ddc807")
+            .unwrap()
+            .try_into()
+            .unwrap();
+        // builds the global index, without opening the pack
+        assert!(repo.has_object(commit));
+
+        // another process repacks the same objects and removes the old pack
+        let other = Repository::new(dir.path(), false).unwrap();
+        let objects = Pack::new(BufReader::new(Cursor::new(&pack)))
+            .unwrap()
+            .resolve_all()
+            .unwrap()
+            .into_iter()
+            .map(|(_, object_type, data)| (object_type, data))
+            .collect::<Vec<_>>();
+        let replacement = other
+            .index_pack(
+                &write_pack(&objects, Compression::none()).unwrap(),
+                Compression::none(),
+            )
+            .unwrap();
+        assert_ne!(replacement.encode_hex::<String>(), id);
+        for ext in ["idx", "pack"] {
+            std::fs::remove_file(
+                dir.path()
+                    .join(format!(".git/objects/pack/pack-{}.{}", id, ext)),
+            )
+            .unwrap();
+        }
+
+        let mut data = Vec::new();
+        let (object_type, source) = repo.read_object_with_source(commit, &mut data).unwrap();
+        assert_eq!(object_type, BinaryObject::Commit);
+        assert!(matches!(source, ObjectSource::Packed { pack, .. } if pack == replacement));
+    }
+
+    #[test]
+    fn skip_object_content_check() {
+        let (dir, _) = temp_repo();
+        let mut repo = Repository::new(dir.path(), false).unwrap();
+        let right = repo
+            .write_object(
+                &GitObject::Blob(BlobObject::from(b"right".to_vec())),
+                true,
+                Compression::default(),
+            )
+            .unwrap();
+        // a loose file whose contents do not hash to its name
+        let wrong = hash_raw_object(b"blob", b"wrong");
+        let path = |sha1: [u8; 20]| {
+            let hex = sha1.encode_hex::<String>();
+            dir.path()
+                .join(".git/objects")
+                .join(&hex[..2])
+                .join(&hex[2..])
+        };
+        std::fs::create_dir_all(path(wrong).parent().unwrap()).unwrap();
+        std::fs::copy(path(right), path(wrong)).unwrap();
+        assert!(repo.read_object_data(wrong, &mut Vec::new()).is_err());
+
+        repo.set_check_object_content(false);
+        let mut data = Vec::new();
+        assert_eq!(
+            repo.read_object_data(wrong, &mut data).unwrap(),
+            BinaryObject::Blob
+        );
+        assert_eq!(data, b"right");
+
+        repo.set_check_object_content(true);
+        repo.config_set("core", "checkObjectContent", "false")
+            .unwrap();
+        assert!(repo.read_object_data(wrong, &mut Vec::new()).is_err());
+        let repo = Repository::new(dir.path(), false).unwrap();
+        assert!(repo.read_object_data(wrong, &mut Vec::new()).is_ok());
+    }
+
+    #[test]
+    fn object_type_without_reading_data() {
+        let (dir, repo) = temp_repo();
+        let id = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        let pack = std::fs::read(format!("test/pack/pack-{}.pack", id)).unwrap();
+        repo.index_pack(&pack, Compression::default()).unwrap();
+
+        // a ref delta within the pack, and a loose copy of its base
+        let base = b"the base of a ref delta\n".to_vec();
+        let base_sha1 = hash_raw_object(b"blob", &base);
+        let mut delta = vec![base.len() as u8, base.len() as u8 + 5];
+        delta.extend_from_slice(&[0b1001_0000, base.len() as u8, 5]);
+        delta.extend_from_slice(b"more\n");
+        repo.index_pack(
+            &write_pack(
+                &[
+                    (BinaryObject::Blob, base.clone()),
+                    (BinaryObject::RefDelta(base_sha1), delta),
+                ],
+                Compression::default(),
+            )
+            .unwrap(),
+            Compression::default(),
+        )
+        .unwrap();
+        let tree = repo
+            .write_object(
+                &GitObject::Tree(TreeBuilder::new().build()),
+                true,
+                Compression::default(),
+            )
+            .unwrap();
+        write_annotated_tag(&repo, "v1", tree);
+        let tag = repo.find_object("v1").unwrap();
+
+        let repo = Repository::new(dir.path(), false).unwrap();
+        let mut objects = repo
+            .verify_pack(id, 1)
+            .unwrap()
+            .into_iter()
+            .map(|(sha1, _, _)| sha1)
+            .collect::<Vec<_>>();
+        objects.extend([
+            base_sha1,
+            hash_raw_object(b"blob", b"the base of a ref delta\nmore\n"),
+        ]);
+        objects.extend([tree, tag]);
+        for sha1 in objects {
+            let mut data = Vec::new();
+            assert_eq!(
+                repo.object_type(sha1).unwrap(),
+                repo.read_object_data(sha1, &mut data).unwrap(),
+                "{}",
+                sha1.encode_hex::<String>()
+            );
+        }
+        assert_eq!(repo.object_type(tag).unwrap(), BinaryObject::Tag);
+        assert_eq!(repo.peel_tags(tag).unwrap(), tree);
+        assert_eq!(repo.peel_tags(tree).unwrap(), tree);
+        assert!(repo.object_type([0; 20]).is_err());
+    }
+
+    #[test]
+    fn delta_type_from_headers() {
+        let (_dir, repo) = temp_repo();
+        let id = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        let data = std::fs::read(format!("test/pack/pack-{}.pack", id)).unwrap();
+        repo.index_pack(&data, Compression::default()).unwrap();
+        let pack = Pack::new(BufReader::new(Cursor::new(&data))).unwrap();
+
+        let deltas = repo
+            .list_pack(id, None)
+            .unwrap()
+            .into_iter()
+            .filter(|object| object.delta.is_some())
+            .collect::<Vec<_>>();
+        assert!(!deltas.is_empty());
+        for object in deltas {
+            let DeltaBase::Offset(base, _) = object.delta.unwrap().1 else {
+                panic!("expected an offset delta");
+            };
+            assert_eq!(
+                pack.read_type_at(object.offset).unwrap(),
+                BinaryObject::OffsetDelta(object.offset - base)
+            );
+            let mut data = Vec::new();
+            let object_type = repo.read_object_data(object.sha1, &mut data).unwrap();
+            assert_eq!(object_type, object.object_type);
+            assert_eq!(repo.object_type(object.sha1).unwrap(), object_type);
+        }
+    }
+
+    #[test]
+    fn read_middle_of_large_blob() {
+        let (dir, repo) = temp_repo();
+        let blob = (0..1 << 20)
+            .map(|i| (i * 7 % 251) as u8)
+            .collect::<Vec<_>>();
+        let sha1 = repo
+            .write_object(
+                &GitObject::Blob(BlobObject::from(blob.clone())),
+                true,
+                Compression::default(),
+            )
+            .unwrap();
+        let middle = &blob[(1 << 19) - 50..(1 << 19) + 50];
+
+        let mut out = Vec::new();
+        assert_eq!(
+            repo.read_object_range(sha1, (1 << 19) - 50, 100, &mut out)
+                .unwrap(),
+            (BinaryObject::Blob, 100)
+        );
+        assert_eq!(out, middle);
+
+        // the same from a pack, with the loose copy gone
+        repo.index_pack(
+            &write_pack(
+                &[(BinaryObject::Blob, blob.clone())],
+                Compression::default(),
+            )
+            .unwrap(),
+            Compression::default(),
+        )
+        .unwrap();
+        let hex = sha1.encode_hex::<String>();
+        std::fs::remove_file(
+            dir.path()
+                .join(".git/objects")
+                .join(&hex[..2])
+                .join(&hex[2..]),
+        )
+        .unwrap();
+        let repo = Repository::new(dir.path(), false).unwrap();
+        let mut out = Vec::new();
+        repo.read_object_range(sha1, (1 << 19) - 50, 100, &mut out)
+            .unwrap();
+        assert_eq!(out, middle);
+
+        // a range running off the end stops with the object
+        let mut out = Vec::new();
+        assert_eq!(
+            repo.read_object_range(sha1, (1 << 20) - 10, 100, &mut out)
+                .unwrap(),
+            (BinaryObject::Blob, 10)
+        );
+        assert_eq!(out, &blob[(1 << 20) - 10..]);
+        assert_eq!(
+            repo.read_object_range(sha1, 2 << 20, 100, &mut Vec::new())
+                .unwrap(),
+            (BinaryObject::Blob, 0)
+        );
+        assert!(matches!(
+            repo.read_object_range([0; 20], 0, 100, &mut Vec::new()),
+            Err(WyagError::NotFound(_))
+        ));
+    }
+
+    #[test]
+    fn global_index_cache() {
+        let (dir, repo) = temp_repo();
+        let pack_dir = dir.path().join(".git/objects/pack");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        let id = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        for ext in ["idx", "pack"] {
+            let name = format!("pack-{}.{}", id, ext);
+            std::fs::copy(format!("test/pack/{}", name), pack_dir.join(name)).unwrap();
+        }
+        let packed = repo.verify_pack(id, 1).unwrap()[0].0;
+
+        let cache = dir.path().join(".git").join(GLOBAL_INDEX_CACHE);
+        assert!(!cache.exists());
+        assert!(repo.has_object(packed));
+        let data = std::fs::read(&cache).unwrap();
+        let key: [u8; 20] = data[4..24].try_into().unwrap();
+        let cached = GlobalIndex::from_bytes(&data, key).unwrap();
+        assert_eq!(cached.hashes.len(), 36);
+        assert!(cached.search(packed).is_some());
+        assert!(GlobalIndex::from_bytes(&data, [0; 20]).is_none());
+        assert!(GlobalIndex::from_bytes(&data[..data.len() - 1], key).is_none());
+
+        // a new repository reads the cache rather than the indexes
+        let repo = Repository::new(dir.path(), false).unwrap();
+        assert!(repo.has_object(packed));
+        assert_eq!(std::fs::read(&cache).unwrap(), data);
+
+        // adding a pack invalidates it
+        let pack = write_pack(
+            &[(BinaryObject::Blob, b"new".to_vec())],
+            Compression::default(),
+        )
+        .unwrap();
+        repo.index_pack(&pack, Compression::default()).unwrap();
+        let repo = Repository::new(dir.path(), false).unwrap();
+        assert!(repo.has_object(hash_raw_object(b"blob", b"new")));
+        assert!(repo.has_object(packed));
+        assert_ne!(std::fs::read(&cache).unwrap(), data);
+    }
+
+    #[test]
+    fn global_index_search_bounds() {
+        let sha = |first: u8, last: u8| {
+            let mut sha1 = [first; 20];
+            sha1[19] = last;
+            sha1
+        };
+        // the first and last objects of the index, of a fanout bucket and of
+        // neighbouring buckets, with empty buckets between them
+        let present = [
+            sha(0x00, 0x00),
+            sha(0x00, 0x01),
+            sha(0x00, 0xff),
+            sha(0x01, 0x00),
+            sha(0x7f, 0x01),
+            sha(0xff, 0x00),
+            sha(0xff, 0xff),
+        ];
+        let pack = [9; 20];
+        let index = GlobalIndex::new(
+            present
+                .iter()
+                .enumerate()
+                .map(|(n, sha1)| (*sha1, pack, n as u64))
+                .collect(),
+        );
+        for (n, sha1) in present.iter().enumerate() {
+            assert!(index.search(*sha1) == Some(PackFile(pack, n as u64)));
+        }
+        for missing in [
+            sha(0x00, 0x02),
+            sha(0x01, 0x01),
+            sha(0x02, 0x00),
+            sha(0x7f, 0x00),
+            sha(0x7f, 0x02),
+            sha(0x80, 0x00),
+            sha(0xff, 0x01),
+        ] {
+            assert!(index.search(missing).is_none());
+        }
+        assert!(GlobalIndex::new(Vec::new()).search(sha(0, 0)).is_none());
+    }
+
+    #[test]
+    fn object_in_two_packs() {
+        let (dir, repo) = temp_repo();
+
+        let shared = (BinaryObject::Blob, b"shared".to_vec());
+        let older = write_pack(
+            &[shared.clone(), (BinaryObject::Blob, b"older".to_vec())],
+            Compression::default(),
+        )
+        .unwrap();
+        let newer = write_pack(
+            &[(BinaryObject::Blob, b"newer".to_vec()), shared],
+            Compression::default(),
+        )
+        .unwrap();
+        let older = repo.index_pack(&older, Compression::default()).unwrap();
+        let newer = repo.index_pack(&newer, Compression::default()).unwrap();
+        let pack_dir = dir.path().join(".git/objects/pack");
+        for (id, age) in [(older, 60), (newer, 0)] {
+            let index = File::options()
+                .write(true)
+                .open(pack_dir.join(format!("pack-{}.idx", id.encode_hex::<String>())))
+                .unwrap();
+            index
+                .set_modified(SystemTime::now() - Duration::from_secs(age))
+                .unwrap();
+        }
+
+        let repo = Repository::new(dir.path(), false).unwrap();
+        let shared = hash_raw_object(b"blob", b"shared");
+        assert!(matches!(
+            repo.find_object_location(shared),
+            Some(PackFile(pack, _)) if pack == newer
+        ));
+        let global = repo.global_index.borrow();
+        let global = global.as_ref().unwrap();
+        assert_eq!(global.hashes.len(), 3);
+        assert_eq!(global.fanout[255], 3);
+        for hash in global.hashes.iter() {
+            assert!(global.search(*hash).is_some());
+        }
+    }
+
+    #[test]
+    fn read_falls_back_to_loose_copy() {
+        let (dir, repo) = temp_repo();
+
+        let pack = write_pack(
+            &[(BinaryObject::Blob, b"both".to_vec())],
+            Compression::default(),
+        )
+        .unwrap();
+        let pack_id = repo.index_pack(&pack, Compression::default()).unwrap();
+        let blob = GitObject::Blob(BlobObject::from(b"both".to_vec()));
+        let sha1 = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap();
+
+        let mut data = Vec::new();
+        let (_, source) = repo.read_object_with_source(sha1, &mut data).unwrap();
+        assert_eq!(
+            source,
+            ObjectSource::Packed {
+                pack: pack_id,
+                offset: 12
+            }
+        );
+
+        // break the packed copy, leaving the header alone
+        let pack_path = dir.path().join(format!(
+            ".git/objects/pack/pack-{}.pack",
+            pack_id.encode_hex::<String>()
+        ));
+        let mut pack = std::fs::read(&pack_path).unwrap();
+        let end = pack.len() - 20;
+        pack[12..end].fill(0xff);
+        std::fs::write(&pack_path, pack).unwrap();
+
+        let repo = Repository::new(dir.path(), false).unwrap();
+        let mut data = Vec::new();
+        let (_, source) = repo.read_object_with_source(sha1, &mut data).unwrap();
+        assert!(matches!(source, ObjectSource::Loose(_)));
+        assert_eq!(data, b"both");
+
+        let (_, source) = repo
+            .read_object_with_source(Repository::EMPTY_TREE_SHA1, &mut data)
+            .unwrap();
+        assert_eq!(source, ObjectSource::Synthesized);
+    }
+
+    #[test]
+    fn reachable_count_uses_bitmap() {
+        let (dir, repo) = temp_repo();
+        let pack_dir = dir.path().join(".git/objects/pack");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        let pack = "pack-f8b8fa138f607a7c74af69f2ea2dae8b7127ede1";
+        for ext in ["bitmap", "idx", "pack"] {
+            let name = format!("{}.{}", pack, ext);
+            std::fs::copy(format!("test/bitmap/{}", name), pack_dir.join(name)).unwrap();
+        }
+
+        // first parent order from the tip, so each commit reaches all those after it
+        let history = [
+            "c0d5f6537fcfb1a0a7cf6b1b16ba154acd0b087e",
+            "16c15b942e5bb31a3be69e717ae52b33bb71ee50",
+            "94cfdc807c74db921207c72b7f1d21c748ddc807",
+            "61d90a71d7455793ffedbf4f3c4714e398d3e082",
+            "2aa194a9b4e81ca764a5b9d0494f155140d63573",
+            "95e59e3a50adc91b35cd1f74edf70e9f8d6ea51c",
+            "69c78e088fed8b4e5c4bde8e42dc6ddd67a2963a",
+            "b25c959f60c3e7939d6820a75121e66f0c9c30f1",
+            "c0ec9a1297cf194e798b9d05bcd9cb4dc48d1566",
+            "0e654b2c6aaf684df545a6ed2371baf3e47a0fde",
+            "2e6f0b41cf51d5a5e4c550af531b73d01f13ce65",
+            "ec97485bf60f3fad3a01339476a458a07267a102",
+            "8b81458e9b355abe216b06f6a39ab6e5e168ea53",
+            "681a4c1dc2981a339a3deb897646a9b43cc6e518",
+        ];
+        for (n, name) in history.iter().enumerate() {
+            let sha1 = repo.find_object(name).unwrap();
+            assert_eq!(repo.reachable_count(&[sha1]).unwrap(), history.len() - n);
+        }
+
+        // a loose commit on top is walked before falling back to the bitmap
+        let commit = "tree b7d1bc3bdb6a3b77488972e0dc02a95ea845e16d\n\
+            parent c0d5f6537fcfb1a0a7cf6b1b16ba154acd0b087e\n\
+            author A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nloose\n";
+        let commit = GitObject::Commit(CommitObject::from(commit.as_bytes().to_vec()).unwrap());
+        let head = repo
+            .write_object(&commit, true, Compression::default())
+            .unwrap();
+        assert_eq!(repo.reachable_count(&[head]).unwrap(), 15);
+        assert_eq!(repo.log_iter(head).unwrap().count(), 15);
+        let tips = [repo.find_object(history[3]).unwrap(), head];
+        assert_eq!(repo.reachable_count(&tips).unwrap(), 15);
+    }
+
+    #[test]
+    fn resolve_reflog_revisions() {
+        let (_dir, repo) = temp_repo();
+
+        let blobs = ["a", "b", "c"].map(|contents| {
+            let blob = GitObject::Blob(BlobObject::from(contents.as_bytes().to_vec()));
+            repo.write_object(&blob, true, Compression::default())
+                .unwrap()
+        });
+        for sha1 in blobs {
+            repo.update_ref("HEAD", sha1, "test").unwrap();
+        }
+
+        assert_eq!(repo.find_object("HEAD@{0}").unwrap(), blobs[2]);
+        assert_eq!(repo.find_object("HEAD@{2}").unwrap(), blobs[0]);
+        assert_eq!(repo.find_object("master@{1}").unwrap(), blobs[1]);
+        assert_eq!(repo.find_object("@{1}").unwrap(), blobs[1]);
+        let err = repo.find_object("HEAD@{3}").unwrap_err();
+        assert!(err.to_string().contains("only has 3 entries"));
+        let err = repo.find_object("other@{0}").unwrap_err();
+        assert!(err.to_string().contains("no reflog for other"));
+    }
+
+    #[test]
+    fn reflog_uses_local_timezone() {
+        // TZ can only be set for a whole process, so this runs again in a child
+        if std::env::var_os("WYAG_TEST_TZ").is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args(["--exact", "repository::test::reflog_uses_local_timezone"])
+                .env("WYAG_TEST_TZ", "1")
+                // POSIX zones count west of UTC, so this is +0530
+                .env("TZ", "IST-5:30")
+                .output()
+                .unwrap();
+            let stdout = String::from_utf8_lossy(&output.stdout);
+            assert!(
+                output.status.success() && stdout.contains("1 passed"),
+                "{:?}",
+                output
+            );
+            return;
+        }
+
+        let (_dir, repo) = temp_repo();
+        let blob = GitObject::Blob(BlobObject::from(b"a".to_vec()));
+        let sha1 = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap();
+        repo.update_ref("HEAD", sha1, "test").unwrap();
+        let reflog = repo.reflog("HEAD").unwrap();
+        assert!(
+            reflog[0].identity.ends_with(" +0530"),
+            "{}",
+            reflog[0].identity
+        );
+    }
+
+    #[test]
+    fn detached_head() {
+        let (_dir, repo) = temp_repo();
+
+        let history = write_history(&repo, 2);
+        repo.write_ref("refs/heads/master", &history[1].encode_hex::<String>())
+            .unwrap();
+        assert_eq!(
+            repo.symbolic_ref("HEAD").unwrap().as_deref(),
+            Some("refs/heads/master")
+        );
+
+        repo.write_ref("HEAD", &history[0].encode_hex::<String>())
+            .unwrap();
+        assert_eq!(repo.symbolic_ref("HEAD").unwrap(), None);
+        assert_eq!(repo.head().unwrap(), Some(history[0]));
+        assert_eq!(repo.find_object("HEAD").unwrap(), history[0]);
+        assert_eq!(
+            repo.branches().unwrap(),
+            vec![("master".to_string(), history[1])]
+        );
+        let log = repo
+            .log_iter(repo.find_object("HEAD").unwrap())
+            .unwrap()
+            .map(|e| e.unwrap().sha)
+            .collect::<Vec<_>>();
+        assert_eq!(log, vec![history[0]]);
+    }
+
+    #[test]
+    fn resolve_packed_and_remote_refs() {
+        let (dir, repo) = temp_repo();
+
+        let history = write_history(&repo, 4);
+        let hex = |n: usize| history[n].encode_hex::<String>();
+        std::fs::write(
+            dir.path().join(".git/packed-refs"),
+            format!(
+                "# pack-refs with: peeled fully-peeled sorted \n\
+                 {} refs/heads/master\n\
+                 {} refs/remotes/origin/main\n\
+                 {} refs/tags/v1\n\
+                 ^{}\n",
+                hex(0),
+                hex(1),
+                hex(2),
+                hex(0)
+            ),
+        )
+        .unwrap();
+        repo.write_ref("refs/remotes/origin/HEAD", "ref: refs/remotes/origin/main")
+            .unwrap();
+
+        let find = |name: &str| repo.find_object(name).unwrap();
+        assert_eq!(find("master"), history[0]);
+        assert_eq!(find("origin/main"), history[1]);
+        assert_eq!(find("remotes/origin/main"), history[1]);
+        assert_eq!(find("refs/remotes/origin/main"), history[1]);
+        assert_eq!(find("origin"), history[1]);
+        assert_eq!(find("v1"), history[2]);
+
+        // a loose ref replaces its packed copy, and tags win over branches
+        repo.write_ref("refs/heads/master", &hex(3)).unwrap();
+        repo.write_ref("refs/heads/v1", &hex(3)).unwrap();
+        assert_eq!(find("master"), history[3]);
+        assert_eq!(find("v1"), history[2]);
+        assert_eq!(find("heads/v1"), history[3]);
+
+        assert_eq!(
+            repo.refs().unwrap(),
+            vec![
+                ("refs/heads/master".to_string(), history[3]),
+                ("refs/heads/v1".to_string(), history[3]),
+                ("refs/remotes/origin/HEAD".to_string(), history[1]),
+                ("refs/remotes/origin/main".to_string(), history[1]),
+                ("refs/tags/v1".to_string(), history[2]),
+            ]
+        );
+    }
+
+    #[test]
+    fn resolve_ref_precedence() {
+        let (dir, mut repo) = temp_repo();
+
+        let history = write_history(&repo, 6);
+        let hex = |n: usize| history[n].encode_hex::<String>();
+        let gitdir = dir.path().join(".git");
+
+        // refs/remotes/x can't be a file while refs/remotes/x/HEAD exists, so
+        // it gets its own turn once that is gone
+        let names = [
+            "refs/x",
+            "refs/tags/x",
+            "refs/heads/x",
+            "refs/remotes/x/HEAD",
+        ];
+        for (n, name) in names.iter().enumerate() {
+            repo.write_ref(name, &hex(n)).unwrap();
+        }
+        for (n, name) in names.iter().enumerate() {
+            assert_eq!(
+                repo.resolve_ref("x").unwrap(),
+                Some((name.to_string(), history[n]))
+            );
+            std::fs::remove_file(gitdir.join(name)).unwrap();
+        }
+        assert_eq!(repo.resolve_ref("x").unwrap(), None);
+
+        std::fs::remove_dir(gitdir.join("refs/remotes/x")).unwrap();
+        repo.write_ref("refs/remotes/x", &hex(4)).unwrap();
+        repo.write_ref("refs/heads/x", &hex(2)).unwrap();
+        assert_eq!(
+            repo.resolve_ref("x").unwrap(),
+            Some(("refs/heads/x".to_string(), history[2]))
+        );
+        assert_eq!(repo.find_object("remotes/x").unwrap(), history[4]);
+
+        // only names like FETCH_HEAD are looked up outside refs/
+        std::fs::write(gitdir.join("FETCH_HEAD"), hex(5) + "\n").unwrap();
+        std::fs::write(gitdir.join("lower"), hex(5) + "\n").unwrap();
+        assert_eq!(repo.find_object("FETCH_HEAD").unwrap(), history[5]);
+        assert_eq!(repo.resolve_ref("lower").unwrap(), None);
+
+        // turning the warning off must not change which ref wins
+        repo.config_set("core", "warnAmbiguousRefs", "false")
+            .unwrap();
+        repo.write_ref("refs/tags/x", &hex(1)).unwrap();
+        assert_eq!(
+            repo.resolve_ref("x").unwrap(),
+            Some(("refs/tags/x".to_string(), history[1]))
+        );
+    }
+
+    #[test]
+    fn list_tags_by_pattern() {
+        let (_dir, repo) = temp_repo();
+
+        let history = write_history(&repo, 3);
+        for name in ["v1.10", "v1.0", "release/2"] {
+            write_annotated_tag(&repo, name, history[1]);
+        }
+        repo.write_ref("refs/tags/light", &history[2].encode_hex::<String>())
+            .unwrap();
+        let names = |patterns: &[&str]| {
+            let patterns = patterns.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            repo.tags(&patterns)
+                .unwrap()
+                .into_iter()
+                .map(|(name, _)| name)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(names(&[]), ["light", "release/2", "v1.0", "v1.10"]);
+        assert_eq!(names(&["v1.*"]), ["v1.0", "v1.10"]);
+        // unlike in ignore files, `*` matches across `/`
+        assert_eq!(names(&["rel*", "l*"]), ["light", "release/2"]);
+        assert!(names(&["v2*"]).is_empty());
+
+        let tags = repo.tags(&[]).unwrap();
+        assert_eq!(repo.tag_message(tags[3].1).unwrap(), "v1.10\n");
+        // a lightweight tag shows its commit's message
+        assert_eq!(tags[0].1, history[2]);
+        assert_eq!(repo.tag_message(tags[0].1).unwrap(), "2\n");
+    }
+
+    #[test]
+    fn name_rev_picks_closest_ref() {
+        let (_dir, repo) = temp_repo();
+
+        let history = write_history(&repo, 5);
+        write_annotated_tag(&repo, "v1", history[1]);
+        repo.write_ref("refs/heads/master", &history[4].encode_hex::<String>())
+            .unwrap();
+        repo.write_ref("refs/heads/topic", &history[3].encode_hex::<String>())
+            .unwrap();
+        // a tag wins over a branch at the same commit
+        repo.write_ref("refs/tags/light", &history[3].encode_hex::<String>())
+            .unwrap();
+
+        let name = |n: usize| repo.name_rev(history[n]).unwrap();
+        assert_eq!(name(4).as_deref(), Some("master"));
+        assert_eq!(name(3).as_deref(), Some("tags/light"));
+        assert_eq!(name(2).as_deref(), Some("tags/light~1"));
+        assert_eq!(name(1).as_deref(), Some("tags/v1"));
+        assert_eq!(name(0).as_deref(), Some("tags/v1~1"));
+        let tree = GitObject::Tree(TreeBuilder::new().build());
+        let tree = repo
+            .write_object(&tree, false, Compression::default())
+            .unwrap();
+        assert_eq!(repo.name_rev(tree).unwrap(), None);
+    }
+
+    #[test]
+    fn describe_finds_nearest_tag() {
+        let (_dir, repo) = temp_repo();
+
+        let history = write_history(&repo, 5);
+        assert_eq!(repo.describe(history[4], true).unwrap(), None);
+
+        write_annotated_tag(&repo, "v1", history[1]);
+        repo.write_ref("refs/tags/light", &history[3].encode_hex::<String>())
+            .unwrap();
+
+        let describe = |n: usize, tags: bool| repo.describe(history[n], tags).unwrap();
+        assert_eq!(describe(4, false), Some(("v1".to_string(), 3)));
+        assert_eq!(describe(1, false), Some(("v1".to_string(), 0)));
+        assert_eq!(describe(4, true), Some(("light".to_string(), 1)));
+        assert_eq!(describe(2, true), Some(("v1".to_string(), 1)));
+        assert_eq!(describe(0, true), None);
+
+        // an annotated tag wins over a lightweight one on the same commit
+        repo.write_ref("refs/tags/a-light", &history[1].encode_hex::<String>())
+            .unwrap();
+        assert_eq!(describe(1, true), Some(("v1".to_string(), 0)));
+    }
+
+    #[test]
+    fn index_thin_pack() {
+        let (dir, repo) = temp_repo();
+
+        let base = b"the base of a thin pack delta\n".to_vec();
+        let base_sha1 = repo
+            .write_object(
+                &GitObject::Blob(BlobObject::from(base.clone())),
+                true,
+                Compression::default(),
+            )
+            .unwrap();
+
+        // copy the whole base, then insert a line
+        let mut delta = vec![base.len() as u8, base.len() as u8 + 5];
+        delta.extend_from_slice(&[0b1001_0000, base.len() as u8, 5]);
+        delta.extend_from_slice(b"more\n");
+        let mut pack = b"PACK\0\0\0\x02\0\0\0\x01".to_vec();
+        write_entry(
+            &mut pack,
+            BinaryObject::RefDelta(base_sha1),
+            &delta,
+            Compression::default(),
+        )
+        .unwrap();
+        let checksum = Sha1::digest(&pack);
+        pack.extend_from_slice(&checksum);
+
+        let mut expected = base.clone();
+        expected.extend_from_slice(b"more\n");
+        let expected_sha1 = repo
+            .write_object(
+                &GitObject::Blob(BlobObject::from(expected.clone())),
+                false,
+                Compression::default(),
+            )
+            .unwrap();
+
+        let (_other, empty) = temp_repo();
+        let err = empty.index_pack(&pack, Compression::default()).unwrap_err();
+        assert!(format!("{:#}", err).contains("is not in the repository"));
+
+        let pack_sha1 = repo.index_pack(&pack, Compression::default()).unwrap();
+        // the base was copied into the pack, so the loose object is no longer needed
+        let base_hex = base_sha1.encode_hex::<String>();
+        std::fs::remove_file(
+            dir.path()
+                .join(".git/objects")
+                .join(&base_hex[..2])
+                .join(&base_hex[2..]),
+        )
+        .unwrap();
+        let repo = Repository::new(dir.path(), false).unwrap();
+        let mut data = Vec::new();
+        repo.read_object_data(expected_sha1, &mut data).unwrap();
+        assert_eq!(data, expected);
+        let mut data = Vec::new();
+        repo.read_object_data(base_sha1, &mut data).unwrap();
+        assert_eq!(data, base);
+
+        let objects = repo
+            .list_pack(&pack_sha1.encode_hex::<String>(), None)
+            .unwrap();
+        assert_eq!(objects.len(), 2);
+        assert_eq!(objects[0].sha1, expected_sha1);
+        assert!(matches!(objects[0].object_type, BinaryObject::Blob));
+        assert_eq!(objects[0].size, delta.len());
+        assert_eq!(objects[0].offset, 12);
+        assert_eq!(objects[0].delta, Some((1, DeltaBase::Ref(base_sha1))));
+        assert_eq!(objects[1].sha1, base_sha1);
+        assert_eq!(objects[1].size, base.len());
+        assert_eq!(objects[1].offset, 12 + objects[0].packed_size);
+        assert_eq!(objects[1].delta, None);
+    }
+
+    #[test]
+    fn download_pack_only_stores_complete_packs() {
+        let (dir, repo) = temp_repo();
+        let name = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+        let pack = std::fs::read(format!("test/pack/pack-{}.pack", name)).unwrap();
+        let leftovers = || {
+            let mut files = [".git", ".git/objects/pack"]
+                .into_iter()
+                .filter_map(|path| std::fs::read_dir(dir.path().join(path)).ok())
+                .flatten()
+                .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+                .filter(|name| name.starts_with(".tmp") || name.starts_with("pack-"))
+                .collect::<Vec<_>>();
+            files.sort();
+            files
+        };
+
+        let truncated = &pack[..pack.len() / 2];
+        let err = repo
+            .download_pack(
+                |file| Ok(file.write_all(truncated)?),
+                Compression::default(),
+            )
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("truncated"));
+        assert!(leftovers().is_empty());
+
+        // a transfer that fails part way leaves nothing behind either
+        let err = repo
+            .download_pack(
+                |file| {
+                    file.write_all(truncated)?;
+                    bail!("connection reset")
+                },
+                Compression::default(),
+            )
+            .unwrap_err();
+        assert!(format!("{:#}", err).contains("connection reset"));
+        assert!(leftovers().is_empty());
+        let commit = decode("94cfdc807c74db921207c72b7f1d21c748ddc807").unwrap();
+        assert!(!repo.has_object(commit.as_slice().try_into().unwrap()));
+
+        let sha1 = repo
+            .download_pack(|file| Ok(file.write_all(&pack)?), Compression::default())
+            .unwrap();
+        assert_eq!(sha1.encode_hex::<String>(), name);
+        assert_eq!(
+            leftovers(),
+            [format!("pack-{}.idx", name), format!("pack-{}.pack", name)]
+        );
+        assert_eq!(repo.verify_pack(name, 1).unwrap().len(), 36);
+        assert!(repo.has_object(commit.as_slice().try_into().unwrap()));
+    }
+
+    #[test]
+    fn gc_packs_reachable_loose_objects() {
+        let (dir, repo) = temp_repo();
+
+        let history = write_history(&repo, 5);
+        repo.write_ref("refs/heads/master", &history[4].encode_hex::<String>())
+            .unwrap();
+        let unreachable = GitObject::Blob(BlobObject::from(b"unreachable".to_vec()));
+        let unreachable = repo
+            .write_object(&unreachable, true, Compression::default())
+            .unwrap();
+        assert_eq!(repo.loose_objects().unwrap().len(), 7);
+
+        // everything is newer than the grace period, so nothing is removed
+        let week = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+        assert_eq!(repo.gc(week, Compression::default()).unwrap(), (6, 0));
+        assert_eq!(repo.loose_objects().unwrap().len(), 7);
+
+        let (object_type, size) = repo.object_info(history[4]).unwrap();
+        assert!(matches!(object_type, BinaryObject::Commit));
+
+        assert_eq!(
+            repo.gc(std::time::Duration::ZERO, Compression::default())
+                .unwrap(),
+            (6, 6)
+        );
+        assert_eq!(repo.loose_objects().unwrap(), vec![unreachable]);
+        let repo = Repository::new(dir.path(), false).unwrap();
+        let (object_type, packed_size) = repo.object_info(history[4]).unwrap();
+        assert!(matches!(object_type, BinaryObject::Commit));
+        assert_eq!(packed_size, size);
+        assert_eq!(repo.log_iter(history[4]).unwrap().count(), 5);
+        assert_eq!(
+            repo.gc(std::time::Duration::ZERO, Compression::default())
+                .unwrap(),
+            (0, 0)
+        );
+    }
+
+    #[test]
+    fn gc_keeps_replaced_objects() {
+        let (dir, repo) = temp_repo();
+        let write = |object: GitObject| {
+            repo.write_object(&object, true, Compression::default())
+                .unwrap()
+        };
+        let original = write(GitObject::Blob(BlobObject::from(b"original\n".to_vec())));
+        let replacement = write(GitObject::Blob(BlobObject::from(b"replacement\n".to_vec())));
+        let mut tree = TreeBuilder::new();
+        tree.insert("file", "100644", original).unwrap();
+        let tree = write(GitObject::Tree(tree.build()));
+        let commit = format!(
+            "tree {}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nreplaced\n",
+            tree.encode_hex::<String>()
+        );
+        let commit = write(GitObject::Commit(
+            CommitObject::from(commit.into_bytes()).unwrap(),
+        ));
+        repo.write_ref("refs/heads/master", &commit.encode_hex::<String>())
+            .unwrap();
+        repo.write_ref(
+            &format!("refs/replace/{}", original.encode_hex::<String>()),
+            &replacement.encode_hex::<String>(),
+        )
+        .unwrap();
+
+        let repo = Repository::new(dir.path(), false).unwrap();
+        let mut data = Vec::new();
+        repo.read_object_data(original, &mut data).unwrap();
+        assert_eq!(data, b"replacement\n");
+
+        assert_eq!(
+            repo.gc(std::time::Duration::ZERO, Compression::default())
+                .unwrap(),
+            (4, 4)
+        );
+        assert!(repo.loose_objects().unwrap().is_empty());
+        assert_eq!(repo.fsck().unwrap(), vec![]);
+        let repo = Repository::new(dir.path(), false).unwrap();
+        for (sha1, expected) in [
+            (original, &b"original\n"[..]),
+            (replacement, b"replacement\n"),
+        ] {
+            let (_, source) = repo.read_stored_object(sha1, &mut data).unwrap();
+            assert!(matches!(source, ObjectSource::Packed { .. }));
+            assert_eq!(data, expected);
+        }
+        assert!(
+            repo.prune(std::time::Duration::ZERO, false)
+                .unwrap()
+                .is_empty()
+        );
+    }
+
+    #[test]
+    fn gc_leaves_kept_packs_alone() {
+        let (dir, repo) = temp_repo();
+        let history = write_history(&repo, 3);
+        repo.write_ref("refs/heads/master", &history[2].encode_hex::<String>())
+            .unwrap();
+
+        let week = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+        assert_eq!(repo.gc(week, Compression::default()).unwrap(), (4, 0));
+        let PackFile(kept, _) = repo.find_object_location(history[2]).unwrap() else {
+            panic!("the commit was not packed");
+        };
+        assert!(repo.kept_packs().unwrap().is_empty());
+        assert!(repo.keep_pack("0".repeat(40).as_str()).is_err());
+        assert!(repo.keep_pack("not a pack").is_err());
+        repo.keep_pack(&kept.encode_hex::<String>()).unwrap();
+        assert_eq!(repo.kept_packs().unwrap(), HashSet::from([kept]));
+
+        // a newer pack holding the same objects does not take over from the kept one
+        let objects = history
+            .iter()
+            .map(|sha1| {
+                let mut data = Vec::new();
+                let object_type = repo.read_object_data(*sha1, &mut data).unwrap();
+                (object_type, data)
+            })
+            .collect::<Vec<_>>();
+        let pack = write_pack(&objects, Compression::none()).unwrap();
+        let other = repo.index_pack(&pack, Compression::none()).unwrap();
+        assert_ne!(other, kept);
+        let repo = Repository::new(dir.path(), false).unwrap();
+        for sha1 in history.iter() {
+            assert!(matches!(
+                repo.find_object_location(*sha1),
+                Some(PackFile(pack, _)) if pack == kept
+            ));
+        }
+
+        // the loose copies are already in the kept pack, so nothing is packed
+        assert_eq!(
+            repo.gc(std::time::Duration::ZERO, Compression::default())
+                .unwrap(),
+            (0, 4)
+        );
+        assert!(repo.loose_objects().unwrap().is_empty());
+        assert_eq!(repo.log_iter(history[2]).unwrap().count(), 3);
+    }
+
+    #[test]
+    fn loose_objects_skip_other_files() {
+        let (dir, repo) = temp_repo();
+        let mut history = write_history(&repo, 3);
+
+        let objects = dir.path().join(".git/objects");
+        let shard = objects.join(&history[0].encode_hex::<String>()[..2]);
+        std::fs::write(shard.join("tmp_obj_Xy12ab"), "partial").unwrap();
+        std::fs::write(shard.join("not-hex-at-all-but-38-characters-long!"), "").unwrap();
+        std::fs::write(shard.join("A".repeat(38)), "").unwrap();
+        std::fs::create_dir_all(objects.join("zz")).unwrap();
+        std::fs::write(objects.join("zz").join("0".repeat(38)), "").unwrap();
+        std::fs::create_dir_all(objects.join("info")).unwrap();
+        std::fs::write(objects.join("info/alternates"), "").unwrap();
+        let pack = write_pack(
+            &[(BinaryObject::Blob, b"packed".to_vec())],
+            Compression::default(),
+        )
+        .unwrap();
+        repo.index_pack(&pack, Compression::default()).unwrap();
+
+        let loose = repo.loose_objects().unwrap();
+        history.push(Repository::EMPTY_TREE_SHA1);
+        history.sort();
+        assert_eq!(loose, history);
+
+        let counts = repo.count_objects().unwrap();
+        assert_eq!(counts.count, loose.len());
+        assert_eq!(counts.packs, 1);
+        assert_eq!(counts.in_pack, 1);
+        assert_eq!(counts.prune_packable, 0);
+        assert_eq!(counts.garbage, 3);
+    }
+
+    #[test]
+    fn fsck_reports_garbage_and_corruption() {
+        let (dir, repo) = temp_repo();
+        let history = write_history(&repo, 2);
+        let pack = write_pack(
+            &[(BinaryObject::Blob, b"packed".to_vec())],
+            Compression::default(),
+        )
+        .unwrap();
+        let pack_id = repo.index_pack(&pack, Compression::default()).unwrap();
+        assert_eq!(repo.fsck().unwrap(), vec![]);
+
+        let first = repo.object_file_path(history[0]).unwrap();
+        let temp = first.with_file_name("tmp_obj_Xy12ab");
+        std::fs::write(&temp, "partial").unwrap();
+        let second = repo.object_file_path(history[1]).unwrap();
+        std::fs::remove_file(&second).unwrap();
+        std::fs::write(&second, "not zlib").unwrap();
+
+        let problems = repo.fsck().unwrap();
+        assert_eq!(problems.len(), 2);
+        assert!(matches!(&problems[0], FsckProblem::Corrupt(sha1, _) if *sha1 == history[1]));
+        assert_eq!(problems[1], FsckProblem::Garbage(temp.clone()));
+        assert_eq!(
+            repo.loose_object_entries().unwrap().last(),
+            Some(&Err(temp))
+        );
+
+        let pack_path = dir.path().join(format!(
+            ".git/objects/pack/pack-{}.pack",
+            pack_id.encode_hex::<String>()
+        ));
+        let mut pack = std::fs::read(&pack_path).unwrap();
+        let end = pack.len() - 20;
+        pack[12..end].fill(0xff);
+        std::fs::write(&pack_path, pack).unwrap();
+        assert!(matches!(
+            repo.fsck().unwrap().last(),
+            Some(FsckProblem::BadPack(..))
+        ));
+    }
+
+    #[test]
+    fn prune_unreachable_loose_objects() {
+        let (dir, repo) = temp_repo();
+
+        let history = write_history(&repo, 2);
+        repo.write_ref("refs/heads/master", &history[1].encode_hex::<String>())
+            .unwrap();
+        let unreachable = GitObject::Blob(BlobObject::from(b"unreachable".to_vec()));
+        let unreachable = repo
+            .write_object(&unreachable, true, Compression::default())
+            .unwrap();
+        // staged content is kept even though no commit reaches it
+        std::fs::write(dir.path().join("staged"), "staged").unwrap();
+        repo.add(&[dir.path().join("staged")], false).unwrap();
+        assert_eq!(repo.loose_objects().unwrap().len(), 5);
+
+        let week = std::time::Duration::from_secs(7 * 24 * 60 * 60);
+        assert!(repo.prune(week, false).unwrap().is_empty());
+        let zero = std::time::Duration::ZERO;
+        assert_eq!(repo.prune(zero, true).unwrap(), vec![unreachable]);
+        assert_eq!(repo.loose_objects().unwrap().len(), 5);
+        assert_eq!(repo.prune(zero, false).unwrap(), vec![unreachable]);
+        assert_eq!(repo.loose_objects().unwrap().len(), 4);
+        assert!(!repo.has_object(unreachable));
+    }
+
+    #[test]
+    fn ls_tree_peels_to_tree() {
+        let (_dir, repo) = temp_repo();
+        let commit = write_nested_commit(&repo);
+        repo.write_ref("refs/heads/master", &commit.encode_hex::<String>())
+            .unwrap();
+        write_annotated_tag(&repo, "v1", commit);
+
+        let ls = |name: &str| {
+            repo.ls_tree(
+                name,
+                false,
+                false,
+                LsTreeFormat::NameOnly,
+                true,
+                Path::new(""),
+                &[],
+            )
+            .unwrap()
+            .into_iter()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(ls("HEAD"), vec!["a", "dir"]);
+        assert_eq!(ls("v1"), ls("HEAD"));
+
+        let blob = repo.object_at_path(commit, Path::new("a")).unwrap();
+        let err = repo
+            .ls_tree(
+                &blob.encode_hex::<String>(),
+                false,
+                false,
+                LsTreeFormat::NameOnly,
+                true,
+                Path::new(""),
+                &[],
+            )
+            .unwrap_err();
+        assert!(err.to_string().contains("not a tree-ish"));
+    }
+
+    #[test]
+    fn commit_objects_in_rev_list_order() {
+        let (_dir, repo) = temp_repo();
+        let history = write_history(&repo, 2);
+        let nested = write_nested_commit(&repo);
+
+        let hex = |objects: Vec<([u8; 20], PathBuf)>| {
+            objects
+                .into_iter()
+                .map(|(sha1, path)| (sha1.encode_hex::<String>(), path))
+                .collect::<Vec<_>>()
+        };
+        let empty_tree = "4b825dc642cb6eb9a060e54bf8d69288fbee4904".to_string();
+        assert_eq!(
+            hex(repo.commit_objects(&history).unwrap()),
+            vec![(empty_tree.clone(), PathBuf::new())]
+        );
+        assert_eq!(
+            hex(repo.commit_objects(&[nested, history[0]]).unwrap()),
+            vec![
+                (
+                    "ef2f0709574bc24577503ea444047aea3cb8b532".to_string(),
+                    PathBuf::new()
+                ),
+                (
+                    "ce013625030ba8dba906f756967f9e9ca394464a".to_string(),
+                    PathBuf::from("a")
+                ),
+                (
+                    "71bcb43de182055689b38cea247134bea019987c".to_string(),
+                    PathBuf::from("dir")
+                ),
+                (
+                    "18df7980ddf987c2e3e20eb8007727c659b37216".to_string(),
+                    PathBuf::from("dir/b")
+                ),
+                (empty_tree, PathBuf::new()),
+            ]
+        );
+
+        let walked = LogIterator::from_tips(&repo, &[history[0], nested, history[1]])
+            .unwrap()
+            .map(|entry| entry.unwrap().sha)
+            .collect::<HashSet<_>>();
+        assert_eq!(walked, HashSet::from([history[0], history[1], nested]));
+        assert!(repo.commit_objects(&[[0; 20]]).is_err());
+    }
+
+    #[test]
+    fn ls_tree_formats() {
+        let (_dir, repo) = temp_repo();
+        let commit = write_nested_commit(&repo).encode_hex::<String>();
+
+        let ls = |recurse, trees_only, format| {
+            repo.ls_tree(
+                &commit,
+                recurse,
+                trees_only,
+                format,
+                true,
+                Path::new(""),
+                &[],
+            )
+            .unwrap()
+            .into_iter()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ls(false, false, LsTreeFormat::Default),
+            vec![
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a\ta",
+                "040000 tree 71bcb43de182055689b38cea247134bea019987c\tdir",
+            ]
+        );
+        assert_eq!(
+            ls(true, false, LsTreeFormat::Long),
+            vec![
+                "100644 blob ce013625030ba8dba906f756967f9e9ca394464a       6\ta",
+                "100644 blob 18df7980ddf987c2e3e20eb8007727c659b37216       7\tdir/b",
+            ]
+        );
+        assert_eq!(ls(true, false, LsTreeFormat::NameOnly), vec!["a", "dir/b"]);
+        assert_eq!(ls(false, true, LsTreeFormat::NameOnly), vec!["dir"]);
+        assert_eq!(
+            ls(false, true, LsTreeFormat::Long),
+            vec!["040000 tree 71bcb43de182055689b38cea247134bea019987c       -\tdir"]
+        );
+    }
+
+    #[test]
+    fn ls_tree_symlinks() {
+        let (_dir, repo) = temp_repo();
+        let target = GitObject::Blob(BlobObject::from(b"target".to_vec()));
+        let target = repo
+            .write_object(&target, true, Compression::default())
+            .unwrap();
+        let mut tree = TreeBuilder::new();
+        tree.insert("link", "120000", target).unwrap();
+        let tree = repo
+            .write_object(&GitObject::Tree(tree.build()), true, Compression::default())
+            .unwrap()
+            .encode_hex::<String>();
+
+        let ls = |format| {
+            repo.ls_tree(&tree, false, false, format, true, Path::new(""), &[])
+                .unwrap()
+                .into_iter()
+                .map(|line| String::from_utf8(line).unwrap())
+                .collect::<Vec<_>>()
+        };
+        // git prints a symlink like any other blob
+        assert_eq!(
+            ls(LsTreeFormat::Default),
+            vec!["120000 blob 1de565933b05f74c75ff9a6520af5f9f8a5a2f1d\tlink"]
+        );
+        assert_eq!(
+            ls(LsTreeFormat::Long),
+            vec!["120000 blob 1de565933b05f74c75ff9a6520af5f9f8a5a2f1d       6\tlink"]
+        );
+    }
+
+    #[test]
+    fn ls_tree_unusual_paths() {
+        let (_dir, repo) = temp_repo();
+        let blob = GitObject::Blob(BlobObject::from(b"odd\n".to_vec()));
+        let blob = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap();
+        let mut tree = TreeBuilder::new();
+        tree.insert("with space\nand newline", "100644", blob)
+            .unwrap();
+        let tree = repo
+            .write_object(&GitObject::Tree(tree.build()), true, Compression::default())
+            .unwrap()
+            .encode_hex::<String>();
+
+        let ls = |quote_paths| {
+            repo.ls_tree(
+                &tree,
+                false,
+                false,
+                LsTreeFormat::Default,
+                quote_paths,
+                Path::new(""),
+                &[],
+            )
+            .unwrap()
+            .into_iter()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(
+            ls(true),
+            vec![
+                "100644 blob 994e126d270f6ab080f20051254741652e2bc726\t\"with space\\nand newline\""
+            ]
+        );
+        assert_eq!(
+            ls(false),
+            vec!["100644 blob 994e126d270f6ab080f20051254741652e2bc726\twith space\nand newline"]
+        );
+
+        // a name that is not utf8 is quoted, or passed through byte for byte
+        let data = [&b"100644 caf\xe9\0"[..], &blob].concat();
+        let tree = repo
+            .write_object(
+                &GitObject::Tree(TreeObject::new(&data).unwrap()),
+                true,
+                Compression::default(),
+            )
+            .unwrap()
+            .encode_hex::<String>();
+        let ls = |quote_paths| {
+            repo.ls_tree(
+                &tree,
+                false,
+                false,
+                LsTreeFormat::NameOnly,
+                quote_paths,
+                Path::new(""),
+                &[],
+            )
+            .unwrap()
+        };
+        assert_eq!(ls(true), vec![b"\"caf\\351\"".to_vec()]);
+        assert_eq!(ls(false), vec![b"caf\xe9".to_vec()]);
+    }
+
+    #[test]
+    fn quote_path_config() {
+        let (_dir, mut repo) = temp_repo();
+        let path = Path::new("caf\u{e9}\tx");
+        assert_eq!(repo.quote_path(path), "\"caf\\303\\251\\tx\"");
+        repo.config_set("core", "quotePath", "false").unwrap();
+        assert_eq!(repo.quote_path(path), "\"caf\u{e9}\\tx\"");
+    }
+
+    #[test]
+    fn ls_tree_pathspecs() {
+        let (_dir, repo) = temp_repo();
+        let commit = write_nested_commit(&repo).encode_hex::<String>();
+
+        let ls = |recurse, pathspecs: &[&str]| {
+            let pathspecs = pathspecs.iter().map(|p| p.to_string()).collect::<Vec<_>>();
+            repo.ls_tree(
+                &commit,
+                recurse,
+                false,
+                LsTreeFormat::NameOnly,
+                true,
+                Path::new(""),
+                &pathspecs,
+            )
+            .unwrap()
+            .into_iter()
+            .map(|line| String::from_utf8(line).unwrap())
+            .collect::<Vec<_>>()
+        };
+        assert_eq!(ls(false, &["dir"]), vec!["dir"]);
+        assert_eq!(ls(false, &["dir/"]), vec!["dir/b"]);
+        assert_eq!(ls(true, &["dir"]), vec!["dir/b"]);
+        assert_eq!(ls(false, &["dir/b", "a"]), vec!["a", "dir/b"]);
+        assert_eq!(ls(false, &["a/"]), vec!["a"]);
+        assert!(ls(false, &["missing", "di"]).is_empty());
+    }
+
+    #[test]
+    fn pack_cache_evicts_least_recently_used() {
+        let (dir, _) = temp_repo();
+        let mut repo = Repository::new(dir.path(), false).unwrap();
+        repo.config_set("core", "maxOpenPacks", "2").unwrap();
+        let repo = Repository::new(dir.path(), false).unwrap();
+
+        let packs = (0..3)
+            .map(|n| {
+                let pack =
+                    write_pack(&[(BinaryObject::Blob, vec![n])], Compression::default()).unwrap();
+                repo.index_pack(&pack, Compression::default()).unwrap()
+            })
+            .collect::<Vec<_>>();
+        let cached = |repo: &Repository| {
+            let cache = repo.pack_cache.borrow();
+            cache.packs.iter().map(|(id, _)| *id).collect::<Vec<_>>()
+        };
+
+        let held = repo.open_pack(packs[0]).unwrap();
+        repo.open_pack(packs[1]).unwrap();
+        repo.open_pack(packs[0]).unwrap();
+        repo.open_pack(packs[2]).unwrap();
+        assert_eq!(cached(&repo), vec![packs[0], packs[2]]);
+
+        repo.open_pack(packs[1]).unwrap();
+        assert_eq!(cached(&repo), vec![packs[2], packs[1]]);
+        // evicted, but still usable by whoever holds it
+        assert_eq!(Rc::strong_count(&held), 1);
+        assert_eq!(held.index_entries().unwrap().0.len(), 1);
+    }
+
+    #[test]
+    fn init_bare_with_initial_branch() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("repo.git");
+        Repository::new_bare(&path, true)
+            .unwrap()
+            .init_with_branch("main")
+            .unwrap();
+        assert!(!path.join(".git").exists());
+        assert_eq!(
+            std::fs::read_to_string(path.join("HEAD")).unwrap(),
+            "ref: refs/heads/main\n"
+        );
+
+        let repo = Repository::find(&path).unwrap();
+        assert!(repo.is_bare());
+        // a second init keeps HEAD
+        assert!(repo.init().unwrap());
+        assert_eq!(
+            std::fs::read_to_string(path.join("HEAD")).unwrap(),
+            "ref: refs/heads/main\n"
+        );
+        assert_eq!(repo.config_get("core", "bare").as_deref(), Some("true"));
+        assert!(repo.add(&[path.join("HEAD")], false).is_err());
+        assert!(
+            Repository::new(&dir.path().join("other"), true)
+                .unwrap()
+                .init_with_branch("bad name")
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn reinit_keeps_existing_repository() {
+        let (dir, repo) = temp_repo();
+        let history = write_history(&repo, 1);
+        repo.update_ref("refs/heads/master", history[0], "test")
+            .unwrap();
+        let config = "[core]\n\trepositoryformatversion = 0\n[user]\n\tname = Kept\n";
+        std::fs::write(dir.path().join(".git/config"), config).unwrap();
+        std::fs::remove_dir(dir.path().join(".git/refs/tags")).unwrap();
+
+        assert!(repo.init_with_branch("main").unwrap());
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(".git/HEAD")).unwrap(),
+            "ref: refs/heads/master\n"
+        );
+        assert_eq!(
+            std::fs::read_to_string(dir.path().join(".git/config")).unwrap(),
+            config
+        );
+        assert!(dir.path().join(".git/refs/tags").is_dir());
+        assert_eq!(repo.head().unwrap(), Some(history[0]));
+    }
+
+    #[test]
+    fn repository_format_extensions() {
+        let (dir, _) = temp_repo();
+        let open = |version: u32, extensions: &str| {
+            let config = format!(
+                "[core]\n\trepositoryformatversion = {}\n[extensions]\n{}",
+                version, extensions
+            );
+            std::fs::write(dir.path().join(".git/config"), config).unwrap();
+            Repository::new(dir.path(), false).map(|_| ())
+        };
+
+        assert!(open(1, "").is_ok());
+        assert!(open(1, "\tobjectFormat = sha1\n\tworktreeConfig = true\n").is_ok());
+        assert!(open(1, "\tobjectFormat = sha256\n").is_err());
+        assert!(open(1, "\tpartialClone = origin\n").is_err());
+        // version 0 predates extensions, so they are ignored
+        assert!(open(0, "\tpartialClone = origin\n").is_ok());
+        assert!(open(2, "").is_err());
+    }
+
+    #[test]
+    fn config_set_keeps_git_config() {
+        let (dir, _) = temp_repo();
+        let original = std::fs::read_to_string("test/config/git-config").unwrap();
+        std::fs::write(dir.path().join(".git/config"), &original).unwrap();
+
+        let mut repo = Repository::new(dir.path(), false).unwrap();
+        repo.config_set("user", "email", "me@x").unwrap();
+        repo.config_set("branch \"Main\"", "remote", "upstream")
+            .unwrap();
+        let written = std::fs::read_to_string(dir.path().join(".git/config")).unwrap();
+        assert_eq!(
+            written,
+            original.replace("\tremote = origin\n", "\tremote = upstream\n")
+                + "[user]\n\temail = me@x\n"
+        );
+        assert_eq!(repo.config_get("user", "email").as_deref(), Some("me@x"));
+
+        let home = tempfile::tempdir().unwrap();
+        assert!(read_global_config(home.path()).is_none());
+        std::fs::write(home.path().join(".gitconfig"), "[broken\nx").unwrap();
+        assert!(read_global_config(home.path()).is_none());
+        std::fs::write(home.path().join(".gitconfig"), "[user]\n\tname = A\n").unwrap();
+        let global = read_global_config(home.path()).unwrap();
+        assert_eq!(global.get("user", "name").as_deref(), Some("A"));
+    }
+
+    #[test]
+    fn worktree_config_takes_precedence() {
+        let (dir, _) = temp_repo();
+        std::fs::write(
+            dir.path().join(".git/config.worktree"),
+            "[core]\n\tsparseCheckout = true\n[user]\n\tname = Worktree\n",
+        )
+        .unwrap();
+        let mut repo = Repository::new(dir.path(), false).unwrap();
+        repo.config_set("user", "name", "Main").unwrap();
+        assert_eq!(repo.config_get("user", "name").as_deref(), Some("Main"));
+
+        repo.config_set("extensions", "worktreeConfig", "true")
+            .unwrap();
+        let repo = Repository::new(dir.path(), false).unwrap();
+        assert_eq!(repo.config_get("user", "name").as_deref(), Some("Worktree"));
+        assert_eq!(repo.config_get("core", "bare").as_deref(), Some("false"));
+        let list = repo.config_list();
+        assert!(list.contains(&("core".into(), "sparsecheckout".into(), "true".into())));
+        assert!(list.contains(&("user".into(), "name".into(), "Worktree".into())));
+    }
+
+    #[test]
+    fn error_variants() {
+        let (dir, repo) = temp_repo();
+        let blob = GitObject::Blob(BlobObject::from(b"contents".to_vec()));
+        let blob = repo
+            .write_object(&blob, true, Compression::default())
+            .unwrap();
+
+        assert!(matches!(
+            repo.read_object_data([1; 20], &mut Vec::new()),
+            Err(WyagError::NotFound(_))
+        ));
+        assert!(matches!(
+            repo.find_object("missing"),
+            Err(WyagError::NotFound(_))
+        ));
+        assert!(matches!(
+            repo.find_object("HEAD@{0}"),
+            Err(WyagError::NotFound(_))
+        ));
+        assert!(matches!(
+            repo.read_object_data_as(blob, ObjectType::Tree, &mut Vec::new()),
+            Err(WyagError::WrongType { sha, .. }) if sha == blob
+        ));
+
+        let path = repo.object_file_path(blob).unwrap();
+        std::fs::write(&path, b"not zlib").unwrap();
+        assert!(matches!(
+            repo.read_object_data(blob, &mut Vec::new()),
+            Err(WyagError::Corrupt { sha, .. }) if sha == blob
+        ));
+        assert!(matches!(
+            repo.object_info(blob),
+            Err(WyagError::Corrupt { sha, .. }) if sha == blob
+        ));
+
+        std::fs::write(
+            dir.path().join(".git/config"),
+            "[core]\n\trepositoryformatversion = 1\n[extensions]\n\tpartialClone = origin\n",
+        )
+        .unwrap();
+        let err = Repository::new(dir.path(), false).err().unwrap();
+        assert!(matches!(
+            err.downcast_ref::<WyagError>(),
+            Some(WyagError::Unsupported(_))
+        ));
+    }
+
+    #[test]
+    fn list_pack_delta_chains() {
+        let (_dir, repo) = temp_repo();
+
+        // each object copies the one before it and appends a line
+        let mut objects = vec![(BinaryObject::Blob, b"base\n".to_vec())];
+        let mut contents = b"base\n".to_vec();
+        let mut shas = vec![];
+        for line in [&b"one\n"[..], b"two\n"] {
+            let base = GitObject::Blob(BlobObject::from(contents.clone()));
+            shas.push(
+                repo.write_object(&base, false, Compression::default())
+                    .unwrap(),
+            );
+            let mut delta = vec![contents.len() as u8, (contents.len() + line.len()) as u8];
+            delta.extend_from_slice(&[0b1001_0000, contents.len() as u8, line.len() as u8]);
+            delta.extend_from_slice(line);
+            objects.push((BinaryObject::RefDelta(*shas.last().unwrap()), delta));
+            contents.extend_from_slice(line);
+        }
+        let pack_sha1 = repo
+            .index_pack(
+                &write_pack(&objects, Compression::default()).unwrap(),
+                Compression::default(),
+            )
+            .unwrap();
+
+        let listed = repo
+            .list_pack(&pack_sha1.encode_hex::<String>(), None)
+            .unwrap();
+        let deltas = listed.iter().map(|o| o.delta).collect::<Vec<_>>();
+        assert_eq!(
+            deltas,
+            vec![
+                None,
+                Some((1, DeltaBase::Ref(shas[0]))),
+                Some((2, DeltaBase::Ref(shas[1])))
+            ]
+        );
+        assert!(
+            listed
+                .iter()
+                .all(|o| matches!(o.object_type, BinaryObject::Blob))
+        );
+    }
 }
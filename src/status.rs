@@ -0,0 +1,203 @@
+use crate::gitignore::GitIgnore;
+use crate::index::IndexEntry;
+use crate::repository::Repository;
+use anyhow::{Context, Result, ensure};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Change {
+    Added,
+    Modified,
+    Deleted,
+}
+
+impl Display for Change {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Change::Added => "new file",
+            Change::Modified => "modified",
+            Change::Deleted => "deleted",
+        };
+        f.write_str(name)
+    }
+}
+
+impl Change {
+    /// The letter `git status --porcelain` uses for the change.
+    pub fn code(&self) -> char {
+        match self {
+            Change::Added => 'A',
+            Change::Modified => 'M',
+            Change::Deleted => 'D',
+        }
+    }
+}
+
+/// The differences between HEAD, the index and the worktree.
+#[derive(Debug, Default)]
+pub struct Status {
+    /// Index compared to HEAD.
+    pub staged: Vec<(Change, PathBuf)>,
+    /// Worktree compared to the index.
+    pub unstaged: Vec<(Change, PathBuf)>,
+    /// Files not in the index, untracked directories end in `/`.
+    pub untracked: Vec<PathBuf>,
+}
+
+impl Status {
+    pub fn new(repository: &Repository) -> Result<Self> {
+        ensure!(!repository.is_bare(), "status must be run in a worktree");
+        let index = repository.read_index().context("reading index")?;
+        let head = match repository.head()? {
+            Some(head) => repository.tree_entries(head).context("reading HEAD tree")?,
+            None => BTreeMap::new(),
+        };
+
+        let mut status = Status::default();
+
+        let staged = index
+            .entries
+            .iter()
+            .map(|e| (e.path.as_path(), e))
+            .collect::<HashMap<_, _>>();
+        for entry in index.entries.iter() {
+            match head.get(&entry.path) {
+                None => status.staged.push((Change::Added, entry.path.clone())),
+                Some((mode, sha1)) if *mode != entry.mode || *sha1 != entry.sha1 => {
+                    status.staged.push((Change::Modified, entry.path.clone()))
+                }
+                _ => {}
+            }
+        }
+        for path in head.keys() {
+            if !staged.contains_key(path.as_path()) {
+                status.staged.push((Change::Deleted, path.clone()));
+            }
+        }
+        status.staged.sort_by(|a, b| a.1.cmp(&b.1));
+
+        for entry in index.entries.iter() {
+            if let Some(change) = worktree_change(repository, entry)
+                .with_context(|| format!("checking {}", entry.path.to_string_lossy()))?
+            {
+                status.unstaged.push((change, entry.path.clone()));
+            }
+        }
+
+        let ignore = repository.gitignore().context("reading ignore rules")?;
+        collect_untracked(
+            repository,
+            &ignore,
+            &staged,
+            Path::new(""),
+            &mut status.untracked,
+        )
+        .context("finding untracked files")?;
+
+        Ok(status)
+    }
+}
+
+fn worktree_change(repository: &Repository, entry: &IndexEntry) -> Result<Option<Change>> {
+    let path = repository.worktree.join(&entry.path);
+    let metadata = match path.symlink_metadata() {
+        Ok(metadata) if !metadata.is_dir() => metadata,
+        Ok(_) => return Ok(Some(Change::Deleted)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Some(Change::Deleted)),
+        Err(e) => return Err(e).context("reading metadata"),
+    };
+
+    if entry.stat_matches(&metadata) {
+        return Ok(None);
+    }
+
+    let current = IndexEntry::new(
+        entry.path.clone(),
+        repository.hash_path(&path, false)?,
+        &metadata,
+    );
+    if current.sha1 != entry.sha1 || current.mode != entry.mode {
+        return Ok(Some(Change::Modified));
+    }
+    Ok(None)
+}
+
+fn collect_untracked(
+    repository: &Repository,
+    ignore: &GitIgnore,
+    staged: &HashMap<&Path, &IndexEntry>,
+    dir: &Path,
+    untracked: &mut Vec<PathBuf>,
+) -> Result<()> {
+    let mut children = repository
+        .worktree
+        .join(dir)
+        .read_dir()
+        .with_context(|| format!("reading directory {}", dir.to_string_lossy()))?
+        .map(|entry| entry.map(|e| (dir.join(e.file_name()), e.path())))
+        .collect::<io::Result<Vec<_>>>()
+        .context("reading directory entry")?;
+    children.sort();
+
+    for (relative, path) in children {
+        if relative.file_name().is_some_and(|n| n == ".git") {
+            continue;
+        }
+        let tracked = staged.contains_key(relative.as_path());
+        if !tracked && ignore.is_ignored(&relative) {
+            continue;
+        }
+        if path.symlink_metadata().is_ok_and(|m| m.is_dir()) {
+            let mut inner = Vec::new();
+            collect_untracked(repository, ignore, staged, &relative, &mut inner)?;
+            // like git, directories holding no files are left out, and
+            // directories with nothing tracked in them are shown collapsed
+            if !inner.is_empty() && !staged.keys().any(|p| p.starts_with(&relative)) {
+                let mut relative = relative.into_os_string();
+                relative.push("/");
+                untracked.push(relative.into());
+            } else {
+                untracked.extend(inner);
+            }
+        } else if !tracked {
+            untracked.push(relative);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::status::{Change, Status};
+    use crate::testutil::temp_repo;
+    use std::fs::{create_dir_all, write};
+    use std::path::PathBuf;
+
+    #[test]
+    fn untracked_directories() {
+        let (dir, repo) = temp_repo();
+        let root = dir.path();
+        create_dir_all(root.join("empty/nested")).unwrap();
+        create_dir_all(root.join("new/inner")).unwrap();
+        write(root.join("new/inner/file"), "new\n").unwrap();
+        create_dir_all(root.join("src")).unwrap();
+        write(root.join("src/lib.rs"), "lib\n").unwrap();
+        write(root.join("src/main.rs"), "main\n").unwrap();
+        create_dir_all(root.join("src/empty")).unwrap();
+        repo.add(&[root.join("src/lib.rs")], false).unwrap();
+
+        let status = Status::new(&repo).unwrap();
+        assert_eq!(
+            status.staged,
+            vec![(Change::Added, PathBuf::from("src/lib.rs"))]
+        );
+        assert!(status.unstaged.is_empty());
+        assert_eq!(
+            status.untracked,
+            vec![PathBuf::from("new/"), PathBuf::from("src/main.rs")]
+        );
+    }
+}
@@ -0,0 +1,95 @@
+//! Setup shared by the tests of every module.
+
+use crate::gitobject::GitObject;
+use crate::gitobject::blob::BlobObject;
+use crate::gitobject::commit::CommitObject;
+use crate::gitobject::tag::TagObject;
+use crate::gitobject::tree::TreeBuilder;
+use crate::objectstore::ObjectStore;
+use crate::repository::Repository;
+use flate2::Compression;
+use hex::ToHex;
+use tempfile::TempDir;
+
+/// A new repository in a temporary directory, which is removed with it.
+pub(crate) fn temp_repo() -> (TempDir, Repository) {
+    let dir = tempfile::tempdir().unwrap();
+    let repo = Repository::new(dir.path(), true).unwrap();
+    repo.init().unwrap();
+    (dir, repo)
+}
+
+/// Write a commit of the empty tree with `parents`, authored and committed
+/// at the given times, returning its sha.
+pub(crate) fn write_commit(
+    store: &dyn ObjectStore,
+    parents: &[[u8; 20]],
+    authored: i64,
+    committed: i64,
+    message: &str,
+) -> [u8; 20] {
+    let tree = store
+        .write(&GitObject::Tree(TreeBuilder::new().build()))
+        .unwrap();
+    let mut commit = format!("tree {}\n", tree.encode_hex::<String>());
+    for parent in parents {
+        commit += &format!("parent {}\n", parent.encode_hex::<String>());
+    }
+    commit += &format!(
+        "author A <a@b> {authored} +0000\ncommitter A <a@b> {committed} +0000\n\n{message}\n"
+    );
+    let commit = CommitObject::from(commit.into_bytes()).unwrap();
+    store.write(&GitObject::Commit(commit)).unwrap()
+}
+
+/// Write a linear history of `len` commits with empty trees, oldest first.
+pub(crate) fn write_history(store: &dyn ObjectStore, len: usize) -> Vec<[u8; 20]> {
+    let mut history: Vec<[u8; 20]> = Vec::new();
+    for n in 0..len {
+        let parents = history.last().map(|p| vec![*p]).unwrap_or_default();
+        let n = n as i64;
+        history.push(write_commit(store, &parents, n, n, &n.to_string()));
+    }
+    history
+}
+
+/// Tag `target` with an annotated tag called `name`, under `refs/tags`.
+pub(crate) fn write_annotated_tag(repo: &Repository, name: &str, target: [u8; 20]) {
+    let tag = format!(
+        "object {}\ntype commit\ntag {name}\ntagger A <a@b> 0 +0000\n\n{name}\n",
+        target.encode_hex::<String>()
+    );
+    let tag = GitObject::Tag(TagObject::from(tag.into_bytes()).unwrap());
+    let tag = repo
+        .write_object(&tag, true, Compression::default())
+        .unwrap();
+    repo.write_ref(&format!("refs/tags/{}", name), &tag.encode_hex::<String>())
+        .unwrap();
+}
+
+/// Write a root commit of `a` and `dir/b`, returning its sha.
+pub(crate) fn write_nested_commit(repo: &Repository) -> [u8; 20] {
+    let blob = |contents: &str| {
+        let blob = GitObject::Blob(BlobObject::from(contents.as_bytes().to_vec()));
+        repo.write_object(&blob, true, Compression::default())
+            .unwrap()
+    };
+    let mut dir = TreeBuilder::new();
+    dir.insert("b", "100644", blob("world!\n")).unwrap();
+    let dir = repo
+        .write_object(&GitObject::Tree(dir.build()), true, Compression::default())
+        .unwrap();
+    let mut root = TreeBuilder::new();
+    root.insert("a", "100644", blob("hello\n")).unwrap();
+    root.insert("dir", "40000", dir).unwrap();
+    let root = repo
+        .write_object(&GitObject::Tree(root.build()), true, Compression::default())
+        .unwrap();
+    let commit = format!(
+        "tree {}\nauthor A <a@b> 0 +0000\ncommitter A <a@b> 0 +0000\n\nnested\n",
+        root.encode_hex::<String>()
+    );
+    let commit = GitObject::Commit(CommitObject::from(commit.into_bytes()).unwrap());
+    repo.write_object(&commit, true, Compression::default())
+        .unwrap()
+}
@@ -1,10 +1,13 @@
 use crate::pack::BinaryObject;
+use anyhow::Context;
 use hex::ToHex;
 use log::{debug, trace};
 use sha1::digest::Update;
 use sha1::{Digest, Sha1};
 use std::io;
 use std::io::{BufReader, Read};
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
 use std::str::from_utf8;
 
 pub fn read_byte<T: Read>(reader: &mut T) -> io::Result<u8> {
@@ -32,8 +35,12 @@ pub fn parse_offset_delta<T: Read>(reader: &mut BufReader<T>) -> io::Result<u64>
     let mut offset = b as u64 & 0x7f;
 
     while b & 0x80 > 0 {
-        offset += 1;
-        offset <<= 7;
+        // each continuation byte shifts in 7 more bits, so a long enough run overflows
+        offset = offset
+            .checked_add(1)
+            .filter(|offset| offset.leading_zeros() >= 7)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "offset delta overflows"))?
+            << 7;
         b = read_byte(reader)?;
         offset += b as u64 & 0x7f;
     }
@@ -41,9 +48,27 @@ pub fn parse_offset_delta<T: Read>(reader: &mut BufReader<T>) -> io::Result<u64>
     Ok(offset)
 }
 
+/// The offset of an offset delta's base, which must come before the delta in the pack.
+pub fn delta_base_offset(offset: u64, delta_offset: u64) -> anyhow::Result<u64> {
+    offset
+        .checked_sub(delta_offset)
+        .filter(|_| delta_offset > 0)
+        .with_context(|| {
+            format!(
+                "offset delta at {} points {} bytes back, outside the pack",
+                offset, delta_offset
+            )
+        })
+}
+
 pub fn get_sha1(object_type: BinaryObject, data: &[u8]) -> [u8; 20] {
+    hash_raw_object(object_type.name().as_bytes(), data)
+}
+
+/// Hash an object under any type word, including ones git does not know.
+pub fn hash_raw_object(type_name: &[u8], data: &[u8]) -> [u8; 20] {
     let mut hasher = Sha1::new();
-    Update::update(&mut hasher, object_type.name().as_bytes());
+    Update::update(&mut hasher, type_name);
     Update::update(&mut hasher, b" ");
     Update::update(&mut hasher, data.len().to_string().as_bytes());
     Update::update(&mut hasher, b"\0");
@@ -67,3 +92,85 @@ pub fn validate_sha1(sha1: [u8; 20], object_type: BinaryObject, data: &[u8]) ->
     );
     Ok(())
 }
+
+/// Quote `path` the way git does in line-based output: left alone unless it
+/// holds control characters, quotes, backslashes or, with `quote_non_ascii`
+/// (git's `core.quotePath`), non-ASCII bytes, and otherwise wrapped in double
+/// quotes with C-style escapes. Bytes that are not UTF-8 are always escaped,
+/// so nothing is lost.
+pub fn quote_path(path: &Path, quote_non_ascii: bool) -> String {
+    let bytes = path.as_os_str().as_bytes();
+    let quote_non_ascii = quote_non_ascii || from_utf8(bytes).is_err();
+    let needs_quoting = |&b: &u8| {
+        b < 0x20 || b == b'"' || b == b'\\' || b == 0x7f || (quote_non_ascii && b >= 0x80)
+    };
+    if !bytes.iter().any(needs_quoting) {
+        return path.to_string_lossy().into_owned();
+    }
+    let mut quoted = vec![b'"'];
+    for &b in bytes {
+        match b {
+            0x07 => quoted.extend(b"\\a"),
+            0x08 => quoted.extend(b"\\b"),
+            b'\t' => quoted.extend(b"\\t"),
+            b'\n' => quoted.extend(b"\\n"),
+            0x0b => quoted.extend(b"\\v"),
+            0x0c => quoted.extend(b"\\f"),
+            b'\r' => quoted.extend(b"\\r"),
+            b'"' => quoted.extend(b"\\\""),
+            b'\\' => quoted.extend(b"\\\\"),
+            b if needs_quoting(&b) => quoted.extend(format!("\\{:03o}", b).bytes()),
+            b => quoted.push(b),
+        }
+    }
+    quoted.push(b'"');
+    // escapes are ASCII and anything left as it was is whole UTF-8
+    String::from_utf8(quoted).expect("quoted path is UTF-8")
+}
+
+#[cfg(test)]
+mod test {
+    use crate::util::{delta_base_offset, parse_offset_delta, quote_path};
+    use std::ffi::OsStr;
+    use std::io::BufReader;
+    use std::os::unix::ffi::OsStrExt;
+    use std::path::Path;
+
+    #[test]
+    fn offset_delta_overflow() {
+        let parse = |bytes: &[u8]| parse_offset_delta(&mut BufReader::new(bytes));
+        assert_eq!(parse(&[0x05]).unwrap(), 5);
+        assert_eq!(parse(&[0x80, 0x00]).unwrap(), 128);
+        assert_eq!(parse(&[0x81, 0x7f]).unwrap(), 383);
+
+        // ten bytes only fit while the high bits stay clear
+        let mut max = vec![0x80; 9];
+        max.push(0x00);
+        assert_eq!(parse(&max).unwrap(), 9295997013522923648);
+        let mut long = vec![0xff; 9];
+        long.push(0x7f);
+        let err = parse(&long).unwrap_err();
+        assert_eq!(err.kind(), std::io::ErrorKind::InvalidData);
+
+        assert_eq!(delta_base_offset(100, 40).unwrap(), 60);
+        assert!(delta_base_offset(100, 101).is_err());
+        assert!(delta_base_offset(100, 0).is_err());
+    }
+
+    #[test]
+    fn quoted_paths() {
+        let quote = |path: &[u8]| quote_path(Path::new(OsStr::from_bytes(path)), true);
+        assert_eq!(quote(b"dir/with space"), "dir/with space");
+        assert_eq!(quote(b"a\nb"), "\"a\\nb\"");
+        assert_eq!(quote(b"a\tb"), "\"a\\tb\"");
+        assert_eq!(quote(b"say \"hi\"\\"), "\"say \\\"hi\\\"\\\\\"");
+        assert_eq!(quote("caf\u{e9}".as_bytes()), "\"caf\\303\\251\"");
+        assert_eq!(quote(b"\x01\x7f\xff"), "\"\\001\\177\\377\"");
+
+        // core.quotePath=false keeps UTF-8 but still escapes invalid bytes
+        let quote = |path: &[u8]| quote_path(Path::new(OsStr::from_bytes(path)), false);
+        assert_eq!(quote("caf\u{e9}".as_bytes()), "caf\u{e9}");
+        assert_eq!(quote("caf\u{e9}\t".as_bytes()), "\"caf\u{e9}\\t\"");
+        assert_eq!(quote(b"high\xff"), "\"high\\377\"");
+    }
+}
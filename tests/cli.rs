@@ -0,0 +1,219 @@
+//! Run the wyag binary against scratch repositories and check what it prints.
+
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, Output, Stdio};
+use tempfile::TempDir;
+
+fn wyag(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_wyag"))
+        .current_dir(dir)
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+/// Run wyag with `input` on its standard input.
+fn wyag_with_input(dir: &Path, args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_wyag"))
+        .current_dir(dir)
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+fn stdout(output: Output) -> String {
+    assert!(output.status.success(), "{:?}", output);
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// A new repository holding the fixture pack with the given name, and every
+/// file that goes with it.
+fn repo_with_pack(dir: &str, pack: &str) -> TempDir {
+    let repo = tempfile::tempdir().unwrap();
+    stdout(wyag(repo.path(), &["init", "."]));
+    let pack_dir = repo.path().join(".git/objects/pack");
+    std::fs::create_dir_all(&pack_dir).unwrap();
+    for file in std::fs::read_dir(dir).unwrap() {
+        let file = file.unwrap();
+        if file.file_name().to_string_lossy().starts_with(pack) {
+            std::fs::copy(file.path(), pack_dir.join(file.file_name())).unwrap();
+        }
+    }
+    repo
+}
+
+#[test]
+fn rev_list_count_uses_bitmap() {
+    let repo = repo_with_pack(
+        "test/bitmap",
+        "pack-f8b8fa138f607a7c74af69f2ea2dae8b7127ede1",
+    );
+    let tip = "c0d5f6537fcfb1a0a7cf6b1b16ba154acd0b087e";
+    let count = |args: &[&str]| stdout(wyag(repo.path(), args));
+
+    let logged = count(&["--log-level", "debug", "rev-list", "--count", tip]);
+    assert!(logged.contains("found bitmap"), "{}", logged);
+    assert!(logged.ends_with("\n14\n"), "{}", logged);
+    assert_eq!(count(&["rev-list", "--count", tip]), "14\n");
+    assert_eq!(
+        count(&[
+            "rev-list",
+            "--count",
+            "61d90a71d7455793ffedbf4f3c4714e398d3e082",
+            tip
+        ]),
+        "14\n"
+    );
+    assert_eq!(
+        count(&[
+            "rev-list",
+            "--count",
+            "61d90a71d7455793ffedbf4f3c4714e398d3e082"
+        ]),
+        "11\n"
+    );
+
+    // the walk gives the same answer without the bitmap
+    let bitmap = "pack-f8b8fa138f607a7c74af69f2ea2dae8b7127ede1.bitmap";
+    std::fs::remove_file(repo.path().join(".git/objects/pack").join(bitmap)).unwrap();
+    let logged = count(&["--log-level", "debug", "rev-list", "--count", tip]);
+    assert!(!logged.contains("found bitmap"), "{}", logged);
+    assert_eq!(count(&["rev-list", "--count", tip]), "14\n");
+    assert_eq!(count(&["rev-list", tip]).lines().count(), 14);
+}
+
+#[test]
+fn status_porcelain() {
+    let repo = tempfile::tempdir().unwrap();
+    let dir = repo.path();
+    stdout(wyag(dir, &["init", "."]));
+    std::fs::create_dir_all(dir.join("empty")).unwrap();
+    std::fs::create_dir_all(dir.join("new")).unwrap();
+    std::fs::write(dir.join("new/file"), "new\n").unwrap();
+    std::fs::write(dir.join("added"), "added\n").unwrap();
+    std::fs::write(dir.join("changed"), "one\n").unwrap();
+    std::fs::write(dir.join("a b"), "space\n").unwrap();
+    stdout(wyag(dir, &["add", "added", "changed"]));
+    std::fs::write(dir.join("changed"), "two\n").unwrap();
+
+    assert_eq!(
+        stdout(wyag(dir, &["status", "--porcelain"])),
+        "A  added\nAM changed\n?? \"a b\"\n?? new/\n"
+    );
+    assert_eq!(
+        stdout(wyag(dir, &["status", "-z"])),
+        "A  added\0AM changed\0?? a b\0?? new/\0"
+    );
+}
+
+#[test]
+fn cat_object_batch() {
+    let repo = tempfile::tempdir().unwrap();
+    let dir = repo.path();
+    stdout(wyag(dir, &["init", "."]));
+    std::fs::write(dir.join("file"), "hello\n").unwrap();
+    let blob = stdout(wyag(dir, &["hash-object", "-w", "file"]));
+    let blob = blob.trim();
+    assert_eq!(blob, "ce013625030ba8dba906f756967f9e9ca394464a");
+    let missing = "0000000000000000000000000000000000000001";
+    let input = format!("{}\n{}\n", blob, missing);
+
+    assert_eq!(
+        stdout(wyag_with_input(
+            dir,
+            &["cat-object", "--batch-check"],
+            input.as_bytes()
+        )),
+        format!("{} blob 6\n{} missing\n", blob, missing)
+    );
+    assert_eq!(
+        stdout(wyag_with_input(
+            dir,
+            &["cat-object", "--batch"],
+            input.as_bytes()
+        )),
+        format!("{} blob 6\nhello\n\n{} missing\n", blob, missing)
+    );
+}
+
+#[test]
+fn verify_pack_object_counts() {
+    let id = "2c61a5470c8319d5ff6cf64199aa714f18414c26";
+    let repo = repo_with_pack("test/pack", &format!("pack-{}", id));
+    let verified = stdout(wyag(repo.path(), &["verify-pack", id]));
+    assert_eq!(verified.lines().count(), 37);
+    assert!(verified.ends_with(&format!("pack-{}.pack: ok\n", id)));
+
+    // a pack header claiming an object the index does not list
+    let pack_path = repo
+        .path()
+        .join(format!(".git/objects/pack/pack-{}.pack", id));
+    let mut pack = std::fs::read(&pack_path).unwrap();
+    pack[8..12].copy_from_slice(&37u32.to_be_bytes());
+    std::fs::write(&pack_path, pack).unwrap();
+    let output = wyag(repo.path(), &["verify-pack", id]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(
+        stderr.contains("has 37 objects but its index lists 36"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+fn exit_codes() {
+    let repo = tempfile::tempdir().unwrap();
+    let dir = repo.path();
+    let code = |args: &[&str]| wyag(dir, args).status.code();
+
+    // outside a repository is a fatal error
+    assert_eq!(code(&["cat-object", "blob", "HEAD"]), Some(128));
+
+    stdout(wyag(dir, &["init", "."]));
+    assert_eq!(code(&["--help"]), Some(0));
+    assert_eq!(code(&["no-such-command"]), Some(128));
+    // a missing object is an answer, not a failure of the command
+    let missing = "0000000000000000000000000000000000000001";
+    assert_eq!(code(&["cat-object", "blob", missing]), Some(1));
+    std::fs::write(dir.join("file"), "hello\n").unwrap();
+    let blob = stdout(wyag(dir, &["hash-object", "-w", "file"]));
+    assert_eq!(code(&["cat-object", "tree", blob.trim()]), Some(1));
+    assert_eq!(code(&["cat-object", "blob", blob.trim()]), Some(0));
+}
+
+#[test]
+fn output_modes() {
+    let repo = repo_with_pack(
+        "test/bitmap",
+        "pack-f8b8fa138f607a7c74af69f2ea2dae8b7127ede1",
+    );
+    let dir = repo.path();
+    let tip = "c0d5f6537fcfb1a0a7cf6b1b16ba154acd0b087e";
+    let old = "61d90a71d7455793ffedbf4f3c4714e398d3e082";
+    std::fs::write(dir.join(".git/refs/heads/master"), format!("{}\n", tip)).unwrap();
+    std::fs::write(dir.join(".git/refs/heads/old"), format!("{}\n", old)).unwrap();
+
+    assert_eq!(stdout(wyag(dir, &["branch"])), "* master\n  old\n");
+    assert_eq!(
+        stdout(wyag(dir, &["branch", "--porcelain"])),
+        format!("* {} master\n  {} old\n", tip, old)
+    );
+    assert_eq!(
+        stdout(wyag(dir, &["branch", "--porcelain", "-z"])),
+        format!("* {} master\0  {} old\0", tip, old)
+    );
+
+    let output = wyag(dir, &["gc", "--quiet"]);
+    assert!(output.stderr.is_empty(), "{:?}", output);
+    assert_eq!(stdout(output), "");
+    let packed = stdout(wyag(dir, &["gc"]));
+    assert!(packed.starts_with("packed "), "{}", packed);
+}